@@ -0,0 +1,477 @@
+use std::{
+    collections::HashMap,
+    ops::Deref,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{Result, ensure};
+use futures::StreamExt;
+use iroh::{PublicKey, Signature};
+use iroh_blobs::Hash;
+use iroh_docs::{
+    AuthorId, DocTicket,
+    api::{Doc, protocol::ShareMode},
+    engine::LiveEvent,
+    store::Query,
+};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::{
+    crypto, iroh_create_author, iroh_create_doc, now_millis,
+    schema::Migrations,
+    server::IrohNet,
+    store::{GetProperties, IrohCls, IrohProperties, ToBytes},
+    validate::Validators,
+};
+
+// name of the access list doc in the tickets file and the control protocol
+pub const ACL_TABLE: &str = "acl";
+
+/// What a key listed in the access list may do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Role {
+    // listed, but writes are ignored
+    Reader,
+    Writer,
+    // writes and changes the access list
+    Admin,
+}
+
+impl std::str::FromStr for Role {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "reader" => Ok(Role::Reader),
+            "writer" => Ok(Role::Writer),
+            "admin" => Ok(Role::Admin),
+            _ => Err(anyhow::anyhow!(
+                "Unknown role '{}', expected admin, writer or reader",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Role::Reader => "reader",
+            Role::Writer => "writer",
+            Role::Admin => "admin",
+        })
+    }
+}
+
+/// Entry of the access list doc, keyed by the hex of an author or node public key and a
+/// random suffix, see [`grant_key`]
+///
+/// Grants link to the grants they build on by content hash, so the list is replayed in
+/// causal order instead of by the time their authors claim. Every grant gets a key of its
+/// own, so later ones don't replace the earlier ones they link to
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Grant {
+    // `None` once the access was revoked
+    pub role: Option<Role>,
+    // milliseconds since the unix epoch, only orders grants written concurrently
+    pub updated_at: u64,
+    // grant that made the writer an admin, `None` for the founding grant
+    pub proof: Option<Hash>,
+    // grant of the same key the writer replaced, `None` for a key that was not listed
+    pub supersedes: Option<Hash>,
+    // founding grant only, signature of the node that created the list, see `founding_message`
+    pub founder: Option<Vec<u8>>,
+}
+
+// layout of version 0, before grants were linked
+#[derive(Deserialize)]
+struct GrantV0 {
+    role: Option<Role>,
+    updated_at: u64,
+}
+
+impl ToBytes<Grant> for Grant {
+    const VERSION: u32 = 1;
+
+    // unlinked grants can't be checked, they are read but never count
+    fn migrations() -> Migrations<Grant> {
+        Migrations::new().register(0, |old: GrantV0| Grant {
+            role: old.role,
+            updated_at: old.updated_at,
+            proof: None,
+            supersedes: None,
+            founder: None,
+        })
+    }
+
+    fn missing_file(_id: String) -> Self {
+        Grant {
+            role: None,
+            updated_at: 0,
+            proof: None,
+            supersedes: None,
+            founder: None,
+        }
+    }
+}
+
+/// Roles currently granted by the access list doc
+///
+/// Shared by the tables of a node. While nobody is listed every author may write,
+/// afterwards writes of authors without the writer or admin role are ignored
+#[derive(Debug, Clone, Default)]
+pub struct Acl {
+    grants: Arc<RwLock<HashMap<[u8; 32], Role>>>,
+    // grant in effect for every key that was ever listed, new grants link to it
+    heads: Arc<RwLock<HashMap<[u8; 32], Hash>>>,
+}
+
+impl Acl {
+    /// Whether anybody is listed, otherwise access is not restricted
+    pub fn is_enabled(&self) -> bool {
+        !self.grants.read().unwrap().is_empty()
+    }
+
+    /// Role of an author or node public key
+    pub fn role(&self, key: &[u8; 32]) -> Option<Role> {
+        self.grants.read().unwrap().get(key).copied()
+    }
+
+    /// Whether entries written by `author` are taken into account
+    pub fn may_write(&self, author: &AuthorId) -> bool {
+        !self.is_enabled() || self.role(author.as_bytes()) >= Some(Role::Writer)
+    }
+
+    pub fn is_admin(&self, key: &[u8; 32]) -> bool {
+        self.role(key) == Some(Role::Admin)
+    }
+
+    /// Listed keys, formatted like author ids, and their roles
+    pub fn grants(&self) -> Vec<(String, Role)> {
+        let mut grants: Vec<(String, Role)> = self
+            .grants
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(key, role)| (AuthorId::from(*key).to_string(), *role))
+            .collect();
+        grants.sort();
+        grants
+    }
+
+    // grant in effect for `key`, also after it was revoked
+    fn head(&self, key: &[u8; 32]) -> Option<Hash> {
+        self.heads.read().unwrap().get(key).copied()
+    }
+
+    fn replace(&self, grants: HashMap<[u8; 32], Role>, heads: HashMap<[u8; 32], Hash>) {
+        *self.grants.write().unwrap() = grants;
+        *self.heads.write().unwrap() = heads;
+    }
+}
+
+/// Parse an author id or node id given on the command line
+pub fn parse_key(s: &str) -> Result<[u8; 32]> {
+    if let Ok(author) = s.parse::<AuthorId>() {
+        return Ok(*author.as_bytes());
+    }
+    if let Ok(node) = s.parse::<PublicKey>() {
+        return Ok(*node.as_bytes());
+    }
+    Err(anyhow::anyhow!(
+        "'{}' is neither an author id nor a node id",
+        s
+    ))
+}
+
+/// Doc holding the access list of all tables
+#[derive(Clone)]
+pub struct AclTable {
+    table: IrohCls<Grant>,
+    // nodes of the ticket the list was created or joined with, only they can found it
+    founders: Vec<PublicKey>,
+}
+
+impl Deref for AclTable {
+    type Target = IrohCls<Grant>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.table
+    }
+}
+
+impl GetProperties for AclTable {
+    fn get_doc(&self) -> &Doc {
+        &self.table.doc
+    }
+}
+
+// a grant read from the doc, with the entry it was read from
+struct Candidate {
+    hash: Hash,
+    author: [u8; 32],
+    key: [u8; 32],
+    grant: Grant,
+}
+
+// doc key of a new grant for `key`, unique so the doc keeps every grant
+fn grant_key(key: &[u8; 32]) -> String {
+    format!(
+        "{}/{}",
+        hex::encode(key),
+        hex::encode(rand::random::<[u8; 8]>())
+    )
+}
+
+// listed key of a grant entry, written by `grant_key`
+fn listed_key(entry_key: &[u8]) -> Option<[u8; 32]> {
+    let (key, _) = std::str::from_utf8(entry_key).ok()?.split_once('/')?;
+    hex::decode(key).ok()?.try_into().ok()
+}
+
+// what the creating node signs to found the list with `author` as its first admin
+fn founding_message(namespace: &[u8; 32], author: &[u8; 32]) -> Vec<u8> {
+    [b"iroh-test acl founder".as_slice(), namespace, author].concat()
+}
+
+impl AclTable {
+    pub async fn new(ticket: &Option<DocTicket>, node: IrohNet) -> Result<Self> {
+        let doc = iroh_create_doc(&node, ticket).await?;
+        let author = iroh_create_author(&node).await?;
+        let (ticket, founders) = match ticket {
+            Some(ticket) => (None, ticket.nodes.iter().map(|addr| addr.id).collect()),
+            None => (
+                Some(doc.share(ShareMode::Write, node.addr_info()).await?),
+                vec![node.router.endpoint().id()],
+            ),
+        };
+        Ok(AclTable {
+            table: IrohCls::<Grant> {
+                node,
+                doc,
+                ticket,
+                author,
+                entity: None,
+                validators: Validators::default(),
+                prefix: Vec::new(),
+            },
+            founders,
+        })
+    }
+
+    /// Give `key` a role, or revoke its access with `None`
+    ///
+    /// Only admins change the access list. The first grant on an empty list also makes
+    /// the local author an admin, so it keeps writing. That one has to be made on the node
+    /// that created the list
+    pub async fn grant(&self, key: [u8; 32], role: Option<Role>) -> Result<()> {
+        // check against the grants stored right now, not the last rebuild
+        self.rebuild().await?;
+        let acl = &self.node.acl;
+        let me = *self.author.as_bytes();
+        if !acl.is_enabled() {
+            self.found(me).await?;
+        } else {
+            ensure!(acl.is_admin(&me), "Only admins can change the access list");
+        }
+        let admins = acl
+            .grants()
+            .iter()
+            .filter(|(_, role)| *role == Role::Admin)
+            .count();
+        ensure!(
+            !(acl.is_admin(&key) && role != Some(Role::Admin) && admins <= 1),
+            "The last admin cannot be removed"
+        );
+        let grant = Grant {
+            role,
+            updated_at: now_millis(),
+            proof: acl.head(&me),
+            supersedes: acl.head(&key),
+            founder: None,
+        };
+        self.insert_bytes(grant_key(&key), grant.as_bytes()?)
+            .await?;
+        self.rebuild().await
+    }
+
+    // make `author` the first admin, signed by this node
+    async fn found(&self, author: [u8; 32]) -> Result<()> {
+        let endpoint = self.node.router.endpoint();
+        ensure!(
+            self.founders.contains(&endpoint.id()),
+            "The access list can only be started on the node that created it"
+        );
+        let message = founding_message(self.doc.id().as_bytes(), &author);
+        let grant = Grant {
+            role: Some(Role::Admin),
+            updated_at: now_millis(),
+            proof: None,
+            supersedes: self.node.acl.head(&author),
+            founder: Some(endpoint.secret_key().sign(&message).to_bytes().to_vec()),
+        };
+        self.insert_bytes(grant_key(&author), grant.as_bytes()?)
+            .await?;
+        self.rebuild().await
+    }
+
+    // whether `candidate` founds the list, signed by one of the founding nodes
+    fn is_founding(&self, candidate: &Candidate) -> bool {
+        let Some(signature) = &candidate.grant.founder else {
+            return false;
+        };
+        let Ok(signature) = <[u8; 64]>::try_from(signature.as_slice()) else {
+            return false;
+        };
+        let signature = Signature::from_bytes(&signature);
+        let message = founding_message(self.doc.id().as_bytes(), &candidate.key);
+        candidate.author == candidate.key
+            && candidate.grant.role == Some(Role::Admin)
+            && candidate.grant.proof.is_none()
+            && self
+                .founders
+                .iter()
+                .any(|node| node.verify(&message, &signature).is_ok())
+    }
+
+    /// Recompute the roles of [`IrohNet::acl`] from the doc
+    ///
+    /// Grants are replayed in causal order. Only a grant signed by the node that created the
+    /// list founds it, every later one counts once the grant it names as proof is the one in
+    /// effect for its author and makes it an admin, and the grant it supersedes is the one in
+    /// effect for its key. Grants that never line up, e.g. ones an author wrote for itself,
+    /// are ignored whatever time they claim. Grants written concurrently are ordered by
+    /// their time and then their hash
+    pub async fn rebuild(&self) -> Result<()> {
+        let mut candidates = vec![];
+        let mut unlinked = 0;
+        for entry in self.entries(Query::all()).await? {
+            // grants written before they were linked are keyed by the listed key alone
+            let Some(key) = listed_key(entry.key()) else {
+                unlinked += 1;
+                continue;
+            };
+            // content of remote grants may not have arrived yet
+            let Ok(bytes) = self
+                .node
+                .blobs_store
+                .blobs()
+                .get_bytes(entry.content_hash())
+                .await
+            else {
+                continue;
+            };
+            let bytes = crypto::open(
+                self.node.cipher.as_ref(),
+                self.doc.id(),
+                entry.key(),
+                bytes.to_vec(),
+            )?;
+            candidates.push(Candidate {
+                hash: entry.content_hash(),
+                author: *entry.author().as_bytes(),
+                key,
+                grant: Grant::from_bytes(bytes.into())?,
+            });
+        }
+        candidates.sort_by_key(|c| (c.grant.updated_at, c.hash));
+
+        let mut roles: HashMap<[u8; 32], Role> = HashMap::new();
+        let mut heads: HashMap<[u8; 32], Hash> = HashMap::new();
+        loop {
+            // the list is founded again only if every admin is gone
+            let has_admin = roles.values().any(|role| *role == Role::Admin);
+            let next = candidates.iter().position(|c| {
+                if !has_admin {
+                    return self.is_founding(c);
+                }
+                let proof = c.grant.proof;
+                proof.is_some()
+                    && roles.get(&c.author) == Some(&Role::Admin)
+                    && heads.get(&c.author).copied() == proof
+                    && heads.get(&c.key).copied() == c.grant.supersedes
+            });
+            let Some(next) = next else {
+                break;
+            };
+            let c = candidates.remove(next);
+            match c.grant.role {
+                Some(role) => roles.insert(c.key, role),
+                None => roles.remove(&c.key),
+            };
+            heads.insert(c.key, c.hash);
+        }
+        if !candidates.is_empty() {
+            debug!(
+                ignored = candidates.len(),
+                "ignoring grants that do not line up"
+            );
+        }
+        if unlinked > 0 && roles.is_empty() {
+            warn!(
+                grants = unlinked,
+                "access list only holds grants of an older version, grant the roles again"
+            );
+        }
+        debug!(grants = roles.len(), "access list updated");
+        self.node.acl.replace(roles, heads);
+        Ok(())
+    }
+}
+
+/// Keep [`IrohNet::acl`] in line with the access list doc
+pub fn spawn_acl_follower(table: AclTable) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut events = match table.doc.subscribe().await {
+            Ok(events) => events,
+            Err(e) => {
+                warn!(error = %e, "failed to follow the access list");
+                return;
+            }
+        };
+        if let Err(e) = table.rebuild().await {
+            warn!(error = %e, "failed to load the access list");
+        }
+        info!(namespace = %table.doc.id(), "following the access list");
+        while let Some(Ok(event)) = events.next().await {
+            if matches!(
+                event,
+                LiveEvent::InsertLocal { .. }
+                    | LiveEvent::InsertRemote { .. }
+                    | LiveEvent::ContentReady { .. }
+            ) && let Err(e) = table.rebuild().await
+            {
+                warn!(error = %e, "failed to update the access list");
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a grant as version 0 encoded it
+    #[derive(Serialize)]
+    struct UnlinkedGrant {
+        role: Option<Role>,
+        updated_at: u64,
+    }
+
+    #[test]
+    fn decodes_unlinked_grants_of_version_0() {
+        let bytes = bincode::serialize(&UnlinkedGrant {
+            role: Some(Role::Writer),
+            updated_at: 42,
+        })
+        .unwrap();
+        let (grant, version) = Grant::from_bytes_versioned(bytes.into()).unwrap();
+        assert_eq!(version, 0);
+        assert_eq!(grant.role, Some(Role::Writer));
+        assert_eq!(grant.updated_at, 42);
+        assert_eq!(grant.proof, None);
+        assert_eq!(grant.founder, None);
+    }
+}
@@ -0,0 +1,355 @@
+use std::{collections::HashSet, sync::Arc};
+
+use anyhow::{Context, Result, ensure};
+use iroh::{
+    Endpoint, EndpointAddr, PublicKey, SecretKey,
+    endpoint::Connection,
+    protocol::{AcceptError, ProtocolHandler},
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, RwLock, mpsc, oneshot};
+use tracing::{info, warn};
+
+use crate::{
+    RelayChoice,
+    acl::Acl,
+    import::ImportReader,
+    model::{folder::Folders, node::Nodes, resource::Resources},
+    store::{IrohProperties, StoreState},
+};
+
+pub const ADMIN_ALPN: &[u8] = b"iroh-test/admin/1";
+
+// upper bound for a single request or response, content streamed after a request is not bound
+pub const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+// admin calls waiting for the interactive loop
+const ADMIN_QUEUE_CAPACITY: usize = 16;
+
+// closes the connection of a node that is not allowed, the reason is shown to its operator
+const REJECTED_CODE: u32 = 1;
+const REJECTED_REASON: &[u8] = b"Node is not allowed to administer this peer";
+
+/// Action an operator invokes on a remote node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AdminRequest {
+    AddFolder {
+        name: String,
+    },
+    // store files, given as name and content, in a resource table or collection
+    AddFiles {
+        table: String,
+        files: Vec<(String, Vec<u8>)>,
+    },
+    // store the content streamed after the request as a single file
+    AddStream {
+        table: String,
+        name: String,
+    },
+    ListResources,
+    Stats,
+    Shutdown,
+}
+
+/// Result of an [`AdminRequest`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AdminResponse {
+    Done(String),
+    // id, name and size of every resource
+    Resources(Vec<(String, String, u64)>),
+    Stats(NodeStats),
+    Error(String),
+}
+
+/// Counters reported by [`AdminRequest::Stats`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeStats {
+    pub resources: usize,
+    pub folders: usize,
+    pub nodes: usize,
+    pub collections: usize,
+    // peers that are currently neighbors for at least one table
+    pub connected_peers: usize,
+    pub downloads_in_flight: usize,
+    // application events dropped by full event channels
+    #[serde(default)]
+    pub dropped_events: u64,
+}
+
+/// An authenticated request handed to the interactive loop
+pub struct AdminCall {
+    pub from: PublicKey,
+    pub request: AdminRequest,
+    // rest of the stream of an [`AdminRequest::AddStream`]
+    content: Option<ImportReader>,
+    reply: oneshot::Sender<AdminResponse>,
+}
+
+impl AdminCall {
+    /// Content streamed after the request, taken once
+    pub fn take_content(&mut self) -> Option<ImportReader> {
+        self.content.take()
+    }
+
+    pub fn respond(self, response: AdminResponse) {
+        let _ = self.reply.send(response);
+    }
+}
+
+impl std::fmt::Debug for AdminCall {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AdminCall")
+            .field("from", &self.from)
+            .field("request", &self.request)
+            .finish()
+    }
+}
+
+/// Accepts admin requests from allowed node ids and queues them for the interactive loop
+///
+/// Node ids with the admin role in the access list are allowed as well
+#[derive(Debug, Clone)]
+pub struct AdminProtocol {
+    allowed: Arc<RwLock<HashSet<PublicKey>>>,
+    acl: Acl,
+    tx: mpsc::Sender<AdminCall>,
+    // taken once by the loop executing the calls
+    rx: Arc<Mutex<Option<mpsc::Receiver<AdminCall>>>>,
+}
+
+impl AdminProtocol {
+    pub fn new(acl: Acl) -> Self {
+        let (tx, rx) = mpsc::channel(ADMIN_QUEUE_CAPACITY);
+        AdminProtocol {
+            allowed: Arc::new(RwLock::new(HashSet::new())),
+            acl,
+            tx,
+            rx: Arc::new(Mutex::new(Some(rx))),
+        }
+    }
+
+    /// Allow `peers` to send admin requests, nobody is allowed by default
+    pub async fn allow(&self, peers: impl IntoIterator<Item = PublicKey>) {
+        self.allowed.write().await.extend(peers);
+    }
+
+    /// Whether `peer` may send admin requests, by the allow list or its role in the access list
+    pub async fn is_allowed(&self, peer: &PublicKey) -> bool {
+        self.allowed.read().await.contains(peer) || self.acl.is_admin(peer.as_bytes())
+    }
+
+    /// Take the queue of admin calls, can only be taken once
+    pub async fn take_requests(&self) -> Option<mpsc::Receiver<AdminCall>> {
+        self.rx.lock().await.take()
+    }
+
+    async fn handle(&self, connection: Connection) -> Result<()> {
+        let remote = connection.remote_id()?;
+        // unknown nodes are turned away before anything they send is read
+        if !self.is_allowed(&remote).await {
+            warn!(from = %remote.fmt_short(), "rejected admin connection");
+            connection.close(REJECTED_CODE.into(), REJECTED_REASON);
+            return Ok(());
+        }
+        let (mut send, mut recv) = connection.accept_bi().await?;
+        let mut len = [0u8; 4];
+        recv.read_exact(&mut len).await?;
+        let len = u32::from_le_bytes(len) as usize;
+        ensure!(
+            len <= MAX_MESSAGE_SIZE,
+            "Admin request of {} bytes is too large",
+            len
+        );
+        let mut request = vec![0u8; len];
+        recv.read_exact(&mut request).await?;
+        let request: AdminRequest = bincode::deserialize(&request)?;
+
+        info!(?request, from = %remote.fmt_short(), "admin request");
+        let content = match request {
+            AdminRequest::AddStream { .. } => Some(Box::pin(recv) as ImportReader),
+            _ => None,
+        };
+        let (reply, response) = oneshot::channel();
+        self.tx
+            .send(AdminCall {
+                from: remote,
+                request,
+                content,
+                reply,
+            })
+            .await?;
+        let response = response
+            .await
+            .unwrap_or_else(|_| AdminResponse::Error("Request was dropped".to_string()));
+        send.write_all(&bincode::serialize(&response)?).await?;
+        send.finish()?;
+        connection.closed().await;
+        Ok(())
+    }
+}
+
+impl ProtocolHandler for AdminProtocol {
+    async fn accept(&self, connection: Connection) -> Result<(), AcceptError> {
+        self.handle(connection)
+            .await
+            .map_err(|e| AcceptError::from_err(std::io::Error::other(e)))
+    }
+}
+
+/// Execute an admin request against the local tables, `content` is the stream of an
+/// [`AdminRequest::AddStream`], see [`AdminCall::take_content`]
+///
+/// [`AdminRequest::Shutdown`] is only acknowledged, stopping is up to the caller
+pub async fn handle_admin(
+    state: &StoreState,
+    request: AdminRequest,
+    content: Option<ImportReader>,
+) -> AdminResponse {
+    let result = match request {
+        AdminRequest::AddFolder { name } => add_folder(state, name).await,
+        AdminRequest::AddFiles { table, files } => add_files(state, table, files).await,
+        AdminRequest::AddStream { table, name } => match content {
+            Some(content) => add_stream(state, table, name, content).await,
+            None => Err(anyhow::anyhow!("No content was streamed")),
+        },
+        AdminRequest::ListResources => list_resources(state).await,
+        AdminRequest::Stats => stats(state).await,
+        AdminRequest::Shutdown => Ok(AdminResponse::Done("Shutting down".to_string())),
+    };
+    result.unwrap_or_else(|e| AdminResponse::Error(e.to_string()))
+}
+
+async fn add_folder(state: &StoreState, name: String) -> Result<AdminResponse> {
+    let folder = state.tables.require::<Folders>("folder").await?;
+    folder.insert_folder(name.clone()).await?;
+    Ok(AdminResponse::Done(format!("Added folder {}", name)))
+}
+
+async fn add_files(
+    state: &StoreState,
+    table: String,
+    files: Vec<(String, Vec<u8>)>,
+) -> Result<AdminResponse> {
+    let resources = state.resource_table(&table).await?;
+    let ids = resources.add_files(files).await?;
+    Ok(AdminResponse::Done(format!(
+        "Added {} files to {}",
+        ids.len(),
+        table
+    )))
+}
+
+// content is written to a temporary file as it arrives, never held in memory
+async fn add_stream(
+    state: &StoreState,
+    table: String,
+    name: String,
+    content: ImportReader,
+) -> Result<AdminResponse> {
+    let resources = state.resource_table(&table).await?;
+    resources
+        .add_file_from_reader(name.clone(), content)
+        .await?;
+    Ok(AdminResponse::Done(format!("Added {} to {}", name, table)))
+}
+
+async fn list_resources(state: &StoreState) -> Result<AdminResponse> {
+    let resources = state
+        .resource_table("resource")
+        .await?
+        .search()
+        .await?
+        .into_iter()
+        .map(|r| (r.id.clone(), r.name.clone(), r.size))
+        .collect();
+    Ok(AdminResponse::Resources(resources))
+}
+
+async fn stats(state: &StoreState) -> Result<AdminResponse> {
+    let tables = &state.tables;
+    let resources = match tables.get::<Resources>("resource").await {
+        Some(t) => t.search().await?.len(),
+        None => 0,
+    };
+    let folders = match tables.get::<Folders>("folder").await {
+        Some(t) => t.search().await?.len(),
+        None => 0,
+    };
+    let nodes = match tables.get::<Nodes>("node").await {
+        Some(t) => t.search().await?.len(),
+        None => 0,
+    };
+    let iroh = &state.iroh;
+    let connected_peers = iroh
+        .peers
+        .list(iroh.router.endpoint())
+        .await
+        .iter()
+        .filter(|p| p.is_connected())
+        .count();
+    Ok(AdminResponse::Stats(NodeStats {
+        resources,
+        folders,
+        nodes,
+        collections: state.list_collections().await.len(),
+        connected_peers,
+        downloads_in_flight: iroh.scheduler.in_flight(),
+        dropped_events: state.dropped_events.count(),
+    }))
+}
+
+/// Send an admin request to `node` as the author of `secret_key`
+pub async fn send_admin(
+    secret_key: SecretKey,
+    relay: &RelayChoice,
+    node: PublicKey,
+    request: &AdminRequest,
+) -> Result<AdminResponse> {
+    call(secret_key, relay, node, request, None).await
+}
+
+/// Send an [`AdminRequest::AddStream`] to `node`, followed by everything read from `content`
+pub async fn send_admin_stream(
+    secret_key: SecretKey,
+    relay: &RelayChoice,
+    node: PublicKey,
+    request: &AdminRequest,
+    mut content: ImportReader,
+) -> Result<AdminResponse> {
+    call(secret_key, relay, node, request, Some(&mut content)).await
+}
+
+// send the length prefixed `request` and `content` after it, then read the response
+async fn call(
+    secret_key: SecretKey,
+    relay: &RelayChoice,
+    node: PublicKey,
+    request: &AdminRequest,
+    content: Option<&mut ImportReader>,
+) -> Result<AdminResponse> {
+    let endpoint = Endpoint::builder()
+        .secret_key(secret_key)
+        .relay_mode(relay.relay_mode())
+        .bind()
+        .await?;
+    let mut addr = EndpointAddr::new(node);
+    if let Some(url) = relay.relay_url() {
+        addr = addr.with_relay_url(url.clone());
+    }
+    let connection = endpoint.connect(addr, ADMIN_ALPN).await?;
+    let (mut send, mut recv) = connection.open_bi().await?;
+    let request = bincode::serialize(request)?;
+    send.write_all(&(request.len() as u32).to_le_bytes())
+        .await?;
+    send.write_all(&request).await?;
+    if let Some(content) = content {
+        tokio::io::copy(content, &mut send)
+            .await
+            .context("Failed to send content")?;
+    }
+    send.finish()?;
+    let response = recv.read_to_end(MAX_MESSAGE_SIZE).await?;
+    connection.close(0u32.into(), b"done");
+    endpoint.close().await;
+    Ok(bincode::deserialize(&response)?)
+}
@@ -0,0 +1,185 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use futures::TryStreamExt;
+use iroh::PublicKey;
+use iroh_docs::{Entry, api::Doc, store::Query};
+use tokio::{sync::RwLock, task::JoinHandle, time::MissedTickBehavior};
+use tracing::{debug, info, warn};
+
+use crate::{
+    model::resource::Resources,
+    server::IrohNet,
+    supervisor::{PausedTables, previous_peers},
+    sync_filter::SyncFilters,
+    tables::Tables,
+};
+
+// how often every doc is resynced unless `--anti-entropy` says otherwise
+pub const DEFAULT_ANTI_ENTROPY_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Docs kept next to the tables by name, the access list, the manifest and the audit log,
+/// resynced by [`anti_entropy_pass`] like the tables
+pub type SystemDocs = Arc<RwLock<Vec<(&'static str, Doc)>>>;
+
+/// What one [`anti_entropy_pass`] did
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AntiEntropyPass {
+    // docs whose sync was restarted
+    pub docs: usize,
+    // blobs of entries and placement blobs of resources that were missing, queued for download
+    pub requested: usize,
+}
+
+/// Restart sync of every open doc that is not paused with the peers it synced with before,
+/// and queue the missing content of its entries and the placement blobs of its resources
+///
+/// Gossip messages lost while the network flapped leave a doc behind until the next change,
+/// a fresh sync run reconciles it with the peers either way. Tables sharing one doc are
+/// looked at by their key prefix, each with its own sync filter. Resources outside the sync
+/// filter of a table stay without content. The `system` docs are resynced as a whole, they
+/// can't be paused or filtered
+pub async fn anti_entropy_pass(
+    node: &IrohNet,
+    tables: &Tables,
+    paused: &PausedTables,
+    filters: &SyncFilters,
+    system: &SystemDocs,
+) -> AntiEntropyPass {
+    let me = node.router.endpoint().id();
+    let mut pass = AntiEntropyPass::default();
+    // tables sharing one doc are resynced once
+    let mut docs: Vec<(Doc, Vec<String>)> = Vec::new();
+    for name in tables.names().await {
+        let Some(doc) = tables.doc(&name).await else {
+            continue;
+        };
+        match docs.iter_mut().find(|(shared, _)| shared.id() == doc.id()) {
+            Some((_, names)) => names.push(name),
+            None => docs.push((doc, vec![name])),
+        }
+    }
+    let table_docs: Vec<_> = docs.iter().map(|(doc, _)| doc.id()).collect();
+    for (doc, names) in docs {
+        // pausing a table leaves its doc, so one paused table keeps the whole doc left
+        {
+            let paused = paused.read().await;
+            if let Some(name) = names.iter().find(|name| paused.contains(*name)) {
+                debug!(table = %name, "paused, not resyncing its doc");
+                continue;
+            }
+        }
+        let Some(providers) = resync(&doc, me).await else {
+            continue;
+        };
+        pass.docs += 1;
+        for name in names {
+            let prefix = tables.prefix(&name).await.unwrap_or_default();
+            match request_missing(node, &doc, &prefix, providers.clone()).await {
+                Ok(requested) => pass.requested += requested,
+                Err(e) => warn!(table = %name, error = %e, "failed to look for missing content"),
+            }
+            if let Some(resources) = tables.get::<Resources>(&name).await {
+                let filter = filters.read().await.get(&name).cloned();
+                match resources.fetch_missing_content(filter.as_ref()).await {
+                    Ok(requested) => pass.requested += requested,
+                    Err(e) => warn!(table = %name, error = %e, "failed to look for missing blobs"),
+                }
+            }
+        }
+    }
+    let system = system.read().await.clone();
+    for (name, doc) in system {
+        if table_docs.contains(&doc.id()) {
+            continue;
+        }
+        let Some(providers) = resync(&doc, me).await else {
+            continue;
+        };
+        pass.docs += 1;
+        match request_missing(node, &doc, &[], providers).await {
+            Ok(requested) => pass.requested += requested,
+            Err(e) => warn!(doc = name, error = %e, "failed to look for missing content"),
+        }
+    }
+    pass
+}
+
+// restart sync of `doc` with the peers it synced with before, returns them unless there
+// were none or the sync could not be restarted
+async fn resync(doc: &Doc, me: PublicKey) -> Option<Vec<PublicKey>> {
+    let peers = match previous_peers(doc, me).await {
+        Ok(peers) => peers,
+        Err(e) => {
+            warn!(namespace = %doc.id(), error = %e, "failed to read sync peers");
+            return None;
+        }
+    };
+    if peers.is_empty() {
+        return None;
+    }
+    let providers = peers.iter().map(|addr| addr.id).collect();
+    if let Err(e) = doc.start_sync(peers).await {
+        warn!(namespace = %doc.id(), error = %e, "failed to restart sync");
+        return None;
+    }
+    Some(providers)
+}
+
+// queue the content of the latest entries under `prefix` that is not in the blob store
+async fn request_missing(
+    node: &IrohNet,
+    doc: &Doc,
+    prefix: &[u8],
+    providers: Vec<PublicKey>,
+) -> Result<usize> {
+    let blobs = node.blobs_store.blobs();
+    let entries: Vec<Entry> = doc
+        .get_many(Query::single_latest_per_key().key_prefix(prefix))
+        .await?
+        .try_collect()
+        .await?;
+    let mut requested = 0;
+    for entry in entries {
+        if entry.content_len() == 0 {
+            continue;
+        }
+        if blobs.has(entry.content_hash()).await? {
+            continue;
+        }
+        node.scheduler
+            .queue(entry.content_hash(), entry.content_len(), providers.clone());
+        requested += 1;
+    }
+    Ok(requested)
+}
+
+/// Run an [`anti_entropy_pass`] every `interval`, the first one an interval after the start
+pub fn spawn_anti_entropy(
+    node: IrohNet,
+    tables: Tables,
+    paused: PausedTables,
+    filters: SyncFilters,
+    system: SystemDocs,
+    interval: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // the tables were just joined, their first sync is still running
+        ticks.tick().await;
+        info!(
+            interval_secs = interval.as_secs(),
+            "anti-entropy resync enabled"
+        );
+        loop {
+            ticks.tick().await;
+            let pass = anti_entropy_pass(&node, &tables, &paused, &filters, &system).await;
+            debug!(
+                docs = pass.docs,
+                requested = pass.requested,
+                "anti-entropy pass"
+            );
+        }
+    })
+}
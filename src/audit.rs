@@ -0,0 +1,312 @@
+use std::{collections::HashSet, ops::Deref};
+
+use anyhow::Result;
+use futures::{StreamExt, TryStreamExt};
+use iroh_blobs::Hash;
+use iroh_docs::{
+    AuthorId, DocTicket, Entry, NamespaceId,
+    api::{Doc, protocol::ShareMode},
+    store::{Query, SortDirection},
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+use crate::{
+    crypto, iroh_create_author, iroh_create_doc,
+    schema::Migrations,
+    server::IrohNet,
+    store::{GetProperties, IrohCls, IrohProperties, ToBytes},
+    trash,
+    validate::Validators,
+};
+
+// name of the audit doc in the tickets file
+pub const AUDIT_TABLE: &str = "audit";
+
+// records waiting to be written, further ones are dropped while the doc is behind
+const AUDIT_QUEUE: usize = 1024;
+
+/// One write to a table, local or remote, as recorded in the audit doc
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub table: String,
+    pub key: String,
+    pub author: AuthorId,
+    // time of the write in microseconds since the unix epoch
+    pub timestamp: u64,
+    pub hash: Hash,
+    // 0 for empty entries
+    pub size: u64,
+    // whether the key held an entry before
+    pub existed: bool,
+    // written by another node
+    pub remote: bool,
+    // doc of the table, `None` for records written before it was recorded
+    pub namespace: Option<NamespaceId>,
+}
+
+// layout of version 0, before the doc of the table was recorded
+#[derive(Deserialize)]
+struct AuditRecordV0 {
+    table: String,
+    key: String,
+    author: AuthorId,
+    timestamp: u64,
+    hash: Hash,
+    size: u64,
+    existed: bool,
+    remote: bool,
+}
+
+impl ToBytes<AuditRecord> for AuditRecord {
+    const VERSION: u32 = 1;
+
+    fn migrations() -> Migrations<AuditRecord> {
+        Migrations::new().register(0, |old: AuditRecordV0| AuditRecord {
+            table: old.table,
+            key: old.key,
+            author: old.author,
+            timestamp: old.timestamp,
+            hash: old.hash,
+            size: old.size,
+            existed: old.existed,
+            remote: old.remote,
+            namespace: None,
+        })
+    }
+
+    fn missing_file(id: String) -> Self {
+        AuditRecord {
+            table: String::new(),
+            key: id,
+            author: AuthorId::from([0; 32]),
+            timestamp: 0,
+            hash: Hash::EMPTY,
+            size: 0,
+            existed: false,
+            remote: false,
+            namespace: None,
+        }
+    }
+}
+
+/// What a recorded write did to its key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    Insert,
+    Update,
+    // an empty entry or a move to the trash
+    Delete,
+}
+
+impl std::fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            AuditAction::Insert => "insert",
+            AuditAction::Update => "update",
+            AuditAction::Delete => "delete",
+        })
+    }
+}
+
+/// Append-only doc recording every write to the tables of this node
+///
+/// Records are keyed by time, author, table and key, so none is ever overwritten and
+/// they are listed in the order they were written. They are written by a task of their own,
+/// so recording never holds up the subscription of a table
+#[derive(Clone)]
+pub struct AuditLog {
+    table: IrohCls<AuditRecord>,
+    queue: mpsc::Sender<AuditRecord>,
+}
+
+impl Deref for AuditLog {
+    type Target = IrohCls<AuditRecord>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.table
+    }
+}
+
+impl GetProperties for AuditLog {
+    fn get_doc(&self) -> &Doc {
+        &self.table.doc
+    }
+}
+
+impl AuditLog {
+    pub async fn new(ticket: &Option<DocTicket>, node: IrohNet) -> Result<Self> {
+        let doc = iroh_create_doc(&node, ticket).await?;
+        let author = iroh_create_author(&node).await?;
+        let ticket = match ticket {
+            Some(_) => None,
+            None => Some(doc.share(ShareMode::Write, node.addr_info()).await?),
+        };
+        let table = IrohCls::<AuditRecord> {
+            node,
+            doc,
+            ticket,
+            author,
+            entity: None,
+            validators: Validators::default(),
+            prefix: Vec::new(),
+        };
+        let (queue, records) = mpsc::channel(AUDIT_QUEUE);
+        tokio::spawn(write_records(table.clone(), records));
+        Ok(AuditLog { table, queue })
+    }
+
+    /// Record a write to `table`, `existed` tells whether the key was set before
+    ///
+    /// The record is queued and written in the background
+    pub fn record(&self, table: &str, entry: &Entry, existed: bool, remote: bool) {
+        let record = AuditRecord {
+            table: table.to_string(),
+            key: String::from_utf8_lossy(entry.key()).to_string(),
+            author: entry.author(),
+            timestamp: entry.timestamp(),
+            hash: entry.content_hash(),
+            size: entry.content_len(),
+            existed,
+            remote,
+            namespace: Some(entry.id().namespace()),
+        };
+        if let Err(e) = self.queue.try_send(record) {
+            warn!(table, error = %e, "dropping audit record");
+        }
+    }
+
+    /// The latest `limit` records, newest first, of `table` or of all tables
+    pub async fn recent(
+        &self,
+        limit: usize,
+        table: Option<&str>,
+    ) -> Result<Vec<(AuditRecord, AuditAction)>> {
+        // keys start with the time, so the newest come first and reading stops at `limit`
+        let query = Query::single_latest_per_key().sort_direction(SortDirection::Desc);
+        let entries = self.doc.get_many(query).await?;
+        let mut entries = std::pin::pin!(entries);
+        let mut records = vec![];
+        while records.len() < limit
+            && let Some(entry) = entries.next().await
+        {
+            let entry = entry?;
+            // read directly, the access list only applies to the shared tables
+            let Ok(bytes) = self
+                .node
+                .blobs_store
+                .blobs()
+                .get_bytes(entry.content_hash())
+                .await
+            else {
+                continue;
+            };
+            let bytes = crypto::open(
+                self.node.cipher.as_ref(),
+                self.doc.id(),
+                entry.key(),
+                bytes.to_vec(),
+            )?;
+            let record = AuditRecord::from_bytes(bytes.into())?;
+            if table.is_some_and(|table| table != record.table) {
+                continue;
+            }
+            let action = self.action(&record).await;
+            records.push((record, action));
+        }
+        Ok(records)
+    }
+
+    // deletions are only told apart once the content of the write is local, and only for
+    // records that name the doc of the table, its values may be bound to it
+    async fn action(&self, record: &AuditRecord) -> AuditAction {
+        if record.size == 0 {
+            return AuditAction::Delete;
+        }
+        if let Some(namespace) = record.namespace
+            && let Ok(bytes) = self.node.blobs_store.blobs().get_bytes(record.hash).await
+            && let Ok(bytes) = crypto::open(
+                self.node.cipher.as_ref(),
+                namespace,
+                record.key.as_bytes(),
+                bytes.to_vec(),
+            )
+            && trash::is_tombstone(&bytes)
+        {
+            return AuditAction::Delete;
+        }
+        if record.existed {
+            AuditAction::Update
+        } else {
+            AuditAction::Insert
+        }
+    }
+}
+
+// write queued records until every `AuditLog` is dropped
+async fn write_records(table: IrohCls<AuditRecord>, mut records: mpsc::Receiver<AuditRecord>) {
+    while let Some(record) = records.recv().await {
+        let doc_key = format!(
+            "{:020}/{}/{}/{}",
+            record.timestamp, record.author, record.table, record.key
+        );
+        let result = match record.as_bytes() {
+            Ok(bytes) => table.insert_bytes(doc_key, bytes).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            warn!(table = %record.table, key = %record.key, error = %e, "failed to write audit record");
+        }
+    }
+    debug!("audit log closed");
+}
+
+/// Keys of `doc` that currently hold an entry, to tell inserts from updates
+pub async fn existing_keys(doc: &Doc) -> Result<HashSet<Vec<u8>>> {
+    let entries = doc.get_many(Query::single_latest_per_key()).await?;
+    Ok(entries
+        .map_ok(|entry| entry.key().to_vec())
+        .try_collect()
+        .await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a record as version 0 encoded it
+    #[derive(Serialize)]
+    struct UnplacedRecord {
+        table: String,
+        key: String,
+        author: AuthorId,
+        timestamp: u64,
+        hash: Hash,
+        size: u64,
+        existed: bool,
+        remote: bool,
+    }
+
+    #[test]
+    fn decodes_records_of_version_0() {
+        let bytes = bincode::serialize(&UnplacedRecord {
+            table: "files".to_string(),
+            key: "1".to_string(),
+            author: AuthorId::from([1; 32]),
+            timestamp: 42,
+            hash: Hash::new(b"entry"),
+            size: 5,
+            existed: true,
+            remote: false,
+        })
+        .unwrap();
+        let (record, version) = AuditRecord::from_bytes_versioned(bytes.into()).unwrap();
+        assert_eq!(version, 0);
+        assert_eq!(record.table, "files");
+        assert_eq!(record.author, AuthorId::from([1; 32]));
+        assert_eq!(record.size, 5);
+        assert!(record.existed);
+        assert_eq!(record.namespace, None);
+    }
+}
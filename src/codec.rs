@@ -0,0 +1,81 @@
+use anyhow::{Result, ensure};
+use serde::{Deserialize, Serialize};
+
+// marks a payload tagged with its codec, followed by the codec id
+//
+// untagged payloads were written with bincode before codecs were selectable
+const CODEC_MAGIC: [u8; 3] = [0xfd, b'c', b'd'];
+
+/// Serialization format of an entity payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Bincode,
+    Postcard,
+    Json,
+    Cbor,
+}
+
+impl Codec {
+    fn id(self) -> u8 {
+        match self {
+            Codec::Bincode => 0,
+            Codec::Postcard => 1,
+            Codec::Json => 2,
+            Codec::Cbor => 3,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Self> {
+        match id {
+            0 => Ok(Codec::Bincode),
+            1 => Ok(Codec::Postcard),
+            2 => Ok(Codec::Json),
+            3 => Ok(Codec::Cbor),
+            _ => Err(anyhow::anyhow!("Unknown codec id {}", id)),
+        }
+    }
+
+    /// Serialize `value` and tag it with this codec
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>> {
+        let mut buf = CODEC_MAGIC.to_vec();
+        buf.push(self.id());
+        match self {
+            Codec::Bincode => bincode::serialize_into(&mut buf, value)?,
+            Codec::Postcard => buf = postcard::to_extend(value, buf)?,
+            Codec::Json => serde_json::to_writer(&mut buf, value)?,
+            Codec::Cbor => ciborium::into_writer(value, &mut buf)?,
+        }
+        Ok(buf)
+    }
+
+    fn decode_with<T: for<'a> Deserialize<'a>>(self, bytes: &[u8]) -> Result<T> {
+        Ok(match self {
+            Codec::Bincode => bincode::deserialize(bytes)?,
+            Codec::Postcard => postcard::from_bytes(bytes)?,
+            Codec::Json => serde_json::from_slice(bytes)?,
+            Codec::Cbor => ciborium::from_reader(bytes)?,
+        })
+    }
+}
+
+/// Deserialize a payload written by [`Codec::encode`], whatever codec it was written with
+///
+/// Untagged payloads are decoded as bincode
+pub fn decode<T: for<'a> Deserialize<'a>>(bytes: &[u8]) -> Result<T> {
+    let (codec, payload) = split(bytes)?;
+    codec.decode_with(payload)
+}
+
+/// Codec a payload written by [`Codec::encode`] was written with
+pub fn codec_of(bytes: &[u8]) -> Result<Codec> {
+    Ok(split(bytes)?.0)
+}
+
+// codec of a payload and the encoded value after its tag
+fn split(bytes: &[u8]) -> Result<(Codec, &[u8])> {
+    let Some(rest) = bytes.strip_prefix(&CODEC_MAGIC) else {
+        return Ok((Codec::Bincode, bytes));
+    };
+    ensure!(!rest.is_empty(), "Payload is missing its codec id");
+    Ok((Codec::from_id(rest[0])?, &rest[1..]))
+}
@@ -0,0 +1,55 @@
+use anyhow::Result;
+use bytes::Bytes;
+
+// marks a compressed payload, followed by a zstd frame
+//
+// plain bincode payloads never start with this byte followed by the zstd magic,
+// so entries written before compression existed still decode
+const COMPRESSED_HEADER: u8 = 0xff;
+
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+// payloads smaller than this are stored as is
+#[cfg(feature = "compression")]
+const COMPRESSION_THRESHOLD: usize = 4 * 1024;
+
+#[cfg(feature = "compression")]
+const COMPRESSION_LEVEL: i32 = 3;
+
+fn is_compressed(bytes: &[u8]) -> bool {
+    bytes.first() == Some(&COMPRESSED_HEADER) && bytes.get(1..5) == Some(&ZSTD_MAGIC[..])
+}
+
+/// Compress a serialized entity when it is large enough to be worth it
+///
+/// Without the `compression` feature the payload is returned unchanged
+pub fn compress(buf: Vec<u8>) -> Result<Vec<u8>> {
+    #[cfg(feature = "compression")]
+    if buf.len() >= COMPRESSION_THRESHOLD {
+        let compressed = zstd::encode_all(buf.as_slice(), COMPRESSION_LEVEL)?;
+        if compressed.len() + 1 < buf.len() {
+            let mut out = Vec::with_capacity(compressed.len() + 1);
+            out.push(COMPRESSED_HEADER);
+            out.extend_from_slice(&compressed);
+            return Ok(out);
+        }
+    }
+    Ok(buf)
+}
+
+/// Undo [`compress`], uncompressed payloads are passed through
+pub fn decompress(bytes: Bytes) -> Result<Bytes> {
+    if !is_compressed(&bytes) {
+        return Ok(bytes);
+    }
+    #[cfg(feature = "compression")]
+    {
+        Ok(zstd::decode_all(&bytes[1..])?.into())
+    }
+    #[cfg(not(feature = "compression"))]
+    {
+        Err(anyhow::anyhow!(
+            "Entry is compressed but the `compression` feature is disabled"
+        ))
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use iroh::{
+    Endpoint, PublicKey,
+    endpoint::Connection,
+    protocol::{AcceptError, ProtocolHandler},
+};
+use iroh_docs::{
+    NamespaceId,
+    api::{Doc, protocol::ShareMode},
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use tracing::{debug, warn};
+
+use crate::{acl::Acl, admin::AdminProtocol, server::addr_info};
+
+pub const CONTROL_ALPN: &[u8] = b"iroh-test/control/0";
+
+// upper bound for a single request or response
+const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+/// Command sent to another peer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ControlRequest {
+    // check that the peer is reachable
+    Ping,
+    // ask the peer for a fresh read-only ticket of one of its tables
+    RequestTicket { table: String },
+    // ask the peer which tables it serves, answered like `RequestTicket`
+    Status,
+}
+
+/// Answer to a [`ControlRequest`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ControlResponse {
+    // peer clock in milliseconds since the unix epoch
+    Pong { time: u64 },
+    Ticket(String),
+    Status(NodeStatus),
+    Error(String),
+}
+
+/// What a peer reports about itself
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeStatus {
+    pub version: String,
+    // tables served by the peer and their namespaces
+    pub tables: Vec<(String, NamespaceId)>,
+}
+
+/// Answers control commands from other peers
+///
+/// Tables become visible once they are registered with [`ControlProtocol::register_table`]
+#[derive(Debug, Clone)]
+pub struct ControlProtocol {
+    // doc of every table and the prefix of its keys in it
+    tables: Arc<RwLock<HashMap<String, (Doc, Vec<u8>)>>>,
+    // tickets handed out carry direct addresses only
+    lan_only: bool,
+    // tickets are only handed out to listed or admin peers
+    acl: Acl,
+    admin: AdminProtocol,
+}
+
+impl ControlProtocol {
+    pub fn new(lan_only: bool, acl: Acl, admin: AdminProtocol) -> Self {
+        ControlProtocol {
+            tables: Arc::new(RwLock::new(HashMap::new())),
+            lan_only,
+            acl,
+            admin,
+        }
+    }
+
+    /// Make a table available to control requests
+    ///
+    /// `prefix` is the prefix of the table's keys, empty unless the doc is shared
+    pub async fn register_table(&self, name: &str, doc: Doc, prefix: Vec<u8>) {
+        self.tables
+            .write()
+            .await
+            .insert(name.to_string(), (doc, prefix));
+    }
+
+    /// Hide a table from control requests again
+    pub async fn unregister_table(&self, name: &str) {
+        self.tables.write().await.remove(name);
+    }
+
+    /// Registered tables and their docs, sorted by name
+    pub async fn tables(&self) -> Vec<(String, Doc)> {
+        self.prefixed_tables()
+            .await
+            .into_iter()
+            .map(|(name, doc, _)| (name, doc))
+            .collect()
+    }
+
+    /// Like [`ControlProtocol::tables`], with the prefix of each table's keys
+    pub async fn prefixed_tables(&self) -> Vec<(String, Doc, Vec<u8>)> {
+        let mut tables: Vec<(String, Doc, Vec<u8>)> = self
+            .tables
+            .read()
+            .await
+            .iter()
+            .map(|(name, (doc, prefix))| (name.clone(), doc.clone(), prefix.clone()))
+            .collect();
+        tables.sort_by(|a, b| a.0.cmp(&b.0));
+        tables
+    }
+
+    // peers with a role, admin peers and peers already syncing one of the registered tables,
+    // the only ones told about the tables
+    async fn may_request_ticket(&self, remote: &PublicKey) -> bool {
+        if self.acl.role(remote.as_bytes()).is_some() || self.admin.is_allowed(remote).await {
+            return true;
+        }
+        let docs: Vec<Doc> = self
+            .tables
+            .read()
+            .await
+            .values()
+            .map(|(doc, _)| doc.clone())
+            .collect();
+        for doc in docs {
+            if let Ok(Some(peers)) = doc.get_sync_peers().await
+                && peers.iter().any(|peer| peer == remote.as_bytes())
+            {
+                return true;
+            }
+        }
+        false
+    }
+
+    async fn respond(&self, remote: PublicKey, request: ControlRequest) -> ControlResponse {
+        match request {
+            ControlRequest::Ping => ControlResponse::Pong {
+                time: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_millis() as u64)
+                    .unwrap_or_default(),
+            },
+            ControlRequest::RequestTicket { table } => {
+                if !self.may_request_ticket(&remote).await {
+                    warn!(from = %remote.fmt_short(), %table, "refused ticket request");
+                    return ControlResponse::Error("Not allowed to request tickets".to_string());
+                }
+                let doc = self
+                    .tables
+                    .read()
+                    .await
+                    .get(&table)
+                    .map(|(doc, _)| doc.clone());
+                let Some(doc) = doc else {
+                    return ControlResponse::Error(format!("Unknown table '{}'", table));
+                };
+                // even known peers only get read access this way
+                match doc.share(ShareMode::Read, addr_info(self.lan_only)).await {
+                    Ok(ticket) => ControlResponse::Ticket(ticket.to_string()),
+                    Err(e) => ControlResponse::Error(e.to_string()),
+                }
+            }
+            ControlRequest::Status => {
+                // a namespace id alone grants read access, so it is only told to the same peers
+                if !self.may_request_ticket(&remote).await {
+                    warn!(from = %remote.fmt_short(), "refused status request");
+                    return ControlResponse::Error("Not allowed to list tables".to_string());
+                }
+                let mut tables: Vec<(String, NamespaceId)> = self
+                    .tables
+                    .read()
+                    .await
+                    .iter()
+                    .map(|(name, (doc, _))| (name.clone(), doc.id()))
+                    .collect();
+                tables.sort();
+                ControlResponse::Status(NodeStatus {
+                    version: env!("CARGO_PKG_VERSION").to_string(),
+                    tables,
+                })
+            }
+        }
+    }
+
+    async fn handle(&self, connection: Connection) -> Result<()> {
+        let remote = connection.remote_id()?;
+        let (mut send, mut recv) = connection.accept_bi().await?;
+        let request: ControlRequest =
+            bincode::deserialize(&recv.read_to_end(MAX_MESSAGE_SIZE).await?)?;
+        debug!(?request, from = %remote.fmt_short(), "control request");
+        let response = self.respond(remote, request).await;
+        send.write_all(&bincode::serialize(&response)?).await?;
+        send.finish()?;
+        connection.closed().await;
+        Ok(())
+    }
+}
+
+impl ProtocolHandler for ControlProtocol {
+    async fn accept(&self, connection: Connection) -> Result<(), AcceptError> {
+        self.handle(connection)
+            .await
+            .map_err(|e| AcceptError::from_err(std::io::Error::other(e)))
+    }
+}
+
+/// Send a control command to `peer` and wait for its answer
+pub async fn send_control(
+    endpoint: &Endpoint,
+    peer: PublicKey,
+    request: &ControlRequest,
+) -> Result<ControlResponse> {
+    let connection = endpoint.connect(peer, CONTROL_ALPN).await?;
+    let (mut send, mut recv) = connection.open_bi().await?;
+    send.write_all(&bincode::serialize(request)?).await?;
+    send.finish()?;
+    let response = recv.read_to_end(MAX_MESSAGE_SIZE).await?;
+    connection.close(0u32.into(), b"done");
+    Ok(bincode::deserialize(&response)?)
+}
@@ -0,0 +1,240 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use tokio::sync::{broadcast, watch};
+
+use crate::doc_subcribe::{ProgressType, SyncProgress};
+
+/// Initial sync progress summed over all tables of a [`SyncCoordinator`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OverallProgress {
+    pub tables: usize,
+    // tables whose entries and content have all been downloaded
+    pub synced_tables: usize,
+    pub total_files: u64,
+    pub remaining_files: u64,
+    pub total_bytes: u64,
+    pub downloaded_bytes: u64,
+}
+
+impl OverallProgress {
+    /// Share of the initial sync that is done, from 0 to 100
+    ///
+    /// Based on bytes once remotes announced any content, on finished tables before that
+    pub fn percent(&self) -> u8 {
+        if self.tables == 0 || self.synced_tables == self.tables {
+            return 100;
+        }
+        let percent = if self.total_bytes > 0 {
+            self.downloaded_bytes * 100 / self.total_bytes
+        } else {
+            (self.synced_tables * 100 / self.tables) as u64
+        };
+        // the last percent is only reached once every table reports completion
+        percent.min(99) as u8
+    }
+
+    /// Every table finished its initial sync
+    pub fn is_complete(&self) -> bool {
+        self.synced_tables == self.tables
+    }
+}
+
+/// Which initial sync events a table has seen, see [`SyncCoordinator::readiness`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TableReadiness {
+    pub table: String,
+    // a sync run with a peer finished, all remote entries are known
+    pub sync_finished: bool,
+    // the content announced by the first sync runs has been downloaded
+    pub content_ready: bool,
+    // placement blobs of the resources that are not here yet, `None` until they were checked
+    pub missing_blobs: Option<usize>,
+}
+
+impl TableReadiness {
+    /// Entries and their content are synced, the blobs placing resources are not checked
+    pub fn is_synced(&self) -> bool {
+        self.sync_finished && self.content_ready
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.is_synced() && self.missing_blobs == Some(0)
+    }
+}
+
+impl std::fmt::Display for TableReadiness {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = match (self.sync_finished, self.content_ready, self.missing_blobs) {
+            (true, true, Some(0)) => "ready".to_string(),
+            (true, true, Some(missing)) => format!("synced, {} placement blobs pending", missing),
+            (true, true, None) => "synced, placement blobs not checked".to_string(),
+            (true, false, _) => "synced, content pending".to_string(),
+            (false, true, _) => "content ready, not synced".to_string(),
+            (false, false, _) => "not synced".to_string(),
+        };
+        write!(f, "{} ({})", self.table, state)
+    }
+}
+
+#[derive(Debug, Default)]
+struct CoordinatorState {
+    // tables that take part in the initial sync
+    expected: BTreeSet<String>,
+    // latest progress of every table
+    tables: HashMap<String, SyncProgress>,
+    // tables whose subscription saw `SyncFinished`
+    sync_finished: HashSet<String>,
+    // tables whose subscription saw `PendingContentReady`
+    content_ready: HashSet<String>,
+    // placement blobs missing per table, see `SyncCoordinator::set_missing_blobs`
+    missing_blobs: HashMap<String, usize>,
+}
+
+impl CoordinatorState {
+    fn overall(&self) -> OverallProgress {
+        let mut overall = OverallProgress {
+            tables: self.expected.len(),
+            ..Default::default()
+        };
+        for table in &self.expected {
+            let Some(progress) = self.tables.get(table) else {
+                continue;
+            };
+            if progress.phase == ProgressType::OnLoadedBlob {
+                overall.synced_tables += 1;
+            }
+            overall.total_files += progress.total_files;
+            overall.remaining_files += progress.remaining_files;
+            overall.total_bytes += progress.total_bytes;
+            overall.downloaded_bytes += progress.downloaded_bytes;
+        }
+        overall
+    }
+
+    fn readiness(&self) -> Vec<TableReadiness> {
+        self.expected
+            .iter()
+            .map(|table| TableReadiness {
+                table: table.clone(),
+                sync_finished: self.sync_finished.contains(table),
+                content_ready: self.content_ready.contains(table),
+                missing_blobs: self.missing_blobs.get(table).copied(),
+            })
+            .collect()
+    }
+}
+
+/// Combines the [`SyncProgress`] of several tables into one initial sync state
+///
+/// Tables join with [`SyncCoordinator::expect`], progress is read from the broadcast
+/// every [`EventRemoteSync`](crate::doc_subcribe::EventRemoteSync) publishes to
+#[derive(Debug, Clone)]
+pub struct SyncCoordinator {
+    state: watch::Sender<CoordinatorState>,
+}
+
+impl SyncCoordinator {
+    /// Follow the progress published on `progress`
+    pub fn new(progress: &broadcast::Sender<SyncProgress>) -> Self {
+        let (state, _) = watch::channel(CoordinatorState::default());
+        let coordinator = SyncCoordinator { state };
+        let updates = coordinator.state.clone();
+        let mut progress = progress.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match progress.recv().await {
+                    Ok(progress) => updates.send_modify(|state| {
+                        let table = progress.table_name.clone();
+                        // the latest phase is overwritten by later runs, what was seen is kept
+                        match progress.phase {
+                            ProgressType::OnLoadedTable => {
+                                state.sync_finished.insert(table.clone());
+                            }
+                            ProgressType::OnLoadedBlob => {
+                                state.content_ready.insert(table.clone());
+                            }
+                            ProgressType::OnInit => {}
+                        }
+                        state.tables.insert(table, progress);
+                    }),
+                    // later updates carry the full counters, nothing is lost by skipping
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+        coordinator
+    }
+
+    /// Count `table` towards the initial sync, under the name its progress is published with
+    pub fn expect(&self, table: &str) {
+        self.state.send_modify(|state| {
+            state.expected.insert(table.to_string());
+        });
+    }
+
+    /// Stop counting `table` towards the initial sync, e.g. once it was left
+    pub fn forget(&self, table: &str) {
+        self.state.send_modify(|state| {
+            state.expected.remove(table);
+            state.tables.remove(table);
+            state.sync_finished.remove(table);
+            state.content_ready.remove(table);
+            state.missing_blobs.remove(table);
+        });
+    }
+
+    /// Record how many placement blobs of `table` are still missing, entry content alone
+    /// does not hold the files of resources
+    pub fn set_missing_blobs(&self, table: &str, missing: usize) {
+        self.state.send_if_modified(|state| {
+            state.missing_blobs.insert(table.to_string(), missing) != Some(missing)
+        });
+    }
+
+    /// Current progress over all expected tables
+    pub fn overall(&self) -> OverallProgress {
+        self.state.borrow().overall()
+    }
+
+    /// Initial sync state of every expected table, sorted by name
+    pub fn readiness(&self) -> Vec<TableReadiness> {
+        self.state.borrow().readiness()
+    }
+
+    /// Wait until every expected table has seen both `SyncFinished` and `PendingContentReady`
+    pub async fn wait_synced(&self) -> Vec<TableReadiness> {
+        self.wait_until(TableReadiness::is_synced).await
+    }
+
+    /// Wait until every expected table is synced and none misses placement blobs
+    ///
+    /// Returns right away when no table is expected
+    pub async fn wait_ready(&self) -> Vec<TableReadiness> {
+        self.wait_until(TableReadiness::is_ready).await
+    }
+
+    async fn wait_until(&self, ready: fn(&TableReadiness) -> bool) -> Vec<TableReadiness> {
+        let mut updates = self.state.subscribe();
+        if let Ok(state) = updates
+            .wait_for(|state| state.readiness().iter().all(ready))
+            .await
+        {
+            return state.readiness();
+        }
+        self.readiness()
+    }
+
+    /// Wait until every expected table has downloaded all entries and content
+    pub async fn wait_for_initial_sync(&self) -> OverallProgress {
+        let mut updates = self.state.subscribe();
+        // the sender lives in self, so waiting only ends once the sync is complete
+        if let Ok(state) = updates
+            .wait_for(|state| state.overall().is_complete())
+            .await
+        {
+            return state.overall();
+        }
+        self.overall()
+    }
+}
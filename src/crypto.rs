@@ -0,0 +1,431 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result, ensure};
+use argon2::Argon2;
+use chacha20poly1305::{
+    Key, KeyInit, XChaCha20Poly1305, XNonce,
+    aead::{Aead, Payload},
+};
+use iroh_blobs::Hash;
+use iroh_docs::NamespaceId;
+
+// marks an encrypted doc value, followed by the nonce and the ciphertext
+//
+// the namespace and the key of the entry are authenticated with it, so the value only opens
+// where it was written. Plain and compressed payloads never start with these bytes
+const ENTRY_MAGIC: [u8; 4] = [0xfe, b'e', b'n', b'3'];
+
+// marks encrypted content stored by hash, followed by the namespace, the nonce and the
+// ciphertext. Doc values were written like this before they were bound to their entry
+const CONTENT_MAGIC: [u8; 4] = [0xfe, b'e', b'n', b'2'];
+
+// values encrypted before keys were derived per namespace, they are only read
+const LEGACY_MAGIC: [u8; 4] = [0xfe, b'e', b'n', b'c'];
+
+// marks a file encrypted with a passphrase, followed by the salt, the nonce and the ciphertext
+const PASSPHRASE_MAGIC: [u8; 4] = [0xfe, b'p', b'w', b'd'];
+
+const NONCE_LEN: usize = 24;
+
+const SALT_LEN: usize = 16;
+
+// salt every peer used before keys were derived per namespace, only read
+const LEGACY_SALT: &[u8] = b"iroh-test/doc-encryption/v1";
+
+const KEY_CONTEXT: &str = "iroh-test 2025 doc encryption key";
+
+const DIGEST_CONTEXT: &str = "iroh-test 2025 doc content digest";
+
+#[derive(Clone)]
+enum Secret {
+    Passphrase(String),
+    Key([u8; 32]),
+}
+
+/// Symmetric key encrypting doc values end to end
+///
+/// Every namespace gets its own key, salted with the namespace id, and every value a random
+/// nonce, so equal values encrypt to different bytes. Doc values are bound to the namespace
+/// and key of their entry, a value copied to another entry or doc does not decrypt and has
+/// to be sealed again. Content stored by hash carries its namespace instead, it is
+/// authenticated by the hash in the sealed record referring to it
+///
+/// Plain values, and values sealed before they were bound to their entry, are rejected
+/// unless [`DocCipher::accept_legacy`] is set while old data is migrated
+#[derive(Clone)]
+pub struct DocCipher {
+    secret: Secret,
+    // derived keys by namespace, `None` holds the legacy key, Argon2 is too slow to rerun
+    keys: Arc<Mutex<HashMap<Option<NamespaceId>, [u8; 32]>>>,
+    legacy: bool,
+}
+
+impl fmt::Debug for DocCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("DocCipher(..)")
+    }
+}
+
+impl DocCipher {
+    /// Derive the keys from a passphrase shared by all peers
+    pub fn from_passphrase(passphrase: &str) -> Result<Self> {
+        ensure!(!passphrase.is_empty(), "Passphrase is empty");
+        Ok(Self::new(Secret::Passphrase(passphrase.to_string())))
+    }
+
+    /// Derive the keys from a 32 byte key exchanged out of band, encoded as hex
+    pub fn from_hex(key: &str) -> Result<Self> {
+        let bytes = hex::decode(key.trim()).context("Encryption key is not valid hex")?;
+        let key = bytes
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Encryption key must be 32 bytes"))?;
+        Ok(Self::new(Secret::Key(key)))
+    }
+
+    fn new(secret: Secret) -> Self {
+        DocCipher {
+            secret,
+            keys: Arc::new(Mutex::new(HashMap::new())),
+            legacy: false,
+        }
+    }
+
+    /// Also read plain values and values sealed before they were bound to their entry
+    ///
+    /// Only meant for migrating old data, anyone able to write an entry can then slip in
+    /// plain or replayed values
+    pub fn accept_legacy(mut self, accept: bool) -> Self {
+        self.legacy = accept;
+        self
+    }
+
+    // key of `namespace`, or the legacy key shared by all namespaces
+    fn key(&self, namespace: Option<NamespaceId>) -> Result<[u8; 32]> {
+        if let Some(key) = self.keys.lock().unwrap().get(&namespace) {
+            return Ok(*key);
+        }
+        let key = match (&self.secret, namespace) {
+            (Secret::Passphrase(passphrase), Some(namespace)) => {
+                stretch(passphrase, namespace.as_bytes())?
+            }
+            (Secret::Passphrase(passphrase), None) => stretch(passphrase, LEGACY_SALT)?,
+            (Secret::Key(key), Some(namespace)) => blake3::derive_key(
+                KEY_CONTEXT,
+                &[key.as_slice(), namespace.as_bytes()].concat(),
+            ),
+            (Secret::Key(key), None) => *key,
+        };
+        self.keys.lock().unwrap().insert(namespace, key);
+        Ok(key)
+    }
+
+    /// Encrypt the value of the entry `key` of `namespace` before it is written to the doc
+    pub fn encrypt(&self, namespace: NamespaceId, key: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        let aad = entry_aad(namespace, key);
+        seal_with(
+            &self.key(Some(namespace))?,
+            &ENTRY_MAGIC,
+            &aad,
+            &[],
+            plaintext,
+        )
+    }
+
+    /// Decrypt a value written by [`DocCipher::encrypt`] for the entry `key` of `namespace`
+    pub fn decrypt(&self, namespace: NamespaceId, key: &[u8], bytes: &[u8]) -> Result<Vec<u8>> {
+        if let Some(rest) = bytes.strip_prefix(&ENTRY_MAGIC) {
+            let aad = entry_aad(namespace, key);
+            return open_with(&self.key(Some(namespace))?, &aad, rest)
+                .context("Value was not encrypted for this entry, or with another key");
+        }
+        ensure!(
+            self.legacy,
+            "Value of '{}' is not encrypted for its entry, pass --accept-legacy to read it while migrating",
+            String::from_utf8_lossy(key)
+        );
+        if is_encrypted(bytes) {
+            self.decrypt_content(bytes)
+        } else {
+            Ok(bytes.to_vec())
+        }
+    }
+
+    /// Encrypt content of `namespace` stored by hash, e.g. a placed file
+    pub fn encrypt_content(&self, namespace: NamespaceId, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let key = self.key(Some(namespace))?;
+        seal_with(
+            &key,
+            &CONTENT_MAGIC,
+            &CONTENT_MAGIC,
+            namespace.as_bytes(),
+            plaintext,
+        )
+    }
+
+    /// Decrypt content written by [`DocCipher::encrypt_content`]
+    pub fn decrypt_content(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        if let Some(rest) = bytes.strip_prefix(&LEGACY_MAGIC) {
+            ensure!(
+                self.legacy,
+                "Content was encrypted with the legacy key, pass --accept-legacy to read it while migrating"
+            );
+            return open_with(&self.key(None)?, &LEGACY_MAGIC, rest);
+        }
+        let rest = bytes
+            .strip_prefix(&CONTENT_MAGIC)
+            .ok_or_else(|| anyhow::anyhow!("Content is not encrypted"))?;
+        ensure!(rest.len() >= 32, "Encrypted content is truncated");
+        let (namespace, rest) = rest.split_at(32);
+        let namespace = NamespaceId::from(<[u8; 32]>::try_from(namespace)?);
+        open_with(&self.key(Some(namespace))?, &CONTENT_MAGIC, rest)
+    }
+
+    /// Keyed hash of plain content of `namespace`, tells equal content apart without
+    /// revealing it
+    pub fn digest(&self, namespace: NamespaceId, plaintext: &[u8]) -> Result<Hash> {
+        Ok(self
+            .digester(namespace)?
+            .update(plaintext)
+            .finalize()
+            .into())
+    }
+
+    /// Hasher computing [`DocCipher::digest`] incrementally, for content streamed from disk
+    pub fn digester(&self, namespace: NamespaceId) -> Result<blake3::Hasher> {
+        let key = blake3::derive_key(DIGEST_CONTEXT, &self.key(Some(namespace))?);
+        Ok(blake3::Hasher::new_keyed(&key))
+    }
+}
+
+// Argon2 key of `passphrase`
+fn stretch(passphrase: &str, salt: &[u8]) -> Result<[u8; 32]> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow::anyhow!("Failed to derive key: {}", e))?;
+    Ok(key)
+}
+
+// additional data of a doc value, its magic and the entry it is stored in
+fn entry_aad(namespace: NamespaceId, key: &[u8]) -> Vec<u8> {
+    [ENTRY_MAGIC.as_slice(), namespace.as_bytes(), key].concat()
+}
+
+// `magic`, `header`, a random nonce and the ciphertext of `plaintext` authenticated with `aad`
+fn seal_with(
+    key: &[u8; 32],
+    magic: &[u8],
+    aad: &[u8],
+    header: &[u8],
+    plaintext: &[u8],
+) -> Result<Vec<u8>> {
+    let nonce: [u8; NONCE_LEN] = rand::random();
+    let ciphertext = XChaCha20Poly1305::new(Key::from_slice(key))
+        .encrypt(
+            XNonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| anyhow::anyhow!("Failed to encrypt value"))?;
+
+    let mut out = Vec::with_capacity(magic.len() + header.len() + NONCE_LEN + ciphertext.len());
+    out.extend_from_slice(magic);
+    out.extend_from_slice(header);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+// decrypt the nonce and ciphertext following the magic and header of a sealed value
+fn open_with(key: &[u8; 32], aad: &[u8], bytes: &[u8]) -> Result<Vec<u8>> {
+    ensure!(bytes.len() >= NONCE_LEN, "Encrypted value is truncated");
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+    XChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| anyhow::anyhow!("Failed to decrypt value, wrong key?"))
+}
+
+/// Encrypt a file with a key stretched from `passphrase` and a random salt stored with it
+pub fn encrypt_with_passphrase(passphrase: &str, plaintext: &[u8]) -> Result<Vec<u8>> {
+    let salt: [u8; SALT_LEN] = rand::random();
+    let key = stretch(passphrase, &salt)?;
+    seal_with(&key, &PASSPHRASE_MAGIC, &PASSPHRASE_MAGIC, &salt, plaintext)
+}
+
+/// Decrypt a file written by [`encrypt_with_passphrase`]
+///
+/// Files encrypted before they were salted individually are read as well
+pub fn decrypt_with_passphrase(passphrase: &str, bytes: &[u8]) -> Result<Vec<u8>> {
+    if let Some(rest) = bytes.strip_prefix(&LEGACY_MAGIC) {
+        return open_with(&stretch(passphrase, LEGACY_SALT)?, &LEGACY_MAGIC, rest);
+    }
+    let rest = bytes
+        .strip_prefix(&PASSPHRASE_MAGIC)
+        .ok_or_else(|| anyhow::anyhow!("File is not encrypted"))?;
+    ensure!(rest.len() >= SALT_LEN, "Encrypted file is truncated");
+    let (salt, rest) = rest.split_at(SALT_LEN);
+    open_with(&stretch(passphrase, salt)?, &PASSPHRASE_MAGIC, rest)
+}
+
+/// Whether `bytes` were produced by [`DocCipher`] or [`encrypt_with_passphrase`]
+pub fn is_encrypted(bytes: &[u8]) -> bool {
+    [ENTRY_MAGIC, CONTENT_MAGIC, LEGACY_MAGIC, PASSPHRASE_MAGIC]
+        .iter()
+        .any(|magic| bytes.starts_with(magic))
+}
+
+/// Whether a doc value is sealed the way [`DocCipher::encrypt`] writes it now
+pub fn is_bound(bytes: &[u8]) -> bool {
+    bytes.starts_with(&ENTRY_MAGIC)
+}
+
+/// Encrypt the value of the entry `key` of `namespace` when a cipher is configured
+pub fn seal(
+    cipher: Option<&DocCipher>,
+    namespace: NamespaceId,
+    key: &[u8],
+    bytes: Vec<u8>,
+) -> Result<Vec<u8>> {
+    match cipher {
+        Some(cipher) => cipher.encrypt(namespace, key, &bytes),
+        None => Ok(bytes),
+    }
+}
+
+/// Decrypt the value of the entry `key` of `namespace` when a cipher is configured
+///
+/// Without a cipher plain values pass through and encrypted ones are refused
+pub fn open(
+    cipher: Option<&DocCipher>,
+    namespace: NamespaceId,
+    key: &[u8],
+    bytes: Vec<u8>,
+) -> Result<Vec<u8>> {
+    match cipher {
+        Some(cipher) => cipher.decrypt(namespace, key, &bytes),
+        None => plain(bytes),
+    }
+}
+
+/// Encrypt content of `namespace` stored by hash when a cipher is configured
+pub fn seal_content(
+    cipher: Option<&DocCipher>,
+    namespace: NamespaceId,
+    bytes: Vec<u8>,
+) -> Result<Vec<u8>> {
+    match cipher {
+        Some(cipher) => cipher.encrypt_content(namespace, &bytes),
+        None => Ok(bytes),
+    }
+}
+
+/// Decrypt content stored by hash when a cipher is configured, see [`open`]
+pub fn open_content(cipher: Option<&DocCipher>, bytes: Vec<u8>) -> Result<Vec<u8>> {
+    match cipher {
+        Some(cipher) if cipher.legacy && !is_encrypted(&bytes) => Ok(bytes),
+        Some(cipher) => cipher.decrypt_content(&bytes),
+        None => plain(bytes),
+    }
+}
+
+fn plain(bytes: Vec<u8>) -> Result<Vec<u8>> {
+    ensure!(
+        !is_encrypted(&bytes),
+        "Value is encrypted, pass --passphrase or --encryption-key to read it"
+    );
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    fn cipher() -> DocCipher {
+        DocCipher::from_hex(KEY).unwrap()
+    }
+
+    fn namespace(byte: u8) -> NamespaceId {
+        NamespaceId::from([byte; 32])
+    }
+
+    #[test]
+    fn opens_values_sealed_for_the_same_entry() {
+        let cipher = cipher();
+        let sealed = cipher.encrypt(namespace(1), b"files/1", b"hello").unwrap();
+        assert!(is_bound(&sealed));
+        assert_ne!(&sealed[ENTRY_MAGIC.len()..], b"hello");
+        let opened = cipher.decrypt(namespace(1), b"files/1", &sealed).unwrap();
+        assert_eq!(opened, b"hello");
+    }
+
+    #[test]
+    fn rejects_tampered_values() {
+        let cipher = cipher();
+        let mut sealed = cipher.encrypt(namespace(1), b"files/1", b"hello").unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert!(cipher.decrypt(namespace(1), b"files/1", &sealed).is_err());
+    }
+
+    #[test]
+    fn rejects_values_copied_to_another_entry() {
+        let cipher = cipher();
+        let sealed = cipher.encrypt(namespace(1), b"files/1", b"hello").unwrap();
+        assert!(cipher.decrypt(namespace(1), b"files/2", &sealed).is_err());
+        assert!(cipher.decrypt(namespace(2), b"files/1", &sealed).is_err());
+    }
+
+    #[test]
+    fn rejects_values_sealed_with_another_key() {
+        let sealed = cipher()
+            .encrypt(namespace(1), b"files/1", b"hello")
+            .unwrap();
+        let other = DocCipher::from_hex(&KEY.replace("00", "ff")).unwrap();
+        assert!(other.decrypt(namespace(1), b"files/1", &sealed).is_err());
+    }
+
+    #[test]
+    fn reads_plain_values_only_when_accepting_legacy() {
+        assert!(
+            cipher()
+                .decrypt(namespace(1), b"files/1", b"hello")
+                .is_err()
+        );
+        let legacy = cipher().accept_legacy(true);
+        let opened = legacy.decrypt(namespace(1), b"files/1", b"hello").unwrap();
+        assert_eq!(opened, b"hello");
+    }
+
+    #[test]
+    fn opens_content_sealed_for_any_namespace() {
+        let cipher = cipher();
+        let sealed = cipher.encrypt_content(namespace(3), b"content").unwrap();
+        assert!(is_encrypted(&sealed) && !is_bound(&sealed));
+        assert_eq!(cipher.decrypt_content(&sealed).unwrap(), b"content");
+        assert!(open_content(None, sealed).is_err());
+    }
+
+    #[test]
+    fn opens_files_sealed_with_a_passphrase() {
+        let sealed = encrypt_with_passphrase("correct horse", b"backup").unwrap();
+        assert_eq!(
+            decrypt_with_passphrase("correct horse", &sealed).unwrap(),
+            b"backup"
+        );
+        assert!(decrypt_with_passphrase("wrong horse", &sealed).is_err());
+    }
+}
@@ -0,0 +1,279 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Result;
+use iroh::endpoint::ConnectionType;
+use ratatui::{
+    DefaultTerminal, Frame,
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    widgets::{Block, List, Paragraph, Row, Table},
+};
+use tokio::sync::{broadcast, mpsc};
+
+use crate::{
+    doc_subcribe::{AppEvent, ProgressType, SyncProgress},
+    peers::PeerInfo,
+    store::{StoreState, TICKET_ORDER},
+};
+
+// events kept in the events pane
+const MAX_EVENTS: usize = 200;
+
+// how often keys are polled and the screen redrawn
+const TICK: Duration = Duration::from_millis(250);
+
+// ticks between refreshes of the peers and the disk usage
+const REFRESH_TICKS: u32 = 4;
+
+#[derive(Default)]
+struct DashboardState {
+    // latest progress of every table
+    tables: BTreeMap<String, SyncProgress>,
+    peers: Vec<PeerInfo>,
+    // newest last
+    events: VecDeque<String>,
+    disk_usage: u64,
+}
+
+/// Show live sync progress, peers, recent events and disk usage until `q` or Esc is pressed
+///
+/// Takes the [`AppEvent`] receivers of all tables, `storage` is the directory whose size is shown
+pub async fn run(state: &StoreState, storage: PathBuf) -> Result<()> {
+    let mut progress = state.subscribe_progress();
+    let mut events = merge_events(state).await;
+    let mut terminal = ratatui::init();
+    let result = run_loop(&mut terminal, state, &storage, &mut progress, &mut events).await;
+    ratatui::restore();
+    result
+}
+
+async fn run_loop(
+    terminal: &mut DefaultTerminal,
+    state: &StoreState,
+    storage: &Path,
+    progress: &mut broadcast::Receiver<SyncProgress>,
+    events: &mut mpsc::Receiver<AppEvent>,
+) -> Result<()> {
+    let mut dashboard = DashboardState::default();
+    let mut tick = tokio::time::interval(TICK);
+    let mut ticks = 0;
+    loop {
+        tokio::select! {
+            update = progress.recv() => match update {
+                Ok(update) => {
+                    dashboard.tables.insert(update.table_name.clone(), update);
+                }
+                // only the latest progress is shown, skipped updates don't matter
+                Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            Some(event) = events.recv() => {
+                dashboard.events.push_back(describe(&event));
+                if dashboard.events.len() > MAX_EVENTS {
+                    dashboard.events.pop_front();
+                }
+            }
+            _ = tick.tick() => {
+                if quit_pressed()? {
+                    break;
+                }
+                if ticks % REFRESH_TICKS == 0 {
+                    let iroh = &state.iroh;
+                    dashboard.peers = iroh.peers.list(iroh.router.endpoint()).await;
+                    let storage = storage.to_path_buf();
+                    dashboard.disk_usage =
+                        tokio::task::spawn_blocking(move || dir_size(&storage)).await?;
+                }
+                ticks += 1;
+                terminal.draw(|frame| draw(frame, &dashboard))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// forward the app events of every table into one channel
+async fn merge_events(state: &StoreState) -> mpsc::Receiver<AppEvent> {
+    let (tx, rx) = mpsc::channel(MAX_EVENTS);
+    let tables = TICKET_ORDER
+        .iter()
+        .map(|t| t.to_string())
+        .chain(state.list_collections().await);
+    for table in tables {
+        let Some(mut events) = state.take_events(&table).await else {
+            continue;
+        };
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                if tx.send(event).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    rx
+}
+
+// drain pending terminal events, true once q or Esc was pressed
+fn quit_pressed() -> Result<bool> {
+    while event::poll(Duration::ZERO)? {
+        if let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+            && matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+        {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn draw(frame: &mut Frame, dashboard: &DashboardState) {
+    let [tables_area, lists_area, footer_area] = Layout::vertical([
+        Constraint::Length(dashboard.tables.len().max(1) as u16 + 3),
+        Constraint::Min(5),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [peers_area, events_area] =
+        Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+            .areas(lists_area);
+
+    let rows = dashboard.tables.values().map(|p| {
+        let phase = match p.phase {
+            ProgressType::OnInit => "init",
+            ProgressType::OnLoadedTable => "entries",
+            ProgressType::OnLoadedBlob => "synced",
+        };
+        let percent = match p.total_bytes {
+            0 => 100,
+            total => p.downloaded_bytes * 100 / total,
+        };
+        Row::new(vec![
+            p.table_name.clone(),
+            phase.to_string(),
+            format!(
+                "{}/{}",
+                p.total_files - p.remaining_files.min(p.total_files),
+                p.total_files
+            ),
+            format!(
+                "{} / {}",
+                format_bytes(p.downloaded_bytes),
+                format_bytes(p.total_bytes)
+            ),
+            format!("{}%", percent),
+        ])
+    });
+    let tables = Table::new(
+        rows,
+        [
+            Constraint::Length(14),
+            Constraint::Length(8),
+            Constraint::Length(12),
+            Constraint::Length(24),
+            Constraint::Length(5),
+        ],
+    )
+    .header(Row::new(vec!["TABLE", "PHASE", "FILES", "BYTES", "DONE"]))
+    .block(Block::bordered().title("Sync progress"));
+    frame.render_widget(tables, tables_area);
+
+    let peers = dashboard.peers.iter().map(|peer| {
+        let path = match &peer.conn_type {
+            Some(ConnectionType::Direct(_)) => "direct",
+            Some(ConnectionType::Relay(_)) => "relay",
+            Some(ConnectionType::Mixed(..)) => "mixed",
+            _ => "not connected",
+        };
+        format!(
+            "{} {} ({} tables)",
+            peer.peer.fmt_short(),
+            path,
+            peer.tables.len()
+        )
+    });
+    let peers = List::new(peers)
+        .block(Block::bordered().title(format!("Peers ({})", dashboard.peers.len())));
+    frame.render_widget(peers, peers_area);
+
+    // newest events first, as many as fit
+    let height = events_area.height.saturating_sub(2) as usize;
+    let events = dashboard.events.iter().rev().take(height).cloned();
+    let events = List::new(events).block(Block::bordered().title("Recent events"));
+    frame.render_widget(events, events_area);
+
+    let footer = Paragraph::new(format!(
+        " storage {}  |  q or Esc to quit",
+        format_bytes(dashboard.disk_usage)
+    ));
+    frame.render_widget(footer, footer_area);
+}
+
+fn describe(event: &AppEvent) -> String {
+    match event {
+        AppEvent::EntityInserted { table, key } => format!("[{}] inserted {}", table, key),
+        AppEvent::EntityDeleted { table, key } => format!("[{}] deleted {}", table, key),
+        AppEvent::BlobReady { table, key } => format!("[{}] content ready {}", table, key),
+        AppEvent::PeerJoined { table, peer } => {
+            format!("[{}] peer joined {}", table, peer.fmt_short())
+        }
+        AppEvent::PeerLeft { table, peer } => format!("[{}] peer left {}", table, peer.fmt_short()),
+        AppEvent::InitialSyncComplete { table } => format!("[{}] initial sync complete", table),
+        AppEvent::BlobFailed { table, key, .. } => format!("[{}] download failed {}", table, key),
+        AppEvent::Disconnected { table } => format!("[{}] relay lost", table),
+        AppEvent::Reconnected { table } => format!("[{}] reconnected, resyncing", table),
+        AppEvent::Replicated { table, key } => format!("[{}] replicated {}", table, key),
+        AppEvent::Conflict {
+            table,
+            key,
+            authors,
+        } => format!(
+            "[{}] conflicting writes of {} by {} authors",
+            table,
+            key,
+            authors.len()
+        ),
+        AppEvent::Unauthorized { table, key, author } => {
+            format!(
+                "[{}] ignored write of {} by {}",
+                table,
+                key,
+                author.fmt_short()
+            )
+        }
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+// total size of the files below `path`
+fn dir_size(path: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return 0;
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let meta = entry.metadata().ok()?;
+            Some(if meta.is_dir() {
+                dir_size(&entry.path())
+            } else {
+                meta.len()
+            })
+        })
+        .sum()
+}
@@ -0,0 +1,164 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use iroh_blobs::Hash;
+use tracing::{debug, info, warn};
+
+use crate::model::resource::{Resource, Resources};
+use crate::store::IrohProperties;
+
+// file inside the synced directory remembering the content of every file at the last sync
+pub const SYNC_STATE_FILE: &str = ".iroh-sync.json";
+
+/// What a [`sync_dir`] run did, file names sorted
+#[derive(Debug, Clone, Default)]
+pub struct SyncReport {
+    // new or locally changed files stored as resources
+    pub uploaded: Vec<String>,
+    // new or remotely changed resources written to the directory
+    pub downloaded: Vec<String>,
+    // changed on both sides since the last sync, or differing without a previous sync
+    pub conflicts: Vec<String>,
+    // synced before and deleted locally since, the resource is kept
+    pub deleted_locally: Vec<String>,
+    // synced before and deleted remotely since, the file is kept
+    pub deleted_remotely: Vec<String>,
+    pub unchanged: usize,
+}
+
+/// Synchronize the files of `dir` with the resources of a table in both directions
+///
+/// Files and resources are matched by name and compared by content hash. A side that
+/// changed since the last sync, as remembered in [`SYNC_STATE_FILE`], wins; when both
+/// changed the file is reported as a conflict and left alone. Deletions are reported but
+/// not applied. Hidden files are skipped and subdirectories are not descended into, resources
+/// whose name is not a plain file name are skipped as well
+pub async fn sync_dir(resources: &Resources, dir: &Path) -> Result<SyncReport> {
+    tokio::fs::create_dir_all(dir)
+        .await
+        .with_context(|| format!("Failed to create {:?}", dir))?;
+    let state_path = dir.join(SYNC_STATE_FILE);
+    let mut base = load_state(&state_path).await?;
+
+    let mut local: HashMap<String, (PathBuf, Hash)> = HashMap::new();
+    let mut entries = tokio::fs::read_dir(dir)
+        .await
+        .with_context(|| format!("Failed to read {:?}", dir))?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') || !entry.file_type().await?.is_file() {
+            continue;
+        }
+        let path = entry.path();
+        let hash = local_hash(resources, &name, &path).await?;
+        local.insert(name, (path, hash));
+    }
+
+    // the newest resource wins when several share a name
+    let mut remote: HashMap<String, Resource> = HashMap::new();
+    for resource in resources.search().await? {
+        let resource = resource.into_inner();
+        if !is_plain_name(&resource.name) {
+            warn!(id = %resource.id, name = %resource.name, "skipping resource not named like a file of the directory");
+            continue;
+        }
+        if remote
+            .get(&resource.name)
+            .is_none_or(|other| other.created_at < resource.created_at)
+        {
+            remote.insert(resource.name.clone(), resource);
+        }
+    }
+
+    let names: BTreeSet<String> = local.keys().chain(remote.keys()).cloned().collect();
+    let mut report = SyncReport::default();
+    for name in names {
+        let known = base.get(&name).copied();
+        match (local.get(&name), remote.get(&name)) {
+            (Some((path, hash)), None) => {
+                if known.is_some() {
+                    report.deleted_remotely.push(name);
+                    continue;
+                }
+                resources.add_path(path).await?;
+                base.insert(name.clone(), *hash);
+                report.uploaded.push(name);
+            }
+            (None, Some(resource)) => {
+                if known.is_some() {
+                    report.deleted_locally.push(name);
+                    continue;
+                }
+                resources.fetch(&resource.id, Some(dir.join(&name))).await?;
+                base.insert(name.clone(), resource.content_id());
+                report.downloaded.push(name);
+            }
+            (Some((path, hash)), Some(resource)) => {
+                let remote_hash = resource.content_id();
+                if *hash == remote_hash {
+                    base.insert(name, *hash);
+                    report.unchanged += 1;
+                } else if known == Some(remote_hash) {
+                    let blob = tokio::fs::read(path).await?;
+                    resources.put_file(&resource.id, name.clone(), blob).await?;
+                    base.insert(name.clone(), *hash);
+                    report.uploaded.push(name);
+                } else if known == Some(*hash) {
+                    resources.fetch(&resource.id, Some(path.clone())).await?;
+                    base.insert(name.clone(), remote_hash);
+                    report.downloaded.push(name);
+                } else {
+                    debug!(name, "changed on both sides");
+                    report.conflicts.push(name);
+                }
+            }
+            (None, None) => {}
+        }
+    }
+    save_state(&state_path, &base).await?;
+    info!(
+        ?dir,
+        uploaded = report.uploaded.len(),
+        downloaded = report.downloaded.len(),
+        conflicts = report.conflicts.len(),
+        "directory synced"
+    );
+    Ok(report)
+}
+
+// whether a synced name is a single visible file name, a name written by a peer must not
+// point outside the directory or at the sync state
+fn is_plain_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    ) && !name.starts_with('.')
+}
+
+// hash of a file as the resource holding it would report, see [`Resource::content_id`]
+async fn local_hash(resources: &Resources, name: &str, path: &Path) -> Result<Hash> {
+    let (_, hash) = resources.path_content_key(name, path).await?;
+    Ok(hash)
+}
+
+async fn load_state(path: &Path) -> Result<HashMap<String, Hash>> {
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    let bytes = tokio::fs::read(path)
+        .await
+        .with_context(|| format!("Failed to read {:?}", path))?;
+    serde_json::from_slice(&bytes).with_context(|| format!("Failed to parse {:?}", path))
+}
+
+async fn save_state(path: &Path, state: &HashMap<String, Hash>) -> Result<()> {
+    // write next to the state and rename, so a crash never leaves a partial file
+    let tmp = path.with_extension("tmp");
+    tokio::fs::write(&tmp, serde_json::to_vec_pretty(state)?).await?;
+    tokio::fs::rename(&tmp, path).await?;
+    Ok(())
+}
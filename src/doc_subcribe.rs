@@ -1,18 +1,24 @@
 use std::{
     collections::HashMap,
-    str::from_utf8,
     sync::{
         Arc,
         atomic::{AtomicBool, AtomicU64, Ordering},
     },
+    time::UNIX_EPOCH,
 };
 
-use iroh_docs::{ContentStatus, NamespaceId, engine::LiveEvent};
+use iroh::PublicKey;
+use iroh_blobs::Hash;
+use iroh_docs::{AuthorId, ContentStatus, Entry, NamespaceId, engine::LiveEvent};
 use serde::{Deserialize, Serialize};
-use tokio::{
-    sync::{Mutex, mpsc},
-    task::JoinHandle,
-};
+use tracing::{debug, info};
+
+use crate::downloader::DownloadScheduler;
+use crate::event_channel::EventSender;
+use crate::peers::PeerRegistry;
+use crate::pending::PendingWrites;
+use crate::retry::{RetryPolicy, RetryScheduler};
+use tokio::sync::{Mutex, broadcast};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RemoteUpdateData {
@@ -34,6 +40,90 @@ pub enum ProgressType {
     OnLoadedBlob,
 }
 
+// application level change of a subscribed table
+#[derive(Debug, Clone, PartialEq)]
+pub enum AppEvent {
+    // an entity was written locally or by a remote
+    EntityInserted {
+        table: String,
+        key: String,
+    },
+    // an entity was removed by a remote
+    EntityDeleted {
+        table: String,
+        key: String,
+    },
+    // the content of an entity has been downloaded
+    BlobReady {
+        table: String,
+        key: String,
+    },
+    // a peer started syncing the table
+    PeerJoined {
+        table: String,
+        peer: PublicKey,
+    },
+    // a peer stopped syncing the table
+    PeerLeft {
+        table: String,
+        peer: PublicKey,
+    },
+    // all remote entries and their content have been downloaded
+    InitialSyncComplete {
+        table: String,
+    },
+    // the content of an entity could not be downloaded after all retries
+    BlobFailed {
+        table: String,
+        key: String,
+        hash: Hash,
+    },
+    // the home relay was lost, sync stalls until it is back
+    Disconnected {
+        table: String,
+    },
+    // the home relay is back and sync of the table was restarted
+    Reconnected {
+        table: String,
+    },
+    // authors wrote the same key concurrently, the first author's version won
+    Conflict {
+        table: String,
+        key: String,
+        authors: Vec<AuthorId>,
+    },
+    // a local write reached a peer for the first time
+    Replicated {
+        table: String,
+        key: String,
+    },
+    // a remote wrote an entry although the access list does not allow its author to
+    Unauthorized {
+        table: String,
+        key: String,
+        author: AuthorId,
+    },
+}
+
+// sync progress of a single table, published after every change
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncProgress {
+    // doc name
+    pub table_name: String,
+    // doc namespace_id
+    pub namespace_id: NamespaceId,
+    // current phase of the sync
+    pub phase: ProgressType,
+    // number of files announced by remotes
+    pub total_files: u64,
+    // number of files still to be downloaded
+    pub remaining_files: u64,
+    // size of the files announced by remotes
+    pub total_bytes: u64,
+    // size of the files already downloaded
+    pub downloaded_bytes: u64,
+}
+
 // data sync event object
 #[derive(Debug)]
 pub struct EventRemoteSync {
@@ -54,21 +144,37 @@ pub struct EventRemoteSync {
     pub init_successed: Arc<AtomicBool>,
     // whether initialization of fetching files succeeded
     pub init_blob_successed: Arc<AtomicBool>,
-    // tx
-    pub tx: Arc<mpsc::Sender<String>>,
-    // handle
-    pub handle: Option<JoinHandle<()>>,
+    // application event sender
+    pub tx: EventSender,
+    // progress subscribers
+    pub progress: broadcast::Sender<SyncProgress>,
+    // re-requests content that never arrived
+    pub retry: RetryScheduler,
+    // fetches announced content in parallel, small blobs first
+    pub scheduler: DownloadScheduler,
+    // shared registry of neighbors and their sync state
+    pub peers: PeerRegistry,
+    // local writes no peer has received yet
+    pub pending: PendingWrites,
+    // prefix of the table's keys when it shares its doc with other tables
+    pub prefix: Vec<u8>,
 }
 
 impl EventRemoteSync {
-    pub fn new(namespace_id: NamespaceId, table_name: String) -> Self {
+    pub fn new(
+        namespace_id: NamespaceId,
+        table_name: String,
+        progress: broadcast::Sender<SyncProgress>,
+        scheduler: DownloadScheduler,
+        peers: PeerRegistry,
+        pending: PendingWrites,
+        tx: EventSender,
+    ) -> Self {
         let hashmap = HashMap::<String, RemoteUpdateData>::new();
 
         let hashmap_clone = Arc::new(Mutex::new(hashmap));
-        // construct message receiver
-        let (tx, mut rx) = mpsc::channel::<String>(1000);
 
-        let instance = Self {
+        Self {
             hashmap: hashmap_clone,
             remaining_remote_num: Arc::new(AtomicU64::new(0)),
             queue_remote_num: Arc::new(AtomicU64::new(0)),
@@ -78,11 +184,41 @@ impl EventRemoteSync {
             table_name: table_name.clone(),
             init_successed: Arc::new(AtomicBool::new(false)),
             init_blob_successed: Arc::new(AtomicBool::new(false)),
-            tx: Arc::new(tx),
-            handle: None,
-        };
+            tx,
+            progress,
+            retry: RetryScheduler::new(RetryPolicy::default()),
+            scheduler,
+            peers,
+            pending,
+            prefix: Vec::new(),
+        }
+    }
 
-        instance
+    // key of an entry within the table
+    fn table_key(&self, entry: &Entry) -> String {
+        let key = entry.key();
+        String::from_utf8_lossy(key.strip_prefix(self.prefix.as_slice()).unwrap_or(key)).to_string()
+    }
+
+    // a full channel is handled by its overflow policy, see `EventSender::send`
+    async fn send_event(&self, event: AppEvent) {
+        self.tx.send(event).await;
+    }
+
+    /// Publish the current counters to progress subscribers
+    pub fn emit_progress(&self, phase: ProgressType) {
+        let total_bytes = self.remaining_remote_bytes.load(Ordering::SeqCst);
+        let remaining_bytes = self.queue_remote_bytes.load(Ordering::SeqCst);
+        // sending only fails when nobody is subscribed
+        let _ = self.progress.send(SyncProgress {
+            table_name: self.table_name.clone(),
+            namespace_id: self.namespace_id,
+            phase,
+            total_files: self.remaining_remote_num.load(Ordering::SeqCst),
+            remaining_files: self.queue_remote_num.load(Ordering::SeqCst),
+            total_bytes,
+            downloaded_bytes: total_bytes.saturating_sub(remaining_bytes),
+        });
     }
     /// Send document modification events to frontend
     ///
@@ -92,7 +228,7 @@ impl EventRemoteSync {
     /// * `binding_app_handle` - app_handle arc reference
     pub async fn emit_doc_edit<'a>(&mut self, live_event: LiveEvent) {
         let table_name = &self.table_name;
-        let hashmap_store = &mut self.hashmap;
+        let hashmap_store = self.hashmap.clone();
         match live_event {
             // remote modification
             LiveEvent::InsertRemote {
@@ -102,37 +238,40 @@ impl EventRemoteSync {
             } => {
                 // Only update if the we already have the content. Likely to happen when a remote user toggles "done".
                 if content_status == ContentStatus::Complete {
-                    println!("[doc_subscribe]Remote {} incoming file{:?}", from, entry);
-                }
-                println!(
-                    "[doc_subscribe]{}:{:?}-{:?}",
-                    table_name.clone(),
-                    content_status,
-                    entry
-                );
-                // resource table does not return progress
-                if table_name.as_str() == "resource" {
-                    return;
+                    debug!(?from, ?entry, "remote entry with content");
                 }
+                debug!(?content_status, ?entry, "remote insert");
                 // get short hash
                 let conetent_hash = entry.record().content_hash().fmt_short();
-                let content_key = entry.key();
                 let content_size = entry.record().content_len();
-                let key = from_utf8(&content_key).unwrap().to_string();
+                let key = self.table_key(&entry);
                 // if download data is 0, it means the data has been deleted and should not be added to download list
                 if content_size == 0 {
-                    if self.init_blob_successed.load(Ordering::Relaxed) {
-                        // send delete data event to editor and main
-                    }
-                    println!(
-                        "[doc_subscribe]Empty data {}:{:?}-{:?}",
-                        table_name.clone(),
-                        content_status,
-                        entry
-                    );
+                    self.send_event(AppEvent::EntityDeleted {
+                        table: table_name.clone(),
+                        key,
+                    })
+                    .await;
+                    debug!(?content_status, ?entry, "remote delete");
                     return;
                 }
 
+                self.send_event(AppEvent::EntityInserted {
+                    table: table_name.clone(),
+                    key: key.clone(),
+                })
+                .await;
+
+                if content_status != ContentStatus::Complete
+                    && let Ok(from) = PublicKey::from_bytes(&from)
+                {
+                    self.scheduler
+                        .queue(entry.content_hash(), content_size, vec![from]);
+                    self.retry
+                        .track(entry.content_hash(), key.clone(), content_size, from)
+                        .await;
+                }
+
                 // record state here for each remote update, then send message to frontend after data loading succeeds
                 let mut hashmap = hashmap_store.lock().await;
                 hashmap
@@ -153,85 +292,140 @@ impl EventRemoteSync {
                     self.queue_remote_num.fetch_add(1, Ordering::SeqCst);
                     self.queue_remote_bytes
                         .fetch_add(content_size, Ordering::SeqCst);
+                    self.emit_progress(ProgressType::OnInit);
                 }
             }
             // local modification
             LiveEvent::InsertLocal { entry } => {
-                println!(
-                    "[doc_subscribe]{} Local file modification{:?}",
-                    table_name.clone(),
-                    entry
-                );
+                debug!(?entry, "local insert");
+                let key = self.table_key(&entry);
+                // heartbeats of the node table are not worth tracking
+                if table_name != "node" {
+                    if self.peers.has_neighbor(table_name).await {
+                        // the docs engine broadcasts local writes to the gossip neighbors right away
+                        self.send_event(AppEvent::Replicated {
+                            table: table_name.clone(),
+                            key: key.clone(),
+                        })
+                        .await;
+                    } else {
+                        self.pending
+                            .record(table_name, key.clone(), entry.timestamp());
+                    }
+                }
+                let event = if entry.content_len() == 0 {
+                    AppEvent::EntityDeleted {
+                        table: table_name.clone(),
+                        key,
+                    }
+                } else {
+                    AppEvent::EntityInserted {
+                        table: table_name.clone(),
+                        key,
+                    }
+                };
+                self.send_event(event).await;
             }
             LiveEvent::ContentReady { hash } => {
-                println!(
-                    "[doc_subscribe]{} starting download {}",
-                    table_name.clone(),
-                    hash
-                );
+                debug!(%hash, "content ready");
+                self.retry.resolve(&hash).await;
                 // get short hash
                 let conetent_hash = hash.fmt_short();
                 let mut hashmap = hashmap_store.lock().await;
                 let rud = hashmap.get(&conetent_hash.to_string());
                 if rud.is_none() {
-                    println!(
-                        "[doc_subscribe]{} file download successful {}, but not recorded in hashmap_store",
-                        table_name.clone(),
-                        hash
-                    );
+                    debug!(%hash, "downloaded content that was not announced by a remote insert");
                     return;
                 }
                 // notify client of data changes after data download completes
                 if let Some((_, remote_update_data)) =
                     hashmap.remove_entry(&conetent_hash.to_string())
                 {
+                    self.send_event(AppEvent::BlobReady {
+                        table: table_name.clone(),
+                        key: remote_update_data.key.clone(),
+                    })
+                    .await;
                     // record state when system is not initialized successfully
                     if !self.init_blob_successed.load(Ordering::SeqCst) {
-                        let _ = self.tx.send(remote_update_data.clone().key).await;
                         self.queue_remote_num.fetch_sub(1, Ordering::SeqCst);
                         self.queue_remote_bytes
                             .fetch_sub(remote_update_data.size, Ordering::SeqCst);
+                        let phase = if self.init_successed.load(Ordering::SeqCst) {
+                            ProgressType::OnLoadedTable
+                        } else {
+                            ProgressType::OnInit
+                        };
+                        self.emit_progress(phase);
                     }
                 }
-                println!(
-                    "[doc_subscribe]{} file download successful {}",
-                    table_name.clone(),
-                    hash
-                );
+                info!(%hash, "download complete");
             }
             // this method executes when system loads for the first time
             LiveEvent::PendingContentReady => {
                 // this method can be used as an indicator of whether loading is successful, including all files in blob
                 let pre_init_blob_successed = self.init_blob_successed.swap(true, Ordering::SeqCst);
-                println!(
-                    "[doc_subscribe]{} all remote files synced successfully, {}",
-                    table_name.clone(),
-                    &pre_init_blob_successed
+                info!(
+                    already_synced = pre_init_blob_successed,
+                    "all remote content synced"
                 );
-                if !pre_init_blob_successed {}
-                // end initialization method
-                let handle = self.handle.as_ref();
-                if handle.is_some() {
-                    handle.unwrap().abort();
-                    self.handle = None;
+                if !pre_init_blob_successed {
+                    self.emit_progress(ProgressType::OnLoadedBlob);
+                    self.send_event(AppEvent::InitialSyncComplete {
+                        table: table_name.clone(),
+                    })
+                    .await;
                 }
             }
             LiveEvent::NeighborUp(public_key) => {
-                println!("[doc_subscribe]New user {public_key}");
+                info!(peer = %public_key, "neighbor up");
+                self.peers.neighbor_up(public_key, table_name).await;
+                self.send_event(AppEvent::PeerJoined {
+                    table: table_name.clone(),
+                    peer: public_key,
+                })
+                .await;
             }
             LiveEvent::NeighborDown(public_key) => {
-                println!("[doc_subscribe]User exited {public_key}");
+                info!(peer = %public_key, "neighbor down");
+                self.peers.neighbor_down(public_key, table_name).await;
+                self.send_event(AppEvent::PeerLeft {
+                    table: table_name.clone(),
+                    peer: public_key,
+                })
+                .await;
             }
             // this method executes when system loads for the first time
             LiveEvent::SyncFinished(sync_event) => {
                 // this method can be used as an indicator of whether table loading is successful, not including files in blob
                 // the method for successful blob download is [`LiveEvent::PendingContentReady`]
                 self.init_successed.store(true, Ordering::SeqCst);
-                println!(
-                    "[doc_subscribe]{} transfer completed {:?}",
-                    table_name.clone(),
-                    sync_event
-                );
+                self.emit_progress(ProgressType::OnLoadedTable);
+                info!(peer = %sync_event.peer, result = ?sync_event.result, "sync finished");
+                // the peer received every local write made before the run started
+                if sync_event.result.is_ok() {
+                    let started = sync_event
+                        .started
+                        .duration_since(UNIX_EPOCH)
+                        .map(|d| d.as_micros() as u64)
+                        .unwrap_or_default();
+                    for key in self.pending.confirm(table_name, started) {
+                        debug!(key, peer = %sync_event.peer.fmt_short(), "local write replicated");
+                        self.send_event(AppEvent::Replicated {
+                            table: table_name.clone(),
+                            key,
+                        })
+                        .await;
+                    }
+                }
+                self.peers
+                    .sync_finished(
+                        sync_event.peer,
+                        table_name,
+                        sync_event.finished,
+                        sync_event.result,
+                    )
+                    .await;
             }
         }
     }
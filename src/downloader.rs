@@ -0,0 +1,408 @@
+use std::{
+    collections::{BinaryHeap, HashMap},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use futures::StreamExt;
+use iroh::{Endpoint, PublicKey};
+use iroh_blobs::{
+    BlobFormat, HashAndFormat,
+    api::{
+        Store,
+        downloader::{DownloadProgressItem, Downloader},
+    },
+    hashseq::HashSeq,
+    provider::events::{EventMask, EventSender, ProviderMessage, ThrottleMode},
+};
+use tokio::{
+    sync::{Mutex, Semaphore, oneshot},
+    time::Instant,
+};
+use tracing::warn;
+
+// blobs fetched at the same time by default
+pub const DEFAULT_DOWNLOAD_CONCURRENCY: usize = 8;
+
+// chunks of uploads waiting for bandwidth before the blobs protocol waits to hand out more
+const THROTTLE_CHANNEL_CAPACITY: usize = 64;
+
+/// Token bucket pacing transfers to an average number of bytes per second
+///
+/// Transfers are charged chunk by chunk as the bytes go over the wire, so a single large
+/// blob is paced as well. Up to one second worth of bytes can be sent in a burst
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    // 0 means unlimited
+    bytes_per_sec: AtomicU64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug, Default)]
+struct Bucket {
+    // bytes that may be sent right away, below zero once transfers ran ahead of the rate
+    tokens: f64,
+    refilled: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let limiter = RateLimiter::default();
+        limiter.set_limit(bytes_per_sec);
+        limiter
+    }
+
+    /// Change the limit, 0 removes it
+    pub fn set_limit(&self, bytes_per_sec: u64) {
+        self.bytes_per_sec.store(bytes_per_sec, Ordering::SeqCst);
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.bytes_per_sec.load(Ordering::SeqCst)
+    }
+
+    /// Charge `bytes` that were or are about to be transferred, waiting while the bucket is
+    /// in debt
+    pub async fn acquire(&self, bytes: u64) {
+        let rate = self.limit();
+        if rate == 0 {
+            return;
+        }
+        let rate = rate as f64;
+        let wait = {
+            let mut bucket = self.bucket.lock().await;
+            let now = Instant::now();
+            let elapsed = bucket
+                .refilled
+                .map_or(Duration::ZERO, |refilled| now.duration_since(refilled));
+            bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * rate).min(rate);
+            bucket.refilled = Some(now);
+            bucket.tokens -= bytes as f64;
+            Duration::from_secs_f64((-bucket.tokens).max(0.0) / rate)
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Provider events that charge every chunk served to other peers to `limiter`
+///
+/// Passed to the blobs protocol, which waits for the answer before it sends the chunk
+pub fn upload_events(limiter: Arc<RateLimiter>) -> EventSender {
+    let mask = EventMask {
+        throttle: ThrottleMode::Intercept,
+        ..EventMask::DEFAULT
+    };
+    let (events, mut requests) = EventSender::channel(THROTTLE_CHANNEL_CAPACITY, mask);
+    tokio::spawn(async move {
+        while let Some(request) = requests.recv().await {
+            let ProviderMessage::Throttle(throttle) = request else {
+                continue;
+            };
+            let limiter = limiter.clone();
+            tokio::spawn(async move {
+                limiter.acquire(throttle.size).await;
+                throttle.tx.send(Ok(())).await.ok();
+            });
+        }
+    });
+    events
+}
+
+/// Fetches blobs from peers with a bounded number of simultaneous downloads
+#[derive(Debug, Clone)]
+pub struct BlobDownloader {
+    downloader: Downloader,
+    blobs_store: Store,
+    permits: Arc<Semaphore>,
+    // download bandwidth limit charged per chunk, shared by all clones
+    pub limiter: Arc<RateLimiter>,
+}
+
+impl BlobDownloader {
+    pub fn new(blobs_store: &Store, endpoint: &Endpoint, concurrency: usize) -> Self {
+        BlobDownloader {
+            downloader: blobs_store.downloader(endpoint),
+            blobs_store: blobs_store.clone(),
+            permits: Arc::new(Semaphore::new(concurrency.max(1))),
+            limiter: Arc::new(RateLimiter::default()),
+        }
+    }
+
+    /// Download `content` of `size` bytes from `providers`, waiting for a free slot and pacing
+    /// the transfer to the bandwidth limit
+    ///
+    /// Returns immediately when the content is already stored locally
+    pub async fn download(
+        &self,
+        content: impl Into<HashAndFormat>,
+        size: u64,
+        providers: Vec<PublicKey>,
+    ) -> Result<()> {
+        let _permit = self.permits.acquire().await?;
+        self.transfer(content.into(), size, providers).await
+    }
+
+    // download once a slot is held
+    async fn transfer(
+        &self,
+        content: HashAndFormat,
+        size: u64,
+        providers: Vec<PublicKey>,
+    ) -> Result<()> {
+        if has_content(&self.blobs_store, content).await? {
+            return Ok(());
+        }
+        let mut progress = self
+            .downloader
+            .download(content, providers)
+            .stream()
+            .await?;
+        // progress counts the bytes received so far, each step is charged once it arrived,
+        // which holds up the download until the bucket allows more
+        let mut received = 0;
+        while let Some(item) = progress.next().await {
+            match item {
+                DownloadProgressItem::Progress(bytes) => {
+                    self.limiter.acquire(bytes.saturating_sub(received)).await;
+                    received = bytes;
+                }
+                DownloadProgressItem::Error(e) => return Err(e),
+                DownloadProgressItem::DownloadError => {
+                    anyhow::bail!("No provider had {} ({} bytes)", content.hash, size)
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `content` is complete in `store`, for a hash sequence the sequence and every
+/// blob it lists
+pub async fn has_content(store: &Store, content: HashAndFormat) -> Result<bool> {
+    let blobs = store.blobs();
+    if !blobs.has(content.hash).await? {
+        return Ok(false);
+    }
+    if content.format == BlobFormat::Raw {
+        return Ok(true);
+    }
+    let seq = HashSeq::try_from(blobs.get_bytes(content.hash).await?)?;
+    for hash in seq.iter() {
+        if !blobs.has(hash).await? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// How urgently a queued blob is needed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DownloadPriority {
+    // announced by a peer during sync
+    Background,
+    // asked for by the user, e.g. with `get`
+    Requested,
+}
+
+// a blob waiting in the queue, the most urgent one compares greatest
+#[derive(Debug)]
+struct QueuedBlob {
+    priority: DownloadPriority,
+    size: u64,
+    // order of arrival, earlier blobs go first among equals
+    seq: u64,
+    content: HashAndFormat,
+}
+
+impl Ord for QueuedBlob {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.size.cmp(&self.size))
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for QueuedBlob {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for QueuedBlob {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == std::cmp::Ordering::Equal
+    }
+}
+
+impl Eq for QueuedBlob {}
+
+// what is known about a queued or running download
+#[derive(Debug)]
+struct Job {
+    priority: DownloadPriority,
+    size: u64,
+    providers: Vec<PublicKey>,
+    // false once a slot picked the job up
+    queued: bool,
+    waiters: Vec<oneshot::Sender<Result<(), String>>>,
+}
+
+#[derive(Debug, Default)]
+struct DownloadQueue {
+    // may hold outdated entries of re-prioritized jobs, they are skipped when popped
+    heap: BinaryHeap<QueuedBlob>,
+    jobs: HashMap<HashAndFormat, Job>,
+    seq: u64,
+}
+
+impl DownloadQueue {
+    fn push(&mut self, content: HashAndFormat, priority: DownloadPriority, size: u64) {
+        self.seq += 1;
+        self.heap.push(QueuedBlob {
+            priority,
+            size,
+            seq: self.seq,
+            content,
+        });
+    }
+
+    // the most urgent job still waiting for a slot
+    fn pop(&mut self) -> Option<(HashAndFormat, u64, Vec<PublicKey>)> {
+        while let Some(blob) = self.heap.pop() {
+            let Some(job) = self.jobs.get_mut(&blob.content) else {
+                continue;
+            };
+            if !job.queued || job.priority != blob.priority {
+                continue;
+            }
+            job.queued = false;
+            return Some((blob.content, job.size, job.providers.clone()));
+        }
+        None
+    }
+}
+
+/// Orders the downloads of announced content, shared by the subscription tasks
+///
+/// Blobs the user asked for with [`DownloadScheduler::request`] jump to the front of the
+/// queue, the others are fetched smallest first, so thumbnails and small files show up
+/// before huge ones. At most as many downloads run as the [`BlobDownloader`] allows.
+/// A hash sequence is queued as one job fetching the sequence and every blob it lists
+#[derive(Debug, Clone)]
+pub struct DownloadScheduler {
+    downloader: BlobDownloader,
+    queue: Arc<std::sync::Mutex<DownloadQueue>>,
+}
+
+impl DownloadScheduler {
+    pub fn new(downloader: BlobDownloader) -> Self {
+        DownloadScheduler {
+            downloader,
+            queue: Arc::new(std::sync::Mutex::new(DownloadQueue::default())),
+        }
+    }
+
+    /// Queue a download in the background, content already queued is skipped
+    pub fn queue(&self, content: impl Into<HashAndFormat>, size: u64, providers: Vec<PublicKey>) {
+        self.enqueue(
+            content.into(),
+            size,
+            providers,
+            DownloadPriority::Background,
+            None,
+        );
+    }
+
+    /// Download content ahead of everything queued in the background and wait for it
+    ///
+    /// Content that is already queued is moved to the front
+    pub async fn request(
+        &self,
+        content: impl Into<HashAndFormat>,
+        size: u64,
+        providers: Vec<PublicKey>,
+    ) -> Result<()> {
+        let content = content.into();
+        if has_content(&self.downloader.blobs_store, content).await? {
+            return Ok(());
+        }
+        let (tx, rx) = oneshot::channel();
+        self.enqueue(
+            content,
+            size,
+            providers,
+            DownloadPriority::Requested,
+            Some(tx),
+        );
+        rx.await?.map_err(|e| anyhow::anyhow!(e))
+    }
+
+    /// Number of downloads queued or running
+    pub fn in_flight(&self) -> usize {
+        self.queue.lock().unwrap().jobs.len()
+    }
+
+    fn enqueue(
+        &self,
+        content: HashAndFormat,
+        size: u64,
+        providers: Vec<PublicKey>,
+        priority: DownloadPriority,
+        waiter: Option<oneshot::Sender<Result<(), String>>>,
+    ) {
+        let mut queue = self.queue.lock().unwrap();
+        if let Some(job) = queue.jobs.get_mut(&content) {
+            job.waiters.extend(waiter);
+            for provider in providers {
+                if !job.providers.contains(&provider) {
+                    job.providers.push(provider);
+                }
+            }
+            if job.queued && priority > job.priority {
+                job.priority = priority;
+                queue.push(content, priority, size);
+            }
+            return;
+        }
+        queue.jobs.insert(
+            content,
+            Job {
+                priority,
+                size,
+                providers,
+                queued: true,
+                waiters: waiter.into_iter().collect(),
+            },
+        );
+        queue.push(content, priority, size);
+        drop(queue);
+
+        // every job gets a task that serves the most urgent job once it holds a slot,
+        // which need not be its own
+        let this = self.clone();
+        tokio::spawn(async move {
+            let Ok(_permit) = this.downloader.permits.clone().acquire_owned().await else {
+                return;
+            };
+            let Some((content, size, providers)) = this.queue.lock().unwrap().pop() else {
+                return;
+            };
+            let result = this.downloader.transfer(content, size, providers).await;
+            if let Err(e) = &result {
+                warn!(hash = %content.hash.fmt_short(), error = %e, "download failed");
+            }
+            let job = this.queue.lock().unwrap().jobs.remove(&content);
+            for waiter in job.map(|job| job.waiters).unwrap_or_default() {
+                let _ = waiter.send(result.as_ref().map(|_| ()).map_err(|e| e.to_string()));
+            }
+        });
+    }
+}
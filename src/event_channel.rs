@@ -0,0 +1,383 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    },
+};
+
+use tokio::sync::Notify;
+
+use crate::doc_subcribe::AppEvent;
+
+// application events buffered per table until a consumer reads them
+pub const DEFAULT_EVENT_CAPACITY: usize = 1000;
+
+/// What happens to an [`AppEvent`] sent while the channel of its table is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait until the consumer made room, which holds up the subscription of the table
+    ///
+    /// Events sent from outside the table, see [`EventSender::try_send`], are dropped instead
+    Block,
+    /// Drop the new event
+    #[default]
+    DropNewest,
+    /// Drop the oldest buffered event to make room for the new one
+    DropOldest,
+    /// Drop the new event if the same one is still buffered, otherwise the oldest one
+    Coalesce,
+}
+
+impl std::str::FromStr for OverflowPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "block" => Ok(OverflowPolicy::Block),
+            "drop-newest" => Ok(OverflowPolicy::DropNewest),
+            "drop-oldest" => Ok(OverflowPolicy::DropOldest),
+            "coalesce" => Ok(OverflowPolicy::Coalesce),
+            _ => Err(anyhow::anyhow!("Unknown overflow policy '{}'", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for OverflowPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OverflowPolicy::Block => write!(f, "block"),
+            OverflowPolicy::DropNewest => write!(f, "drop-newest"),
+            OverflowPolicy::DropOldest => write!(f, "drop-oldest"),
+            OverflowPolicy::Coalesce => write!(f, "coalesce"),
+        }
+    }
+}
+
+/// Size and overflow policy of the [`AppEvent`] channel of every subscribed table
+#[derive(Debug, Clone, Copy)]
+pub struct EventChannelConfig {
+    pub capacity: usize,
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for EventChannelConfig {
+    fn default() -> Self {
+        EventChannelConfig {
+            capacity: DEFAULT_EVENT_CAPACITY,
+            overflow: OverflowPolicy::default(),
+        }
+    }
+}
+
+/// Number of events dropped by the channels sharing this counter
+#[derive(Debug, Clone, Default)]
+pub struct DroppedEvents(Arc<AtomicU64>);
+
+impl DroppedEvents {
+    pub fn count(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn add(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+struct Shared {
+    queue: Mutex<VecDeque<AppEvent>>,
+    config: EventChannelConfig,
+    dropped: DroppedEvents,
+    // wakes the receiver once an event is queued or the last sender is gone
+    ready: Notify,
+    // wakes senders blocked on a full queue
+    space: Notify,
+    senders: AtomicUsize,
+    receiver_alive: AtomicBool,
+}
+
+/// Sends the [`AppEvent`]s of a table, applying the [`OverflowPolicy`] of the channel
+pub struct EventSender {
+    shared: Arc<Shared>,
+}
+
+/// Receives the [`AppEvent`]s of a table, ends once every [`EventSender`] is dropped
+pub struct EventReceiver {
+    shared: Arc<Shared>,
+}
+
+/// Create a channel buffering up to `config.capacity` events, drops are counted in `dropped`
+pub fn event_channel(
+    config: EventChannelConfig,
+    dropped: DroppedEvents,
+) -> (EventSender, EventReceiver) {
+    let config = EventChannelConfig {
+        capacity: config.capacity.max(1),
+        ..config
+    };
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(VecDeque::new()),
+        config,
+        dropped,
+        ready: Notify::new(),
+        space: Notify::new(),
+        senders: AtomicUsize::new(1),
+        receiver_alive: AtomicBool::new(true),
+    });
+    (
+        EventSender {
+            shared: shared.clone(),
+        },
+        EventReceiver { shared },
+    )
+}
+
+impl EventSender {
+    /// Queue `event`, returns false if it was dropped or nobody receives it anymore
+    pub async fn send(&self, mut event: AppEvent) -> bool {
+        let shared = &self.shared;
+        loop {
+            let space = shared.space.notified();
+            tokio::pin!(space);
+            space.as_mut().enable();
+            match self.push(event) {
+                Ok(sent) => return sent,
+                Err(full) => event = full,
+            }
+            space.await;
+        }
+    }
+
+    /// Queue `event` without waiting, a full channel drops it even with [`OverflowPolicy::Block`]
+    ///
+    /// For events sent to other tables, whose consumers must not hold up the sender
+    pub fn try_send(&self, event: AppEvent) -> bool {
+        match self.push(event) {
+            Ok(sent) => sent,
+            Err(_) => {
+                self.shared.dropped.add();
+                false
+            }
+        }
+    }
+
+    // queue `event` by the overflow policy, hands it back if the policy is to wait for room
+    fn push(&self, event: AppEvent) -> Result<bool, AppEvent> {
+        let shared = &self.shared;
+        if !shared.receiver_alive.load(Ordering::Acquire) {
+            return Ok(false);
+        }
+        let mut queue = shared.queue.lock().unwrap();
+        if queue.len() < shared.config.capacity {
+            queue.push_back(event);
+            drop(queue);
+            shared.ready.notify_one();
+            return Ok(true);
+        }
+        match shared.config.overflow {
+            OverflowPolicy::Block => Err(event),
+            OverflowPolicy::DropNewest => {
+                shared.dropped.add();
+                Ok(false)
+            }
+            OverflowPolicy::Coalesce if queue.contains(&event) => {
+                shared.dropped.add();
+                Ok(true)
+            }
+            OverflowPolicy::DropOldest | OverflowPolicy::Coalesce => {
+                queue.pop_front();
+                queue.push_back(event);
+                drop(queue);
+                shared.dropped.add();
+                shared.ready.notify_one();
+                Ok(true)
+            }
+        }
+    }
+
+    /// Events waiting for the receiver
+    pub fn len(&self) -> usize {
+        self.shared.queue.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Clone for EventSender {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::AcqRel);
+        EventSender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for EventSender {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.ready.notify_one();
+        }
+    }
+}
+
+impl std::fmt::Debug for EventSender {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventSender")
+            .field("config", &self.shared.config)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EventReceiver {
+    /// Next event, `None` once the queue is drained and every sender is dropped
+    pub async fn recv(&mut self) -> Option<AppEvent> {
+        let shared = &self.shared;
+        loop {
+            if let Some(event) = shared.queue.lock().unwrap().pop_front() {
+                shared.space.notify_one();
+                return Some(event);
+            }
+            if shared.senders.load(Ordering::Acquire) == 0 {
+                return None;
+            }
+            // a notification sent since the queue was checked is kept as a permit
+            shared.ready.notified().await;
+        }
+    }
+}
+
+impl Drop for EventReceiver {
+    fn drop(&mut self) {
+        self.shared.receiver_alive.store(false, Ordering::Release);
+        self.shared.queue.lock().unwrap().clear();
+        self.shared.space.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn channel(
+        capacity: usize,
+        overflow: OverflowPolicy,
+    ) -> (EventSender, EventReceiver, DroppedEvents) {
+        let dropped = DroppedEvents::default();
+        let (tx, rx) = event_channel(EventChannelConfig { capacity, overflow }, dropped.clone());
+        (tx, rx, dropped)
+    }
+
+    fn inserted(key: &str) -> AppEvent {
+        AppEvent::EntityInserted {
+            table: "resources".to_string(),
+            key: key.to_string(),
+        }
+    }
+
+    async fn drain(rx: &mut EventReceiver, n: usize) -> Vec<AppEvent> {
+        let mut events = Vec::new();
+        for _ in 0..n {
+            events.push(rx.recv().await.unwrap());
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn block_waits_for_room() {
+        let (tx, mut rx, dropped) = channel(1, OverflowPolicy::Block);
+        assert!(tx.send(inserted("a")).await);
+
+        let blocked = tokio::spawn({
+            let tx = tx.clone();
+            async move { tx.send(inserted("b")).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!blocked.is_finished());
+
+        assert_eq!(rx.recv().await, Some(inserted("a")));
+        assert!(blocked.await.unwrap());
+        assert_eq!(rx.recv().await, Some(inserted("b")));
+        assert_eq!(dropped.count(), 0);
+    }
+
+    #[tokio::test]
+    async fn try_send_drops_instead_of_blocking() {
+        let (tx, mut rx, dropped) = channel(1, OverflowPolicy::Block);
+        assert!(tx.try_send(inserted("a")));
+        assert!(!tx.try_send(inserted("b")));
+        assert_eq!(dropped.count(), 1);
+        assert_eq!(rx.recv().await, Some(inserted("a")));
+        assert!(tx.is_empty());
+    }
+
+    #[tokio::test]
+    async fn block_releases_senders_when_the_receiver_is_dropped() {
+        let (tx, rx, _) = channel(1, OverflowPolicy::Block);
+        assert!(tx.send(inserted("a")).await);
+        let blocked = tokio::spawn({
+            let tx = tx.clone();
+            async move { tx.send(inserted("b")).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        drop(rx);
+        let sent = tokio::time::timeout(Duration::from_secs(1), blocked)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!sent);
+    }
+
+    #[tokio::test]
+    async fn coalesce_skips_buffered_duplicates() {
+        let (tx, mut rx, dropped) = channel(2, OverflowPolicy::Coalesce);
+        assert!(tx.send(inserted("a")).await);
+        assert!(tx.send(inserted("b")).await);
+        // still buffered, the duplicate is dropped
+        assert!(tx.send(inserted("a")).await);
+        assert_eq!(dropped.count(), 1);
+        assert_eq!(tx.len(), 2);
+        // a new event makes room by dropping the oldest one
+        assert!(tx.send(inserted("c")).await);
+        assert_eq!(dropped.count(), 2);
+        assert_eq!(drain(&mut rx, 2).await, vec![inserted("b"), inserted("c")]);
+    }
+
+    #[tokio::test]
+    async fn drop_newest_keeps_the_buffer() {
+        let (tx, mut rx, dropped) = channel(2, OverflowPolicy::DropNewest);
+        assert!(tx.send(inserted("a")).await);
+        assert!(tx.send(inserted("b")).await);
+        assert!(!tx.send(inserted("c")).await);
+        assert_eq!(dropped.count(), 1);
+        assert_eq!(drain(&mut rx, 2).await, vec![inserted("a"), inserted("b")]);
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_makes_room() {
+        let (tx, mut rx, dropped) = channel(2, OverflowPolicy::DropOldest);
+        assert!(tx.send(inserted("a")).await);
+        assert!(tx.send(inserted("b")).await);
+        assert!(tx.send(inserted("c")).await);
+        assert_eq!(dropped.count(), 1);
+        assert_eq!(drain(&mut rx, 2).await, vec![inserted("b"), inserted("c")]);
+    }
+
+    #[tokio::test]
+    async fn receiver_ends_after_the_last_sender() {
+        let (tx, mut rx, _) = channel(4, OverflowPolicy::Block);
+        let waiting = tokio::spawn(async move {
+            let first = rx.recv().await;
+            (first, rx.recv().await)
+        });
+        // the receiver is woken by the permit even if it is not waiting yet
+        assert!(tx.send(inserted("a")).await);
+        drop(tx);
+        let (first, last) = waiting.await.unwrap();
+        assert_eq!(first, Some(inserted("a")));
+        assert_eq!(last, None);
+    }
+}
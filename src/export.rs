@@ -0,0 +1,145 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::store::{IrohCls, ToBytes};
+
+/// A whole table as written by [`export_table`]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TableDump<Entity> {
+    pub table: String,
+    // namespace the entries were exported from
+    pub namespace: String,
+    pub entries: Vec<DumpEntry<Entity>>,
+}
+
+/// A single entry of a [`TableDump`]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DumpEntry<Entity> {
+    pub key: String,
+    // original author and write time, informational only
+    pub author: String,
+    pub timestamp: u64,
+    pub entity: Entity,
+}
+
+/// Write all entities of a table to a JSON file
+///
+/// Small resource content is embedded as base64, larger content is referenced by hash.
+/// Returns the number of exported entities
+pub async fn export_table<Entity>(table: &IrohCls<Entity>, name: &str, path: &Path) -> Result<usize>
+where
+    Entity: ToBytes<Entity> + Serialize + Clone + for<'a> Deserialize<'a> + Send,
+{
+    let mut entries = Vec::new();
+    for entry in table
+        .entries(iroh_docs::store::Query::single_latest_per_key().key_prefix(&table.prefix))
+        .await?
+    {
+        // deleted and trashed entries are not exported
+        let Some(entity) = table.entity(&entry).await? else {
+            continue;
+        };
+        entries.push(DumpEntry {
+            key: String::from_utf8_lossy(table.table_key(entry.key())).to_string(),
+            author: entry.author().to_string(),
+            timestamp: entry.timestamp(),
+            entity,
+        });
+    }
+    let count = entries.len();
+    let dump = TableDump {
+        table: name.to_string(),
+        namespace: table.doc.id().to_string(),
+        entries,
+    };
+    let json = serde_json::to_vec_pretty(&dump)?;
+    tokio::fs::write(path, json)
+        .await
+        .with_context(|| format!("Failed to write {:?}", path))?;
+    Ok(count)
+}
+
+/// Write the entities of a JSON file created by [`export_table`] into a table
+///
+/// Entries are written with this node's author. Content referenced by hash must be
+/// available in the blob store or external storage to be readable.
+/// Returns the number of imported entities
+pub async fn import_table<Entity>(table: &IrohCls<Entity>, path: &Path) -> Result<usize>
+where
+    Entity: ToBytes<Entity> + Serialize + Clone + for<'a> Deserialize<'a> + Send,
+{
+    let json = tokio::fs::read(path)
+        .await
+        .with_context(|| format!("Failed to read {:?}", path))?;
+    let dump: TableDump<Entity> =
+        serde_json::from_slice(&json).with_context(|| format!("Invalid table dump {:?}", path))?;
+    info!(
+        entries = dump.entries.len(),
+        table = %dump.table,
+        namespace = %dump.namespace,
+        "importing table dump"
+    );
+    let count = dump.entries.len();
+    for entry in dump.entries {
+        table.insert(entry.key.as_bytes(), &entry.entity).await?;
+    }
+    Ok(count)
+}
+
+/// Serialize bytes as base64 in human readable formats and as plain bytes otherwise
+///
+/// Binary formats are unaffected, so existing records keep decoding
+pub mod base64_bytes {
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    use serde::{
+        Deserialize, Deserializer, Serializer,
+        de::{SeqAccess, Visitor},
+    };
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&STANDARD.encode(bytes))
+        } else {
+            serializer.serialize_bytes(bytes)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        if deserializer.is_human_readable() {
+            let s = String::deserialize(deserializer)?;
+            STANDARD.decode(s).map_err(serde::de::Error::custom)
+        } else {
+            deserializer.deserialize_byte_buf(BytesVisitor)
+        }
+    }
+
+    // binary formats hand out either a byte string or a sequence of bytes
+    struct BytesVisitor;
+
+    impl<'de> Visitor<'de> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("bytes")
+        }
+
+        fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+            Ok(v.to_vec())
+        }
+
+        fn visit_byte_buf<E: serde::de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+            Ok(v)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(b) = seq.next_element()? {
+                bytes.push(b);
+            }
+            Ok(bytes)
+        }
+    }
+}
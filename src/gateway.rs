@@ -0,0 +1,451 @@
+use std::{collections::HashMap, net::SocketAddr};
+
+use anyhow::{Context, Result, ensure};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+use tracing::{Instrument, debug, info, info_span, warn};
+
+use crate::model::resource::{Resource, Resources};
+use crate::store::IrohProperties;
+
+// address `serve-files` listens on when none is given
+pub const DEFAULT_GATEWAY_ADDR: &str = "127.0.0.1:8080";
+// largest request line and headers accepted
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+// content read from the store per write to the socket
+const STREAM_PIECE: u64 = 1024 * 1024;
+
+/// Part of the content asked for by a `Range` header
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ByteRange {
+    // no usable range, the whole content is sent
+    Full,
+    // first and last byte, both included
+    Partial(u64, u64),
+    // the range lies outside the content
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    /// Parse a `Range` header for content of `size` bytes
+    ///
+    /// Only single `bytes` ranges are supported, other ranges are ignored as the RFC allows
+    pub fn parse(header: Option<&str>, size: u64) -> Self {
+        let Some(spec) = header.and_then(|h| h.trim().strip_prefix("bytes=")) else {
+            return ByteRange::Full;
+        };
+        if spec.contains(',') {
+            return ByteRange::Full;
+        }
+        let Some((start, end)) = spec.trim().split_once('-') else {
+            return ByteRange::Full;
+        };
+        let range = match (start.parse::<u64>(), end.parse::<u64>()) {
+            // the last `end` bytes
+            (Err(_), Ok(suffix)) if start.is_empty() => {
+                if suffix == 0 || size == 0 {
+                    return ByteRange::Unsatisfiable;
+                }
+                (size.saturating_sub(suffix), size - 1)
+            }
+            (Ok(start), Err(_)) if end.is_empty() => (start, size.saturating_sub(1)),
+            (Ok(start), Ok(end)) if start <= end => (start, end.min(size.saturating_sub(1))),
+            _ => return ByteRange::Full,
+        };
+        if range.0 >= size {
+            return ByteRange::Unsatisfiable;
+        }
+        ByteRange::Partial(range.0, range.1)
+    }
+}
+
+/// A parsed request head
+#[derive(Debug)]
+pub(crate) struct Request {
+    pub method: String,
+    // percent-encoded, without the query string
+    pub path: String,
+    // header names in lower case
+    pub headers: HashMap<String, String>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(String::as_str)
+    }
+}
+
+/// Serve the resources of a table over HTTP on `addr` until the returned task is aborted
+///
+/// `GET /` lists the resources and `GET /<id>` sends the content of one. `Range` requests
+/// are answered with only the asked part, read piece by piece from the blob store, so
+/// browsers and video players can seek in large media without downloading all of it.
+/// Returns the address actually bound, e.g. when `addr` asks for any free port
+pub async fn serve_files(
+    resources: Resources,
+    addr: SocketAddr,
+) -> Result<(SocketAddr, JoinHandle<()>)> {
+    listen(addr, "files", resources, handle_connection).await
+}
+
+/// Accept connections on `addr` and answer each with `handle` in its own task
+pub(crate) async fn listen<S, F, Fut>(
+    addr: SocketAddr,
+    service: &'static str,
+    state: S,
+    handle: F,
+) -> Result<(SocketAddr, JoinHandle<()>)>
+where
+    S: Clone + Send + Sync + 'static,
+    F: Fn(S, TcpStream) -> Fut + Copy + Send + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to listen on {}", addr))?;
+    let addr = listener.local_addr()?;
+    info!(%addr, service, "listening");
+    let span = info_span!("gateway", %addr, service);
+    let task = tokio::spawn(
+        async move {
+            loop {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!(error = %e, "failed to accept connection");
+                        continue;
+                    }
+                };
+                let state = state.clone();
+                tokio::spawn(
+                    async move {
+                        if let Err(e) = handle(state, stream).await {
+                            debug!(error = %e, "connection failed");
+                        }
+                    }
+                    .instrument(info_span!("request", %peer)),
+                );
+            }
+        }
+        .instrument(span),
+    );
+    Ok((addr, task))
+}
+
+// answer a single request, the connection is closed afterwards
+async fn handle_connection(resources: Resources, stream: TcpStream) -> Result<()> {
+    let mut stream = BufReader::new(stream);
+    let request = read_request(&mut stream).await?;
+    debug!(method = %request.method, path = %request.path, "request");
+    let stream = stream.get_mut();
+    let head = request.method == "HEAD";
+    if request.method != "GET" && !head {
+        return write_response(
+            stream,
+            "405 Method Not Allowed",
+            &[("Allow", "GET, HEAD")],
+            b"",
+        )
+        .await;
+    }
+    let id = request.path.trim_start_matches('/');
+    if id.is_empty() {
+        let listing = listing(&resources).await?;
+        let body: &[u8] = if head { b"" } else { listing.as_bytes() };
+        let length = listing.len().to_string();
+        let headers = [
+            ("Content-Type", "text/html; charset=utf-8"),
+            ("Content-Length", length.as_str()),
+        ];
+        return write_response(stream, "200 OK", &headers, body).await;
+    }
+    let Some(resource) = resources.get(id).await? else {
+        return write_response(stream, "404 Not Found", &[], b"").await;
+    };
+    send_content(&resources, stream, &resource, request.header("range"), head).await
+}
+
+pub(crate) async fn read_request(stream: &mut BufReader<TcpStream>) -> Result<Request> {
+    let mut line = String::new();
+    stream.read_line(&mut line).await?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        anyhow::bail!("Malformed request line: {:?}", line.trim());
+    };
+    let method = method.to_string();
+    // the query string is not used
+    let path = target.split('?').next().unwrap_or_default().to_string();
+    let mut size = line.len();
+    let mut headers = HashMap::new();
+    loop {
+        line.clear();
+        let read = stream.read_line(&mut line).await?;
+        size += read;
+        ensure!(size <= MAX_HEAD_SIZE, "Request head is too large");
+        if read == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+    Ok(Request {
+        method,
+        path,
+        headers,
+    })
+}
+
+/// Read the body of `request`, sent with a length or in chunks, failing above `max` bytes
+pub(crate) async fn read_body(
+    stream: &mut BufReader<TcpStream>,
+    request: &Request,
+    max: u64,
+) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    copy_body(stream, request, max, &mut body).await?;
+    Ok(body)
+}
+
+/// Write the body of `request` to `writer` as it arrives, failing above `max` bytes
+///
+/// Nothing is allocated up front from the announced length, so large uploads can go to a file.
+/// Returns the number of bytes written
+pub(crate) async fn copy_body(
+    stream: &mut BufReader<TcpStream>,
+    request: &Request,
+    max: u64,
+    writer: &mut (impl AsyncWrite + Unpin),
+) -> Result<u64> {
+    // clients waiting for a go-ahead before sending large bodies
+    if request
+        .header("expect")
+        .is_some_and(|e| e.eq_ignore_ascii_case("100-continue"))
+    {
+        stream
+            .get_mut()
+            .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+            .await?;
+    }
+    let mut written = 0;
+    if request
+        .header("transfer-encoding")
+        .is_some_and(|e| e.eq_ignore_ascii_case("chunked"))
+    {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            stream.read_line(&mut line).await?;
+            // chunk extensions after `;` are ignored
+            let size = line.split(';').next().unwrap_or_default().trim();
+            let size = u64::from_str_radix(size, 16)
+                .with_context(|| format!("Malformed chunk size: {:?}", size))?;
+            if size == 0 {
+                // skip the trailers up to the final empty line
+                loop {
+                    line.clear();
+                    if stream.read_line(&mut line).await? == 0 || line.trim().is_empty() {
+                        break;
+                    }
+                }
+                writer.flush().await?;
+                return Ok(written);
+            }
+            ensure!(
+                written + size <= max,
+                "Body exceeds the limit of {} bytes",
+                max
+            );
+            let copied = tokio::io::copy(&mut (&mut *stream).take(size), writer).await?;
+            ensure!(copied == size, "Body ended early");
+            written += copied;
+            line.clear();
+            stream.read_line(&mut line).await?;
+        }
+    }
+    let length: u64 = match request.header("content-length") {
+        Some(length) => length.parse().context("Malformed Content-Length")?,
+        None => 0,
+    };
+    ensure!(length <= max, "Body exceeds the limit of {} bytes", max);
+    written = tokio::io::copy(&mut (&mut *stream).take(length), writer).await?;
+    ensure!(written == length, "Body ended early");
+    writer.flush().await?;
+    Ok(written)
+}
+
+/// Send the content of `resource`, or the part asked for by `range`
+pub(crate) async fn send_content(
+    resources: &Resources,
+    stream: &mut TcpStream,
+    resource: &Resource,
+    range: Option<&str>,
+    head: bool,
+) -> Result<()> {
+    let size = resource.size;
+    let (partial, start, end) = match ByteRange::parse(range, size) {
+        ByteRange::Full => (false, 0, size),
+        ByteRange::Partial(start, last) => (true, start, last + 1),
+        ByteRange::Unsatisfiable => {
+            let content_range = format!("bytes */{}", size);
+            let headers = [("Content-Range", content_range.as_str())];
+            return write_response(stream, "416 Range Not Satisfiable", &headers, b"").await;
+        }
+    };
+    let content_type = resource
+        .content_type
+        .as_deref()
+        .unwrap_or("application/octet-stream");
+    let length = (end - start).to_string();
+    let etag = format!("\"{}\"", resource.content_hash());
+    let content_range = format!("bytes {}-{}/{}", start, end.saturating_sub(1), size);
+    let mut headers = vec![
+        ("Content-Type", content_type),
+        ("Content-Length", length.as_str()),
+        ("Accept-Ranges", "bytes"),
+        ("ETag", etag.as_str()),
+    ];
+    let status = if partial {
+        headers.push(("Content-Range", content_range.as_str()));
+        "206 Partial Content"
+    } else {
+        "200 OK"
+    };
+    write_response(stream, status, &headers, b"").await?;
+    if head {
+        return Ok(());
+    }
+
+    // encrypted content can't be read in parts, it is decrypted once for the whole response
+    let decrypted = match resources.node.cipher {
+        Some(_) => Some(resources.load_content(resource).await?),
+        None => None,
+    };
+    let mut position = start;
+    while position < end {
+        let len = STREAM_PIECE.min(end - position);
+        let piece = match &decrypted {
+            Some(content) => {
+                let from = (position as usize).min(content.len());
+                let to = ((position + len) as usize).min(content.len());
+                content.slice(from..to)
+            }
+            None => resources.read_range(resource, position, len).await?,
+        };
+        ensure!(!piece.is_empty(), "Content of {} ended early", resource.id);
+        stream.write_all(&piece).await?;
+        position += piece.len() as u64;
+    }
+    stream.flush().await?;
+    Ok(())
+}
+
+pub(crate) async fn write_response(
+    stream: &mut TcpStream,
+    status: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Result<()> {
+    let mut head = format!("HTTP/1.1 {}\r\nConnection: close\r\n", status);
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if !headers.iter().any(|(name, _)| *name == "Content-Length") {
+        head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+// a page linking every resource of the table
+async fn listing(resources: &Resources) -> Result<String> {
+    let mut page = String::from("<!DOCTYPE html>\n<html><body><ul>\n");
+    for resource in resources.search().await? {
+        page.push_str(&format!(
+            "<li><a href=\"/{}\">{}</a> {} bytes</li>\n",
+            resource.id,
+            escape_html(&resource.name),
+            resource.size
+        ));
+    }
+    page.push_str("</ul></body></html>\n");
+    Ok(page)
+}
+
+/// Decode the `%XX` escapes of a path segment, invalid escapes are kept as they are
+pub(crate) fn percent_decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(byte) = segment
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            decoded.push(byte);
+            i += 3;
+            continue;
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Escape everything but unreserved characters of a path segment
+pub(crate) fn percent_encode(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Format milliseconds since the unix epoch as an HTTP date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+pub(crate) fn http_date(millis: u64) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let secs = millis / 1000;
+    let days = secs / 86400;
+    let (hour, minute, second) = (secs % 86400 / 3600, secs % 3600 / 60, secs % 60);
+    // civil date from days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAYS[(days % 7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        hour,
+        minute,
+        second
+    )
+}
+
+pub(crate) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
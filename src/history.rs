@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use futures::StreamExt;
+use iroh_blobs::Hash;
+use iroh_docs::{AuthorId, Entry, api::Doc, store::Query};
+
+// writes of different authors closer in time than this count as concurrent
+pub const CONFLICT_WINDOW: Duration = Duration::from_secs(30);
+
+/// One write of a key, by any author
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub author: AuthorId,
+    // time of the write in microseconds since the unix epoch
+    pub timestamp: u64,
+    pub hash: Hash,
+    // 0 for deletions
+    pub size: u64,
+}
+
+impl From<&Entry> for HistoryEntry {
+    fn from(entry: &Entry) -> Self {
+        HistoryEntry {
+            author: entry.author(),
+            timestamp: entry.timestamp(),
+            hash: entry.content_hash(),
+            size: entry.content_len(),
+        }
+    }
+}
+
+/// All entries stored for `key`, newest first
+///
+/// Docs keep the latest entry of every author, so concurrent writes of
+/// different authors show up here even though listings only return the newest
+pub async fn history(doc: &Doc, key: impl AsRef<[u8]>) -> Result<Vec<HistoryEntry>> {
+    let query = Query::all().key_exact(key.as_ref()).build();
+    let entries = doc.get_many(query).await?;
+    let entries = entries.collect::<Vec<Result<Entry>>>().await;
+    let mut history: Vec<HistoryEntry> = entries
+        .iter()
+        .filter_map(|e| e.as_ref().ok())
+        .map(HistoryEntry::from)
+        .collect();
+    history.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+    Ok(history)
+}
+
+/// Versions of other authors that `entry` was written concurrently with
+///
+/// Entries hold no causal information, so writes count as concurrent when they are at most
+/// `window` apart. A version of `unsynced` counts regardless of time, it is a local write
+/// that has not reached any peer, so the writer of `entry` cannot have seen it
+pub async fn concurrent_writes(
+    doc: &Doc,
+    entry: &Entry,
+    window: Duration,
+    unsynced: Option<AuthorId>,
+) -> Result<Vec<HistoryEntry>> {
+    let window = window.as_micros() as u64;
+    Ok(history(doc, entry.key())
+        .await?
+        .into_iter()
+        .filter(|version| {
+            version.author != entry.author()
+                && version.size > 0
+                && version.hash != entry.content_hash()
+                && (version.timestamp.abs_diff(entry.timestamp()) <= window
+                    || Some(version.author) == unsynced)
+        })
+        .collect())
+}
+
+/// Versions of `key` that lost against the latest one, newest first
+///
+/// Docs keep one version per author, so these are the latest writes of the other authors
+pub async fn losing_versions(doc: &Doc, key: impl AsRef<[u8]>) -> Result<Vec<HistoryEntry>> {
+    let mut versions = history(doc, key).await?;
+    if versions.is_empty() {
+        return Ok(versions);
+    }
+    versions.remove(0);
+    versions.retain(|version| version.size > 0);
+    Ok(versions)
+}
+
+/// Write an earlier version of `key` again so it becomes the latest
+pub async fn restore_version(
+    doc: &Doc,
+    author: AuthorId,
+    key: impl AsRef<[u8]>,
+    version: &HistoryEntry,
+) -> Result<()> {
+    doc.set_hash(author, key.as_ref().to_vec(), version.hash, version.size)
+        .await?;
+    Ok(())
+}
+
+/// Restore the newest version of another author whose content differs from the current one
+///
+/// Docs keep only the latest entry per author and key, so an earlier write of the author of
+/// the current version is gone and can't be restored. Undo only goes back to the versions of
+/// other authors, the ones [`losing_versions`] lists.
+///
+/// Returns the restored version, or `None` when there is nothing to go back to
+pub async fn undo(
+    doc: &Doc,
+    author: AuthorId,
+    key: impl AsRef<[u8]>,
+) -> Result<Option<HistoryEntry>> {
+    let history = history(doc, key.as_ref()).await?;
+    let Some(current) = history.first() else {
+        return Ok(None);
+    };
+    let Some(previous) = history
+        .iter()
+        .find(|e| e.author != current.author && e.hash != current.hash && e.size > 0)
+        .cloned()
+    else {
+        return Ok(None);
+    };
+    restore_version(doc, author, key, &previous).await?;
+    Ok(Some(previous))
+}
@@ -0,0 +1,136 @@
+use std::io::{BufRead, BufReader, Cursor, Seek};
+use std::path::Path;
+
+use exif::{DateTime, Exif, In, Reader, Tag, Value};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+/// Dimensions and EXIF details of an image resource
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ImageMeta {
+    // size in pixels, 0 when the format is not recognized
+    pub width: u32,
+    pub height: u32,
+    // capture time in milliseconds since the unix epoch, times without an offset are taken as UTC
+    pub captured_at: Option<u64>,
+    // camera make and model
+    pub camera: Option<String>,
+}
+
+/// Whether content of `content_type` is worth looking at for image metadata
+pub fn is_image(content_type: Option<&str>) -> bool {
+    content_type.is_some_and(|t| t.starts_with("image/"))
+}
+
+/// Read the metadata of an image held in memory, `None` if it is not an image
+pub fn extract(content: &[u8]) -> Option<ImageMeta> {
+    let size = imagesize::blob_size(content).ok();
+    let exif = read_exif(&mut Cursor::new(content));
+    build(size, exif)
+}
+
+/// Read the metadata of an image file, only the headers are read
+pub fn extract_path(path: &Path) -> Option<ImageMeta> {
+    let size = imagesize::size(path).ok();
+    let exif = std::fs::File::open(path)
+        .ok()
+        .and_then(|file| read_exif(&mut BufReader::new(file)));
+    build(size, exif)
+}
+
+fn read_exif(reader: &mut (impl BufRead + Seek)) -> Option<Exif> {
+    match Reader::new().read_from_container(reader) {
+        Ok(exif) => Some(exif),
+        Err(e) => {
+            debug!(error = %e, "no exif data");
+            None
+        }
+    }
+}
+
+fn build(size: Option<imagesize::ImageSize>, exif: Option<Exif>) -> Option<ImageMeta> {
+    if size.is_none() && exif.is_none() {
+        return None;
+    }
+    let mut meta = ImageMeta::default();
+    if let Some(size) = size {
+        meta.width = size.width as u32;
+        meta.height = size.height as u32;
+    }
+    if let Some(exif) = exif {
+        if meta.width == 0 {
+            meta.width = uint_field(&exif, Tag::PixelXDimension).unwrap_or_default();
+            meta.height = uint_field(&exif, Tag::PixelYDimension).unwrap_or_default();
+        }
+        meta.captured_at = captured_at(&exif);
+        let make = ascii_field(&exif, Tag::Make);
+        let model = ascii_field(&exif, Tag::Model);
+        meta.camera = match (make, model) {
+            // models usually repeat the make, e.g. "Canon" and "Canon EOS 5D"
+            (Some(make), Some(model)) if model.starts_with(&make) => Some(model),
+            (Some(make), Some(model)) => Some(format!("{} {}", make, model)),
+            (make, model) => make.or(model),
+        };
+    }
+    Some(meta)
+}
+
+fn ascii_field(exif: &Exif, tag: Tag) -> Option<String> {
+    let field = exif.get_field(tag, In::PRIMARY)?;
+    let Value::Ascii(values) = &field.value else {
+        return None;
+    };
+    let text = String::from_utf8_lossy(values.first()?);
+    let text = text.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+fn uint_field(exif: &Exif, tag: Tag) -> Option<u32> {
+    exif.get_field(tag, In::PRIMARY)?.value.get_uint(0)
+}
+
+// the time the picture was taken, the file time of the camera as a fallback
+fn captured_at(exif: &Exif) -> Option<u64> {
+    let (time, offset) = [
+        (Tag::DateTimeOriginal, Tag::OffsetTimeOriginal),
+        (Tag::DateTimeDigitized, Tag::OffsetTimeDigitized),
+        (Tag::DateTime, Tag::OffsetTime),
+    ]
+    .into_iter()
+    .find_map(|(time, offset)| {
+        exif.get_field(time, In::PRIMARY)
+            .map(|field| (field, exif.get_field(offset, In::PRIMARY)))
+    })?;
+    let Value::Ascii(values) = &time.value else {
+        return None;
+    };
+    let mut time = DateTime::from_ascii(values.first()?).ok()?;
+    if let Some(Value::Ascii(values)) = offset.map(|field| &field.value)
+        && let Some(offset) = values.first()
+    {
+        // a malformed offset leaves the time as UTC
+        let _ = time.parse_offset(offset);
+    }
+    unix_millis(&time)
+}
+
+fn unix_millis(time: &DateTime) -> Option<u64> {
+    if !(1..=12).contains(&time.month) || !(1..=31).contains(&time.day) {
+        return None;
+    }
+    // days since 1970-01-01 of a civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let (month, day) = (i64::from(time.month), i64::from(time.day));
+    let year = i64::from(time.year) - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    let secs = days * 86400
+        + i64::from(time.hour) * 3600
+        + i64::from(time.minute) * 60
+        + i64::from(time.second)
+        - i64::from(time.offset.unwrap_or(0)) * 60;
+    let millis = secs * 1000 + i64::from(time.nanosecond.unwrap_or(0) / 1_000_000);
+    u64::try_from(millis).ok()
+}
@@ -0,0 +1,486 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{Context, Result};
+use futures::{StreamExt, TryStreamExt};
+use tokio::io::AsyncRead;
+use tokio::sync::mpsc;
+use tokio_util::io::StreamReader;
+use tracing::debug;
+use url::Url;
+
+use crate::model::resource::{ContentIndex, ContentKey, Resources};
+
+// number of items buffered between an importer and the store
+const IMPORT_QUEUE_CAPACITY: usize = 16;
+
+// files written to the store in one batch
+const IMPORT_BATCH_SIZE: usize = 32;
+
+// content size after which a batch is written early
+const IMPORT_BATCH_BYTES: usize = 64 * 1024 * 1024;
+
+// bytes of a tar entry in flight between the archive and the store
+const TAR_PIPE_CAPACITY: usize = 64 * 1024;
+
+// a file waiting in the current batch
+struct PendingFile {
+    key: ContentKey,
+    name: String,
+    content_type: Option<String>,
+    content: Vec<u8>,
+}
+
+pub type ImportReader = Pin<Box<dyn AsyncRead + Send>>;
+
+// a single file produced by an importer
+pub struct ImportItem {
+    // file name stored in the resource
+    pub name: String,
+    // mime type, if the source knows it
+    pub content_type: Option<String>,
+    // file content
+    pub reader: ImportReader,
+    // file on disk holding the content, lets large files be streamed into the store
+    pub path: Option<PathBuf>,
+}
+
+impl ImportItem {
+    pub fn new(name: String, content_type: Option<String>, reader: ImportReader) -> Self {
+        let content_type = content_type.or_else(|| guess_content_type(&name));
+        ImportItem {
+            name,
+            content_type,
+            reader,
+            path: None,
+        }
+    }
+
+    /// Item for a file on disk, named like the file
+    pub async fn from_path(path: &Path) -> Result<Self> {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let file = tokio::fs::File::open(path)
+            .await
+            .with_context(|| format!("Failed to read file: {:?}", path))?;
+        let mut item = ImportItem::new(name, None, Box::pin(file));
+        item.path = Some(path.to_path_buf());
+        Ok(item)
+    }
+}
+
+/// A source of files that can be imported into [`Resources`]
+pub trait Importer {
+    /// Produce items into `tx` until the source is exhausted
+    ///
+    /// Importers should stop early once `tx` is closed
+    fn run(
+        self,
+        tx: mpsc::Sender<Result<ImportItem>>,
+    ) -> impl std::future::Future<Output = Result<()>> + Send;
+}
+
+/// Guess the mime type from a file name
+pub fn guess_content_type(name: &str) -> Option<String> {
+    mime_guess::from_path(name).first().map(|m| m.to_string())
+}
+
+/// Totals of an import so far, reported by [`import_into_with_progress`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ImportProgress {
+    // files stored so far
+    pub files: usize,
+    // content bytes stored so far
+    pub bytes: u64,
+}
+
+/// Run an importer and add every produced item to `resources`
+///
+/// Files are written in batches, files whose name and content are already stored are skipped.
+/// Items without a file on disk are first streamed to a temporary file. Large files are
+/// streamed into the store one by one instead of being buffered. Returns the number of
+/// imported files
+pub async fn import_into<I>(resources: &Resources, importer: I) -> Result<usize>
+where
+    I: Importer + Send + 'static,
+{
+    import_into_with_progress(resources, importer, |_| {}).await
+}
+
+/// Run an importer like [`import_into`], calling `progress` as files are stored
+pub async fn import_into_with_progress<I>(
+    resources: &Resources,
+    importer: I,
+    progress: impl Fn(ImportProgress) + Send + Sync,
+) -> Result<usize>
+where
+    I: Importer + Send + 'static,
+{
+    let (tx, mut rx) = mpsc::channel(IMPORT_QUEUE_CAPACITY);
+    let producer = tokio::spawn(importer.run(tx));
+
+    let mut index = resources.content_index().await?;
+    let mut batch: Vec<PendingFile> = Vec::new();
+    let mut batch_bytes = 0;
+    let mut count = 0;
+    let mut done = ImportProgress::default();
+    while let Some(item) = rx.recv().await {
+        let mut item = item?;
+        // content without a file on disk is spooled to one as it is read, so no item is
+        // held in memory before its size is known
+        let (path, _spooled) = match item.path.take() {
+            Some(path) => (path, None),
+            None => {
+                let temp = resources
+                    .spool(&mut item.reader)
+                    .await
+                    .with_context(|| format!("Failed to read {}", item.name))?;
+                (temp.path.clone(), Some(temp))
+            }
+        };
+        let size = tokio::fs::metadata(&path).await?.len();
+        if resources.streams_file(size) {
+            count += stream_file(
+                resources, &mut index, item, &path, size, &mut done, &progress,
+            )
+            .await?;
+            continue;
+        }
+        let content = tokio::fs::read(&path)
+            .await
+            .with_context(|| format!("Failed to read {}", item.name))?;
+
+        let key = resources.content_key(&item.name, &content)?;
+        if index.contains_key(&key) || batch.iter().any(|f| f.key == key) {
+            debug!(name = %item.name, "skipping duplicate file");
+            continue;
+        }
+        batch_bytes += content.len();
+        batch.push(PendingFile {
+            key,
+            name: item.name,
+            content_type: item.content_type,
+            content,
+        });
+        if batch.len() >= IMPORT_BATCH_SIZE || batch_bytes >= IMPORT_BATCH_BYTES {
+            count += write_batch(
+                resources,
+                &mut index,
+                std::mem::take(&mut batch),
+                &mut done,
+                &progress,
+            )
+            .await?;
+            batch_bytes = 0;
+        }
+    }
+    count += write_batch(resources, &mut index, batch, &mut done, &progress).await?;
+
+    producer.await??;
+    Ok(count)
+}
+
+// add a single file without reading it into memory and record it in `index`
+async fn stream_file(
+    resources: &Resources,
+    index: &mut ContentIndex,
+    item: ImportItem,
+    path: &Path,
+    size: u64,
+    done: &mut ImportProgress,
+    progress: &(dyn Fn(ImportProgress) + Send + Sync),
+) -> Result<usize> {
+    let key = resources.path_content_key(&item.name, path).await?;
+    if index.contains_key(&key) {
+        debug!(name = %item.name, "skipping duplicate file");
+        return Ok(0);
+    }
+    debug!(
+        name = %item.name,
+        content_type = item.content_type.as_deref().unwrap_or("unknown"),
+        "streaming file"
+    );
+    let before = *done;
+    let id = resources
+        .add_path_with_progress(path, item.name, item.content_type, |upload| {
+            progress(ImportProgress {
+                files: before.files,
+                bytes: before.bytes + upload.written,
+            });
+        })
+        .await
+        .with_context(|| format!("Failed to add {:?} to resources", path))?;
+    done.files += 1;
+    done.bytes += size;
+    progress(*done);
+    index.insert(key, id);
+    Ok(1)
+}
+
+// add a batch of files and record them in `index`
+async fn write_batch(
+    resources: &Resources,
+    index: &mut ContentIndex,
+    batch: Vec<PendingFile>,
+    done: &mut ImportProgress,
+    progress: &(dyn Fn(ImportProgress) + Send + Sync),
+) -> Result<usize> {
+    if batch.is_empty() {
+        return Ok(0);
+    }
+    let batch_bytes = batch.iter().map(|f| f.content.len() as u64).sum::<u64>();
+    let mut keys = Vec::with_capacity(batch.len());
+    let mut files = Vec::with_capacity(batch.len());
+    for file in batch {
+        debug!(
+            name = %file.name,
+            content_type = file.content_type.as_deref().unwrap_or("unknown"),
+            size = file.content.len(),
+            "adding file"
+        );
+        keys.push(file.key);
+        files.push((file.name, file.content_type, file.content));
+    }
+    let before = *done;
+    let stored = AtomicUsize::new(0);
+    let ids = resources
+        .add_files_with_progress(files, |upload| {
+            let files = stored.fetch_add(1, Ordering::SeqCst) + 1;
+            progress(ImportProgress {
+                files: before.files + files,
+                bytes: before.bytes + upload.written,
+            });
+        })
+        .await
+        .context("Failed to add files to resources")?;
+    let count = ids.len();
+    done.files += count;
+    done.bytes += batch_bytes;
+    index.extend(keys.into_iter().zip(ids));
+    Ok(count)
+}
+
+/// Imports every regular, non-hidden file of a local directory
+pub struct DirImporter {
+    pub path: PathBuf,
+}
+
+impl DirImporter {
+    /// Total size of the files the importer would produce
+    pub fn total_size(&self) -> u64 {
+        let Ok(entries) = fs::read_dir(&self.path) else {
+            return 0;
+        };
+        entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+            .filter_map(|entry| entry.metadata().ok())
+            .filter(|meta| meta.is_file())
+            .map(|meta| meta.len())
+            .sum()
+    }
+}
+
+impl Importer for DirImporter {
+    async fn run(self, tx: mpsc::Sender<Result<ImportItem>>) -> Result<()> {
+        if !self.path.exists() {
+            return Err(anyhow::anyhow!(
+                "Images directory does not exist: {:?}",
+                self.path
+            ));
+        }
+
+        let entries = fs::read_dir(&self.path)
+            .with_context(|| format!("Failed to read directory: {:?}", self.path))?;
+
+        for entry in entries {
+            let path = entry?.path();
+
+            // Skip directories and hidden files (like .DS_Store)
+            let Some(file_name) = path.file_name() else {
+                continue;
+            };
+            let file_name = file_name.to_string_lossy().to_string();
+            if !path.is_file() || file_name.starts_with('.') {
+                continue;
+            }
+
+            let item = ImportItem::from_path(&path).await?;
+            if tx.send(Ok(item)).await.is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Imports a single file, or the non-hidden files of a directory and, if `recursive`, its subdirectories
+pub struct PathImporter {
+    pub path: PathBuf,
+    pub recursive: bool,
+}
+
+impl PathImporter {
+    /// Total size of the files the importer would produce
+    pub fn total_size(&self) -> u64 {
+        collect_files(&self.path, self.recursive)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|path| path.metadata().ok())
+            .map(|meta| meta.len())
+            .sum()
+    }
+}
+
+impl Importer for PathImporter {
+    async fn run(self, tx: mpsc::Sender<Result<ImportItem>>) -> Result<()> {
+        for path in collect_files(&self.path, self.recursive)? {
+            let item = ImportItem::from_path(&path).await?;
+            if tx.send(Ok(item)).await.is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Files below `path` in path order, hidden files and directories are skipped
+///
+/// A `path` pointing to a file yields just that file
+pub fn collect_files(path: &Path, recursive: bool) -> Result<Vec<PathBuf>> {
+    if !path.exists() {
+        return Err(anyhow::anyhow!("Path does not exist: {:?}", path));
+    }
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = Vec::new();
+    let mut dirs = vec![path.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries =
+            fs::read_dir(&dir).with_context(|| format!("Failed to read directory: {:?}", dir))?;
+        for entry in entries {
+            let path = entry?.path();
+            let hidden = path
+                .file_name()
+                .is_none_or(|n| n.to_string_lossy().starts_with('.'));
+            if hidden {
+                continue;
+            }
+            if path.is_dir() {
+                if recursive {
+                    dirs.push(path);
+                }
+            } else if path.is_file() {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Imports the whole stdin stream as a single file
+pub struct StdinImporter {
+    pub name: String,
+}
+
+impl StdinImporter {
+    /// The single item of the importer, reading stdin until its end
+    pub fn item(self) -> ImportItem {
+        ImportItem::new(self.name, None, Box::pin(tokio::io::stdin()))
+    }
+}
+
+impl Importer for StdinImporter {
+    async fn run(self, tx: mpsc::Sender<Result<ImportItem>>) -> Result<()> {
+        let _ = tx.send(Ok(self.item())).await;
+        Ok(())
+    }
+}
+
+/// Imports the regular files contained in a tar archive
+pub struct TarImporter {
+    pub path: PathBuf,
+}
+
+impl Importer for TarImporter {
+    async fn run(self, tx: mpsc::Sender<Result<ImportItem>>) -> Result<()> {
+        let file = tokio::fs::File::open(&self.path)
+            .await
+            .with_context(|| format!("Failed to open archive: {:?}", self.path))?;
+        let mut archive = tokio_tar::Archive::new(file);
+        let mut entries = archive.entries()?;
+
+        while let Some(entry) = entries.next().await {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let entry_path = entry.path()?.into_owned();
+            let Some(file_name) = entry_path.file_name() else {
+                continue;
+            };
+            let file_name = file_name.to_string_lossy().to_string();
+            if file_name.starts_with('.') {
+                continue;
+            }
+
+            // tar entries borrow the archive, so each one is piped to the reader of its item
+            let (mut pipe, reader) = tokio::io::duplex(TAR_PIPE_CAPACITY);
+            let item = ImportItem::new(file_name, None, Box::pin(reader));
+            if tx.send(Ok(item)).await.is_err() {
+                break;
+            }
+            tokio::io::copy(&mut entry, &mut pipe)
+                .await
+                .with_context(|| format!("Failed to read {:?} from archive", entry_path))?;
+        }
+        Ok(())
+    }
+}
+
+/// Downloads files over http(s)
+pub struct UrlImporter {
+    pub urls: Vec<Url>,
+}
+
+impl Importer for UrlImporter {
+    async fn run(self, tx: mpsc::Sender<Result<ImportItem>>) -> Result<()> {
+        for url in self.urls {
+            let response = reqwest::get(url.clone())
+                .await
+                .and_then(|r| r.error_for_status())
+                .with_context(|| format!("Failed to download {}", url));
+            let response = match response {
+                Ok(response) => response,
+                Err(e) => {
+                    let _ = tx.send(Err(e)).await;
+                    break;
+                }
+            };
+
+            let name = url
+                .path_segments()
+                .and_then(|mut segments| segments.next_back())
+                .filter(|s| !s.is_empty())
+                .unwrap_or("download")
+                .to_string();
+            let content_type = response
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string());
+            let stream = response.bytes_stream().map_err(std::io::Error::other);
+            let item = ImportItem::new(name, content_type, Box::pin(StreamReader::new(stream)));
+            if tx.send(Ok(item)).await.is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use futures::StreamExt;
+use iroh_blobs::Hash;
+use iroh_docs::{AuthorId, ContentStatus, Entry, engine::LiveEvent, store::Query};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tracing::{Instrument, info_span, warn};
+
+use crate::store::{IrohCls, ToBytes};
+
+/// Metadata of an entity as seen by the secondary indexes
+#[derive(Debug, Clone)]
+pub struct EntityMeta {
+    pub key: String,
+    pub name: Option<String>,
+    // text matched by the full-text search
+    pub text: String,
+    // content size, or the entry size for entities without content
+    pub size: u64,
+    pub folder_id: Option<String>,
+    // author of the latest write
+    pub author: AuthorId,
+    // time of the latest write in microseconds since the unix epoch
+    pub timestamp: u64,
+    // creation time in milliseconds since the unix epoch, if the entity records it
+    pub created_at: Option<u64>,
+    // capture time in milliseconds since the unix epoch, for photos that record it
+    pub captured_at: Option<u64>,
+    // hash of the entry content
+    pub hash: Hash,
+}
+
+impl EntityMeta {
+    pub fn new<Entity>(entry: &Entry, entity: &Entity) -> Self
+    where
+        Entity: ToBytes<Entity> + Serialize + Clone + for<'a> Deserialize<'a>,
+    {
+        EntityMeta {
+            key: String::from_utf8_lossy(entry.key()).to_string(),
+            name: entity.sort_name().map(str::to_string),
+            text: entity.search_text(),
+            size: entity.content_size().unwrap_or(entry.content_len()),
+            folder_id: entity.folder_id().map(str::to_string),
+            author: entry.author(),
+            timestamp: entry.timestamp(),
+            created_at: entity.created_at(),
+            captured_at: entity.captured_at(),
+            hash: entry.content_hash(),
+        }
+    }
+}
+
+/// A local index derived from the entities of the tables
+pub trait EntityIndex: Clone + Send + Sync + 'static {
+    /// Replace everything indexed for `table`
+    fn reindex(
+        &self,
+        table: &str,
+        entities: Vec<EntityMeta>,
+    ) -> impl std::future::Future<Output = Result<()>> + Send;
+
+    /// Index a changed entity of `table`, `None` removes `key`
+    fn update(
+        &self,
+        table: &str,
+        key: &str,
+        entity: Option<EntityMeta>,
+    ) -> impl std::future::Future<Output = Result<()>> + Send;
+}
+
+/// Index all entities of `table` and keep `index` up to date with its changes
+pub fn spawn_indexer<I, Entity>(index: I, table: String, cls: IrohCls<Entity>) -> JoinHandle<()>
+where
+    I: EntityIndex,
+    Entity: ToBytes<Entity> + Serialize + Clone + for<'a> Deserialize<'a> + Send + Sync + 'static,
+{
+    let span = info_span!("indexer", table = %table);
+    tokio::spawn(
+        async move {
+        // subscribe before the first pass so no change in between is lost
+        let mut events = match cls.doc.subscribe().await {
+            Ok(events) => events,
+            Err(e) => {
+                warn!(error = %e, "failed to subscribe");
+                return;
+            }
+        };
+        if let Err(e) = reindex(&index, &table, &cls).await {
+            warn!(error = %e, "failed to index table");
+        }
+
+        // keys of remote entries whose content has not arrived yet
+        let mut pending: HashMap<Hash, Vec<u8>> = HashMap::new();
+        while let Some(Ok(event)) = events.next().await {
+            let key = match event {
+                LiveEvent::InsertLocal { entry } => entry.key().to_vec(),
+                LiveEvent::InsertRemote {
+                    entry,
+                    content_status,
+                    ..
+                } => {
+                    if entry.content_len() > 0 && content_status != ContentStatus::Complete {
+                        pending.insert(entry.content_hash(), entry.key().to_vec());
+                        continue;
+                    }
+                    entry.key().to_vec()
+                }
+                LiveEvent::ContentReady { hash } => match pending.remove(&hash) {
+                    Some(key) => key,
+                    None => continue,
+                },
+                _ => continue,
+            };
+            // other tables sharing the doc are indexed by their own indexers
+            if !cls.owns(&key) {
+                continue;
+            }
+            if let Err(e) = update(&index, &table, &cls, &key).await {
+                warn!(key = %String::from_utf8_lossy(&key), error = %e, "failed to index entry");
+            }
+        }
+        }
+        .instrument(span),
+    )
+}
+
+async fn reindex<I, Entity>(index: &I, table: &str, cls: &IrohCls<Entity>) -> Result<()>
+where
+    I: EntityIndex,
+    Entity: ToBytes<Entity> + Serialize + Clone + for<'a> Deserialize<'a> + Send,
+{
+    let mut entities = Vec::new();
+    let query = Query::single_latest_per_key().key_prefix(&cls.prefix);
+    for entry in cls.entries(query).await? {
+        // entries of authors the access list does not allow are not indexed
+        let Some(entry) = cls.allowed_entry(&entry).await? else {
+            continue;
+        };
+        if let Some(entity) = cls.decode(&entry).await? {
+            entities.push(meta(cls, &entry, &entity));
+        }
+    }
+    index.reindex(table, entities).await
+}
+
+// re-read a single key, removing it from the index once deleted
+async fn update<I, Entity>(index: &I, table: &str, cls: &IrohCls<Entity>, key: &[u8]) -> Result<()>
+where
+    I: EntityIndex,
+    Entity: ToBytes<Entity> + Serialize + Clone + for<'a> Deserialize<'a> + Send,
+{
+    let entry = cls
+        .doc
+        .get_one(Query::single_latest_per_key().key_exact(key))
+        .await?;
+    let entry = match entry {
+        Some(entry) => cls.allowed_entry(&entry).await?,
+        None => None,
+    };
+    let meta = match entry {
+        Some(entry) => cls
+            .decode(&entry)
+            .await?
+            .map(|entity| meta(cls, &entry, &entity)),
+        None => None,
+    };
+    let key = cls.table_key(key);
+    index
+        .update(table, &String::from_utf8_lossy(key), meta)
+        .await
+}
+
+// metadata of an entry, keyed within its table
+fn meta<Entity>(cls: &IrohCls<Entity>, entry: &Entry, entity: &Entity) -> EntityMeta
+where
+    Entity: ToBytes<Entity> + Serialize + Clone + for<'a> Deserialize<'a> + Send,
+{
+    EntityMeta {
+        key: String::from_utf8_lossy(cls.table_key(entry.key())).to_string(),
+        ..EntityMeta::new(entry, entity)
+    }
+}
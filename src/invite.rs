@@ -0,0 +1,317 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, Result, ensure};
+use iroh::{
+    Endpoint, EndpointAddr, PublicKey, SecretKey,
+    endpoint::Connection,
+    protocol::{AcceptError, ProtocolHandler, Router},
+};
+use rand::Rng;
+use tracing::{info, warn};
+
+use crate::{RelayChoice, crypto};
+
+pub const INVITE_ALPN: &[u8] = b"iroh-test/invite/2";
+
+// answers with the endpoint serving the bundle of an invitation
+pub const INVITE_LOOKUP_ALPN: &[u8] = b"iroh-test/invite-lookup/2";
+
+// how long an invitation code can be redeemed
+pub const DEFAULT_INVITE_TTL: Duration = Duration::from_secs(10 * 60);
+
+// characters of a code, without the easily confused 0/O and 1/I/L
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKMNPQRSTUVWXYZ23456789";
+
+// the first part of a code locates the invitation, the second part never leaves the peers and
+// encrypts the bundle. 10 characters of the alphabet are about 2^49 codes, beyond guessing
+// even when the ciphertext is collected
+const LOOKUP_LEN: usize = 4;
+const SECRET_LEN: usize = 10;
+
+// an invitation stops serving its bundle after this many peers fetched it, so its ciphertext
+// can't be collected to guess the code
+const MAX_REDEMPTIONS: usize = 8;
+
+// upper bound for a ticket bundle
+const MAX_BUNDLE_SIZE: usize = 64 * 1024;
+
+/// Generate a random invitation code, e.g. `K7PX-3MQAH-T2WXE`
+pub fn generate_code() -> String {
+    let mut rng = rand::rng();
+    let mut part = |len: usize| -> String {
+        (0..len)
+            .map(|_| CODE_ALPHABET[rng.random_range(0..CODE_ALPHABET.len())] as char)
+            .collect()
+    };
+    let lookup = part(LOOKUP_LEN);
+    let secret = part(SECRET_LEN);
+    let (first, second) = secret.split_at(SECRET_LEN / 2);
+    format!("{}-{}-{}", lookup, first, second)
+}
+
+// lookup and secret part of a code as typed by a user
+fn split_code(code: &str) -> Result<(String, String)> {
+    let code: String = code
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .collect::<String>()
+        .to_uppercase();
+    ensure!(
+        code.len() == LOOKUP_LEN + SECRET_LEN && code.bytes().all(|c| CODE_ALPHABET.contains(&c)),
+        "Invalid invitation code"
+    );
+    let (lookup, secret) = code.split_at(LOOKUP_LEN);
+    Ok((lookup.to_string(), secret.to_string()))
+}
+
+// both sides derive the lookup endpoint from the first part of the code
+fn lookup_key(lookup: &str) -> SecretKey {
+    SecretKey::from_bytes(&blake3::derive_key(
+        "iroh-test invite v3 lookup",
+        lookup.as_bytes(),
+    ))
+}
+
+// the bundle is sealed with the whole code, stretched by `crypto::encrypt_with_passphrase`
+fn code_passphrase(lookup: &str, secret: &str) -> String {
+    format!("iroh-test invite v3 {}{}", lookup, secret)
+}
+
+/// Tells whoever asks which endpoint serves the bundle, nothing secret
+#[derive(Debug, Clone)]
+struct LookupProtocol {
+    target: PublicKey,
+}
+
+impl LookupProtocol {
+    async fn handle(&self, connection: Connection) -> Result<()> {
+        let (mut send, _recv) = connection.accept_bi().await?;
+        send.write_all(self.target.as_bytes()).await?;
+        send.finish()?;
+        connection.closed().await;
+        Ok(())
+    }
+}
+
+impl ProtocolHandler for LookupProtocol {
+    async fn accept(&self, connection: Connection) -> Result<(), AcceptError> {
+        self.handle(connection)
+            .await
+            .map_err(|e| AcceptError::from_err(std::io::Error::other(e)))
+    }
+}
+
+/// Serves the sealed ticket bundle to at most [`MAX_REDEMPTIONS`] peers
+#[derive(Debug, Clone)]
+struct InviteProtocol {
+    sealed: Arc<Vec<u8>>,
+    redeemed: Arc<Mutex<HashSet<PublicKey>>>,
+}
+
+impl InviteProtocol {
+    async fn handle(&self, connection: Connection) -> Result<()> {
+        let remote = connection.remote_id()?;
+        {
+            let mut redeemed = self.redeemed.lock().unwrap();
+            if !redeemed.contains(&remote) && redeemed.len() >= MAX_REDEMPTIONS {
+                warn!(from = %remote.fmt_short(), "refused invitation redemption");
+                return Ok(());
+            }
+            redeemed.insert(remote);
+        }
+        let (mut send, _recv) = connection.accept_bi().await?;
+        send.write_all(&self.sealed).await?;
+        send.finish()?;
+        connection.closed().await;
+        info!(to = %remote.fmt_short(), "sent tickets");
+        Ok(())
+    }
+}
+
+impl ProtocolHandler for InviteProtocol {
+    async fn accept(&self, connection: Connection) -> Result<(), AcceptError> {
+        self.handle(connection)
+            .await
+            .map_err(|e| AcceptError::from_err(std::io::Error::other(e)))
+    }
+}
+
+/// A published invitation, redeemable until it expires or is dropped
+pub struct Invitation {
+    pub code: String,
+    lookup: Router,
+    router: Router,
+}
+
+impl Drop for Invitation {
+    fn drop(&mut self) {
+        let lookup = self.lookup.clone();
+        let router = self.router.clone();
+        tokio::spawn(async move {
+            let _ = lookup.shutdown().await;
+            let _ = router.shutdown().await;
+        });
+    }
+}
+
+/// Publish `bundle` under a fresh code for `ttl`
+///
+/// The bundle is served by an endpoint with a random key, sealed with the code. The first
+/// part of the code only locates that endpoint, so a peer has to know the whole code to read
+/// the bundle, and only a few peers can fetch it at all. Keep the ttl short all the same
+pub async fn publish_invite(
+    bundle: String,
+    ttl: Duration,
+    relay: &RelayChoice,
+) -> Result<Invitation> {
+    let code = generate_code();
+    let (lookup, secret) = split_code(&code)?;
+    let sealed =
+        crypto::encrypt_with_passphrase(&code_passphrase(&lookup, &secret), bundle.as_bytes())?;
+
+    let endpoint = Endpoint::builder()
+        .secret_key(SecretKey::generate(&mut rand::rng()))
+        .relay_mode(relay.relay_mode())
+        .discovery_n0()
+        .bind()
+        .await?;
+    let target = endpoint.id();
+    let router = Router::builder(endpoint)
+        .accept(
+            INVITE_ALPN,
+            InviteProtocol {
+                sealed: Arc::new(sealed),
+                redeemed: Arc::default(),
+            },
+        )
+        .spawn();
+
+    let lookup_endpoint = Endpoint::builder()
+        .secret_key(lookup_key(&lookup))
+        .relay_mode(relay.relay_mode())
+        .discovery_n0()
+        .bind()
+        .await?;
+    let lookup = Router::builder(lookup_endpoint)
+        .accept(INVITE_LOOKUP_ALPN, LookupProtocol { target })
+        .spawn();
+
+    let expiring = [lookup.clone(), router.clone()];
+    let expired_code = code.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(ttl).await;
+        info!(code = %expired_code, "invitation code expired");
+        for router in expiring {
+            if !router.is_shutdown() {
+                let _ = router.shutdown().await;
+            }
+        }
+    });
+    Ok(Invitation {
+        code,
+        lookup,
+        router,
+    })
+}
+
+// read everything a protocol of `peer` answers to an empty request
+async fn fetch(
+    endpoint: &Endpoint,
+    peer: PublicKey,
+    alpn: &[u8],
+    relay: &RelayChoice,
+) -> Result<Vec<u8>> {
+    let mut addr = EndpointAddr::new(peer);
+    if let Some(url) = relay.relay_url() {
+        addr = addr.with_relay_url(url.clone());
+    }
+    let connection = endpoint.connect(addr, alpn).await?;
+    let (mut send, mut recv) = connection.open_bi().await?;
+    send.finish()?;
+    let response = recv.read_to_end(MAX_BUNDLE_SIZE).await?;
+    connection.close(0u32.into(), b"done");
+    Ok(response)
+}
+
+/// Fetch the ticket bundle published under `code`
+///
+/// Both sides must use the same relay, without a custom one the address is looked up via discovery
+pub async fn redeem_invite(code: &str, relay: &RelayChoice) -> Result<String> {
+    let (lookup, secret) = split_code(code)?;
+    let endpoint = Endpoint::builder()
+        .relay_mode(relay.relay_mode())
+        .discovery_n0()
+        .bind()
+        .await?;
+    let target = fetch(
+        &endpoint,
+        lookup_key(&lookup).public(),
+        INVITE_LOOKUP_ALPN,
+        relay,
+    )
+    .await
+    .with_context(|| format!("No invitation found for code {}", code))?;
+    let target = PublicKey::from_bytes(
+        target
+            .as_slice()
+            .try_into()
+            .context("Invalid invitation lookup response")?,
+    )?;
+    let sealed = fetch(&endpoint, target, INVITE_ALPN, relay)
+        .await
+        .with_context(|| format!("Invitation {} can't be redeemed anymore", code))?;
+    endpoint.close().await;
+    let bundle = crypto::decrypt_with_passphrase(&code_passphrase(&lookup, &secret), &sealed)
+        .with_context(|| format!("Invitation {} does not match its code", code))?;
+    Ok(String::from_utf8(bundle)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_back_generated_codes_as_typed() {
+        let code = generate_code();
+        let (lookup, secret) = split_code(&code).unwrap();
+        assert_eq!(lookup.len(), LOOKUP_LEN);
+        assert_eq!(secret.len(), SECRET_LEN);
+        let typed = format!(" {} ", code.to_lowercase().replace('-', " "));
+        assert_eq!(split_code(&typed).unwrap(), (lookup, secret));
+    }
+
+    #[test]
+    fn rejects_malformed_codes() {
+        assert!(split_code("K7PX-3MQAH").is_err());
+        // 0, O, 1, I and L are not part of the alphabet
+        assert!(split_code("K7PX-3MQAH-T2WX0").is_err());
+    }
+
+    #[test]
+    fn opens_bundles_only_with_the_whole_code() {
+        let (lookup, secret) = split_code(&generate_code()).unwrap();
+        let sealed =
+            crypto::encrypt_with_passphrase(&code_passphrase(&lookup, &secret), b"tickets")
+                .unwrap();
+        let opened =
+            crypto::decrypt_with_passphrase(&code_passphrase(&lookup, &secret), &sealed).unwrap();
+        assert_eq!(opened, b"tickets");
+
+        let mut wrong = secret.into_bytes();
+        wrong[0] = if wrong[0] == b'A' { b'B' } else { b'A' };
+        let wrong = String::from_utf8(wrong).unwrap();
+        assert!(
+            crypto::decrypt_with_passphrase(&code_passphrase(&lookup, &wrong), &sealed).is_err()
+        );
+    }
+
+    #[test]
+    fn derives_the_lookup_endpoint_from_the_code() {
+        assert_eq!(lookup_key("K7PX").public(), lookup_key("K7PX").public());
+        assert_ne!(lookup_key("K7PX").public(), lookup_key("K7PY").public());
+    }
+}
@@ -0,0 +1,198 @@
+use std::path::Path;
+
+use anyhow::{Context, Result, ensure};
+use iroh::SecretKey;
+use iroh_docs::{
+    Capability, NamespaceSecret,
+    api::protocol::{AddrInfoOptions, ShareMode},
+};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+use crate::{
+    author_export, author_import, crypto,
+    keys::KeyScheme,
+    rejoin::{SavedTable, SavedTables, save_tables},
+    server::IrohNet,
+    sync_filter::SyncFilter,
+};
+
+// layout of the backup file, bump when it changes
+const BACKUP_VERSION: u32 = 1;
+
+/// Write capability of one table, the secret of its namespace, and its saved settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TableKey {
+    pub table: String,
+    // namespace secret as hex
+    pub secret: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sync_filter: Option<SyncFilter>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_scheme: Option<KeyScheme>,
+}
+
+/// The secrets needed to rebuild a node without losing write access to its docs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyBackup {
+    pub version: u32,
+    // secret key of the node as hex, the node id is derived from it
+    pub node_secret: String,
+    // secret of the default author as hex, so entries keep being written by the same author
+    pub author: Option<String>,
+    pub tables: Vec<TableKey>,
+}
+
+impl KeyBackup {
+    /// Secret key to start the restored node with
+    pub fn node_secret_key(&self) -> Result<SecretKey> {
+        let bytes: [u8; 32] = hex::decode(&self.node_secret)?
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Node secret must be 32 bytes"))?;
+        Ok(SecretKey::from_bytes(&bytes))
+    }
+}
+
+/// Collect the node key, the default author and the namespace secrets of the saved tables
+///
+/// Tables this node only has read access to are skipped
+pub async fn backup_keys(node: &IrohNet, saved: &SavedTables) -> Result<KeyBackup> {
+    let mut tables = vec![];
+    for table in &saved.tables {
+        let doc = node
+            .docs
+            .open(table.namespace)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Doc of table '{}' is not stored", table.table))?;
+        match doc.share(ShareMode::Write, AddrInfoOptions::Id).await {
+            Ok(ticket) => match ticket.capability {
+                Capability::Write(secret) => tables.push(TableKey {
+                    table: table.table.clone(),
+                    secret: hex::encode(secret.to_bytes()),
+                    sync_filter: table.sync_filter.clone(),
+                    key_scheme: table.key_scheme,
+                }),
+                Capability::Read(_) => {
+                    warn!(table = %table.table, "read-only table, not backed up")
+                }
+            },
+            Err(e) => warn!(table = %table.table, error = %e, "read-only table, not backed up"),
+        }
+    }
+    let author = match author_export(node).await {
+        Ok(author) => Some(author),
+        Err(e) => {
+            warn!(error = %e, "failed to export the default author");
+            None
+        }
+    };
+    Ok(KeyBackup {
+        version: BACKUP_VERSION,
+        node_secret: hex::encode(node.router.endpoint().secret_key().to_bytes()),
+        author,
+        tables,
+    })
+}
+
+/// Write a backup to `path`, encrypted with a key derived from `passphrase` if given
+///
+/// Without a passphrase the file holds every secret in plain text, keep it safe. Either way
+/// only the current user can read it, see [`write_private`]
+pub async fn write_key_backup(
+    path: &Path,
+    backup: &KeyBackup,
+    passphrase: Option<&str>,
+) -> Result<()> {
+    let json = serde_json::to_vec_pretty(backup)?;
+    let bytes = match passphrase {
+        Some(passphrase) => crypto::encrypt_with_passphrase(passphrase, &json)?,
+        None => json,
+    };
+    write_private(path, &bytes).await
+}
+
+/// Write `bytes` to a file at `path` only the current user can read and write
+///
+/// An existing file is truncated and its permissions are narrowed before anything is written
+pub async fn write_private(path: &Path, bytes: &[u8]) -> Result<()> {
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options
+        .open(path)
+        .await
+        .with_context(|| format!("Failed to create {:?}", path))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))
+            .await?;
+    }
+    file.write_all(bytes)
+        .await
+        .with_context(|| format!("Failed to write {:?}", path))?;
+    file.sync_all().await?;
+    Ok(())
+}
+
+/// Read a backup written by [`write_key_backup`]
+pub async fn read_key_backup(path: &Path, passphrase: Option<&str>) -> Result<KeyBackup> {
+    let bytes = tokio::fs::read(path)
+        .await
+        .with_context(|| format!("Failed to read {:?}", path))?;
+    let json = if crypto::is_encrypted(&bytes) {
+        let passphrase = passphrase
+            .ok_or_else(|| anyhow::anyhow!("The backup is encrypted, pass its passphrase"))?;
+        crypto::decrypt_with_passphrase(passphrase, &bytes)?
+    } else {
+        bytes
+    };
+    let backup: KeyBackup =
+        serde_json::from_slice(&json).with_context(|| format!("Failed to parse {:?}", path))?;
+    ensure!(
+        backup.version <= BACKUP_VERSION,
+        "Backup version {} is newer than this build supports",
+        backup.version
+    );
+    Ok(backup)
+}
+
+/// Import the author and the namespaces of a backup and save the tables for the next start
+///
+/// `node` must run with [`KeyBackup::node_secret_key`]. The docs start empty and fill up
+/// again once the node syncs with peers that still have the entries
+pub async fn restore_keys(node: &IrohNet, backup: &KeyBackup) -> Result<SavedTables> {
+    ensure!(
+        hex::encode(node.router.endpoint().secret_key().to_bytes()) == backup.node_secret,
+        "The node must be started with the secret key of the backup"
+    );
+    if let Some(author) = &backup.author {
+        author_import(node, author).await?;
+    }
+    let mut saved = SavedTables::default();
+    for key in &backup.tables {
+        let bytes: [u8; 32] = hex::decode(&key.secret)?
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Secret of table '{}' must be 32 bytes", key.table))?;
+        let secret = NamespaceSecret::from_bytes(&bytes);
+        let doc = node
+            .docs
+            .import_namespace(Capability::Write(secret))
+            .await?;
+        let ticket = doc.share(ShareMode::Write, node.addr_info()).await?;
+        info!(table = %key.table, namespace = %doc.id(), "restored table");
+        saved.tables.push(SavedTable {
+            table: key.table.clone(),
+            namespace: doc.id(),
+            ticket: ticket.to_string(),
+            sync_filter: key.sync_filter.clone(),
+            key_scheme: key.key_scheme,
+        });
+    }
+    if let Some(root) = node.storage_path() {
+        save_tables(root, &saved).await?;
+    }
+    Ok(saved)
+}
@@ -0,0 +1,134 @@
+use std::sync::{Arc, RwLock};
+
+use iroh_blobs::Hash;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Chooses the key of a new resource or folder, see [`Resources::set_key_strategy`]
+///
+/// Any `Fn(&Hash) -> String` is a key strategy
+///
+/// [`Resources::set_key_strategy`]: crate::model::resource::Resources::set_key_strategy
+pub trait KeyStrategy: Send + Sync {
+    /// Key of a new entity whose unencrypted content has `hash`, keyed when docs are encrypted
+    fn key(&self, hash: &Hash) -> String;
+}
+
+impl<F> KeyStrategy for F
+where
+    F: Fn(&Hash) -> String + Send + Sync,
+{
+    fn key(&self, hash: &Hash) -> String {
+        self(hash)
+    }
+}
+
+/// A random UUID per resource, adding the same file twice gives two resources
+pub struct RandomKeys;
+
+impl KeyStrategy for RandomKeys {
+    fn key(&self, _hash: &Hash) -> String {
+        Uuid::new_v4().to_string()
+    }
+}
+
+/// A UUIDv7 per entity, which starts with the creation time so keys sort chronologically
+///
+/// See [`IrohProperties::search_ordered_by_creation`]
+///
+/// [`IrohProperties::search_ordered_by_creation`]: crate::store::IrohProperties::search_ordered_by_creation
+pub struct TimeKeys;
+
+impl KeyStrategy for TimeKeys {
+    fn key(&self, _hash: &Hash) -> String {
+        Uuid::now_v7().to_string()
+    }
+}
+
+/// The content hash as key
+///
+/// With a [`DocCipher`](crate::crypto::DocCipher) the hash is keyed, see [`DocCipher::digest`](crate::crypto::DocCipher::digest), since doc keys are
+/// not encrypted and a plain hash would reveal which files are stored. Adding content that is already stored overwrites the resource holding it instead of
+/// adding another one, so re-imports are idempotent and peers adding the same file end up
+/// with a single resource. The name of the last write wins
+pub struct ContentKeys;
+
+impl KeyStrategy for ContentKeys {
+    fn key(&self, hash: &Hash) -> String {
+        hash.to_string()
+    }
+}
+
+/// The built-in key strategies, saved with a table so it is keyed the same after a restart
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyScheme {
+    #[default]
+    Random,
+    Time,
+    Content,
+}
+
+impl KeyScheme {
+    pub fn strategy(self) -> Arc<dyn KeyStrategy> {
+        match self {
+            KeyScheme::Random => Arc::new(RandomKeys),
+            KeyScheme::Time => Arc::new(TimeKeys),
+            KeyScheme::Content => Arc::new(ContentKeys),
+        }
+    }
+}
+
+impl std::str::FromStr for KeyScheme {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "random" => Ok(KeyScheme::Random),
+            "time" => Ok(KeyScheme::Time),
+            "content" => Ok(KeyScheme::Content),
+            _ => Err(anyhow::anyhow!("Unknown key scheme '{}'", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for KeyScheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyScheme::Random => write!(f, "random"),
+            KeyScheme::Time => write!(f, "time"),
+            KeyScheme::Content => write!(f, "content"),
+        }
+    }
+}
+
+/// Key strategy of a table, shared by all clones of its handle
+#[derive(Clone)]
+pub struct Keys {
+    strategy: Arc<RwLock<Arc<dyn KeyStrategy>>>,
+}
+
+impl Default for Keys {
+    fn default() -> Self {
+        Keys {
+            strategy: Arc::new(RwLock::new(Arc::new(RandomKeys))),
+        }
+    }
+}
+
+impl std::fmt::Debug for Keys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Keys").finish_non_exhaustive()
+    }
+}
+
+impl Keys {
+    pub fn set(&self, strategy: Arc<dyn KeyStrategy>) {
+        *self.strategy.write().unwrap() = strategy;
+    }
+
+    /// Key of a new entity whose unencrypted content has `hash`
+    pub fn key(&self, hash: &Hash) -> String {
+        self.strategy.read().unwrap().key(hash)
+    }
+}
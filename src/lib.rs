@@ -1,26 +1,82 @@
 use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use anyhow::Result;
+use anyhow::{Context, Result, ensure};
 use futures::TryStreamExt;
-use iroh::{RelayConfig, RelayMap, SecretKey};
+use iroh::{RelayConfig, RelayMap, RelayMode, RelayUrl, SecretKey};
 use iroh_docs::api::Doc;
-use iroh_docs::{Author, AuthorId, DocTicket};
+use iroh_docs::{Author, AuthorId, Capability, DocTicket};
 use iroh_relay::RelayQuicConfig;
+use tracing::info;
 use url::Url;
 
 use crate::server::IrohNet;
 
+pub mod acl;
+pub mod admin;
+pub mod anti_entropy;
+pub mod audit;
+pub mod codec;
+pub mod compression;
+pub mod control;
+pub mod coordinator;
+pub mod crypto;
+pub mod dashboard;
+pub mod dir_sync;
 pub mod doc_subcribe;
+pub mod downloader;
+pub mod event_channel;
+pub mod export;
+pub mod gateway;
+pub mod history;
+pub mod image_meta;
+pub mod import;
+pub mod indexer;
+pub mod invite;
+pub mod key_backup;
+pub mod keys;
+pub mod manifest;
 pub mod model;
+#[cfg(all(feature = "fuse", unix))]
+pub mod mount;
+pub mod pairing;
+pub mod peers;
+pub mod pending;
+pub mod pin;
+pub mod placement;
+pub mod quota;
+pub mod rejoin;
+pub mod replication;
+pub mod retry;
+pub mod schema;
+pub mod search;
 pub mod server;
+pub mod shell;
+pub mod signal;
+#[cfg(feature = "snapshot")]
+pub mod snapshot;
+pub mod sql_index;
 pub mod store;
+pub mod subscription;
+pub mod subshare;
+pub mod supervisor;
+pub mod sync_filter;
+pub mod tables;
+pub mod testing;
+pub mod trash;
+pub mod usage;
+pub mod validate;
+pub mod view;
+pub mod watch;
+pub mod webdav;
 
 pub const DEFAULT_RELAY_HOSTNAME: &str = "picorca.com";
 
-pub const AUTHOR: &[u8; 32] = &[
-    7, 57, 234, 237, 239, 151, 201, 39, 210, 244, 128, 178, 34, 67, 38, 216, 247, 76, 126, 49, 255,
-    112, 41, 183, 79, 0, 138, 66, 249, 34, 109, 14,
-];
+// environment variable holding the relay url, overridden by `--relay-url`
+pub const RELAY_ENV: &str = "IROH_TEST_RELAY";
+
+// environment variable holding the images directory, overridden by `--images-dir`
+pub const IMAGES_DIR_ENV: &str = "IROH_TEST_IMAGES_DIR";
 
 /// Get the default [`RelayMap`]
 pub fn default_relay_map() -> RelayMap {
@@ -33,12 +89,91 @@ pub fn default_relay_node() -> RelayConfig {
     let url: Url = format!("https://{DEFAULT_RELAY_HOSTNAME}.:4430")
         .parse()
         .expect("default url");
+    relay_node(url.into())
+}
+
+/// Get the [`RelayNode`] for a relay server
+pub fn relay_node(url: RelayUrl) -> RelayConfig {
     RelayConfig {
-        url: url.into(),
+        url,
         quic: Some(RelayQuicConfig::default()),
     }
 }
 
+/// Parse and validate a relay url given on the command line or in [`RELAY_ENV`]
+pub fn parse_relay_url(s: &str) -> Result<RelayUrl> {
+    let url: Url = s
+        .parse()
+        .with_context(|| format!("Invalid relay url: {}", s))?;
+    ensure!(
+        matches!(url.scheme(), "http" | "https"),
+        "Relay url must use http or https: {}",
+        s
+    );
+    ensure!(url.host().is_some(), "Relay url has no host: {}", s);
+    Ok(url.into())
+}
+
+/// Which relay servers the endpoint uses
+#[derive(Debug, Clone, PartialEq)]
+pub enum RelayChoice {
+    // a single self-hosted relay
+    Custom(RelayUrl),
+    // number0's public relays
+    Default,
+    // no relay, peers must reach each other directly
+    Disabled,
+}
+
+impl std::str::FromStr for RelayChoice {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "default" => Ok(RelayChoice::Default),
+            "disabled" => Ok(RelayChoice::Disabled),
+            _ => match s.strip_prefix("custom:") {
+                Some(url) => Ok(RelayChoice::Custom(parse_relay_url(url)?)),
+                None => Err(anyhow::anyhow!(
+                    "Unknown relay mode '{}', expected custom:<url>, default or disabled",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for RelayChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RelayChoice::Custom(url) => write!(f, "custom:{}", url),
+            RelayChoice::Default => f.write_str("default"),
+            RelayChoice::Disabled => f.write_str("disabled"),
+        }
+    }
+}
+
+impl RelayChoice {
+    /// The [`RelayMode`] passed to the endpoint builder
+    pub fn relay_mode(&self) -> RelayMode {
+        match self {
+            RelayChoice::Custom(url) => {
+                RelayMode::Custom(RelayMap::from_iter([relay_node(url.clone())]))
+            }
+            RelayChoice::Default => RelayMode::Default,
+            RelayChoice::Disabled => RelayMode::Disabled,
+        }
+    }
+
+    /// Url of the custom relay, if one is used
+    pub fn relay_url(&self) -> Option<&RelayUrl> {
+        match self {
+            RelayChoice::Custom(url) => Some(url),
+            _ => None,
+        }
+    }
+}
+
 /// Generate a new random private key
 pub fn generate_private_key() -> SecretKey {
     SecretKey::generate(&mut rand::rng())
@@ -62,33 +197,103 @@ pub enum TableType {
 
 pub async fn iroh_create_doc(node: &IrohNet, ticket: &Option<DocTicket>) -> Result<Doc> {
     let doc: Doc = match ticket {
-        Some(tic) => {
-            let doc = node.docs.import(tic.clone()).await?;
-            println!("Imported doc with id: {}, ticket: {:?}", doc.id(), tic);
-            // doc.start_sync(tic.nodes.clone()).await?;
-            doc
-        }
+        Some(tic) => match node.docs.open(tic.capability.id()).await? {
+            // a doc this node already has, e.g. after a restart, is reopened instead of imported
+            Some(doc) => {
+                let me = node.router.endpoint().id();
+                let peers = tic.nodes.iter().filter(|a| a.id != me).cloned().collect();
+                doc.start_sync(peers).await?;
+                info!(namespace = %doc.id(), "reopened doc");
+                doc
+            }
+            None => {
+                let doc = node.docs.import(tic.clone()).await?;
+                info!(namespace = %doc.id(), ticket = ?tic, "imported doc");
+                // doc.start_sync(tic.nodes.clone()).await?;
+                doc
+            }
+        },
         None => {
             let doc = node.docs.create().await?;
-            println!("Created new doc with id: {}", doc.id());
+            info!(namespace = %doc.id(), "created doc");
             doc
         }
     };
     Ok(doc)
 }
 
-pub async fn iroh_create_author(node: &IrohNet) -> Result<AuthorId> {
-    let author_list: Vec<_> = node.docs.author_list().await?.try_collect().await?;
-    let author = Author::from_bytes(AUTHOR);
-    if let Some(_author) = author_list.iter().find(|a| a.as_bytes() == AUTHOR) {
-        // todo
-    } else {
-        let author = Author::from_bytes(AUTHOR);
-        node.docs.author_import(author.clone()).await?;
+/// Read-only ticket for the namespace of `ticket`, with the same peer addresses
+///
+/// Works offline on any ticket, a read-only ticket is returned unchanged
+pub fn read_only_ticket(ticket: &DocTicket) -> DocTicket {
+    DocTicket {
+        capability: Capability::Read(ticket.capability.id()),
+        ..ticket.clone()
+    }
+}
+
+/// Doc of a built-in table or collection, see [`iroh_create_doc`]
+///
+/// In single-doc mode every table gets the same doc, created or joined by the first table
+/// that is opened, and the tickets of the other tables are ignored
+pub async fn iroh_table_doc(node: &IrohNet, ticket: &Option<DocTicket>) -> Result<Doc> {
+    match &node.shared_doc {
+        Some(shared) => shared
+            .get_or_try_init(|| iroh_create_doc(node, ticket))
+            .await
+            .cloned(),
+        None => iroh_create_doc(node, ticket).await,
     }
-    Ok(author.id())
 }
 
+/// Get the author this node writes with
+///
+/// The docs engine creates it on first start and persists it in the storage path,
+/// so every device keeps its own identity across restarts
+pub async fn iroh_create_author(node: &IrohNet) -> Result<AuthorId> {
+    node.docs.author_default().await
+}
+
+/// List the authors stored on this node
+pub async fn author_list(node: &IrohNet) -> Result<Vec<AuthorId>> {
+    Ok(node.docs.author_list().await?.try_collect().await?)
+}
+
+/// Export the secret of the default author as hex, e.g. to move it to another device
+pub async fn author_export(node: &IrohNet) -> Result<String> {
+    let id = node.docs.author_default().await?;
+    let author = node
+        .docs
+        .author_export(id)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Author {} is not stored on this node", id))?;
+    Ok(hex::encode(author.to_bytes()))
+}
+
+/// Import an author secret exported with [`author_export`] and make it the default
+pub async fn author_import(node: &IrohNet, secret: &str) -> Result<AuthorId> {
+    let bytes: [u8; 32] = hex::decode(secret.trim())?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Author secret must be 32 bytes"))?;
+    let author = Author::from_bytes(&bytes);
+    let id = author.id();
+    node.docs.author_import(author).await?;
+    node.docs.author_set_default(id).await?;
+    Ok(id)
+}
+
+/// Current time in milliseconds since the unix epoch
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Guess the images directory from the location of the executable
+///
+/// Only a fallback for development builds, deployments should pass `--images-dir`
+/// or set [`IMAGES_DIR_ENV`], see [`IrohNet::images_directory`]
 pub fn get_images_directory() -> Result<PathBuf> {
     // Get the path of the current executable file
     let exe_path = std::env::current_exe()?;
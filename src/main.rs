@@ -1,16 +1,657 @@
-use std::path::PathBuf;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::{Context, Result};
 use clap::Parser;
+use futures::StreamExt;
+use iroh::RelayUrl;
+use iroh::endpoint::ConnectionType;
 use iroh::protocol::DynProtocolHandler;
-use iroh_test::get_images_directory;
-use iroh_test::store::{IrohProperties, load_images_to_resources};
-use iroh_test::{generate_private_key, server::start_server, store::create_files};
+use iroh_docs::DocTicket;
+use iroh_docs::api::protocol::{AddrInfoOptions, ShareMode};
+use iroh_docs::store::SortDirection;
+use iroh_test::acl::Role;
+use iroh_test::admin::{
+    AdminRequest, AdminResponse, MAX_MESSAGE_SIZE as ADMIN_MAX_MESSAGE_SIZE, handle_admin,
+    send_admin, send_admin_stream,
+};
+use iroh_test::anti_entropy::DEFAULT_ANTI_ENTROPY_INTERVAL;
+use iroh_test::control::{ControlRequest, ControlResponse, send_control};
+use iroh_test::coordinator::OverallProgress;
+use iroh_test::crypto::DocCipher;
+use iroh_test::dashboard;
+use iroh_test::dir_sync::sync_dir;
+use iroh_test::downloader::{DEFAULT_DOWNLOAD_CONCURRENCY, has_content};
+use iroh_test::event_channel::{DEFAULT_EVENT_CAPACITY, EventChannelConfig, OverflowPolicy};
+use iroh_test::gateway::{DEFAULT_GATEWAY_ADDR, serve_files};
+use iroh_test::history;
+use iroh_test::import::{
+    DirImporter, PathImporter, StdinImporter, TarImporter, UrlImporter, collect_files, import_into,
+    import_into_with_progress,
+};
+use iroh_test::invite::{publish_invite, redeem_invite};
+use iroh_test::key_backup::{backup_keys, read_key_backup, restore_keys, write_key_backup};
+use iroh_test::keys::KeyScheme;
+use iroh_test::manifest::{JOIN_TIMEOUT, MANIFEST_TABLE, Manifest, ManifestEntry};
+use iroh_test::model::folder::Folders;
+use iroh_test::model::node::Nodes;
+use iroh_test::model::resource::{Resource, Resources};
+use iroh_test::pairing::{DEFAULT_PAIRING_TIMEOUT, pair, publish_pairing};
+use iroh_test::peers::{PeerInfo, connection_info};
+use iroh_test::pin;
+use iroh_test::placement::{
+    DEFAULT_CHUNK_SIZE, DEFAULT_EXTERNAL_MIN, DEFAULT_INLINE_MAX, DEFAULT_MAX_FILE_SIZE,
+    PlacementPolicy,
+};
+use iroh_test::rejoin::{load_tables, share_saved_tables};
+use iroh_test::replication::{push_replicas, under_replicated};
+use iroh_test::server::{IrohNet, IrohNetBuilder, NetStatus, StorageMode, parse_addr_info};
+use iroh_test::shell::{HISTORY_FILE, Shell, ShellInput, split_args};
+use iroh_test::signal::ShutdownSignal;
+#[cfg(feature = "snapshot")]
+use iroh_test::snapshot;
+use iroh_test::sql_index::MetaQuery;
+use iroh_test::store::{
+    IrohProperties, SortField, StoreState, TICKET_ORDER, TableListing, fetch_resource, read_tables,
+};
+use iroh_test::sync_filter::SyncFilter;
+use iroh_test::trash;
+use iroh_test::usage::{DiskUsage, disk_usage};
+use iroh_test::view::FolderNode;
+use iroh_test::watch::spawn_watcher;
+use iroh_test::webdav::{DEFAULT_WEBDAV_ADDR, serve_webdav};
+use iroh_test::{
+    IMAGES_DIR_ENV, RELAY_ENV, RelayChoice, author_export, author_import, author_list,
+    generate_private_key, iroh_create_author, parse_relay_url, store::create_files,
+};
 use tokio::fs;
-use tokio::io::AsyncBufReadExt;
 use tokio::time::sleep;
+use tracing_subscriber::EnvFilter;
+
+// how long `wait-ready` waits without an argument
+const DEFAULT_WAIT_READY_SECS: u64 = 60;
+
+// print `value` on a single line, for the --json mode
+fn print_json(value: &serde_json::Value) {
+    println!("{}", value);
+}
+
+fn peers_json(peers: &[PeerInfo]) -> serde_json::Value {
+    let peers: Vec<serde_json::Value> = peers
+        .iter()
+        .map(|peer| {
+            let (path, addr, relay) = match &peer.conn_type {
+                Some(ConnectionType::Direct(addr)) => ("direct", Some(addr.to_string()), None),
+                Some(ConnectionType::Relay(url)) => ("relay", None, Some(url.to_string())),
+                Some(ConnectionType::Mixed(addr, url)) => {
+                    ("mixed", Some(addr.to_string()), Some(url.to_string()))
+                }
+                _ => ("none", None, None),
+            };
+            let tables: serde_json::Map<String, serde_json::Value> = peer
+                .tables
+                .iter()
+                .map(|(table, status)| {
+                    let last_sync = millis_since_epoch(status.last_sync);
+                    let status = serde_json::json!({
+                        "connected": status.connected,
+                        "last_sync": last_sync,
+                        "last_error": status.last_error,
+                    });
+                    (table.clone(), status)
+                })
+                .collect();
+            serde_json::json!({
+                "peer": peer.peer.to_string(),
+                "connection": path,
+                "addr": addr,
+                "relay": relay,
+                "tables": tables,
+            })
+        })
+        .collect();
+    serde_json::json!({ "peers": peers })
+}
+
+fn millis_since_epoch(time: Option<std::time::SystemTime>) -> Option<u64> {
+    time.and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_millis() as u64)
+}
+
+fn status_json(
+    status: &NetStatus,
+    overall: &OverallProgress,
+    dropped_events: u64,
+) -> serde_json::Value {
+    let docs: Vec<serde_json::Value> = status
+        .docs
+        .iter()
+        .map(|doc| {
+            serde_json::json!({
+                "table": doc.table,
+                "namespace": doc.namespace.to_string(),
+                "syncing": doc.syncing,
+                "sync_peers": doc.sync_peers,
+                "neighbors": doc.neighbors,
+                "last_sync": millis_since_epoch(doc.last_sync),
+                "last_error": doc.last_error,
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "running": true,
+        "node_id": status.node_id.to_string(),
+        "home_relay": status.home_relay.as_ref().map(|url| url.to_string()),
+        "bound_addrs": status.bound_addrs.iter().map(|a| a.to_string()).collect::<Vec<_>>(),
+        "connections": status.connections,
+        "blob_store_size": status.blob_store_size,
+        "blob_store_quota": status.blob_store_quota,
+        "initial_sync_percent": overall.percent(),
+        "dropped_events": dropped_events,
+        "docs": docs,
+    })
+}
+
+fn usage_json(usage: &DiskUsage) -> serde_json::Value {
+    let tables: Vec<serde_json::Value> = usage
+        .tables
+        .iter()
+        .map(|table| {
+            serde_json::json!({
+                "table": table.table,
+                "entries": table.entries,
+                "content_bytes": table.content_bytes,
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "blobs": usage.blobs,
+        "blob_bytes": usage.blob_bytes,
+        "docs_store_bytes": usage.docs_store_bytes,
+        "storage_path_bytes": usage.storage_path_bytes,
+        "reclaimable_bytes": usage.reclaimable_bytes,
+        "tables": tables,
+    })
+}
+
+// folders depth first with their resources, indented by depth
+fn print_folder_tree(nodes: &[FolderNode], depth: usize) {
+    let indent = "  ".repeat(depth);
+    for node in nodes {
+        println!("{}📁 {}  {}", indent, node.name, node.folder_id);
+        for r in &node.resources {
+            let name = r.meta.name.as_deref().unwrap_or_default();
+            println!("{}  📄 {}  {}  {}", indent, name, r.meta.size, r.table);
+        }
+        print_folder_tree(&node.children, depth + 1);
+    }
+}
+
+fn print_usage(usage: &DiskUsage) {
+    println!("✅ Disk usage");
+    println!(
+        "   Blob store:   {} bytes in {} blobs",
+        usage.blob_bytes, usage.blobs
+    );
+    match usage.docs_store_bytes {
+        Some(bytes) => println!("   Docs store:   {} bytes", bytes),
+        None => println!("   Docs store:   in memory"),
+    }
+    if let Some(bytes) = usage.storage_path_bytes {
+        println!("   Storage path: {} bytes", bytes);
+    }
+    println!("   Reclaimable:  {} bytes", usage.reclaimable_bytes);
+    for table in &usage.tables {
+        println!(
+            "   {:<12} {} entries, {} bytes",
+            table.table, table.entries, table.content_bytes
+        );
+    }
+}
+
+fn print_status(status: &NetStatus, overall: &OverallProgress, dropped_events: u64) {
+    println!("✅ Node {}", status.node_id);
+    match &status.home_relay {
+        Some(url) => println!("   Home relay:  {}", url),
+        None => println!("   Home relay:  not connected"),
+    }
+    let addrs: Vec<String> = status.bound_addrs.iter().map(|a| a.to_string()).collect();
+    println!("   Bound:       {}", addrs.join(", "));
+    println!("   Connections: {}", status.connections);
+    match status.blob_store_quota {
+        0 => println!("   Blob store:  {} bytes", status.blob_store_size),
+        quota => println!(
+            "   Blob store:  {} of {} bytes ({}%)",
+            status.blob_store_size,
+            quota,
+            status.blob_store_size * 100 / quota
+        ),
+    }
+    println!(
+        "   Initial sync {}% ({}/{} tables, {} files left)",
+        overall.percent(),
+        overall.synced_tables,
+        overall.tables,
+        overall.remaining_files
+    );
+    if dropped_events > 0 {
+        println!("   Dropped events: {}", dropped_events);
+    }
+    for doc in &status.docs {
+        let state = if doc.syncing { "syncing" } else { "stopped" };
+        let last = match (&doc.last_error, doc.last_sync) {
+            (Some(e), _) => format!("last sync failed: {}", e),
+            (None, Some(t)) => format!("synced {}s ago", t.elapsed().unwrap_or_default().as_secs()),
+            (None, None) => "not synced".to_string(),
+        };
+        println!(
+            "   {:<12} {} {:<8} {} neighbors, {} peers, {}",
+            doc.table,
+            doc.namespace.fmt_short(),
+            state,
+            doc.neighbors,
+            doc.sync_peers,
+            last
+        );
+    }
+}
+
+// start a node keeping its data in `dir` below the storage path, or only in memory
+async fn start_node(
+    secret_key: iroh::SecretKey,
+    storage_path: &str,
+    dir: &str,
+    relay: &RelayChoice,
+    ephemeral: bool,
+) -> Result<IrohNet> {
+    let storage = if ephemeral {
+        StorageMode::Memory
+    } else {
+        let path = PathBuf::from(storage_path).join(dir);
+        fs::create_dir_all(&path)
+            .await
+            .with_context(|| format!("Failed to create {} storage directory: {:?}", dir, path))?;
+        StorageMode::Persistent(path)
+    };
+    IrohNetBuilder::new(secret_key)
+        .storage(storage)
+        .relay(relay.clone())
+        .spawn()
+        .await
+}
+
+// options of the interactive `add` command
+struct AddArgs {
+    path: Option<PathBuf>,
+    collection: String,
+    recursive: bool,
+}
+
+fn parse_add_args(args: &[&str]) -> Result<AddArgs> {
+    let mut options = AddArgs {
+        path: None,
+        collection: "resource".to_string(),
+        recursive: true,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match *arg {
+            "--collection" => {
+                let table = args
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("--collection needs a table name"))?;
+                options.collection = table.to_string();
+            }
+            "--no-recursive" => options.recursive = false,
+            path if options.path.is_none() => options.path = Some(PathBuf::from(path)),
+            other => return Err(anyhow::anyhow!("Unexpected argument '{}'", other)),
+        }
+    }
+    Ok(options)
+}
+
+// options of the interactive `ticket` command, the addressing mode of the node by default
+fn parse_ticket_args(args: &[&str]) -> Result<(ShareMode, Option<AddrInfoOptions>)> {
+    let mut mode = ShareMode::Write;
+    let mut addr = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match *arg {
+            "--read-only" => mode = ShareMode::Read,
+            "--addr" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("--addr needs id, relay, direct or both"))?;
+                addr = Some(parse_addr_info(value)?);
+            }
+            other => return Err(anyhow::anyhow!("Unexpected argument '{}'", other)),
+        }
+    }
+    Ok((mode, addr))
+}
+
+// options of the interactive `share` command, a write ticket with the addressing mode of the node by default
+fn parse_share_args(args: &[&str]) -> Result<(ShareMode, Option<AddrInfoOptions>)> {
+    let mut mode = ShareMode::Write;
+    let mut addr = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match *arg {
+            "--mode" => {
+                mode = match args.next().copied() {
+                    Some("read") => ShareMode::Read,
+                    Some("write") => ShareMode::Write,
+                    _ => return Err(anyhow::anyhow!("--mode needs read or write")),
+                };
+            }
+            "--addr" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("--addr needs id, relay, direct or both"))?;
+                addr = Some(parse_addr_info(value)?);
+            }
+            other => return Err(anyhow::anyhow!("Unexpected argument '{}'", other)),
+        }
+    }
+    Ok((mode, addr))
+}
+
+// options of the interactive `log` command
+fn parse_log_args(args: &[&str]) -> Result<(usize, Option<String>)> {
+    let mut limit = 20;
+    let mut table = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match *arg {
+            "--table" => {
+                let name = args
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("--table needs a table name"))?;
+                table = Some(name.to_string());
+            }
+            count => {
+                limit = count
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Unexpected argument '{}'", count))?
+            }
+        }
+    }
+    Ok((limit, table))
+}
+
+// parse `[--prefix <prefix>]... [--max-size <bytes>] [--ext <ext,...>]` of the filter command
+fn parse_filter_args(args: &[&str]) -> Result<SyncFilter> {
+    let mut filter = SyncFilter::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow::anyhow!("{} needs a value", arg))
+        };
+        match *arg {
+            "--prefix" => filter.prefixes.push(value()?.to_string()),
+            "--max-size" => {
+                let size = value()?;
+                filter.max_size = Some(
+                    size.parse()
+                        .map_err(|_| anyhow::anyhow!("Invalid size '{}'", size))?,
+                );
+            }
+            "--ext" => filter.extensions.extend(
+                value()?
+                    .split(',')
+                    .map(|e| e.trim().trim_start_matches('.').to_lowercase())
+                    .filter(|e| !e.is_empty()),
+            ),
+            other => return Err(anyhow::anyhow!("Unexpected argument '{}'", other)),
+        }
+    }
+    Ok(filter)
+}
+
+// print tickets of `share_tickets`, with the client command when all built-in tables are shared
+fn print_tickets(tickets: &[(String, String)], mode: ShareMode, json: bool) {
+    if json {
+        let map: serde_json::Map<String, serde_json::Value> = tickets
+            .iter()
+            .map(|(table, ticket)| (table.clone(), ticket.clone().into()))
+            .collect();
+        print_json(&serde_json::Value::Object(map));
+        return;
+    }
+    for (table, ticket) in tickets {
+        println!("🎫 {}: {}", table, ticket);
+    }
+    let builtin: Vec<&str> = TICKET_ORDER
+        .iter()
+        .filter_map(|name| {
+            tickets
+                .iter()
+                .find(|(table, _)| table == name)
+                .map(|(_, ticket)| ticket.as_str())
+        })
+        .collect();
+    if builtin.len() == TICKET_ORDER.len() {
+        let command = match mode {
+            ShareMode::Write => "client",
+            ShareMode::Read => "read",
+        };
+        // in single-doc mode all tables hand out the same ticket
+        let tickets =
+            if matches!(mode, ShareMode::Write) && builtin.iter().all(|t| *t == builtin[0]) {
+                builtin[0].to_string()
+            } else {
+                builtin.join(" ")
+            };
+        println!("Join with: ./iroh-test {} {}", command, tickets);
+    }
+}
+
+// send the files below `path` to the admin endpoint of `node`, in requests that fit a message
+async fn add_remote(
+    secret_key: iroh::SecretKey,
+    relay: &RelayChoice,
+    node: iroh::PublicKey,
+    path: &std::path::Path,
+    table: String,
+    recursive: bool,
+) -> Result<()> {
+    // leave room for the table name and the encoding of the request
+    let batch_limit = ADMIN_MAX_MESSAGE_SIZE - 64 * 1024;
+    let mut batch: Vec<(String, Vec<u8>)> = Vec::new();
+    let mut batch_bytes = 0;
+    let mut paths = collect_files(path, recursive)?.into_iter().peekable();
+    while let Some(file) = paths.next() {
+        let content = fs::read(&file)
+            .await
+            .with_context(|| format!("Failed to read file: {:?}", file))?;
+        if content.len() > batch_limit {
+            println!("❌ Skipping {:?}, it is too large to send", file);
+        } else {
+            if batch_bytes + content.len() > batch_limit {
+                send_files(&secret_key, relay, node, &table, std::mem::take(&mut batch)).await?;
+                batch_bytes = 0;
+            }
+            let name = file
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            batch_bytes += content.len();
+            batch.push((name, content));
+        }
+        if paths.peek().is_none() && !batch.is_empty() {
+            send_files(&secret_key, relay, node, &table, std::mem::take(&mut batch)).await?;
+        }
+    }
+    Ok(())
+}
+
+// stream the bytes piped into stdin to `node` as a single file named `name`
+async fn add_stdin(
+    secret_key: iroh::SecretKey,
+    relay: &RelayChoice,
+    node: iroh::PublicKey,
+    name: Option<String>,
+    table: String,
+) -> Result<()> {
+    let name = name.ok_or_else(|| anyhow::anyhow!("--name is required when reading from stdin"))?;
+    let item = StdinImporter { name }.item();
+    let request = AdminRequest::AddStream {
+        table,
+        name: item.name,
+    };
+    match send_admin_stream(secret_key, relay, node, &request, item.reader).await? {
+        AdminResponse::Done(message) => println!("✅ {}", message),
+        AdminResponse::Error(e) => return Err(anyhow::anyhow!(e)),
+        response => return Err(anyhow::anyhow!("Unexpected response {:?}", response)),
+    }
+    Ok(())
+}
+
+async fn send_files(
+    secret_key: &iroh::SecretKey,
+    relay: &RelayChoice,
+    node: iroh::PublicKey,
+    table: &str,
+    files: Vec<(String, Vec<u8>)>,
+) -> Result<()> {
+    let request = AdminRequest::AddFiles {
+        table: table.to_string(),
+        files,
+    };
+    match send_admin(secret_key.clone(), relay, node, &request).await? {
+        AdminResponse::Done(message) => println!("✅ {}", message),
+        AdminResponse::Error(e) => return Err(anyhow::anyhow!(e)),
+        response => return Err(anyhow::anyhow!("Unexpected response {:?}", response)),
+    }
+    Ok(())
+}
+
+// print the entries of `table` as an aligned table, names come from the metadata index
+async fn print_listing(state: &StoreState, table: &str) -> Result<()> {
+    let entries = state.list_entries(table).await?;
+    let names: std::collections::HashMap<String, String> = state
+        .iroh
+        .sql_index
+        .query(MetaQuery::table(table))
+        .await?
+        .into_iter()
+        .filter_map(|meta| Some((meta.key, meta.name?)))
+        .collect();
+    println!("📋 {} ({} entries)", table, entries.len());
+    println!(
+        "  {:<36}  {:<32}  {:>10}  {:<10}  {}",
+        "ID", "NAME", "SIZE", "AUTHOR", "SYNCED"
+    );
+    // a resource is only synced once the blobs holding its content are here as well
+    let placements = match state.tables.get::<Resources>(table).await {
+        Some(resources) => resources.placements().await.unwrap_or_default(),
+        None => std::collections::HashMap::new(),
+    };
+    let store = &state.iroh.blobs_store;
+    for entry in entries {
+        let name = names.get(&entry.key).map(String::as_str).unwrap_or("-");
+        let mut synced = store.blobs().has(entry.hash).await.unwrap_or(false);
+        for content in placements.get(&entry.hash).into_iter().flatten() {
+            if !synced {
+                break;
+            }
+            synced = has_content(store, *content).await.unwrap_or(false);
+        }
+        println!(
+            "  {:<36}  {:<32}  {:>10}  {:<10}  {}",
+            entry.key,
+            truncate(name, 32),
+            entry.size,
+            entry.author.fmt_short(),
+            if synced { "yes" } else { "no" }
+        );
+    }
+    Ok(())
+}
+
+// entities of a table read with `read`
+fn print_table_listing(table_name: &str, listing: &TableListing) {
+    match listing {
+        TableListing::Folders(folders) => {
+            println!("📁 {} ({} entries)", table_name, folders.len());
+            for folder in folders {
+                println!("  {:<36}  {}", folder.folder_id, folder.folder_name);
+            }
+        }
+        TableListing::Nodes(nodes) => {
+            println!("🖥  {} ({} entries)", table_name, nodes.len());
+            for node in nodes {
+                println!("  {:<36}  {}", node.node_id, node.node_name);
+            }
+        }
+        TableListing::Resources(resources) => {
+            println!("🖼  {} ({} entries)", table_name, resources.len());
+            for resource in resources {
+                println!(
+                    "  {:<36}  {:>10}  {:<24}  {}  {}",
+                    resource.id,
+                    resource.size,
+                    resource.content_type.as_deref().unwrap_or("-"),
+                    resource.author.fmt_short(),
+                    resource.name
+                );
+            }
+        }
+    }
+}
+
+// table names and entity ids offered by tab completion, read from the metadata index
+async fn completion_words(state: &StoreState) -> Vec<String> {
+    let mut tables: Vec<String> = TICKET_ORDER.iter().map(|t| t.to_string()).collect();
+    tables.extend(state.list_collections().await);
+    let mut words = tables.clone();
+    for table in tables {
+        if let Ok(entities) = state.iroh.sql_index.query(MetaQuery::table(&table)).await {
+            words.extend(entities.into_iter().map(|meta| meta.key));
+        }
+    }
+    words
+}
+
+// shorten `s` to at most `max` characters, marking the cut with an ellipsis
+fn truncate(s: &str, max: usize) -> String {
+    if s.chars().count() <= max {
+        return s.to_string();
+    }
+    let mut short: String = s.chars().take(max - 1).collect();
+    short.push('…');
+    short
+}
+
+fn print_fetched(resource: &Resource, path: &std::path::Path, size: u64) {
+    println!(
+        "✅ Wrote {} ({} bytes, hash {}) to {:?}",
+        resource.name,
+        size,
+        resource.content_hash(),
+        path
+    );
+}
+
+// redraw a progress bar of `done` out of `total` bytes on the current line
+fn print_progress(files: usize, done: u64, total: u64) {
+    const WIDTH: u64 = 30;
+    let total = total.max(done).max(1);
+    let filled = (done * WIDTH / total) as usize;
+    print!(
+        "\r[{}{}] {:>3}% {:.1}/{:.1} MB, {} files",
+        "#".repeat(filled),
+        ".".repeat(WIDTH as usize - filled),
+        done * 100 / total,
+        done as f64 / 1_048_576.0,
+        total as f64 / 1_048_576.0,
+        files
+    );
+    let _ = std::io::Write::flush(&mut std::io::stdout());
+}
 
 fn parse_secret_key(s: &str) -> Result<Vec<u8>, String> {
     // Handle array format [1,2,3,4] or [1, 2, 3, 4]
@@ -50,54 +691,331 @@ struct Args {
     #[clap(long, short = 'k')]
     secret_key: Option<String>,
 
+    /// Secondary storage root for large resources, e.g. an external drive
+    #[clap(long)]
+    external_storage: Option<PathBuf>,
+
+    /// Resources up to this many bytes are stored inline in the doc entry
+    #[clap(long, default_value_t = DEFAULT_INLINE_MAX)]
+    inline_max: usize,
+
+    /// Resources from this many bytes are stored under the external storage root
+    #[clap(long, default_value_t = DEFAULT_EXTERNAL_MIN)]
+    external_min: usize,
+
+    /// Largest file accepted in bytes, 0 for no limit
+    #[clap(long, default_value_t = DEFAULT_MAX_FILE_SIZE)]
+    max_file_size: u64,
+
+    /// Resources stored in the local blob store above this many bytes are split into chunks of this size
+    #[clap(long, default_value_t = DEFAULT_CHUNK_SIZE)]
+    chunk_size: usize,
+
+    /// Maximum number of blobs downloaded at the same time
+    #[clap(long, default_value_t = DEFAULT_DOWNLOAD_CONCURRENCY)]
+    download_concurrency: usize,
+
+    /// Minutes the invitation code printed by the server stays valid
+    #[clap(long, default_value_t = 10)]
+    invite_ttl: u64,
+
+    /// Also serve the tickets under a PIN, clients on the same network join with `client --pin`, valid for --invite-ttl minutes
+    #[clap(long)]
+    pair: bool,
+
+    /// Maximum rate of resource content downloads in bytes per second, 0 for unlimited. Doc entries fetched by the sync itself are not paced
+    #[clap(long, default_value_t = 0)]
+    download_limit: u64,
+
+    /// Maximum rate of blobs served to other peers in bytes per second, 0 for unlimited
+    #[clap(long, default_value_t = 0)]
+    upload_limit: u64,
+
+    /// Application events buffered per table until they are read, --event-overflow applies above it
+    #[clap(long, default_value_t = DEFAULT_EVENT_CAPACITY)]
+    event_capacity: usize,
+
+    /// What happens to new events while the buffer of a table is full: block, drop-newest, drop-oldest or coalesce
+    #[clap(long, default_value_t = OverflowPolicy::DropNewest)]
+    event_overflow: OverflowPolicy,
+
+    /// Seconds between resyncs of every doc with its previous peers, which also re-requests missing content, 0 to disable
+    #[clap(long, default_value_t = DEFAULT_ANTI_ENTROPY_INTERVAL.as_secs())]
+    anti_entropy: u64,
+
+    /// Maximum size of the blob store in bytes, least recently read blobs are evicted above it, 0 for unlimited
+    #[clap(long, default_value_t = 0)]
+    max_storage: u64,
+
+    /// Relay server url, shorthand for `--relay-mode custom:<url>`
+    #[clap(long, env = RELAY_ENV, value_parser = parse_relay_url)]
+    relay_url: Option<RelayUrl>,
+
+    /// Relay servers to use: custom:<url>, default (iroh's public relays) or disabled, overrides --relay-url
+    #[clap(long)]
+    relay_mode: Option<RelayChoice>,
+
+    /// LAN only: no relays, peers are found via local discovery, same as `--relay-mode disabled`
+    #[clap(long, conflicts_with = "relay_mode")]
+    no_relay: bool,
+
+    /// Node ids allowed to send admin requests to this node, can be repeated
+    #[clap(long)]
+    admin_allow: Vec<iroh::PublicKey>,
+
+    /// Number of nodes that should hold every blob
+    #[clap(long, default_value_t = 2)]
+    replication_factor: usize,
+
+    /// Encrypt doc values with a key derived from this passphrase
+    #[clap(long, env = "IROH_TEST_PASSPHRASE", conflicts_with = "encryption_key")]
+    passphrase: Option<String>,
+
+    /// Encrypt doc values with this 32 byte key, encoded as hex
+    #[clap(long, env = "IROH_TEST_ENCRYPTION_KEY")]
+    encryption_key: Option<String>,
+
+    /// Also read plain values and values encrypted before they were bound to their entry, only while migrating old data
+    #[clap(long)]
+    accept_legacy: bool,
+
+    /// Log filter, a level like `debug` or per module targets like `info,iroh_test::doc_subcribe=debug`
+    #[clap(long, env = "RUST_LOG", default_value = "info")]
+    log: String,
+
+    /// Directory of the test data loaded into new resource tables, guessed from the executable location when unset
+    #[clap(long, env = IMAGES_DIR_ENV)]
+    images_dir: Option<PathBuf>,
+
+    /// Name other peers see in the node list, the short node id by default
+    #[clap(long)]
+    name: Option<String>,
+
+    /// Store dimensions, capture time and camera of added images, lets `sorted resource captured` order a gallery by when photos were taken
+    #[clap(long)]
+    exif: bool,
+
+    /// Keep all built-in tables in one doc under key prefixes, so clients join with a single ticket
+    #[clap(long)]
+    single_doc: bool,
+
+    /// Keep blobs, docs and indexes in memory only, nothing is written below the storage path
+    #[clap(long)]
+    ephemeral: bool,
+
+    /// Print the results of get, get_folder, peers and status as JSON lines
+    #[clap(long, global = true)]
+    json: bool,
+
     #[clap(subcommand)]
     command: Commands,
 }
 
+// what `server`, `client` and `dashboard` start
+enum NodeCommand {
+    Server,
+    // tickets in the order of `client`, empty with a code, PIN or manifest
+    Client {
+        code: Option<String>,
+        manifest: Option<String>,
+        pin: Option<String>,
+        tickets: Vec<String>,
+    },
+}
+
 #[derive(clap::Subcommand, Debug, Clone)]
 pub enum Commands {
     /// Start the server
     Server,
-    /// Join the server
+    /// Join the server, without tickets or code the tables of the previous start are reopened
     Client {
+        /// Invitation code printed by the server, replaces the tickets
+        #[clap(long)]
+        code: Option<String>,
+        /// Manifest ticket printed by the server, replaces the tickets and joins collections created later too
+        #[clap(long, conflicts_with = "code")]
+        manifest: Option<String>,
+        /// Pairing PIN printed by a server started with --pair, replaces the tickets
+        #[clap(long, conflicts_with_all = ["code", "manifest"])]
+        pin: Option<String>,
         /// Resource ticket for accessing resources
         #[clap(
             value_name = "RESOURCE_TICKET",
             help = "Resource ticket for resource access"
         )]
-        resource_ticket: String,
+        resource_ticket: Option<String>,
         /// Folder ticket for accessing folders
         #[clap(value_name = "FOLDER_TICKET", help = "Folder ticket for folder access")]
-        folder_ticket: String,
+        folder_ticket: Option<String>,
         /// Node ticket for connecting to the server
         #[clap(value_name = "NODE_TICKET", help = "Node ticket for connecting")]
-        node_ticket: String,
+        node_ticket: Option<String>,
         #[clap(
             value_name = "RESOURCE_TICKET1",
             help = "Resource ticket1 for resource access"
         )]
-        resource_ticket1: String,
+        resource_ticket1: Option<String>,
         #[clap(
             value_name = "RESOURCE_TICKET2",
             help = "Resource ticket2 for resource access"
         )]
-        resource_ticket2: String,
+        resource_ticket2: Option<String>,
         #[clap(
             value_name = "RESOURCE_TICKET3",
             help = "Resource ticket3 for resource access"
         )]
-        resource_ticket3: String,
+        resource_ticket3: Option<String>,
+    },
+    /// Start a server, or join with tickets or a code like `client`, and show a live dashboard
+    Dashboard {
+        /// Invitation code printed by the server, replaces the tickets
+        #[clap(long)]
+        code: Option<String>,
+        /// Tickets printed by the server, in the same order as for `client`
+        #[clap(value_name = "TICKETS", num_args = 0..=6)]
+        tickets: Vec<String>,
+    },
+    /// Invoke an action on a running node, the node must allow this node's id with --admin-allow
+    Admin {
+        /// Node id of the running node
+        node_id: iroh::PublicKey,
+        #[clap(subcommand)]
+        action: AdminAction,
+    },
+    /// Add a file or directory to a running node, the node must allow this node's id with --admin-allow
+    Add {
+        /// File or directory to add, `-` reads the content of a single file from stdin
+        path: PathBuf,
+        /// Name of the resource read from stdin
+        #[clap(long)]
+        name: Option<String>,
+        /// Node id of the running node
+        #[clap(long)]
+        node: iroh::PublicKey,
+        /// Resource table or collection receiving the files
+        #[clap(long, default_value = "resource")]
+        collection: String,
+        /// Only add the files directly inside a directory
+        #[clap(long)]
+        no_recursive: bool,
+    },
+    /// Download a single resource from a resource ticket
+    Get {
+        /// Id of the resource
+        id: String,
+        /// File to write, defaults to the resource name in the current directory
+        out: Option<PathBuf>,
+        /// Ticket of the resource table holding the resource
+        #[clap(long)]
+        ticket: String,
+        /// Seconds to wait for the initial sync before reading
+        #[clap(long, default_value_t = 30)]
+        timeout: u64,
     },
     /// Read data from the server
-    Read,
+    Read {
+        /// Tickets printed by the server, in the same order as for `client`
+        #[clap(value_name = "TICKETS", required = true, num_args = 1..=6)]
+        tickets: Vec<String>,
+        /// Seconds to wait for the initial sync before listing
+        #[clap(long, default_value_t = 30)]
+        timeout: u64,
+    },
+    /// Print fresh tickets for the tables saved by a previous start, while the node is stopped
+    Ticket {
+        /// Storage directory of the node below the storage path, `server` or `client1`
+        #[clap(long, default_value = "server")]
+        node: String,
+        /// Share read-only tickets instead of write tickets
+        #[clap(long)]
+        read_only: bool,
+        /// Addresses put into the tickets: id, relay, direct or both
+        #[clap(long, value_parser = parse_addr_info)]
+        addr: Option<AddrInfoOptions>,
+    },
+    /// Export the node key, the author and the namespace secrets of the saved tables, while the node is stopped
+    BackupKeys {
+        /// Storage directory of the node below the storage path, `server` or `client1`
+        #[clap(long, default_value = "server")]
+        node: String,
+        /// File to write the secrets to
+        #[clap(long)]
+        out: PathBuf,
+        /// Encrypt the file with a key derived from this passphrase
+        #[clap(
+            long,
+            env = "IROH_TEST_BACKUP_PASSPHRASE",
+            required_unless_present = "plaintext"
+        )]
+        backup_passphrase: Option<String>,
+        /// Write the secrets unencrypted instead, anyone holding the file can write to the tables
+        #[clap(long, conflicts_with = "backup_passphrase")]
+        plaintext: bool,
+    },
+    /// Write the entries of the saved tables and the blob store to a .tar.zst archive, while the node is stopped
+    #[cfg(feature = "snapshot")]
+    Backup {
+        /// Storage directory of the node below the storage path, `server` or `client1`
+        #[clap(long, default_value = "server")]
+        node: String,
+        /// Archive to write
+        #[clap(long)]
+        out: PathBuf,
+        /// Encrypt the docs store in the archive, which holds the secrets, with a key derived from this passphrase
+        #[clap(long, env = "IROH_TEST_BACKUP_PASSPHRASE")]
+        backup_passphrase: String,
+    },
+    /// Recreate the tables and blobs of an archive written by `backup` in a fresh storage directory
+    #[cfg(feature = "snapshot")]
+    Restore {
+        /// Storage directory of the node below the storage path, `server` or `client1`
+        #[clap(long, default_value = "server")]
+        node: String,
+        /// Archive written by `backup`
+        #[clap(long = "in")]
+        input: PathBuf,
+        /// Passphrase the archive was written with
+        #[clap(long, env = "IROH_TEST_BACKUP_PASSPHRASE")]
+        backup_passphrase: String,
+    },
+    /// Import the secrets written by `backup-keys` into a fresh storage directory
+    RestoreKeys {
+        /// Storage directory of the node below the storage path, `server` or `client1`
+        #[clap(long, default_value = "server")]
+        node: String,
+        /// File written by `backup-keys`
+        #[clap(long = "in")]
+        input: PathBuf,
+        /// Passphrase the file was encrypted with
+        #[clap(long, env = "IROH_TEST_BACKUP_PASSPHRASE")]
+        backup_passphrase: Option<String>,
+    },
+}
+
+#[derive(clap::Subcommand, Debug, Clone)]
+pub enum AdminAction {
+    /// Add a folder
+    AddFolder { name: String },
+    /// List the resources
+    Resources,
+    /// Show table sizes and connection counters
+    Stats,
+    /// Stop the node
+    Shutdown,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt().with_env_filter("info").init();
-
     let args = Args::parse();
 
+    let logs = tracing_subscriber::fmt().with_env_filter(EnvFilter::new(&args.log));
+    if matches!(args.command, Commands::Dashboard { .. }) {
+        // log lines would tear up the dashboard
+        logs.with_writer(std::io::sink).init();
+    } else {
+        logs.init();
+    }
+
     let iroh_secret_key = if let Some(secret_key_str) = args.secret_key {
         match parse_secret_key(&secret_key_str) {
             Ok(secret_key_bytes) => match secret_key_bytes.as_slice().try_into() {
@@ -127,68 +1045,458 @@ async fn main() -> anyhow::Result<()> {
     );
 
     let storage_path = args.storage_path;
+    let json = args.json;
+    let replication_factor = args.replication_factor;
+    let placement = PlacementPolicy {
+        inline_max: args.inline_max,
+        external_min: args.external_min,
+        external_root: args.external_storage,
+        max_file_size: args.max_file_size,
+        chunk_size: args.chunk_size.max(1),
+    };
+    let event_config = EventChannelConfig {
+        capacity: args.event_capacity,
+        overflow: args.event_overflow,
+    };
+    let cipher = match (&args.passphrase, &args.encryption_key) {
+        (Some(passphrase), _) => Some(DocCipher::from_passphrase(passphrase)?),
+        (None, Some(key)) => Some(DocCipher::from_hex(key)?),
+        (None, None) => None,
+    }
+    .map(|cipher| cipher.accept_legacy(args.accept_legacy));
+    if cipher.is_some() {
+        println!("Doc values are encrypted");
+        if args.accept_legacy {
+            println!(
+                "⚠️ Plain and legacy encrypted values are accepted, run `migrate <table>` to seal them again"
+            );
+        }
+    }
+
+    let relay = match (args.no_relay, args.relay_mode, args.relay_url) {
+        (true, _, _) => RelayChoice::Disabled,
+        (false, Some(relay), _) => relay,
+        (false, None, Some(url)) => RelayChoice::Custom(url),
+        (false, None, None) => RelayChoice::Default,
+    };
+    println!("Relay mode: {}", relay);
+    let ephemeral = args.ephemeral;
+    if ephemeral {
+        println!("Ephemeral mode, nothing is stored on disk");
+    }
+
+    // kept alive so the invitation can be redeemed while the server runs
+    let mut _invitation = None;
+    let mut _pairing = None;
+
+    // `server` and `client` start a node, the dashboard runs one of them and replaces the
+    // interactive loop, every other command returns once it is done
+    let (node, dashboard) = match args.command {
+        Commands::Server => (NodeCommand::Server, false),
+        Commands::Client {
+            code,
+            manifest,
+            pin,
+            resource_ticket,
+            folder_ticket,
+            node_ticket,
+            resource_ticket1,
+            resource_ticket2,
+            resource_ticket3,
+        } => {
+            let tickets = [
+                resource_ticket,
+                folder_ticket,
+                node_ticket,
+                resource_ticket1,
+                resource_ticket2,
+                resource_ticket3,
+            ]
+            .into_iter()
+            .flatten()
+            .collect();
+            let command = NodeCommand::Client {
+                code,
+                manifest,
+                pin,
+                tickets,
+            };
+            (command, false)
+        }
+        Commands::Dashboard { code, tickets } if code.is_none() && tickets.is_empty() => {
+            (NodeCommand::Server, true)
+        }
+        Commands::Dashboard { code, tickets } => {
+            let command = NodeCommand::Client {
+                code,
+                manifest: None,
+                pin: None,
+                tickets,
+            };
+            (command, true)
+        }
+        Commands::Admin { node_id, action } => {
+            let request = match action {
+                AdminAction::AddFolder { name } => AdminRequest::AddFolder { name },
+                AdminAction::Resources => AdminRequest::ListResources,
+                AdminAction::Stats => AdminRequest::Stats,
+                AdminAction::Shutdown => AdminRequest::Shutdown,
+            };
+            match send_admin(iroh_secret_key, &relay, node_id, &request).await? {
+                AdminResponse::Done(message) => println!("✅ {}", message),
+                AdminResponse::Resources(resources) => {
+                    println!("✅ {} resources:", resources.len());
+                    for (id, name, size) in resources {
+                        println!("  {:<36}  {:>10}  {}", id, size, name);
+                    }
+                }
+                AdminResponse::Stats(stats) => println!("✅ {:#?}", stats),
+                AdminResponse::Error(e) => println!("❌ {}", e),
+            }
+            return Ok(());
+        }
+        Commands::Add {
+            path,
+            name,
+            node,
+            collection,
+            no_recursive,
+        } => {
+            if path == Path::new("-") {
+                add_stdin(iroh_secret_key, &relay, node, name, collection).await?;
+                return Ok(());
+            }
+            add_remote(
+                iroh_secret_key,
+                &relay,
+                node,
+                &path,
+                collection,
+                !no_recursive,
+            )
+            .await?;
+            return Ok(());
+        }
+        Commands::Get {
+            id,
+            out,
+            ticket,
+            timeout,
+        } => {
+            let mut iroh_net =
+                start_node(iroh_secret_key, &storage_path, "reader", &relay, ephemeral).await?;
+            iroh_net.cipher = cipher;
+            let result = fetch_resource(
+                &iroh_net,
+                ticket.parse()?,
+                &id,
+                out,
+                Duration::from_secs(timeout),
+            )
+            .await;
+            iroh_net.router.shutdown().await?;
+            let (resource, path, size) = result?;
+            print_fetched(&resource, &path, size);
+            return Ok(());
+        }
+        Commands::Read { tickets, timeout } => {
+            println!("Reading data from server...");
+            let mut iroh_net =
+                start_node(iroh_secret_key, &storage_path, "reader", &relay, ephemeral).await?;
+            iroh_net.cipher = cipher;
+
+            let mut ticket_map = std::collections::HashMap::new();
+            for (table_name, ticket) in TICKET_ORDER.iter().zip(tickets) {
+                ticket_map.insert(table_name.to_string(), ticket.parse()?);
+            }
+            let listings = read_tables(&iroh_net, ticket_map, Duration::from_secs(timeout)).await;
+            iroh_net.router.shutdown().await?;
+            for (table_name, listing) in listings? {
+                print_table_listing(&table_name, &listing);
+            }
+            return Ok(());
+        }
+        Commands::BackupKeys {
+            node,
+            out,
+            backup_passphrase,
+            plaintext: _,
+        } => {
+            anyhow::ensure!(!ephemeral, "An ephemeral node has no saved tables");
+            let iroh_net = start_node(iroh_secret_key, &storage_path, &node, &relay, false).await?;
+            let saved = match iroh_net.storage_path() {
+                Some(root) => load_tables(root).await?,
+                None => None,
+            };
+            let Some(saved) = saved else {
+                iroh_net.router.shutdown().await?;
+                return Err(anyhow::anyhow!("No tables saved by node '{}'", node));
+            };
+            let result = backup_keys(&iroh_net, &saved).await;
+            iroh_net.router.shutdown().await?;
+            let backup = result?;
+            write_key_backup(&out, &backup, backup_passphrase.as_deref()).await?;
+            println!(
+                "✅ Wrote the keys of {} tables to {:?}{}",
+                backup.tables.len(),
+                out,
+                if backup_passphrase.is_some() {
+                    ", encrypted"
+                } else {
+                    ""
+                }
+            );
+            if backup_passphrase.is_none() {
+                println!("⚠️ The file is not encrypted, anyone holding it can write to the tables");
+            }
+            return Ok(());
+        }
+        #[cfg(feature = "snapshot")]
+        Commands::Backup {
+            node,
+            out,
+            backup_passphrase,
+        } => {
+            anyhow::ensure!(!ephemeral, "An ephemeral node has no saved tables");
+            let iroh_net = start_node(iroh_secret_key, &storage_path, &node, &relay, false).await?;
+            let saved = match iroh_net.storage_path() {
+                Some(root) => load_tables(root).await?,
+                None => None,
+            };
+            let Some(saved) = saved else {
+                iroh_net.router.shutdown().await?;
+                return Err(anyhow::anyhow!("No tables saved by node '{}'", node));
+            };
+            // shuts the node down before the docs store is copied
+            let index = snapshot::backup(iroh_net, &saved, &out, &backup_passphrase).await?;
+            println!(
+                "✅ Wrote {} tables and {} blobs to {:?}",
+                index.saved.tables.len(),
+                index.blobs,
+                out
+            );
+            return Ok(());
+        }
+        #[cfg(feature = "snapshot")]
+        Commands::Restore {
+            node,
+            input,
+            backup_passphrase,
+        } => {
+            anyhow::ensure!(
+                !ephemeral,
+                "An ephemeral node cannot keep a restored snapshot"
+            );
+            let snapshot = snapshot::unpack(&input).await?;
+            // the docs store is put in place before the node opens it
+            snapshot
+                .restore_docs(
+                    &PathBuf::from(&storage_path).join(&node),
+                    &backup_passphrase,
+                )
+                .await?;
+            let iroh_net = start_node(iroh_secret_key, &storage_path, &node, &relay, false).await?;
+            let result = snapshot.restore_blobs(&iroh_net).await;
+            iroh_net.router.shutdown().await?;
+            let blobs = result?;
+            println!(
+                "✅ Restored {} tables and {} blobs, start the node with the same --secret-key to serve them",
+                snapshot.index.saved.tables.len(),
+                blobs
+            );
+            return Ok(());
+        }
+        Commands::RestoreKeys {
+            node,
+            input,
+            backup_passphrase,
+        } => {
+            anyhow::ensure!(!ephemeral, "An ephemeral node cannot keep restored keys");
+            let backup = read_key_backup(&input, backup_passphrase.as_deref()).await?;
+            // the node id is derived from the key, peers find the restored node under the old id
+            let secret_key = backup.node_secret_key()?;
+            let node_id = secret_key.public();
+            let iroh_net = start_node(secret_key, &storage_path, &node, &relay, false).await?;
+            let result = restore_keys(&iroh_net, &backup).await;
+            iroh_net.router.shutdown().await?;
+            let saved = result?;
+            println!("✅ Restored {} tables", saved.tables.len());
+            println!(
+                "Start the node with the --secret-key it was backed up with, its node id is {}",
+                node_id
+            );
+            return Ok(());
+        }
+        Commands::Ticket {
+            node,
+            read_only,
+            addr,
+        } => {
+            anyhow::ensure!(!ephemeral, "An ephemeral node has no saved tables");
+            let iroh_net = start_node(iroh_secret_key, &storage_path, &node, &relay, false).await?;
+            let saved = match iroh_net.storage_path() {
+                Some(root) => load_tables(root).await?,
+                None => None,
+            };
+            let Some(saved) = saved else {
+                iroh_net.router.shutdown().await?;
+                return Err(anyhow::anyhow!("No tables saved by node '{}'", node));
+            };
+            // tickets carry the home relay only once the endpoint is connected to it
+            if relay != RelayChoice::Disabled
+                && tokio::time::timeout(
+                    Duration::from_secs(10),
+                    iroh_net.router.endpoint().online(),
+                )
+                .await
+                .is_err()
+            {
+                println!("⚠️ Not connected to a relay, the tickets only carry direct addresses");
+            }
+            let mode = if read_only {
+                ShareMode::Read
+            } else {
+                ShareMode::Write
+            };
+            let addr = addr.unwrap_or_else(|| iroh_net.addr_info());
+            let result = share_saved_tables(&iroh_net, &saved, mode, addr).await;
+            iroh_net.router.shutdown().await?;
+            print_tickets(&result?, mode, json);
+            return Ok(());
+        }
+    };
 
-    let store_state = match args.command {
-        Commands::Server => {
+    let store_state = match node {
+        NodeCommand::Server => {
             let client_secret_key = String::from(
                 "[89,188,181,9,112,70,251,252,214,80,117,4,225,245,67,162,60,124,215,26,121,9, 14, 212, 25, 38, 103, 185, 247, 133, 224, 240]",
             );
             println!("Starting server...");
-            let server_src = PathBuf::from(&storage_path).join("server");
-            if !server_src.exists() {
-                fs::create_dir_all(&server_src).await.with_context(|| {
-                    format!(
-                        "Failed to create server storage directory: {:?}",
-                        server_src
-                    )
-                })?;
+            let mut iroh_net =
+                start_node(iroh_secret_key, &storage_path, "server", &relay, ephemeral).await?;
+            iroh_net.placement = placement;
+            iroh_net.images_dir = args.images_dir.clone();
+            iroh_net.display_name = args.name.clone();
+            iroh_net.extract_image_meta = args.exif;
+            if args.single_doc {
+                iroh_net.enable_single_doc();
             }
-            let server_path = server_src.to_string_lossy().into_owned();
-            let iroh_net = start_server(iroh_secret_key, server_path).await?;
+            iroh_net.cipher = cipher;
+            iroh_net.set_download_concurrency(args.download_concurrency);
+            iroh_net.events = event_config;
+            iroh_net.anti_entropy = Duration::from_secs(args.anti_entropy);
+            iroh_net.set_download_limit(args.download_limit);
+            iroh_net.set_upload_limit(args.upload_limit);
+            iroh_net.quota.set_max_bytes(args.max_storage);
+            iroh_net.admin.allow(args.admin_allow.clone()).await;
             let store_state = create_files(&iroh_net, None).await?;
             println!("Server started.");
             println!(
                 "Use the following commands to connect clients: ./iroh-test --secret-key \"{}\" client {}",
                 client_secret_key, store_state.ticket_string
             );
+            if let Some(manifest) = store_state.manifest_ticket() {
+                println!(
+                    "Or join all tables, including collections created later, with: ./iroh-test client --manifest {}",
+                    manifest
+                );
+            }
+            match publish_invite(
+                store_state.ticket_string.clone(),
+                Duration::from_secs(args.invite_ttl * 60),
+                &relay,
+            )
+            .await
+            {
+                Ok(invite) => {
+                    println!(
+                        "Or join within {} minutes with: ./iroh-test client --code {}",
+                        args.invite_ttl, invite.code
+                    );
+                    _invitation = Some(invite);
+                }
+                Err(e) => println!("Failed to publish invitation code: {}", e),
+            }
+            if args.pair {
+                match publish_pairing(
+                    store_state.ticket_string.clone(),
+                    Duration::from_secs(args.invite_ttl * 60),
+                    &relay,
+                )
+                .await
+                {
+                    Ok(pairing) => {
+                        println!(
+                            "Or pair within {} minutes with: ./iroh-test client --pin {}",
+                            args.invite_ttl, pairing.pin
+                        );
+                        _pairing = Some(pairing);
+                    }
+                    Err(e) => println!("Failed to start pairing: {}", e),
+                }
+            }
             Some(store_state)
         }
-        Commands::Client {
-            resource_ticket,
-            folder_ticket,
-            node_ticket,
-            resource_ticket1,
-            resource_ticket2,
-            resource_ticket3,
+        NodeCommand::Client {
+            code,
+            manifest,
+            pin,
+            tickets,
         } => {
-            println!("Resource ticket: {}", resource_ticket);
-            println!("Folder ticket: {}", folder_ticket);
-            println!("Node ticket: {}", node_ticket);
-            println!("Resource ticket1: {}", resource_ticket1);
-            println!("Resource ticket2: {}", resource_ticket2);
-            println!("Resource ticket3: {}", resource_ticket3);
+            let tickets: Vec<String> = match (code, pin) {
+                (Some(code), _) => {
+                    println!("Redeeming invitation code {}...", code);
+                    let bundle = redeem_invite(&code, &relay).await?;
+                    bundle.split_whitespace().map(str::to_string).collect()
+                }
+                (None, Some(pin)) => {
+                    println!("Looking for a server pairing with PIN {}...", pin);
+                    let bundle = pair(&pin, &relay, DEFAULT_PAIRING_TIMEOUT).await?;
+                    bundle.split_whitespace().map(str::to_string).collect()
+                }
+                (None, None) => tickets,
+            };
+            // a single ticket joins the doc of a server in single-doc mode
+            let single_doc = args.single_doc || tickets.len() == 1;
+            // without tickets the tables saved by the previous start are reopened
+            let tickets = if tickets.is_empty() {
+                None
+            } else if let [ticket] = tickets.as_slice() {
+                println!("Ticket: {}", ticket);
+                let ticket: DocTicket = ticket.parse()?;
+                Some(
+                    TICKET_ORDER
+                        .iter()
+                        .map(|table| (table.to_string(), ticket.clone()))
+                        .collect(),
+                )
+            } else {
+                let [
+                    resource_ticket,
+                    folder_ticket,
+                    node_ticket,
+                    resource_ticket1,
+                    resource_ticket2,
+                    resource_ticket3,
+                ]: [String; 6] = tickets
+                    .try_into()
+                    .map_err(|_| anyhow::anyhow!("Expected 1 or {} tickets", TICKET_ORDER.len()))?;
+                println!("Resource ticket: {}", resource_ticket);
+                println!("Folder ticket: {}", folder_ticket);
+                println!("Node ticket: {}", node_ticket);
+                println!("Resource ticket1: {}", resource_ticket1);
+                println!("Resource ticket2: {}", resource_ticket2);
+                println!("Resource ticket3: {}", resource_ticket3);
+                let mut tickets = std::collections::HashMap::new();
+                tickets.insert("node".to_string(), node_ticket.parse()?);
+                tickets.insert("folder".to_string(), folder_ticket.parse()?);
+                tickets.insert("resource".to_string(), resource_ticket.parse()?);
+                tickets.insert("resource1".to_string(), resource_ticket1.parse()?);
+                tickets.insert("resource2".to_string(), resource_ticket2.parse()?);
+                tickets.insert("resource3".to_string(), resource_ticket3.parse()?);
+                Some(tickets)
+            };
             println!("Starting client...");
-            let client_src = PathBuf::from(&storage_path).join("client");
-            if !client_src.exists() {
-                fs::create_dir_all(&client_src).await.with_context(|| {
-                    format!(
-                        "Failed to create client storage directory: {:?}",
-                        client_src
-                    )
-                })?;
-            }
-            let client_src1 = PathBuf::from(&storage_path).join("client1");
-            if !client_src1.exists() {
-                fs::create_dir_all(&client_src1).await.with_context(|| {
-                    format!(
-                        "Failed to create client storage directory: {:?}",
-                        client_src1
-                    )
-                })?;
-            }
-
-            let client_path = client_src.to_string_lossy().into_owned();
-            let client_path1 = client_src1.to_string_lossy().into_owned();
 
             // If you want to restart the client with a new connection, uncomment the following lines to stop the previous instance
             // But it cause some issue
@@ -199,105 +1507,389 @@ async fn main() -> anyhow::Result<()> {
             // iroh_net.router.shutdown().await?;
             // sleep(Duration::from_secs(1)).await;
 
-            let iroh_net1 = start_server(iroh_secret_key, client_path1).await?;
+            let mut iroh_net1 =
+                start_node(iroh_secret_key, &storage_path, "client1", &relay, ephemeral).await?;
+            iroh_net1.placement = placement;
+            iroh_net1.images_dir = args.images_dir.clone();
+            iroh_net1.display_name = args.name.clone();
+            iroh_net1.extract_image_meta = args.exif;
+            if single_doc {
+                iroh_net1.enable_single_doc();
+            }
+            iroh_net1.cipher = cipher;
+            iroh_net1.set_download_concurrency(args.download_concurrency);
+            iroh_net1.events = event_config;
+            iroh_net1.anti_entropy = Duration::from_secs(args.anti_entropy);
+            iroh_net1.set_download_limit(args.download_limit);
+            iroh_net1.set_upload_limit(args.upload_limit);
+            iroh_net1.quota.set_max_bytes(args.max_storage);
+            iroh_net1.admin.allow(args.admin_allow.clone()).await;
 
-            let mut tickets = std::collections::HashMap::new();
-            tickets.insert("node".to_string(), node_ticket.parse()?);
-            tickets.insert("folder".to_string(), folder_ticket.parse()?);
-            tickets.insert("resource".to_string(), resource_ticket.parse()?);
-            tickets.insert("resource1".to_string(), resource_ticket1.parse()?);
-            tickets.insert("resource2".to_string(), resource_ticket2.parse()?);
-            tickets.insert("resource3".to_string(), resource_ticket3.parse()?);
-            let store_state = create_files(&iroh_net1, Some(tickets)).await?;
+            // the manifest lists the tickets of all tables
+            let tickets = match manifest {
+                Some(manifest) => {
+                    println!("Joining the manifest {}...", manifest);
+                    let ticket: DocTicket = manifest.parse()?;
+                    let manifest = Manifest::new(&Some(ticket.clone()), iroh_net1.clone()).await?;
+                    let mut tickets = manifest.join_tickets(&TICKET_ORDER, JOIN_TIMEOUT).await?;
+                    // collections are joined once the tables are open, see `take_manifest_updates`
+                    tickets.retain(|table, _| TICKET_ORDER.contains(&table.as_str()));
+                    // a server in single-doc mode announces the same doc for every table
+                    let namespace = tickets["resource"].capability.id();
+                    if tickets.values().all(|t| t.capability.id() == namespace) {
+                        iroh_net1.enable_single_doc();
+                    }
+                    tickets.insert(MANIFEST_TABLE.to_string(), ticket);
+                    Some(tickets)
+                }
+                None => tickets,
+            };
+            if tickets.is_none() {
+                let saved = match iroh_net1.storage_path() {
+                    Some(root) => load_tables(root).await?,
+                    None => None,
+                };
+                if saved.is_none() {
+                    iroh_net1.router.shutdown().await?;
+                    return Err(anyhow::anyhow!(
+                        "No tickets given and no tables saved by a previous start, pass the tickets or --code"
+                    ));
+                }
+                println!("Reopening the tables of the previous start");
+            }
+            let store_state = create_files(&iroh_net1, tickets).await?;
+            // the dashboard shows the progress itself
+            if !dashboard {
+                let sync = store_state.sync.clone();
+                tokio::spawn(async move {
+                    let overall = sync.wait_for_initial_sync().await;
+                    println!(
+                        "✅ Ready, {} tables with {} files downloaded",
+                        overall.tables, overall.total_files
+                    );
+                });
+            }
             Some(store_state)
         }
-        Commands::Read => {
-            println!("Reading data from server...");
-            None
-        }
     };
+    if dashboard {
+        if let Some(state) = &store_state {
+            dashboard::run(state, PathBuf::from(&storage_path)).await?;
+        }
+        return Ok(());
+    }
+
     println!("Waiting for input or Ctrl+C...");
     println!("Type 'help' for commands, 'quit' to exit, or press Ctrl+C to stop.");
 
-    // Install signal handler
-    let mut sigint = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt())?;
+    // Install signal handler, SIGINT/SIGTERM on Unix and the console events on Windows
+    let mut shutdown = ShutdownSignal::install()?;
+
+    // Listen for user input, with history kept under the storage path
+    let history = (!ephemeral).then(|| PathBuf::from(&storage_path).join(HISTORY_FILE));
+    let mut shell = Shell::spawn(history)?;
 
-    // Listen for user input
-    let mut stdin = tokio::io::BufReader::new(tokio::io::stdin());
-    let mut line = String::new();
+    // directories mirrored into the resource table
+    let mut watchers = std::collections::HashMap::new();
+    // servers started with `serve-files` and `serve-webdav`, keyed by their address
+    let mut servers = std::collections::HashMap::new();
+    // tables mounted with `mount`, keyed by their mount point
+    #[cfg(all(feature = "fuse", unix))]
+    let mut mounts = std::collections::HashMap::new();
+
+    // admin requests are executed here, next to the commands typed on stdin
+    let mut admin_requests = match &store_state {
+        Some(state) => state.iroh.admin.take_requests().await,
+        None => None,
+    };
+
+    // tables announced in the manifest, the ones not open yet are joined
+    let mut manifest_updates = match &store_state {
+        Some(state) => state.take_manifest_updates().await,
+        None => None,
+    };
+
+    // collections announced in the manifest, joined once confirmed with `join-announced`
+    let mut announced: std::collections::HashMap<String, ManifestEntry> =
+        std::collections::HashMap::new();
 
     let store_state_binding = Arc::new(store_state);
     let store_state_weak = Arc::downgrade(&store_state_binding);
 
     loop {
-        line.clear();
+        if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+            shell.set_candidates(completion_words(store_state_arc).await);
+        }
         tokio::select! {
-            // Listen for SIGINT (Ctrl+C) signal
-            _ = sigint.recv() => {
-                println!("\n🛑 Received SIGINT (Ctrl+C), shutting down gracefully...");
+            // Listen for Ctrl+C and termination signals
+            signal = shutdown.recv() => {
+                println!("\n🛑 Received {}, shutting down gracefully...", signal);
                 break;
             }
+            // Listen for remote admin requests
+            Some(mut call) = async { admin_requests.as_mut()?.recv().await } => {
+                let shutdown = matches!(call.request, AdminRequest::Shutdown);
+                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                    let content = call.take_content();
+                    let response = handle_admin(store_state_arc, call.request.clone(), content).await;
+                    call.respond(response);
+                }
+                if shutdown {
+                    println!("\n🛑 Shutdown requested remotely, shutting down gracefully...");
+                    break;
+                }
+            }
+            // Listen for tables announced in the manifest
+            Some(entry) = async { manifest_updates.as_mut()?.recv().await } => {
+                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref()
+                    && !store_state_arc.tables.contains(&entry.table).await
+                    && !store_state_arc.has_left(&entry.namespace).await
+                {
+                    println!("📣 Collection '{}' was announced in the manifest, enter `join-announced {}` to join it", entry.table, entry.table);
+                    announced.insert(entry.table.clone(), entry);
+                }
+            }
             // Listen for user input
-            result = stdin.read_line(&mut line) => {
-                match result {
-                    Ok(0) => {
+            input = shell.read_line("> ") => {
+                match input {
+                    ShellInput::Eof => {
                         // EOF reached
                         println!("📤 Input stream closed");
                         break;
                     }
-                    Ok(_) => {
+                    ShellInput::Interrupted => {
+                        println!("\n🛑 Received Ctrl+C, shutting down gracefully...");
+                        break;
+                    }
+                    ShellInput::Line(line) => {
                         let input = line.trim();
                         if input.is_empty() {
                             continue;
                         }
 
-                        println!("📝 You entered: {}", input);
+                        if !json {
+                            println!("📝 You entered: {}", input);
+                        }
 
-                        // Handle specific commands
-                        match input {
-                            "quit" | "exit" => {
+                        // Handle specific commands, quoted text is a single argument
+                        let words = match split_args(input) {
+                            Ok(words) => words,
+                            Err(e) => {
+                                println!("❌ {}", e);
+                                continue;
+                            }
+                        };
+                        let args: Vec<&str> = words.iter().map(String::as_str).collect();
+                        match args.as_slice() {
+                            ["quit"] | ["exit"] => {
                                 println!("👋 Goodbye!");
                                 break;
                             }
-                            "help" => {
+                            ["help"] => {
                                 println!("📋 Available commands:");
                                 println!("  help   - Show this help message");
                                 println!("  quit   - Exit the program");
                                 println!("  exit   - Exit the program");
-                                println!("  status - Show current status");
-                                println!("  add    - Load images from a directory into resources");
+                                println!("  status - Show node id, relay, addresses, connections, blob store size and table sync state");
+                                println!("  wait-ready [seconds] - Wait until every joined table has synced and downloaded its content, 60 seconds by default");
+                                println!("  pending - List local writes that no peer has received yet");
+                                println!("  du - Show the size of the blob and docs stores, entries and content per table and what GC can reclaim");
+                                println!("  add [path] [--collection <table>] [--no-recursive] - Add a file or directory, the bundled images by default");
                                 println!("  add_folder - Add a new folder named 'New Folder1'");
+                                println!("  mkdir <name> [parent_id] - Create a folder, nested in parent_id if given");
+                                println!("  mvdir <folder_id> <parent_id|root> - Move a folder into another one");
+                                println!("  tree - Show the folder hierarchy with the resources filed into each folder");
+                                println!("  mv <table> <resource_id> <folder_id|none> - File a resource into a folder");
+                                println!("  ls-folder <table> <folder_id|none> - List the resources filed into a folder");
                                 println!("  get    - Retrieve and display the number of resources");
                                 println!("  get_folder - Retrieve and display the number of folders");
+                                println!("  get <id> --out <path> - Write a resource to a file");
+                                println!("  get <id> [path] - Download a resource, to a file named like it by default, and show its size and hash");
+                                println!("  get_page <offset> <limit> - List one page of resources");
+                                println!("  ls [table] - List the entries of a table, or of every table, with id, name, size, author and whether the content is local");
+                                println!("  sorted <resource|folder> <key|name|size|time|captured> [desc] - List entries in order, captured orders photos by EXIF capture time");
+                                println!("  replication <table> [push] - Show under-replicated content, optionally ask peers to fetch it");
+                                println!("  share-folder <folder_id> - Print a ticket granting read access to a single folder and its resources");
+                                println!("  pin <id> - Keep a resource available locally, exempt from eviction");
+                                println!("  unpin <id> - Remove the pin of a resource");
+                                println!("  pins - List the pinned resources");
+                                println!("  bundle <dir> - Add the files of a directory as one resource holding a blob collection");
+                                println!("  unbundle <id> <dir> - Write the files of a blob collection resource to a directory");
+                                println!("  watch <dir> - Keep resources in sync with the files of a directory");
+                                println!("  unwatch <dir> - Stop watching a directory");
+                                println!("  sync <dir> <collection> - Upload new and changed files of a directory, download new and changed resources and report conflicts");
+                                println!("  serve-files <table> [addr] - Serve the resources of a table over HTTP with range requests, on {} by default", DEFAULT_GATEWAY_ADDR);
+                                println!("  serve-webdav <table> [addr] - Share the folders and the resources of a table over WebDAV, on {} by default", DEFAULT_WEBDAV_ADDR);
+                                println!("  unserve <addr> - Stop serving files or WebDAV on addr");
+                                #[cfg(all(feature = "fuse", unix))]
+                                println!("  mount <path> [table] - Mount the folders and the resources of a table, resource by default, at path");
+                                #[cfg(all(feature = "fuse", unix))]
+                                println!("  unmount <path> - Unmount a mounted table");
+                                println!("  import <dir|tar|url> <location> - Import files into resources");
+                                println!("  create-collection <name> - Create a new resource collection");
+                                println!("  join-collection <name> <ticket> - Join a collection shared by another peer");
+                                println!("  join-announced <name> - Join a collection the server announced in the manifest");
+                                println!("  collections - List runtime-defined collections");
+                                println!("  share-collection <name> - Print the ticket of a collection");
+                                println!("  leave <name> [--gc] - Stop syncing a collection and remove it from this node, --gc also deletes its blobs");
+                                println!("  copy-doc <table> <ticket> - Copy the latest entries of a table into the doc of a write ticket");
+                                println!("  share <table> [--mode read|write] [--addr id|relay|direct|both] - Print a fresh ticket of a table or collection, read-only tickets add viewers");
+                                println!("  ticket [--read-only] [--addr id|relay|direct|both] - Print fresh tickets for all open tables");
+                                println!("  peers - List peers with their connection type and per-table sync status");
+                                println!("  conn-info - Show relay or direct path, RTT and addresses of every remote");
+                                println!("  filter <table> [--prefix <p>]... [--max-size <bytes>] [--ext <ext,...>] - Only download content of matching resources");
+                                println!("  filter <table> off - Download all content of a table again");
+                                println!("  filters - List the sync filters of the tables");
+                                println!("  keys <table> <random|time|content> - Key new folders or resources of a table by random id, time-ordered id or content hash");
+                                println!("  limit [download|upload] <bytes_per_sec|off> - Change the rate limit of blob downloads or uploads");
+                                println!("  pause <table> - Stop syncing a table with its peers");
+                                println!("  resume <table> - Restart syncing a paused table");
+                                println!("  paused - List the tables whose sync is paused");
+                                println!("  subscriptions - List the doc subscriptions and whether they are running");
+                                println!("  unsubscribe <name> - Stop processing the doc events of a subscription");
+                                println!("  resubscribe <name> - Restart a stopped subscription");
+                                println!("  control <node_id> <ping|status|ticket <table>> - Send a control command to another peer");
+                                println!("  export --table <table> --out <file> - Dump all entities of a table to JSON");
+                                println!("  import --table <table> <file> - Restore a table dumped with export");
+                                println!("  meta <table> [key|name|size|time] [desc] - List a table from the local metadata index");
+                                println!("  search <words> - Find folders and resources by name across all tables");
+                                println!("  history <table> <key> - List every version written for a key");
+                                println!("  conflicts <table> <key> - List the versions of other authors that lost against the latest one");
+                                println!("  undo <table> <key> - Restore the latest version of a key by another author");
+                                println!("  rm <table> <key> [--force] - Move an entity to the trash, asking for confirmation unless --force is given");
+                                println!("  trash <table> - List the deleted entities of a table");
+                                println!("  restore <table> <key> - Restore a deleted entity from the trash");
+                                println!("  migrate <table> - Rewrite entries stored with an older schema version");
+                                println!("  author show - Print the author this node writes with");
+                                println!("  author list - List the authors stored on this node");
+                                println!("  author export - Print the secret of the current author");
+                                println!("  author import <secret> - Import an author and write with it after a restart");
+                                println!("  nodes - List every peer that joined with its name and whether it is online");
+                                println!("  log [count] [--table <table>] - Show the latest writes to all tables, 20 by default");
+                                println!("  acl - List the authors and nodes in the access list and their roles");
+                                println!("  acl grant <author_id|node_id> <admin|writer|reader> - Give a key a role, admins only");
+                                println!("  acl revoke <author_id|node_id> - Remove a key from the access list, admins only");
+                                println!("  acl share [--read-only] - Print the ticket of the access list");
+                                println!("  acl join <ticket> - Follow the access list of another node, or gain write access to it");
                                 println!("  Ctrl+C - Force exit");
                             }
-                            "status" => {
-                                println!("✅ System is running and listening for input...");
+                            ["status"] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let status = match store_state_arc.iroh.status().await {
+                                        Ok(status) => status,
+                                        Err(e) => {
+                                            println!("❌ Could not read status: {}", e);
+                                            continue;
+                                        }
+                                    };
+                                    let overall = store_state_arc.sync.overall();
+                                    let dropped = store_state_arc.dropped_events.count();
+                                    if json {
+                                        print_json(&status_json(&status, &overall, dropped));
+                                    } else {
+                                        print_status(&status, &overall, dropped);
+                                    }
+                                }
                             }
-                            "add"=>{
+                            ["wait-ready", rest @ ..] => {
                                 if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
-                                    if let Some(resource)=&*store_state_arc.resource.read().await{
-                                        match get_images_directory() {
-                                            Ok(images_path) => {
-                                                println!("📁 Loading images from: {:?}", images_path);
-                                                if let Err(e) = load_images_to_resources(resource, &images_path).await {
-                                                    println!("❌ Failed to load images: {}", e);
-                                                } else {
-                                                    println!("✅ Images loaded successfully.");
-                                                }
+                                    let seconds = match rest {
+                                        [] => DEFAULT_WAIT_READY_SECS,
+                                        [seconds] => match seconds.parse() {
+                                            Ok(seconds) => seconds,
+                                            Err(e) => {
+                                                println!("❌ Invalid number of seconds: {}", e);
+                                                continue;
                                             }
+                                        },
+                                        _ => {
+                                            println!("❌ Usage: wait-ready [seconds]");
+                                            continue;
+                                        }
+                                    };
+                                    let result = store_state_arc.wait_ready(Duration::from_secs(seconds)).await;
+                                    if json {
+                                        let tables: Vec<serde_json::Value> = store_state_arc
+                                            .sync
+                                            .readiness()
+                                            .iter()
+                                            .map(|t| serde_json::json!({
+                                                "table": t.table,
+                                                "sync_finished": t.sync_finished,
+                                                "content_ready": t.content_ready,
+                                                "missing_blobs": t.missing_blobs,
+                                            }))
+                                            .collect();
+                                        print_json(&serde_json::json!({ "ready": result.is_ok(), "tables": tables }));
+                                        continue;
+                                    }
+                                    match result {
+                                        Ok(tables) => println!("✅ Ready, {} tables synced with their content", tables.len()),
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["pending"] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let pending = store_state_arc.pending.list();
+                                    println!("✅ Writes not replicated to any peer ({}):", pending.len());
+                                    for write in pending {
+                                        println!("  {:<12} {} written at {}", write.table, write.key, write.timestamp / 1000);
+                                    }
+                                }
+                            }
+                            ["du"] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    match disk_usage(&store_state_arc.iroh).await {
+                                        Ok(usage) if json => print_json(&usage_json(&usage)),
+                                        Ok(usage) => print_usage(&usage),
+                                        Err(e) => println!("❌ Could not measure disk usage: {}", e),
+                                    }
+                                }
+                            }
+                            ["add", rest @ ..]=>{
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let options = match parse_add_args(rest) {
+                                        Ok(options) => options,
+                                        Err(e) => {
+                                            println!("❌ {}", e);
+                                            continue;
+                                        }
+                                    };
+                                    let path = match options.path {
+                                        Some(path) => path,
+                                        None => match store_state_arc.iroh.images_directory() {
+                                            Ok(images_path) => images_path,
                                             Err(e) => {
                                                 println!("❌ Could not find images directory: {}", e);
+                                                continue;
+                                            }
+                                        },
+                                    };
+                                    match store_state_arc.resource_table(&options.collection).await {
+                                        Ok(resource) => {
+                                            println!("📁 Loading files from: {:?} into {}", path, options.collection);
+                                            let importer = PathImporter { path, recursive: options.recursive };
+                                            let total = importer.total_size();
+                                            let result = import_into_with_progress(&resource, importer, |p| {
+                                                print_progress(p.files, p.bytes, total)
+                                            })
+                                            .await;
+                                            println!();
+                                            match result {
+                                                Ok(count) => println!("✅ Files loaded successfully ({} files).", count),
+                                                Err(e) => println!("❌ Failed to load files: {}", e),
                                             }
                                         }
+                                        Err(e) => println!("❌ {}", e),
                                     }
                                 } else {
                                     println!("❌ IrohNet is not available.");
                                 }
                             }
-                            "add_folder"=>{
+                            ["add_folder"]=>{
                                 if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
-                                    if let Some(folder)=&*store_state_arc.folder.read().await{
+                                    if let Some(folder) = store_state_arc.tables.get::<Folders>("folder").await {
                                         folder.insert_folder("New Folder".to_string()).await?;
                                         println!("✅ Folder added.");
                                     }
@@ -305,19 +1897,1168 @@ async fn main() -> anyhow::Result<()> {
                                     println!("❌ IrohNet is not available.");
                                 }
                             }
-                            "get"=>{
+                            ["mkdir", name, parent @ ..] if parent.len() <= 1 => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    if let Some(folder) = store_state_arc.tables.get::<Folders>("folder").await {
+                                        let parent_id = parent.first().map(|p| p.to_string());
+                                        match folder.create_folder(name.to_string(), parent_id).await {
+                                            Ok(id) => println!("✅ Created folder {}", id),
+                                            Err(e) => println!("❌ {}", e),
+                                        }
+                                    }
+                                }
+                            }
+                            ["mvdir", folder_id, parent_id] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    if let Some(folder) = store_state_arc.tables.get::<Folders>("folder").await {
+                                        let parent_id = (*parent_id != "root").then(|| parent_id.to_string());
+                                        match folder.move_folder(folder_id, parent_id).await {
+                                            Ok(()) => println!("✅ Moved folder {}", folder_id),
+                                            Err(e) => println!("❌ {}", e),
+                                        }
+                                    }
+                                }
+                            }
+                            ["tree"] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let view = &store_state_arc.iroh.view;
+                                    print_folder_tree(&view.folder_tree().await, 0);
+                                    let unfiled = view.unfiled().await;
+                                    if !unfiled.is_empty() {
+                                        println!("Unfiled ({}):", unfiled.len());
+                                        for r in unfiled {
+                                            println!("  📄 {}  {}  {}", r.meta.name.unwrap_or_default(), r.meta.size, r.table);
+                                        }
+                                    }
+                                }
+                            }
+                            ["mv", table, resource_id, folder_id] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let folder_id = (*folder_id != "none").then(|| folder_id.to_string());
+                                    match store_state_arc.move_to_folder(table, resource_id, folder_id).await {
+                                        Ok(()) => println!("✅ Moved resource {}", resource_id),
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["ls-folder", table, folder_id] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let folder_id = (*folder_id != "none").then_some(*folder_id);
+                                    match store_state_arc.list_in_folder(table, folder_id).await {
+                                        Ok(resources) => {
+                                            println!("✅ {} resources", resources.len());
+                                            for r in resources {
+                                                println!("  {:<36}  {:>10}  {}", r.id, r.size, r.name);
+                                            }
+                                        }
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["get"]=>{
                                  if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
-                                    if let Some(resource)=&*store_state_arc.resource.read().await{
-                                        let resources = resource.search().await?;
-                                        println!("✅ Retrieved resources len: {:?}", resources.len());
+                                    if let Some(resource) = store_state_arc.tables.get::<Resources>("resource").await {
+                                        // unreadable entries are reported without failing the whole listing
+                                        let mut resources = 0;
+                                        let mut unreadable = Vec::new();
+                                        let mut stream = std::pin::pin!(resource.search_stream());
+                                        while let Some(result) = stream.next().await {
+                                            match result {
+                                                Ok(_) => resources += 1,
+                                                Err(e) if json => unreadable.push(e.to_string()),
+                                                Err(e) => println!("❌ Unreadable resource: {}", e),
+                                            }
+                                        }
+                                        if json {
+                                            print_json(&serde_json::json!({ "resources": resources, "unreadable": unreadable }));
+                                        } else {
+                                            println!("✅ Retrieved resources len: {:?}", resources);
+                                        }
                                     }
                                 }
                             }
-                             "get_folder"=>{
+                             ["get_folder"]=>{
                                  if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
-                                    if let Some(folder)=&*store_state_arc.folder.read().await{
+                                    if let Some(folder) = store_state_arc.tables.get::<Folders>("folder").await {
                                         let folders = folder.search().await?;
-                                        println!("✅ Retrieved folders len: {:?}", folders.len());
+                                        if json {
+                                            print_json(&serde_json::json!({ "folders": folders.len() }));
+                                        } else {
+                                            println!("✅ Retrieved folders len: {:?}", folders.len());
+                                        }
+                                    }
+                                }
+                            }
+                            ["get_page", offset, limit] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    if let Some(resource) = store_state_arc.tables.get::<Resources>("resource").await {
+                                        match (offset.parse::<u64>(), limit.parse::<u64>()) {
+                                            (Ok(offset), Ok(limit)) => {
+                                                let resources = resource.search_page(offset, limit).await?;
+                                                println!("✅ Retrieved {} resources:", resources.len());
+                                                for r in resources {
+                                                    println!("  {} {} ({}, {} bytes, by {})", r.id, r.name, r.content_type.as_deref().unwrap_or("unknown"), r.size, r.author.fmt_short());
+                                                }
+                                            }
+                                            _ => println!("❌ offset and limit must be numbers"),
+                                        }
+                                    }
+                                }
+                            }
+                            ["get", id, "--out", path] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    if let Some(resource) = store_state_arc.tables.get::<Resources>("resource").await {
+                                        match resource.export(id, &PathBuf::from(path)).await {
+                                            Ok(size) => println!("✅ Wrote {} bytes to {}", size, path),
+                                            Err(e) => println!("❌ Export failed: {}", e),
+                                        }
+                                    }
+                                }
+                            }
+                            ["ls", table @ ..] if table.len() <= 1 => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let tables = match table.first() {
+                                        Some(table) => vec![table.to_string()],
+                                        None => TICKET_ORDER
+                                            .iter()
+                                            .map(|t| t.to_string())
+                                            .chain(store_state_arc.list_collections().await)
+                                            .collect(),
+                                    };
+                                    for table in tables {
+                                        if let Err(e) = print_listing(store_state_arc, &table).await {
+                                            println!("❌ {}: {}", table, e);
+                                        }
+                                    }
+                                }
+                            }
+                            ["get", id, out @ ..] if out.len() <= 1 => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    if let Some(resource) = store_state_arc.tables.get::<Resources>("resource").await {
+                                        let out = out.first().map(PathBuf::from);
+                                        match resource.fetch(id, out).await {
+                                            Ok((resource, path, size)) => print_fetched(&resource, &path, size),
+                                            Err(e) => println!("❌ Download failed: {}", e),
+                                        }
+                                    }
+                                }
+                            }
+                            ["pin", id] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    if let Some(resource) = store_state_arc.tables.get::<Resources>("resource").await {
+                                        match resource.pin(id).await {
+                                            Ok(()) => println!("📌 Pinned {}", id),
+                                            Err(e) => println!("❌ {}", e),
+                                        }
+                                    }
+                                }
+                            }
+                            ["unpin", id] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    if let Some(resource) = store_state_arc.tables.get::<Resources>("resource").await {
+                                        match resource.unpin(id).await {
+                                            Ok(true) => println!("✅ Unpinned {}", id),
+                                            Ok(false) => println!("❌ {} is not pinned", id),
+                                            Err(e) => println!("❌ {}", e),
+                                        }
+                                    }
+                                }
+                            }
+                            ["pins"] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    match pin::pins(&store_state_arc.iroh).await {
+                                        Ok(pins) => {
+                                            println!("✅ Pinned resources ({}):", pins.len());
+                                            for (id, blobs) in pins {
+                                                println!("  {} ({} blobs)", id, blobs.len());
+                                            }
+                                        }
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["bundle", dir] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    if let Some(resource) = store_state_arc.tables.get::<Resources>("resource").await {
+                                        match resource.add_collection(Path::new(dir)).await {
+                                            Ok(id) => println!("✅ Added {} as collection {}", dir, id),
+                                            Err(e) => println!("❌ {}", e),
+                                        }
+                                    }
+                                }
+                            }
+                            ["unbundle", id, dir] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    if let Some(resource) = store_state_arc.tables.get::<Resources>("resource").await {
+                                        match resource.export_collection(id, Path::new(dir)).await {
+                                            Ok(count) => println!("✅ Wrote {} files to {}", count, dir),
+                                            Err(e) => println!("❌ {}", e),
+                                        }
+                                    }
+                                }
+                            }
+                            ["watch", dir] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let dir = PathBuf::from(dir);
+                                    if watchers.contains_key(&dir) {
+                                        println!("❌ {:?} is already watched", dir);
+                                        continue;
+                                    }
+                                    let resources = match store_state_arc.resource_table("resource").await {
+                                        Ok(resources) => resources,
+                                        Err(e) => {
+                                            println!("❌ {}", e);
+                                            continue;
+                                        }
+                                    };
+                                    match spawn_watcher(resources, dir.clone()) {
+                                        Ok(handle) => {
+                                            watchers.insert(dir, handle);
+                                            println!("✅ Watching for changes.");
+                                        }
+                                        Err(e) => println!("❌ Failed to watch directory: {}", e),
+                                    }
+                                }
+                            }
+                            ["sync", dir, table] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let resources = match store_state_arc.resource_table(table).await {
+                                        Ok(resources) => resources,
+                                        Err(e) => {
+                                            println!("❌ {}", e);
+                                            continue;
+                                        }
+                                    };
+                                    match sync_dir(&resources, Path::new(dir)).await {
+                                        Ok(report) => {
+                                            println!(
+                                                "✅ Synced {} with {}: {} uploaded, {} downloaded, {} unchanged",
+                                                dir,
+                                                table,
+                                                report.uploaded.len(),
+                                                report.downloaded.len(),
+                                                report.unchanged
+                                            );
+                                            for name in &report.uploaded {
+                                                println!("  ⬆️ {}", name);
+                                            }
+                                            for name in &report.downloaded {
+                                                println!("  ⬇️ {}", name);
+                                            }
+                                            for name in &report.deleted_locally {
+                                                println!("  🗑️ {} was deleted locally, the resource is kept", name);
+                                            }
+                                            for name in &report.deleted_remotely {
+                                                println!("  🗑️ {} was deleted remotely, the file is kept", name);
+                                            }
+                                            for name in &report.conflicts {
+                                                println!("  ⚠️ {} changed on both sides, resolve it and sync again", name);
+                                            }
+                                        }
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["serve-files", table, rest @ ..] if rest.len() <= 1 => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let addr = match rest.first().copied().unwrap_or(DEFAULT_GATEWAY_ADDR).parse::<SocketAddr>() {
+                                        Ok(addr) => addr,
+                                        Err(e) => {
+                                            println!("❌ Invalid address: {}", e);
+                                            continue;
+                                        }
+                                    };
+                                    let resources = match store_state_arc.resource_table(table).await {
+                                        Ok(resources) => resources,
+                                        Err(e) => {
+                                            println!("❌ {}", e);
+                                            continue;
+                                        }
+                                    };
+                                    match serve_files(resources, addr).await {
+                                        Ok((addr, handle)) => {
+                                            servers.insert(addr, handle);
+                                            println!("✅ Serving {} on http://{}", table, addr);
+                                        }
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["serve-webdav", table, rest @ ..] if rest.len() <= 1 => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let addr = match rest.first().copied().unwrap_or(DEFAULT_WEBDAV_ADDR).parse::<SocketAddr>() {
+                                        Ok(addr) => addr,
+                                        Err(e) => {
+                                            println!("❌ Invalid address: {}", e);
+                                            continue;
+                                        }
+                                    };
+                                    let Some(folders) = store_state_arc.tables.get::<Folders>("folder").await else {
+                                        println!("❌ Folder table is not open");
+                                        continue;
+                                    };
+                                    let resources = match store_state_arc.resource_table(table).await {
+                                        Ok(resources) => resources,
+                                        Err(e) => {
+                                            println!("❌ {}", e);
+                                            continue;
+                                        }
+                                    };
+                                    match serve_webdav(folders, resources, addr).await {
+                                        Ok((addr, handle)) => {
+                                            servers.insert(addr, handle);
+                                            println!("✅ Sharing {} over WebDAV on http://{}", table, addr);
+                                        }
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            #[cfg(all(feature = "fuse", unix))]
+                            ["mount", path, rest @ ..] if rest.len() <= 1 => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let path = PathBuf::from(path);
+                                    if mounts.contains_key(&path) {
+                                        println!("❌ {:?} is already mounted", path);
+                                        continue;
+                                    }
+                                    let table = rest.first().copied().unwrap_or("resource");
+                                    let Some(folders) = store_state_arc.tables.get::<Folders>("folder").await else {
+                                        println!("❌ Folder table is not open");
+                                        continue;
+                                    };
+                                    let resources = match store_state_arc.resource_table(table).await {
+                                        Ok(resources) => resources,
+                                        Err(e) => {
+                                            println!("❌ {}", e);
+                                            continue;
+                                        }
+                                    };
+                                    match iroh_test::mount::mount(folders, resources, &path) {
+                                        Ok(session) => {
+                                            println!("✅ Mounted {} at {:?}", table, path);
+                                            mounts.insert(path, session);
+                                        }
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            #[cfg(all(feature = "fuse", unix))]
+                            ["unmount", path] => {
+                                // dropping the session unmounts the filesystem
+                                match mounts.remove(&PathBuf::from(path)) {
+                                    Some(session) => {
+                                        drop(session);
+                                        println!("✅ Unmounted {}", path);
+                                    }
+                                    None => println!("❌ {} is not mounted", path),
+                                }
+                            }
+                            ["unserve", addr] => {
+                                match addr.parse::<SocketAddr>().ok().and_then(|addr| servers.remove(&addr)) {
+                                    Some(handle) => {
+                                        handle.abort();
+                                        println!("✅ Stopped serving on {}", addr);
+                                    }
+                                    None => println!("❌ Nothing is served on {}", addr),
+                                }
+                            }
+                            ["unwatch", dir] => {
+                                match watchers.remove(&PathBuf::from(dir)) {
+                                    Some(handle) => {
+                                        handle.abort();
+                                        println!("✅ Stopped watching {}", dir);
+                                    }
+                                    None => println!("❌ {} is not watched", dir),
+                                }
+                            }
+                            ["sorted", table, field, rest @ ..] => {
+                                let direction = if rest == ["desc"] { SortDirection::Desc } else { SortDirection::Asc };
+                                let field = match field.parse::<SortField>() {
+                                    Ok(field) => field,
+                                    Err(e) => {
+                                        println!("❌ {}", e);
+                                        continue;
+                                    }
+                                };
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    match *table {
+                                        "resource" => {
+                                            if let Some(resource) = store_state_arc.tables.get::<Resources>("resource").await {
+                                                for r in resource.search_sorted(field, direction).await? {
+                                                    match &r.image {
+                                                        Some(image) => println!(
+                                                            "  {} {} ({}x{}{})",
+                                                            r.id,
+                                                            r.name,
+                                                            image.width,
+                                                            image.height,
+                                                            image.camera.as_deref().map(|c| format!(", {}", c)).unwrap_or_default()
+                                                        ),
+                                                        None => println!("  {} {}", r.id, r.name),
+                                                    }
+                                                }
+                                            }
+                                        }
+                                        "folder" => {
+                                            if let Some(folder) = store_state_arc.tables.get::<Folders>("folder").await {
+                                                for f in folder.search_sorted(field, direction).await? {
+                                                    println!("  {} {}", f.folder_id, f.folder_name);
+                                                }
+                                            }
+                                        }
+                                        _ => println!("❌ Unknown table '{}'", table),
+                                    }
+                                }
+                            }
+                            ["replication", table, rest @ ..] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let (Some(doc), Some(prefix)) = (store_state_arc.doc(table).await, store_state_arc.tables.prefix(table).await) else {
+                                        println!("❌ Unknown table '{}'", table);
+                                        continue;
+                                    };
+                                    let iroh = &store_state_arc.iroh;
+                                    let placements = match store_state_arc.tables.get::<Resources>(table).await {
+                                        Some(resources) => resources.placements().await.unwrap_or_default(),
+                                        None => std::collections::HashMap::new(),
+                                    };
+                                    match under_replicated(iroh, &doc, &prefix, &placements, replication_factor).await {
+                                        Ok(statuses) => {
+                                            println!("✅ {} entries below replication factor {}", statuses.len(), replication_factor);
+                                            for status in &statuses {
+                                                println!("  ⚠️  {} {} ({} replicas)", status.key, status.hash.fmt_short(), status.replicas);
+                                            }
+                                            if rest == ["push"] && !statuses.is_empty() {
+                                                match push_replicas(iroh, &doc).await {
+                                                    Ok(count) => println!("📤 Asked {} peers to fetch missing content", count),
+                                                    Err(e) => println!("❌ Failed to push replicas: {}", e),
+                                                }
+                                            }
+                                        }
+                                        Err(e) => println!("❌ Failed to check replication: {}", e),
+                                    }
+                                }
+                            }
+                            ["share-folder", folder_id] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    match store_state_arc.share_folder(folder_id).await {
+                                        Ok(ticket) => println!("🎫 Ticket: {}", ticket),
+                                        Err(e) => println!("❌ Failed to share folder: {}", e),
+                                    }
+                                }
+                            }
+                            ["import", kind, location] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    if let Some(resource) = store_state_arc.tables.get::<Resources>("resource").await {
+                                        let result = match *kind {
+                                            "dir" => import_into(&resource, DirImporter { path: PathBuf::from(location) }).await,
+                                            "tar" => import_into(&resource, TarImporter { path: PathBuf::from(location) }).await,
+                                            "url" => match location.parse() {
+                                                Ok(url) => import_into(&resource, UrlImporter { urls: vec![url] }).await,
+                                                Err(e) => Err(anyhow::anyhow!("Invalid url: {}", e)),
+                                            },
+                                            _ => Err(anyhow::anyhow!("Unknown import source '{}'", kind)),
+                                        };
+                                        match result {
+                                            Ok(count) => println!("✅ Imported {} files.", count),
+                                            Err(e) => println!("❌ Import failed: {}", e),
+                                        }
+                                    }
+                                }
+                            }
+                            ["create-collection", name] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    match store_state_arc.create_collection(name).await {
+                                        Ok(ticket) => {
+                                            println!("✅ Collection '{}' created.", name);
+                                            println!("🎫 Ticket: {}", ticket);
+                                        }
+                                        Err(e) => println!("❌ Failed to create collection: {}", e),
+                                    }
+                                } else {
+                                    println!("❌ IrohNet is not available.");
+                                }
+                            }
+                            ["join-collection", name, ticket] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    match ticket.parse() {
+                                        Ok(ticket) => match store_state_arc.join_collection(name, ticket).await {
+                                            Ok(_) => println!("✅ Joined collection '{}'.", name),
+                                            Err(e) => println!("❌ Failed to join collection: {}", e),
+                                        },
+                                        Err(e) => println!("❌ Invalid ticket: {}", e),
+                                    }
+                                } else {
+                                    println!("❌ IrohNet is not available.");
+                                }
+                            }
+                            ["join-announced", name] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let Some(entry) = announced.get(*name) else {
+                                        println!("❌ No collection '{}' was announced in the manifest", name);
+                                        continue;
+                                    };
+                                    match entry.ticket.parse() {
+                                        Ok(ticket) => match store_state_arc.join_collection(name, ticket).await {
+                                            Ok(_) => {
+                                                announced.remove(*name);
+                                                println!("✅ Joined collection '{}' announced in the manifest", name);
+                                            }
+                                            Err(e) => println!("❌ Failed to join collection '{}': {}", name, e),
+                                        },
+                                        Err(e) => println!("❌ Invalid ticket for collection '{}': {}", name, e),
+                                    }
+                                } else {
+                                    println!("❌ IrohNet is not available.");
+                                }
+                            }
+                            ["collections"] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let names = store_state_arc.list_collections().await;
+                                    println!("✅ Collections ({}):", names.len());
+                                    for name in names {
+                                        println!("  {}", name);
+                                    }
+                                }
+                            }
+                            ["leave", name, rest @ ..] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let gc = match rest {
+                                        [] => false,
+                                        ["--gc"] => true,
+                                        _ => {
+                                            println!("❌ Usage: leave <name> [--gc]");
+                                            continue;
+                                        }
+                                    };
+                                    match store_state_arc.leave_collection(name, gc).await {
+                                        Ok(deleted) if gc => println!("✅ Left collection {}, deleted {} blobs", name, deleted),
+                                        Ok(_) => println!("✅ Left collection {}", name),
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["copy-doc", table, ticket] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let ticket = match ticket.parse() {
+                                        Ok(ticket) => ticket,
+                                        Err(e) => {
+                                            println!("❌ Invalid ticket: {}", e);
+                                            continue;
+                                        }
+                                    };
+                                    match store_state_arc.copy_doc(table, ticket).await {
+                                        Ok(report) => {
+                                            println!(
+                                                "✅ Copied {} entries, {} already there",
+                                                report.copied, report.unchanged
+                                            );
+                                            if report.missing_content > 0 {
+                                                println!(
+                                                    "⚠️ The content of {} entries is not stored on this node",
+                                                    report.missing_content
+                                                );
+                                            }
+                                        }
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["share-collection", name] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    match store_state_arc.share_collection(name).await {
+                                        Ok(ticket) => println!("🎫 Ticket: {}", ticket),
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["share", table, rest @ ..] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let (mode, addr) = match parse_share_args(rest) {
+                                        Ok(options) => options,
+                                        Err(e) => {
+                                            println!("❌ {}", e);
+                                            continue;
+                                        }
+                                    };
+                                    let addr = addr.unwrap_or_else(|| store_state_arc.iroh.addr_info());
+                                    match store_state_arc.share_table(table, mode, addr).await {
+                                        Ok(ticket) => println!("🎫 Ticket: {}", ticket),
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["ticket", rest @ ..] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let (mode, addr) = match parse_ticket_args(rest) {
+                                        Ok(options) => options,
+                                        Err(e) => {
+                                            println!("❌ {}", e);
+                                            continue;
+                                        }
+                                    };
+                                    let addr = addr.unwrap_or_else(|| store_state_arc.iroh.addr_info());
+                                    match store_state_arc.share_tickets(mode, addr).await {
+                                        Ok(tickets) => print_tickets(&tickets, mode, json),
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["peers"] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let iroh = &store_state_arc.iroh;
+                                    let peers = iroh.peers.list(iroh.router.endpoint()).await;
+                                    if json {
+                                        print_json(&peers_json(&peers));
+                                        continue;
+                                    }
+                                    println!("✅ Peers ({}):", peers.len());
+                                    for peer in peers {
+                                        let conn = match &peer.conn_type {
+                                            Some(ConnectionType::Direct(addr)) => format!("direct {}", addr),
+                                            Some(ConnectionType::Relay(url)) => format!("relay {}", url),
+                                            Some(ConnectionType::Mixed(addr, url)) => format!("mixed {} / {}", addr, url),
+                                            _ => "not connected".to_string(),
+                                        };
+                                        println!("  {} ({})", peer.peer, conn);
+                                        for (table, status) in &peer.tables {
+                                            let state = if status.connected { "neighbor" } else { "gone" };
+                                            let sync = match (&status.last_error, status.last_sync) {
+                                                (Some(e), _) => format!("last sync failed: {}", e),
+                                                (None, Some(t)) => format!("synced {}s ago", t.elapsed().unwrap_or_default().as_secs()),
+                                                (None, None) => "not synced".to_string(),
+                                            };
+                                            println!("    {:<12} {:<8} {}", table, state, sync);
+                                        }
+                                    }
+                                }
+                            }
+                            ["conn-info"] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let infos = connection_info(store_state_arc.iroh.router.endpoint());
+                                    println!("✅ Remotes ({}):", infos.len());
+                                    for info in infos {
+                                        let path = match &info.conn_type {
+                                            ConnectionType::Direct(addr) => format!("direct {}", addr),
+                                            ConnectionType::Relay(url) => format!("relay {}", url),
+                                            ConnectionType::Mixed(addr, url) => format!("mixed {} / {}", addr, url),
+                                            _ => "no path".to_string(),
+                                        };
+                                        let rtt = info.rtt.map(|d| format!("{}ms", d.as_millis())).unwrap_or_else(|| "-".to_string());
+                                        let last_used = info.last_used.map(|d| format!("{}s ago", d.as_secs())).unwrap_or_else(|| "never".to_string());
+                                        println!("  {} {} rtt {} used {}", info.peer, path, rtt, last_used);
+                                        if let Some(relay) = &info.relay_url {
+                                            println!("    relay  {}", relay);
+                                        }
+                                        for (addr, latency) in &info.addrs {
+                                            let latency = latency.map(|d| format!("{}ms", d.as_millis())).unwrap_or_else(|| "-".to_string());
+                                            println!("    direct {} {}", addr, latency);
+                                        }
+                                    }
+                                }
+                            }
+                            ["pause", table] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    match store_state_arc.pause_sync(table).await {
+                                        Ok(()) => println!("⏸️ Paused sync of {}", table),
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["resume", table] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    match store_state_arc.resume_sync(table).await {
+                                        Ok(peers) => println!("✅ Resumed sync of {} with {} peers", table, peers),
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["paused"] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let paused = store_state_arc.paused_tables().await;
+                                    println!("✅ Paused tables ({}):", paused.len());
+                                    for table in paused {
+                                        println!("  {}", table);
+                                    }
+                                }
+                            }
+                            ["subscriptions"] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let subscriptions = store_state_arc.subscriptions.list().await;
+                                    println!("✅ Subscriptions ({}):", subscriptions.len());
+                                    for (name, running) in subscriptions {
+                                        println!("  {} {}", name, if running { "running" } else { "stopped" });
+                                    }
+                                }
+                            }
+                            ["unsubscribe", name] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    match store_state_arc.stop_subscription(name).await {
+                                        Ok(true) => println!("⏹️ Stopped subscription {}", name),
+                                        Ok(false) => println!("⚠️ Subscription {} was not running", name),
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["resubscribe", name] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    match store_state_arc.restart_subscription(name).await {
+                                        Ok(()) => println!("✅ Restarted subscription {}", name),
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["filter", table, "off"] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    match store_state_arc.set_sync_filter(table, SyncFilter::default()).await {
+                                        Ok(requested) => println!("✅ {} downloads all content again, {} blobs requested", table, requested),
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["filter", table, rest @ ..] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let filter = match parse_filter_args(rest) {
+                                        Ok(filter) => filter,
+                                        Err(e) => {
+                                            println!("❌ {}", e);
+                                            continue;
+                                        }
+                                    };
+                                    match store_state_arc.set_sync_filter(table, filter.clone()).await {
+                                        Ok(requested) => println!("✅ {} downloads {}, {} blobs requested", table, filter, requested),
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["filters"] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let filters = store_state_arc.sync_filters().await;
+                                    println!("✅ Sync filters ({}):", filters.len());
+                                    for (table, filter) in filters {
+                                        println!("  {}: {}", table, filter);
+                                    }
+                                }
+                            }
+                            ["keys", table, scheme] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let scheme = match scheme.parse::<KeyScheme>() {
+                                        Ok(scheme) => scheme,
+                                        Err(e) => {
+                                            println!("❌ {}", e);
+                                            continue;
+                                        }
+                                    };
+                                    match store_state_arc.set_key_scheme(table, scheme).await {
+                                        Ok(()) => println!("✅ New resources of {} are keyed by {}", table, scheme),
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["limit", rest @ ..] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let (upload, rate) = match rest {
+                                        [rate] | ["download", rate] => (false, rate),
+                                        ["upload", rate] => (true, rate),
+                                        _ => {
+                                            println!("❌ Usage: limit [download|upload] <bytes_per_sec|off>");
+                                            continue;
+                                        }
+                                    };
+                                    let direction = if upload { "Upload" } else { "Download" };
+                                    let rate = match if *rate == "off" { Ok(0) } else { rate.parse::<u64>() } {
+                                        Ok(rate) => rate,
+                                        Err(_) => {
+                                            println!("❌ Rate must be a number of bytes per second or 'off'");
+                                            continue;
+                                        }
+                                    };
+                                    if upload {
+                                        store_state_arc.iroh.set_upload_limit(rate);
+                                    } else {
+                                        store_state_arc.iroh.set_download_limit(rate);
+                                    }
+                                    if rate == 0 {
+                                        println!("✅ {} rate limit removed", direction);
+                                    } else {
+                                        println!("✅ {}s limited to {} bytes/s", direction, rate);
+                                    }
+                                }
+                            }
+                            ["control", node_id, command @ ..] => {
+                                let request = match command {
+                                    ["ping"] => ControlRequest::Ping,
+                                    ["status"] => ControlRequest::Status,
+                                    ["ticket", table] => ControlRequest::RequestTicket { table: table.to_string() },
+                                    _ => {
+                                        println!("❌ Usage: control <node_id> <ping|status|ticket <table>>");
+                                        continue;
+                                    }
+                                };
+                                let peer = match node_id.parse::<iroh::PublicKey>() {
+                                    Ok(peer) => peer,
+                                    Err(e) => {
+                                        println!("❌ Invalid node id: {}", e);
+                                        continue;
+                                    }
+                                };
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let started = std::time::Instant::now();
+                                    match send_control(store_state_arc.iroh.router.endpoint(), peer, &request).await {
+                                        Ok(ControlResponse::Pong { .. }) => println!("✅ Pong after {}ms", started.elapsed().as_millis()),
+                                        Ok(ControlResponse::Ticket(ticket)) => println!("🎫 Ticket: {}", ticket),
+                                        Ok(ControlResponse::Status(status)) => {
+                                            println!("✅ Peer runs version {}, tables:", status.version);
+                                            for (table, namespace) in status.tables {
+                                                println!("  {:<12} {}", table, namespace);
+                                            }
+                                        }
+                                        Ok(ControlResponse::Error(e)) => println!("❌ Peer answered: {}", e),
+                                        Err(e) => println!("❌ Control request failed: {}", e),
+                                    }
+                                }
+                            }
+                            ["export", "--table", table, "--out", path] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    match store_state_arc.export_table(table, &PathBuf::from(path)).await {
+                                        Ok(count) => println!("✅ Exported {} entities to {}", count, path),
+                                        Err(e) => println!("❌ Export failed: {}", e),
+                                    }
+                                }
+                            }
+                            ["import", "--table", table, path] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    match store_state_arc.import_table(table, &PathBuf::from(path)).await {
+                                        Ok(count) => println!("✅ Imported {} entities into {}", count, table),
+                                        Err(e) => println!("❌ Import failed: {}", e),
+                                    }
+                                }
+                            }
+                            ["meta", table, rest @ ..] => {
+                                let mut query = MetaQuery::table(table);
+                                if let [field, ..] = rest {
+                                    match field.parse::<SortField>() {
+                                        Ok(field) => query.sort = field,
+                                        Err(e) => {
+                                            println!("❌ {}", e);
+                                            continue;
+                                        }
+                                    }
+                                }
+                                if rest.get(1) == Some(&"desc") {
+                                    query.direction = SortDirection::Desc;
+                                }
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    match store_state_arc.iroh.sql_index.query(query).await {
+                                        Ok(entities) => {
+                                            println!("✅ {} entities", entities.len());
+                                            for e in entities {
+                                                println!(
+                                                    "  {:<36}  {:>10}  {}  {}",
+                                                    e.key,
+                                                    e.size,
+                                                    e.author.fmt_short(),
+                                                    e.name.as_deref().unwrap_or("-")
+                                                );
+                                            }
+                                        }
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["search", words @ ..] if !words.is_empty() => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let query = words.join(" ").replace('"', "");
+                                    let hits = store_state_arc.iroh.search.search(&query).await;
+                                    println!("🔍 {} matches for \"{}\"", hits.len(), query);
+                                    for hit in hits {
+                                        println!("  {:<10}  {:<36}  {}", hit.table, hit.key, hit.text);
+                                    }
+                                }
+                            }
+                            ["history", table, key] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let Some(doc) = store_state_arc.doc(table).await else {
+                                        println!("❌ Table '{}' is not open", table);
+                                        continue;
+                                    };
+                                    match history::history(&doc, key).await {
+                                        Ok(versions) if versions.is_empty() => println!("❌ No entries for key {}", key),
+                                        Ok(versions) => {
+                                            for version in versions {
+                                                println!(
+                                                    "  {}  {}  {:>10}  {}",
+                                                    version.timestamp,
+                                                    version.author.fmt_short(),
+                                                    version.size,
+                                                    version.hash.fmt_short()
+                                                );
+                                            }
+                                        }
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["conflicts", table, key] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let Some(doc) = store_state_arc.doc(table).await else {
+                                        println!("❌ Table '{}' is not open", table);
+                                        continue;
+                                    };
+                                    match history::losing_versions(&doc, key).await {
+                                        Ok(versions) => {
+                                            println!("✅ Losing versions of {} ({}):", key, versions.len());
+                                            for version in versions {
+                                                println!(
+                                                    "  {}  {}  {:>10}  {}",
+                                                    version.timestamp,
+                                                    version.author.fmt_short(),
+                                                    version.size,
+                                                    version.hash.fmt_short()
+                                                );
+                                            }
+                                        }
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["undo", table, key] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let Some(doc) = store_state_arc.doc(table).await else {
+                                        println!("❌ Table '{}' is not open", table);
+                                        continue;
+                                    };
+                                    let result = match iroh_create_author(&store_state_arc.iroh).await {
+                                        Ok(author) => history::undo(&doc, author, key).await,
+                                        Err(e) => Err(e),
+                                    };
+                                    match result {
+                                        Ok(Some(version)) => println!("✅ Restored version {} of {}", version.hash.fmt_short(), key),
+                                        Ok(None) => println!("❌ No version of {} by another author", key),
+                                        Err(e) => println!("❌ Undo failed: {}", e),
+                                    }
+                                }
+                            }
+                            ["trash", table] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let Some(doc) = store_state_arc.doc(table).await else {
+                                        println!("❌ Table '{}' is not open", table);
+                                        continue;
+                                    };
+                                    match trash::list(&store_state_arc.iroh, &doc).await {
+                                        Ok(entries) if entries.is_empty() => println!("🗑  Trash of {} is empty", table),
+                                        Ok(entries) => {
+                                            println!("🗑  {} ({} entries)", table, entries.len());
+                                            for (key, tombstone) in entries {
+                                                println!(
+                                                    "  {:<36}  {}  {}",
+                                                    key,
+                                                    tombstone.deleted_at,
+                                                    tombstone.deleted_by.fmt_short()
+                                                );
+                                            }
+                                        }
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["restore", table, key] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let Some(doc) = store_state_arc.doc(table).await else {
+                                        println!("❌ Table '{}' is not open", table);
+                                        continue;
+                                    };
+                                    let result = match iroh_create_author(&store_state_arc.iroh).await {
+                                        Ok(author) => trash::restore(&store_state_arc.iroh, &doc, author, key.as_bytes()).await,
+                                        Err(e) => Err(e),
+                                    };
+                                    match result {
+                                        Ok(()) => println!("✅ Restored {}", key),
+                                        Err(e) => println!("❌ Restore failed: {}", e),
+                                    }
+                                }
+                            }
+                            ["rm", table, key, rest @ ..] if rest.is_empty() || rest == ["--force"] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let Some(doc) = store_state_arc.doc(table).await else {
+                                        println!("❌ Table '{}' is not open", table);
+                                        continue;
+                                    };
+                                    if rest.is_empty() {
+                                        let prompt = format!("❓ Move {} of {} to the trash? [y/N] ", key, table);
+                                        let confirmed = matches!(
+                                            shell.read_line(&prompt).await,
+                                            ShellInput::Line(answer) if matches!(answer.trim(), "y" | "Y" | "yes")
+                                        );
+                                        if !confirmed {
+                                            println!("Cancelled.");
+                                            continue;
+                                        }
+                                    }
+                                    let result = match iroh_create_author(&store_state_arc.iroh).await {
+                                        Ok(author) => trash::delete(&store_state_arc.iroh, &doc, author, key.as_bytes()).await,
+                                        Err(e) => Err(e),
+                                    };
+                                    match result {
+                                        Ok(()) => println!("🗑  Moved {} to the trash, `restore {} {}` brings it back", key, table, key),
+                                        Err(e) => println!("❌ Delete failed: {}", e),
+                                    }
+                                }
+                            }
+                            ["migrate", table] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    match store_state_arc.migrate_table(table).await {
+                                        Ok(report) => {
+                                            println!("✅ Migrated {} entries of {}", report.migrated, table);
+                                            for (key, error) in &report.failed {
+                                                println!("⚠️ Skipped {}: {}", key, error);
+                                            }
+                                        }
+                                        Err(e) => println!("❌ Migration failed: {}", e),
+                                    }
+                                }
+                            }
+                            ["author", "show"] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    match iroh_create_author(&store_state_arc.iroh).await {
+                                        Ok(author) => println!("✅ Author: {}", author),
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["author", "list"] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    match author_list(&store_state_arc.iroh).await {
+                                        Ok(authors) => {
+                                            println!("✅ Authors ({}):", authors.len());
+                                            for author in authors {
+                                                println!("  {}", author);
+                                            }
+                                        }
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["author", "export"] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    match author_export(&store_state_arc.iroh).await {
+                                        Ok(secret) => println!("🔑 Author secret: {}", secret),
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["author", "import", secret] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    match author_import(&store_state_arc.iroh, secret).await {
+                                        Ok(author) => println!("✅ Imported author {}, restart to write with it", author),
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["nodes"] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let Some(nodes) = store_state_arc.tables.get::<Nodes>("node").await else {
+                                        println!("❌ Table 'node' is not open");
+                                        continue;
+                                    };
+                                    match nodes.roster().await {
+                                        Ok(roster) => {
+                                            println!("✅ Nodes ({}):", roster.len());
+                                            for node in roster {
+                                                let state = if node.is_online() { "online" } else { "offline" };
+                                                println!("  {:<20} {:<8} {} last seen {}", node.node_name, state, node.node_id, node.last_seen);
+                                            }
+                                        }
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["log", rest @ ..] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let Some(audit) = &store_state_arc.audit else {
+                                        println!("❌ The audit log is not available");
+                                        continue;
+                                    };
+                                    let (limit, table) = match parse_log_args(rest) {
+                                        Ok(options) => options,
+                                        Err(e) => {
+                                            println!("❌ {}", e);
+                                            continue;
+                                        }
+                                    };
+                                    match audit.recent(limit, table.as_deref()).await {
+                                        Ok(records) => {
+                                            println!("📜 Recent changes ({}):", records.len());
+                                            for (record, action) in records {
+                                                println!(
+                                                    "  {}  {:<6}  {:<10}  {:<36}  {}  {}  {}",
+                                                    record.timestamp / 1000,
+                                                    action,
+                                                    record.table,
+                                                    record.key,
+                                                    record.author.fmt_short(),
+                                                    record.hash.fmt_short(),
+                                                    if record.remote { "remote" } else { "local" }
+                                                );
+                                            }
+                                        }
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["acl"] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let grants = store_state_arc.iroh.acl.grants();
+                                    if grants.is_empty() {
+                                        println!("✅ Access list is empty, every author may write");
+                                    } else {
+                                        println!("✅ Access list ({}):", grants.len());
+                                    }
+                                    for (key, role) in grants {
+                                        println!("  {:<8} {}", role, key);
+                                    }
+                                }
+                            }
+                            ["acl", "grant", key, role] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let result = match role.parse::<Role>() {
+                                        Ok(role) => store_state_arc.acl_grant(key, Some(role)).await,
+                                        Err(e) => Err(e),
+                                    };
+                                    match result {
+                                        Ok(()) => println!("✅ Granted {} to {}", role, key),
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["acl", "revoke", key] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    match store_state_arc.acl_grant(key, None).await {
+                                        Ok(()) => println!("✅ Revoked the access of {}", key),
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["acl", "share", rest @ ..] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let mode = match rest {
+                                        ["--read-only"] => ShareMode::Read,
+                                        _ => ShareMode::Write,
+                                    };
+                                    match store_state_arc.share_acl(mode).await {
+                                        Ok(ticket) => println!("🎫 Ticket: {}", ticket),
+                                        Err(e) => println!("❌ {}", e),
+                                    }
+                                }
+                            }
+                            ["acl", "join", ticket] => {
+                                if let Some(store_state_arc) = store_state_weak.upgrade().unwrap().as_ref() {
+                                    let result = match ticket.parse() {
+                                        Ok(ticket) => store_state_arc.join_acl(ticket).await,
+                                        Err(e) => Err(e.into()),
+                                    };
+                                    match result {
+                                        Ok(()) => println!("✅ Joined the access list"),
+                                        Err(e) => println!("❌ {}", e),
                                     }
                                 }
                             }
@@ -326,7 +3067,7 @@ async fn main() -> anyhow::Result<()> {
                             }
                         }
                     }
-                    Err(e) => {
+                    ShellInput::Error(e) => {
                         eprintln!("❌ Error reading input: {}", e);
                         break;
                     }
@@ -337,6 +3078,9 @@ async fn main() -> anyhow::Result<()> {
 
     // Give some time for cleanup to complete
     println!("🔄 Cleaning up...");
+    if let Some(store_state_arc) = store_state_binding.as_ref() {
+        store_state_arc.subscriptions.shutdown().await;
+    }
     tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
     println!("✅ Shutdown complete.");
 
@@ -0,0 +1,331 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Deref,
+    time::Duration,
+};
+
+use anyhow::{Result, bail};
+use futures::StreamExt;
+use iroh::{PublicKey, Signature};
+use iroh_docs::{
+    DocTicket, NamespaceId,
+    api::{Doc, protocol::ShareMode},
+    engine::LiveEvent,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use crate::{
+    iroh_create_author, iroh_create_doc,
+    schema::Migrations,
+    server::IrohNet,
+    store::{GetProperties, IrohCls, IrohProperties, ToBytes},
+    validate::Validators,
+};
+
+// name of the manifest doc in the tickets file
+pub const MANIFEST_TABLE: &str = "manifest";
+// newly announced tables waiting to be joined
+const UPDATES_CAPACITY: usize = 64;
+// how long a client joining the manifest waits for the tables to be listed
+pub const JOIN_TIMEOUT: Duration = Duration::from_secs(60);
+// how often `join_tickets` looks for the tables while the manifest syncs
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A table announced in the manifest, keyed by its name
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub table: String,
+    pub namespace: NamespaceId,
+    pub ticket: String,
+    // signature of the node that created the manifest, see `entry_message`
+    pub signature: Option<Vec<u8>>,
+}
+
+// layout of version 0, before entries were signed
+#[derive(Deserialize)]
+struct ManifestEntryV0 {
+    table: String,
+    namespace: NamespaceId,
+    ticket: String,
+}
+
+impl ToBytes<ManifestEntry> for ManifestEntry {
+    const VERSION: u32 = 1;
+
+    // unsigned entries are read, but only count when an admin wrote them
+    fn migrations() -> Migrations<ManifestEntry> {
+        Migrations::new().register(0, |old: ManifestEntryV0| ManifestEntry {
+            table: old.table,
+            namespace: old.namespace,
+            ticket: old.ticket,
+            signature: None,
+        })
+    }
+
+    fn missing_file(id: String) -> Self {
+        ManifestEntry {
+            table: id,
+            namespace: NamespaceId::from([0; 32]),
+            ticket: String::new(),
+            signature: None,
+        }
+    }
+}
+
+// what the creating node signs to announce `table` in the manifest `manifest`
+fn entry_message(
+    manifest: &NamespaceId,
+    table: &str,
+    namespace: &NamespaceId,
+    ticket: &str,
+) -> Vec<u8> {
+    [
+        b"iroh-test manifest entry".as_slice(),
+        manifest.as_bytes(),
+        namespace.as_bytes(),
+        table.as_bytes(),
+        b"\0",
+        ticket.as_bytes(),
+    ]
+    .concat()
+}
+
+/// Doc listing the tickets of all tables of a server
+///
+/// Clients join the manifest with a single read-only ticket and follow it to join every
+/// table announced in it, including collections created later. Only entries signed by the
+/// node that created the manifest, or written by an admin of the access list, are listed
+#[derive(Clone)]
+pub struct Manifest {
+    table: IrohCls<ManifestEntry>,
+    // nodes of the ticket the manifest was created or joined with, only they announce tables
+    founders: Vec<PublicKey>,
+}
+
+impl Deref for Manifest {
+    type Target = IrohCls<ManifestEntry>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.table
+    }
+}
+
+impl GetProperties for Manifest {
+    fn get_doc(&self) -> &Doc {
+        &self.table.doc
+    }
+}
+
+impl Manifest {
+    pub async fn new(ticket: &Option<DocTicket>, node: IrohNet) -> Result<Self> {
+        let doc = iroh_create_doc(&node, ticket).await?;
+        let author = iroh_create_author(&node).await?;
+        let founders = match ticket {
+            Some(ticket) => ticket.nodes.iter().map(|addr| addr.id).collect(),
+            None => vec![node.router.endpoint().id()],
+        };
+        // clients only read the manifest, announcing is up to the server
+        let ticket = Some(doc.share(ShareMode::Read, node.addr_info()).await?);
+        Ok(Manifest {
+            table: IrohCls::<ManifestEntry> {
+                node,
+                doc,
+                ticket,
+                author,
+                entity: None,
+                validators: Validators::default(),
+                prefix: Vec::new(),
+            },
+            founders,
+        })
+    }
+
+    /// Announce a table or update its ticket, nothing is written if it is listed as is
+    ///
+    /// Only the node that created the manifest announces tables, other nodes skip this
+    pub async fn publish(&self, table: &str, namespace: NamespaceId, ticket: String) -> Result<()> {
+        let endpoint = self.node.router.endpoint();
+        if !self.founders.contains(&endpoint.id()) {
+            debug!(
+                table,
+                "not announcing the table, the manifest is read-only here"
+            );
+            return Ok(());
+        }
+        let message = entry_message(&self.doc.id(), table, &namespace, &ticket);
+        let entry = ManifestEntry {
+            table: table.to_string(),
+            namespace,
+            ticket,
+            signature: Some(endpoint.secret_key().sign(&message).to_bytes().to_vec()),
+        };
+        if self.get_exact(table).await?.as_ref() == Some(&entry) {
+            return Ok(());
+        }
+        self.insert_bytes(table, entry.as_bytes()?).await?;
+        debug!(table, %namespace, "announced table in the manifest");
+        Ok(())
+    }
+
+    /// Withdraw the announcement of `table`, nodes following the manifest stop listing it
+    pub async fn unpublish(&self, table: &str, namespace: NamespaceId) -> Result<()> {
+        // entries without a ticket are skipped by `entries`
+        self.publish(table, namespace, String::new()).await
+    }
+
+    // whether `entry` was signed by a founding node
+    fn is_signed(&self, entry: &ManifestEntry) -> bool {
+        let Some(signature) = &entry.signature else {
+            return false;
+        };
+        let Ok(signature) = <[u8; 64]>::try_from(signature.as_slice()) else {
+            return false;
+        };
+        let signature = Signature::from_bytes(&signature);
+        let message = entry_message(
+            &self.doc.id(),
+            &entry.table,
+            &entry.namespace,
+            &entry.ticket,
+        );
+        self.founders
+            .iter()
+            .any(|node| node.verify(&message, &signature).is_ok())
+    }
+
+    /// All tables announced so far
+    ///
+    /// Entries neither signed by the node that created the manifest nor written by an admin
+    /// are skipped
+    pub async fn entries(&self) -> Result<Vec<ManifestEntry>> {
+        let mut entries = Vec::new();
+        for versioned in self.search().await? {
+            let trusted = self.node.acl.is_admin(versioned.author.as_bytes())
+                || self.is_signed(&versioned.entity);
+            if !trusted {
+                debug!(table = %versioned.entity.table, author = %versioned.author.fmt_short(), "ignoring unsigned manifest entry");
+                continue;
+            }
+            if !versioned.entity.ticket.is_empty() {
+                entries.push(versioned.entity);
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Tickets of `tables`, waiting up to `timeout` for the manifest to sync them
+    pub async fn join_tickets(
+        &self,
+        tables: &[&str],
+        timeout: Duration,
+    ) -> Result<HashMap<String, DocTicket>> {
+        let wait = async {
+            loop {
+                let tickets: HashMap<String, DocTicket> = self
+                    .entries()
+                    .await?
+                    .into_iter()
+                    .filter_map(|entry| Some((entry.table, entry.ticket.parse().ok()?)))
+                    .collect();
+                if tables.iter().all(|table| tickets.contains_key(*table)) {
+                    return anyhow::Ok(tickets);
+                }
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        };
+        match tokio::time::timeout(timeout, wait).await {
+            Ok(tickets) => tickets,
+            Err(_) => bail!(
+                "The manifest did not list all tables within {} seconds",
+                timeout.as_secs()
+            ),
+        }
+    }
+}
+
+/// Send every table announced in the manifest once, now and whenever one is added
+pub fn spawn_manifest_follower(manifest: Manifest) -> mpsc::Receiver<ManifestEntry> {
+    let (sender, receiver) = mpsc::channel(UPDATES_CAPACITY);
+    tokio::spawn(async move {
+        let mut events = match manifest.doc.subscribe().await {
+            Ok(events) => events,
+            Err(e) => {
+                warn!(error = %e, "failed to follow the manifest");
+                return;
+            }
+        };
+        info!(namespace = %manifest.doc.id(), "following the manifest");
+        let mut seen = HashSet::new();
+        loop {
+            match manifest.entries().await {
+                Ok(entries) => {
+                    for entry in entries {
+                        if seen.insert((entry.table.clone(), entry.namespace))
+                            && sender.send(entry).await.is_err()
+                        {
+                            return;
+                        }
+                    }
+                }
+                Err(e) => warn!(error = %e, "failed to read the manifest"),
+            }
+            // wait for the next change, the content of remote entries arrives after the insert
+            loop {
+                match events.next().await {
+                    Some(Ok(
+                        LiveEvent::InsertLocal { .. }
+                        | LiveEvent::InsertRemote { .. }
+                        | LiveEvent::ContentReady { .. },
+                    )) => break,
+                    Some(_) => continue,
+                    None => return,
+                }
+            }
+        }
+    });
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an entry as version 0 encoded it
+    #[derive(Serialize)]
+    struct UnsignedEntry {
+        table: String,
+        namespace: NamespaceId,
+        ticket: String,
+    }
+
+    #[test]
+    fn decodes_unsigned_entries_of_version_0() {
+        let bytes = bincode::serialize(&UnsignedEntry {
+            table: "files".to_string(),
+            namespace: NamespaceId::from([1; 32]),
+            ticket: "docaaa".to_string(),
+        })
+        .unwrap();
+        let (entry, version) = ManifestEntry::from_bytes_versioned(bytes.into()).unwrap();
+        assert_eq!(version, 0);
+        assert_eq!(entry.table, "files");
+        assert_eq!(entry.namespace, NamespaceId::from([1; 32]));
+        assert_eq!(entry.signature, None);
+    }
+
+    #[test]
+    fn decodes_entries_of_the_current_version() {
+        let entry = ManifestEntry {
+            table: "files".to_string(),
+            namespace: NamespaceId::from([1; 32]),
+            ticket: "docaaa".to_string(),
+            signature: Some(vec![1, 2, 3]),
+        };
+        let (decoded, version) =
+            ManifestEntry::from_bytes_versioned(entry.as_bytes().unwrap()).unwrap();
+        assert_eq!(version, ManifestEntry::VERSION);
+        assert_eq!(decoded, entry);
+    }
+}
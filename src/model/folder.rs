@@ -1,37 +1,70 @@
+use std::collections::HashSet;
 use std::ops::{Deref, DerefMut};
 
+use anyhow::{Result, ensure};
+use iroh_blobs::Hash;
 use iroh_docs::{
     DocTicket,
-    api::{
-        Doc,
-        protocol::{AddrInfoOptions, ShareMode},
-    },
+    api::{Doc, protocol::ShareMode},
 };
 use serde::{Deserialize, Serialize};
-use uuid::Uuid;
 
 use crate::{
-    iroh_create_author, iroh_create_doc,
+    iroh_create_author, iroh_table_doc,
+    keys::{KeyScheme, Keys},
+    schema::Migrations,
     server::IrohNet,
     store::{GetProperties, IrohCls, IrohProperties, ToBytes},
+    validate::Validators,
 };
 
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
 pub struct Folder {
     pub folder_id: String,
     pub folder_name: String,
+    // containing folder, `None` for top level folders
+    #[serde(default)]
+    pub parent_id: Option<String>,
+}
+
+// layout of version 0, before folders could be nested
+#[derive(Deserialize)]
+struct FolderV0 {
+    folder_id: String,
+    folder_name: String,
 }
 
 impl ToBytes<Folder> for Folder {
+    const VERSION: u32 = 1;
+
+    fn migrations() -> Migrations<Folder> {
+        Migrations::new().register(0, |old: FolderV0| Folder {
+            folder_id: old.folder_id,
+            folder_name: old.folder_name,
+            parent_id: None,
+        })
+    }
+
     fn missing_file(id: String) -> Self {
         Folder {
             folder_id: id,
             folder_name: "Untitled".to_string(),
+            parent_id: None,
         }
     }
+
+    fn sort_name(&self) -> Option<&str> {
+        Some(&self.folder_name)
+    }
+
+    fn folder_id(&self) -> Option<&str> {
+        self.parent_id.as_deref()
+    }
 }
 
-pub struct Folders(IrohCls<Folder>);
+// the table and how it keys new folders
+#[derive(Clone)]
+pub struct Folders(IrohCls<Folder>, Keys);
 
 impl Deref for Folders {
     type Target = IrohCls<Folder>;
@@ -51,44 +84,189 @@ impl GetProperties for Folders {
     fn get_doc(&self) -> &Doc {
         &self.0.doc
     }
+
+    fn key_prefix(&self) -> &[u8] {
+        &self.0.prefix
+    }
 }
 
 impl Folders {
     pub async fn new(ticket: &Option<DocTicket>, node: IrohNet) -> anyhow::Result<Self> {
-        let doc = iroh_create_doc(&node, &ticket).await?;
+        let doc = iroh_table_doc(&node, &ticket).await?;
 
         let author_common = iroh_create_author(&node).await?;
         if !ticket.is_some() {
-            let ticket = doc
-                .share(ShareMode::Write, AddrInfoOptions::RelayAndAddresses)
-                .await?;
-            Ok(Folders(IrohCls::<Folder> {
-                node,
-                doc,
-                ticket: Some(ticket),
-                author: author_common,
-                entity: None,
-            }))
+            let ticket = doc.share(ShareMode::Write, node.addr_info()).await?;
+            Ok(Folders(
+                IrohCls::<Folder> {
+                    node,
+                    doc,
+                    ticket: Some(ticket),
+                    author: author_common,
+                    entity: None,
+                    validators: Validators::default(),
+                    prefix: Vec::new(),
+                },
+                Keys::default(),
+            ))
         } else {
-            Ok(Folders(IrohCls::<Folder> {
-                node,
-                doc,
-                ticket: None,
-                author: author_common,
-                entity: None,
-            }))
+            Ok(Folders(
+                IrohCls::<Folder> {
+                    node,
+                    doc,
+                    ticket: None,
+                    author: author_common,
+                    entity: None,
+                    validators: Validators::default(),
+                    prefix: Vec::new(),
+                },
+                Keys::default(),
+            ))
         }
     }
 
     pub async fn insert_folder(&self, folder_name: String) -> anyhow::Result<()> {
-        let folder_id = Uuid::new_v4().to_string();
+        self.create_folder(folder_name, None).await?;
+        Ok(())
+    }
+
+    /// Create a folder inside `parent_id`, or at the top level, and return its id
+    pub async fn create_folder(
+        &self,
+        folder_name: String,
+        parent_id: Option<String>,
+    ) -> Result<String> {
+        if let Some(parent_id) = &parent_id {
+            ensure!(
+                self.get(parent_id).await?.is_some(),
+                "Folder '{}' does not exist",
+                parent_id
+            );
+        }
+        // the place is salted, a folder moved away would otherwise be overwritten by a new
+        // folder at its old place under the content scheme
+        let salt: [u8; 16] = rand::random();
+        let place = format!(
+            "{}/{}/{}",
+            parent_id.as_deref().unwrap_or_default(),
+            folder_name,
+            hex::encode(salt)
+        );
         let folder = Folder {
-            folder_id,
+            folder_id: self.1.key(&Hash::new(place)),
             folder_name,
+            parent_id,
         };
+        self.save(&folder).await?;
+        Ok(folder.folder_id)
+    }
+
+    /// Choose how new folders are keyed, folders created before keep their keys
+    pub fn set_key_scheme(&self, scheme: KeyScheme) {
+        self.1.set(scheme.strategy());
+    }
+
+    /// Find a folder by id
+    pub async fn get(&self, folder_id: &str) -> Result<Option<Folder>> {
+        self.get_exact(folder_id).await
+    }
+
+    /// Folders directly inside `folder_id`, or the top level folders for `None`
+    pub async fn children(&self, folder_id: Option<&str>) -> Result<Vec<Folder>> {
+        let mut children: Vec<Folder> = self
+            .search()
+            .await?
+            .into_iter()
+            .map(|f| f.into_inner())
+            .filter(|f| f.parent_id.as_deref() == folder_id)
+            .collect();
+        children.sort_by(|a, b| a.folder_name.cmp(&b.folder_name));
+        Ok(children)
+    }
+
+    /// Folders from the top level down to `folder_id`, including it
+    ///
+    /// Fails if the parents form a cycle, which concurrent moves on different peers can cause
+    pub async fn path(&self, folder_id: &str) -> Result<Vec<Folder>> {
+        let mut path = Vec::new();
+        let mut seen = HashSet::new();
+        let mut next = Some(folder_id.to_string());
+        while let Some(id) = next {
+            ensure!(
+                seen.insert(id.clone()),
+                "Folder '{}' is part of a cycle",
+                id
+            );
+            let folder = self
+                .get(&id)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Folder '{}' does not exist", id))?;
+            next = folder.parent_id.clone();
+            path.push(folder);
+        }
+        path.reverse();
+        Ok(path)
+    }
 
-        self.0
-            .insert_bytes(folder.folder_id.as_bytes(), folder.as_bytes()?)
-            .await
+    /// Move a folder into `parent_id`, or to the top level for `None`
+    ///
+    /// A folder can't be moved into itself or one of its descendants
+    pub async fn move_folder(&self, folder_id: &str, parent_id: Option<String>) -> Result<()> {
+        let mut folder = self
+            .get(folder_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Folder '{}' does not exist", folder_id))?;
+        if let Some(parent_id) = &parent_id {
+            let ancestors = self.path(parent_id).await?;
+            ensure!(
+                ancestors.iter().all(|f| f.folder_id != folder_id),
+                "Folder '{}' can't be moved into itself",
+                folder_id
+            );
+        }
+        folder.parent_id = parent_id;
+        self.save(&folder).await
+    }
+
+    async fn save(&self, folder: &Folder) -> Result<()> {
+        self.0.insert(folder.folder_id.as_bytes(), folder).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a folder as version 0 encoded it
+    #[derive(Serialize)]
+    struct FlatFolder {
+        folder_id: String,
+        folder_name: String,
+    }
+
+    #[test]
+    fn decodes_folders_of_version_0() {
+        let bytes = bincode::serialize(&FlatFolder {
+            folder_id: "1".to_string(),
+            folder_name: "photos".to_string(),
+        })
+        .unwrap();
+        let (folder, version) = Folder::from_bytes_versioned(bytes.into()).unwrap();
+        assert_eq!(version, 0);
+        assert_eq!(folder.folder_id, "1");
+        assert_eq!(folder.folder_name, "photos");
+        assert_eq!(folder.parent_id, None);
+    }
+
+    #[test]
+    fn decodes_folders_of_the_current_version() {
+        let folder = Folder {
+            folder_id: "2".to_string(),
+            folder_name: "2025".to_string(),
+            parent_id: Some("1".to_string()),
+        };
+        let (decoded, version) = Folder::from_bytes_versioned(folder.as_bytes().unwrap()).unwrap();
+        assert_eq!(version, Folder::VERSION);
+        assert_eq!(decoded.parent_id.as_deref(), Some("1"));
     }
 }
@@ -1,37 +1,72 @@
 use std::ops::{Deref, DerefMut};
+use std::time::Duration;
 
 use iroh_docs::{
     DocTicket,
-    api::{
-        Doc,
-        protocol::{AddrInfoOptions, ShareMode},
-    },
+    api::{Doc, protocol::ShareMode},
 };
 use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
 
 use crate::{
-    iroh_create_author, iroh_create_doc,
+    iroh_create_author, iroh_table_doc, now_millis,
+    schema::Migrations,
     server::IrohNet,
-    store::{GetProperties, IrohCls, ToBytes},
+    store::{GetProperties, IrohCls, IrohProperties, ToBytes},
+    validate::Validators,
 };
 
+// how often every peer refreshes its own entry, each refresh is a write synced to every peer
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+// peers whose entry is older are listed as offline
+pub const ONLINE_TIMEOUT: Duration = Duration::from_secs(45 * 60);
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Node {
     pub node_name: String,
     pub key: i64,
     pub node_id: String,
+    // last heartbeat in milliseconds since the unix epoch
+    pub last_seen: u64,
+}
+
+// layout of version 0, before peers sent heartbeats
+#[derive(Deserialize)]
+struct NodeV0 {
+    node_name: String,
+    key: i64,
+    node_id: String,
 }
 
 impl ToBytes<Node> for Node {
+    const VERSION: u32 = 1;
+
+    fn migrations() -> Migrations<Node> {
+        Migrations::new().register(0, |old: NodeV0| Node {
+            node_name: old.node_name,
+            key: old.key,
+            node_id: old.node_id,
+            last_seen: 0,
+        })
+    }
+
     fn missing_file(id: String) -> Self {
         Node {
             node_name: "文件不存在".to_string(),
             key: 0,
             node_id: id,
+            last_seen: 0,
         }
     }
+
+    fn sort_name(&self) -> Option<&str> {
+        Some(&self.node_name)
+    }
 }
 
+#[derive(Clone)]
 pub struct Nodes(IrohCls<Node>);
 
 impl Deref for Nodes {
@@ -52,23 +87,34 @@ impl GetProperties for Nodes {
     fn get_doc(&self) -> &Doc {
         &self.0.doc
     }
+
+    fn key_prefix(&self) -> &[u8] {
+        &self.0.prefix
+    }
+}
+
+impl Node {
+    /// Whether the peer sent a heartbeat within [`ONLINE_TIMEOUT`]
+    pub fn is_online(&self) -> bool {
+        now_millis().saturating_sub(self.last_seen) < ONLINE_TIMEOUT.as_millis() as u64
+    }
 }
 
 impl Nodes {
     pub async fn new(ticket: &Option<DocTicket>, node: IrohNet) -> anyhow::Result<Self> {
-        let doc = iroh_create_doc(&node, &ticket).await?;
+        let doc = iroh_table_doc(&node, &ticket).await?;
 
         let author_common = iroh_create_author(&node).await?;
         if !ticket.is_some() {
-            let ticket = doc
-                .share(ShareMode::Write, AddrInfoOptions::RelayAndAddresses)
-                .await?;
+            let ticket = doc.share(ShareMode::Write, node.addr_info()).await?;
             Ok(Nodes(IrohCls::<Node> {
                 node,
                 doc,
                 ticket: Some(ticket),
                 author: author_common,
                 entity: None,
+                validators: Validators::default(),
+                prefix: Vec::new(),
             }))
         } else {
             Ok(Nodes(IrohCls::<Node> {
@@ -77,7 +123,105 @@ impl Nodes {
                 ticket: None,
                 author: author_common,
                 entity: None,
+                validators: Validators::default(),
+                prefix: Vec::new(),
             }))
         }
     }
+
+    /// Find a peer by node id
+    pub async fn get(&self, node_id: &str) -> anyhow::Result<Option<Node>> {
+        self.get_exact(node_id).await
+    }
+
+    /// Write the entry of this peer, keyed by its node id, with the current time
+    ///
+    /// The name is [`IrohNet::display_name`], the short node id if it is not set
+    pub async fn upsert_self(&self) -> anyhow::Result<Node> {
+        let id = self.node.router.endpoint().id();
+        let node_name = self
+            .node
+            .display_name
+            .clone()
+            .unwrap_or_else(|| id.fmt_short().to_string());
+        let key = match self.get(&id.to_string()).await {
+            Ok(Some(existing)) => existing.key,
+            _ => 0,
+        };
+        let node = Node {
+            node_name,
+            key,
+            node_id: id.to_string(),
+            last_seen: now_millis(),
+        };
+        self.0.insert(node.node_id.as_bytes(), &node).await?;
+        Ok(node)
+    }
+
+    /// All peers that ever joined, most recently seen first
+    pub async fn roster(&self) -> anyhow::Result<Vec<Node>> {
+        let mut nodes: Vec<Node> = self
+            .search()
+            .await?
+            .into_iter()
+            .map(|n| n.into_inner())
+            .collect();
+        nodes.sort_by(|a, b| b.last_seen.cmp(&a.last_seen));
+        Ok(nodes)
+    }
+}
+
+/// Refresh the entry of this peer every [`HEARTBEAT_INTERVAL`], see [`Nodes::upsert_self`]
+pub fn spawn_heartbeat(nodes: Nodes) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+        loop {
+            interval.tick().await;
+            match nodes.upsert_self().await {
+                Ok(node) => debug!(name = %node.node_name, "sent heartbeat"),
+                Err(e) => warn!(error = %e, "failed to send heartbeat"),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a node as version 0 encoded it
+    #[derive(Serialize)]
+    struct SilentNode {
+        node_name: String,
+        key: i64,
+        node_id: String,
+    }
+
+    #[test]
+    fn decodes_nodes_of_version_0() {
+        let bytes = bincode::serialize(&SilentNode {
+            node_name: "laptop".to_string(),
+            key: 7,
+            node_id: "abc".to_string(),
+        })
+        .unwrap();
+        let (node, version) = Node::from_bytes_versioned(bytes.into()).unwrap();
+        assert_eq!(version, 0);
+        assert_eq!(node.node_name, "laptop");
+        assert_eq!(node.key, 7);
+        assert_eq!(node.last_seen, 0);
+    }
+
+    #[test]
+    fn decodes_nodes_of_the_current_version() {
+        let node = Node {
+            node_name: "laptop".to_string(),
+            key: 7,
+            node_id: "abc".to_string(),
+            last_seen: 42,
+        };
+        let (decoded, version) = Node::from_bytes_versioned(node.as_bytes().unwrap()).unwrap();
+        assert_eq!(version, Node::VERSION);
+        assert_eq!(decoded.last_seen, 42);
+    }
 }
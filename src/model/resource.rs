@@ -1,19 +1,39 @@
-use anyhow::Result;
+use anyhow::{Context, Result, ensure};
+use bytes::Bytes;
+use futures::{StreamExt, TryStreamExt, stream};
+use std::collections::{HashMap, HashSet};
 use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::task::JoinHandle;
+use tracing::{Instrument, debug, info_span, warn};
 
 use crate::{
-    iroh_create_author, iroh_create_doc,
+    crypto,
+    downloader::has_content,
+    image_meta::{self, ImageMeta},
+    import::{collect_files, guess_content_type},
+    iroh_create_author, iroh_table_doc,
+    keys::{KeyScheme, KeyStrategy, Keys},
+    now_millis, pin,
+    placement::{Placement, hash_file, hash_file_with},
+    schema::Migrations,
     store::{GetProperties, IrohCls, IrohProperties, ToBytes},
+    supervisor::previous_peers,
+    sync_filter::{SyncFilter, SyncFilters},
+    validate::Validators,
 };
+use iroh::PublicKey;
+use iroh_blobs::{Hash, HashAndFormat, format::collection::Collection, hashseq::HashSeq};
 use iroh_docs::{
-    DocTicket,
-    api::{
-        Doc,
-        protocol::{AddrInfoOptions, ShareMode},
-    },
+    ContentStatus, DocTicket,
+    api::{Doc, protocol::ShareMode},
+    engine::LiveEvent,
+    store::Query,
 };
 use serde::{Deserialize, Serialize};
-use uuid::Uuid;
 
 use crate::server::IrohNet;
 
@@ -21,20 +41,305 @@ use crate::server::IrohNet;
 pub struct Resource {
     pub id: String,
     pub name: String,
+    // only holds the content when `placement` is inline
+    #[serde(with = "crate::export::base64_bytes")]
     pub blob: Vec<u8>,
+    pub placement: Placement,
+    // mime type detected when the file was added
+    pub content_type: Option<String>,
+    // content length in bytes
+    pub size: u64,
+    // creation time in milliseconds since the unix epoch
+    pub created_at: u64,
+    // folder of the folder table holding the resource, `None` when unfiled
+    #[serde(default)]
+    pub folder_id: Option<String>,
+    // dimensions and EXIF details, only extracted for images when the node asks for it
+    #[serde(default)]
+    pub image: Option<ImageMeta>,
+    // keyed hash of the plain content when it is encrypted, see `DocCipher::digest`
+    #[serde(default)]
+    pub digest: Option<Hash>,
+}
+
+// first layout of version 0, resources written before their content could be placed
+// outside the record
+#[derive(Deserialize)]
+struct ResourceBase {
+    id: String,
+    name: String,
+    #[serde(with = "crate::export::base64_bytes")]
+    blob: Vec<u8>,
+}
+
+// layout of version 0, resources written before the content type, size and creation
+// time were recorded
+#[derive(Deserialize)]
+struct ResourceV0 {
+    id: String,
+    name: String,
+    #[serde(with = "crate::export::base64_bytes")]
+    blob: Vec<u8>,
+    placement: Placement,
+}
+
+// second layout of version 0, with the content type, size and creation time but before
+// resources were filed into folders
+#[derive(Deserialize)]
+struct ResourceV0Typed {
+    id: String,
+    name: String,
+    #[serde(with = "crate::export::base64_bytes")]
+    blob: Vec<u8>,
+    placement: Placement,
+    content_type: Option<String>,
+    size: u64,
+    created_at: u64,
+}
+
+// layout of version 1, before image metadata was extracted
+#[derive(Deserialize)]
+struct ResourceV1 {
+    id: String,
+    name: String,
+    #[serde(with = "crate::export::base64_bytes")]
+    blob: Vec<u8>,
+    placement: Placement,
+    content_type: Option<String>,
+    size: u64,
+    created_at: u64,
+    folder_id: Option<String>,
+}
+
+// layout of version 2, before encrypted content was digested
+#[derive(Deserialize)]
+struct ResourceV2 {
+    id: String,
+    name: String,
+    #[serde(with = "crate::export::base64_bytes")]
+    blob: Vec<u8>,
+    placement: Placement,
+    content_type: Option<String>,
+    size: u64,
+    created_at: u64,
+    folder_id: Option<String>,
+    image: Option<ImageMeta>,
 }
 
 impl ToBytes<Resource> for Resource {
+    const VERSION: u32 = 3;
+
+    fn migrations() -> Migrations<Resource> {
+        // the longer layout first, the older one would also read its leading fields
+        Migrations::new()
+            .register(0, |old: ResourceV0Typed| Resource {
+                id: old.id,
+                name: old.name,
+                blob: old.blob,
+                placement: old.placement,
+                content_type: old.content_type,
+                size: old.size,
+                created_at: old.created_at,
+                folder_id: None,
+                image: None,
+                digest: None,
+            })
+            .register(0, |old: ResourceV0| Resource {
+                content_type: guess_content_type(&old.name),
+                size: match old.placement {
+                    Placement::Inline => old.blob.len() as u64,
+                    Placement::Local { size, .. }
+                    | Placement::External { size, .. }
+                    | Placement::Chunked { size, .. }
+                    | Placement::Collection { size, .. } => size,
+                },
+                created_at: 0,
+                id: old.id,
+                name: old.name,
+                blob: old.blob,
+                placement: old.placement,
+                folder_id: None,
+                image: None,
+                digest: None,
+            })
+            // reads the leading fields of every other layout, so it is tried last
+            .register(0, |old: ResourceBase| Resource {
+                content_type: guess_content_type(&old.name),
+                size: old.blob.len() as u64,
+                created_at: 0,
+                id: old.id,
+                name: old.name,
+                blob: old.blob,
+                placement: Placement::Inline,
+                folder_id: None,
+                image: None,
+                digest: None,
+            })
+            .register(1, |old: ResourceV1| Resource {
+                id: old.id,
+                name: old.name,
+                blob: old.blob,
+                placement: old.placement,
+                content_type: old.content_type,
+                size: old.size,
+                created_at: old.created_at,
+                folder_id: old.folder_id,
+                image: None,
+                digest: None,
+            })
+            .register(2, |old: ResourceV2| Resource {
+                id: old.id,
+                name: old.name,
+                blob: old.blob,
+                placement: old.placement,
+                content_type: old.content_type,
+                size: old.size,
+                created_at: old.created_at,
+                folder_id: old.folder_id,
+                image: old.image,
+                digest: None,
+            })
+    }
+
     fn missing_file(id: String) -> Self {
         Resource {
             id,
             name: "文件不存在".to_string(),
             blob: vec![],
+            placement: Placement::Inline,
+            content_type: None,
+            size: 0,
+            created_at: 0,
+            folder_id: None,
+            image: None,
+            digest: None,
+        }
+    }
+
+    fn sort_name(&self) -> Option<&str> {
+        Some(&self.name)
+    }
+
+    fn content_size(&self) -> Option<u64> {
+        Some(self.size)
+    }
+
+    fn folder_id(&self) -> Option<&str> {
+        self.folder_id.as_deref()
+    }
+
+    fn created_at(&self) -> Option<u64> {
+        Some(self.created_at)
+    }
+
+    fn captured_at(&self) -> Option<u64> {
+        self.image.as_ref().and_then(|image| image.captured_at)
+    }
+
+    fn search_text(&self) -> String {
+        match &self.content_type {
+            Some(content_type) => format!("{} {}", self.name, content_type),
+            None => self.name.clone(),
+        }
+    }
+}
+
+impl Resource {
+    /// Hash of the content as it is stored, wherever it is placed
+    pub fn content_hash(&self) -> Hash {
+        match &self.placement {
+            Placement::Inline => Hash::new(&self.blob),
+            Placement::Local { hash, .. }
+            | Placement::External { hash, .. }
+            | Placement::Chunked { hash, .. }
+            | Placement::Collection { hash, .. } => *hash,
+        }
+    }
+
+    /// Identity of the content to find duplicates, the digest when the content is encrypted
+    pub fn content_id(&self) -> Hash {
+        self.digest.unwrap_or_else(|| self.content_hash())
+    }
+}
+
+/// Progress of storing resource content
+#[derive(Debug, Clone, Copy)]
+pub struct UploadProgress {
+    // bytes stored so far
+    pub written: u64,
+    pub total: u64,
+}
+
+// files of a batch placed at the same time
+const BATCH_CONCURRENCY: usize = 8;
+
+// a resource about to be stored, before its content is placed
+struct NewFile {
+    id: String,
+    name: String,
+    content_type: Option<String>,
+    blob: Vec<u8>,
+    created_at: u64,
+    folder_id: Option<String>,
+}
+
+impl NewFile {
+    // an unfiled resource created now
+    fn new(id: String, name: String, content_type: Option<String>, blob: Vec<u8>) -> Self {
+        NewFile {
+            id,
+            name,
+            content_type,
+            blob,
+            created_at: now_millis(),
+            folder_id: None,
         }
     }
 }
 
-pub struct Resources(IrohCls<Resource>);
+/// A temporary file for content that is streamed in before it is added, removed when dropped
+///
+/// Lives under the storage path of the node, or in the temporary directory of the system
+pub struct TempFile {
+    pub path: PathBuf,
+    pub file: tokio::fs::File,
+}
+
+impl TempFile {
+    pub async fn create(node: &IrohNet) -> Result<Self> {
+        let dir = match node.storage_path() {
+            Some(root) => root.join("tmp"),
+            None => std::env::temp_dir(),
+        };
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .with_context(|| format!("Failed to create {:?}", dir))?;
+        let path = dir.join(format!("upload-{}", hex::encode(rand::random::<[u8; 8]>())));
+        let file = tokio::fs::File::create(&path)
+            .await
+            .with_context(|| format!("Failed to create {:?}", path))?;
+        Ok(TempFile { path, file })
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// mime type of resources holding a collection of files, see [`Resources::add_collection`]
+pub const COLLECTION_CONTENT_TYPE: &str = "application/x-iroh-collection";
+
+// identifies a file by name and stored content hash
+pub type ContentKey = (String, Hash);
+
+// existing resource ids keyed by name and content hash
+pub type ContentIndex = HashMap<ContentKey, String>;
+
+// the table and how it keys new resources
+#[derive(Clone)]
+pub struct Resources(IrohCls<Resource>, Keys);
 
 impl Deref for Resources {
     type Target = IrohCls<Resource>;
@@ -54,45 +359,1120 @@ impl GetProperties for Resources {
     fn get_doc(&self) -> &Doc {
         &self.0.doc
     }
+
+    fn key_prefix(&self) -> &[u8] {
+        &self.0.prefix
+    }
 }
 
 impl Resources {
     pub async fn new(ticket: &Option<DocTicket>, node: IrohNet) -> anyhow::Result<Self> {
-        let doc = iroh_create_doc(&node, &ticket).await?;
+        let doc = iroh_table_doc(&node, &ticket).await?;
 
         let author_common = iroh_create_author(&node).await?;
         if !ticket.is_some() {
-            let ticket = doc
-                .share(ShareMode::Write, AddrInfoOptions::RelayAndAddresses)
-                .await?;
-            Ok(Resources(IrohCls::<Resource> {
-                node,
-                doc,
-                ticket: Some(ticket),
-                author: author_common,
-                entity: None,
-            }))
+            let ticket = doc.share(ShareMode::Write, node.addr_info()).await?;
+            Ok(Resources(
+                IrohCls::<Resource> {
+                    node,
+                    doc,
+                    ticket: Some(ticket),
+                    author: author_common,
+                    entity: None,
+                    validators: Validators::default(),
+                    prefix: Vec::new(),
+                },
+                Keys::default(),
+            ))
         } else {
-            Ok(Resources(IrohCls::<Resource> {
-                node,
-                doc,
-                ticket: None,
-                author: author_common,
-                entity: None,
-            }))
+            Ok(Resources(
+                IrohCls::<Resource> {
+                    node,
+                    doc,
+                    ticket: None,
+                    author: author_common,
+                    entity: None,
+                    validators: Validators::default(),
+                    prefix: Vec::new(),
+                },
+                Keys::default(),
+            ))
         }
     }
 
-    pub async fn add_file(&self, name: String, blob: Vec<u8>) -> Result<()> {
-        let file_id = Uuid::new_v4().to_string();
+    /// Add a new resource and return its id, the mime type is guessed from `name`
+    pub async fn add_file(&self, name: String, blob: Vec<u8>) -> Result<String> {
+        let content_type = guess_content_type(&name);
+        self.add_file_with_type(name, content_type, blob).await
+    }
+
+    /// Add a new resource with the bytes read from `reader` until its end and return its id
+    ///
+    /// Lets other programs pipe generated content in, e.g. the item of a [`StdinImporter`].
+    /// The content is streamed to a [`TempFile`] up to the largest file size of the placement
+    /// policy and added from there like [`Resources::add_path`]
+    ///
+    /// [`StdinImporter`]: crate::import::StdinImporter
+    pub async fn add_file_from_reader(
+        &self,
+        name: String,
+        reader: impl AsyncRead + Unpin,
+    ) -> Result<String> {
+        let temp = self.spool(reader).await?;
+        let content_type = guess_content_type(&name);
+        self.add_path_with_progress(&temp.path, name, content_type, |_| {})
+            .await
+    }
+
+    /// Write the bytes read from `reader` until its end to a [`TempFile`]
+    ///
+    /// Fails once the input exceeds the largest file size of the placement policy
+    pub async fn spool(&self, reader: impl AsyncRead + Unpin) -> Result<TempFile> {
+        let max = self.node.placement.max_file_size;
+        let limit = if max == 0 { u64::MAX } else { max + 1 };
+        let mut temp = TempFile::create(&self.node).await?;
+        let size = tokio::io::copy(&mut reader.take(limit), &mut temp.file).await?;
+        temp.file.flush().await?;
+        ensure!(
+            max == 0 || size <= max,
+            "Input exceeds the limit of {} bytes",
+            max
+        );
+        Ok(temp)
+    }
+
+    /// Add a new resource with a known mime type and return its id
+    pub async fn add_file_with_type(
+        &self,
+        name: String,
+        content_type: Option<String>,
+        blob: Vec<u8>,
+    ) -> Result<String> {
+        let file_id = self.new_key(&blob)?;
+        self.store_file(
+            NewFile::new(file_id.clone(), name, content_type, blob),
+            &|_| {},
+        )
+        .await?;
+        Ok(file_id)
+    }
+
+    /// Add a new resource like [`Resources::add_file`], reporting how much content is stored
+    pub async fn add_file_with_progress(
+        &self,
+        name: String,
+        blob: Vec<u8>,
+        progress: impl Fn(UploadProgress) + Send + Sync,
+    ) -> Result<String> {
+        let file_id = self.new_key(&blob)?;
+        let content_type = guess_content_type(&name);
+        self.store_file(
+            NewFile::new(file_id.clone(), name, content_type, blob),
+            &progress,
+        )
+        .await?;
+        Ok(file_id)
+    }
+
+    /// Add the file at `path` as a new resource and return its id
+    ///
+    /// The name is the file name and the mime type is guessed from it. Large files are
+    /// streamed into the blob store, see [`Resources::streams_file`]
+    pub async fn add_path(&self, path: &Path) -> Result<String> {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let content_type = guess_content_type(&name);
+        self.add_path_with_progress(path, name, content_type, |_| {})
+            .await
+    }
+
+    /// Add the file at `path` like [`Resources::add_path`] with a given name and mime type,
+    /// reporting how much content is stored
+    pub async fn add_path_with_progress(
+        &self,
+        path: &Path,
+        name: String,
+        content_type: Option<String>,
+        progress: impl Fn(UploadProgress) + Send + Sync,
+    ) -> Result<String> {
+        let resource = self
+            .build_path_resource(path, name, content_type, &progress)
+            .await?;
+        self.0.insert(resource.id.as_bytes(), &resource).await?;
+        Ok(resource.id)
+    }
+
+    /// Create or overwrite the resource stored under `id` with the file at `path`
+    ///
+    /// Like [`Resources::put_file`] without reading a large file into memory
+    pub async fn put_path(&self, id: &str, name: String, path: &Path) -> Result<()> {
+        let (created_at, folder_id) = match self.get(id).await {
+            Ok(Some(existing)) => (existing.created_at, existing.folder_id),
+            _ => (now_millis(), None),
+        };
+        let content_type = guess_content_type(&name);
         let resource = Resource {
-            id: file_id,
+            id: id.to_string(),
+            created_at,
+            folder_id,
+            ..self
+                .build_path_resource(path, name, content_type, &|_| {})
+                .await?
+        };
+        self.0.insert(resource.id.as_bytes(), &resource).await
+    }
+
+    // place the file at `path` for an unfiled resource keyed by its content
+    async fn build_path_resource(
+        &self,
+        path: &Path,
+        name: String,
+        content_type: Option<String>,
+        progress: &(dyn Fn(UploadProgress) + Send + Sync),
+    ) -> Result<Resource> {
+        let size = tokio::fs::metadata(path).await?.len();
+        if !self.streams_file(size) {
+            let blob = tokio::fs::read(path).await?;
+            let file_id = self.new_key(&blob)?;
+            return self
+                .build_resource(NewFile::new(file_id, name, content_type, blob), progress)
+                .await;
+        }
+        let placement = self
+            .node
+            .placement
+            .place_file(&self.node, path, &|written| {
+                progress(UploadProgress {
+                    written,
+                    total: size,
+                })
+            })
+            .await?;
+        let image = if self.extracts_image_meta(content_type.as_deref()) {
+            let path = path.to_path_buf();
+            tokio::task::spawn_blocking(move || image_meta::extract_path(&path)).await?
+        } else {
+            None
+        };
+        let mut resource = Resource {
+            id: String::new(),
             name,
+            blob: vec![],
+            placement,
+            content_type,
+            size,
+            created_at: now_millis(),
+            folder_id: None,
+            image,
+            digest: None,
+        };
+        // streamed content is only hashed while it is placed
+        resource.id = self.1.key(&resource.content_hash());
+        Ok(resource)
+    }
+
+    /// Whether a file of `size` bytes is streamed from disk instead of read into memory
+    ///
+    /// Content that stays inline is small enough to read, and encrypted content is
+    /// sealed as a whole, so those are always read
+    pub fn streams_file(&self, size: u64) -> bool {
+        self.node.cipher.is_none() && size > self.node.placement.inline_max as u64
+    }
+
+    /// Choose how new resources are keyed, resources added before keep their keys
+    ///
+    /// See [`KeyScheme`] for the built-in strategies
+    pub fn set_key_strategy(&self, strategy: Arc<dyn KeyStrategy>) {
+        self.1.set(strategy);
+    }
+
+    /// Key new resources with one of the built-in strategies
+    pub fn set_key_scheme(&self, scheme: KeyScheme) {
+        self.set_key_strategy(scheme.strategy());
+    }
+
+    // key of a new resource holding `blob`, hashed before it is sealed
+    //
+    // keys are not encrypted, so with a cipher the strategy gets the keyed digest of the
+    // content instead of its plain hash
+    fn new_key(&self, blob: &[u8]) -> Result<String> {
+        let hash = match &self.node.cipher {
+            Some(cipher) => cipher.digest(self.doc.id(), blob)?,
+            None => Hash::new(blob),
+        };
+        Ok(self.1.key(&hash))
+    }
+
+    // whether new content of `content_type` gets its image metadata extracted
+    fn extracts_image_meta(&self, content_type: Option<&str>) -> bool {
+        self.node.extract_image_meta && image_meta::is_image(content_type)
+    }
+
+    /// Add the files below `dir` as one resource holding an iroh blobs collection
+    ///
+    /// The files are stored as separate blobs and listed by their path relative to `dir`
+    /// in a single hash sequence, so the whole set is shared and fetched as one unit.
+    /// Returns the id of the new resource
+    pub async fn add_collection(&self, dir: &Path) -> Result<String> {
+        ensure!(dir.is_dir(), "{:?} is not a directory", dir);
+        let dir = std::path::absolute(dir)?;
+        let store = &self.node.blobs_store;
+        let mut entries = Vec::new();
+        let mut size = 0;
+        for path in collect_files(&dir, true)? {
+            let name = path
+                .strip_prefix(&dir)?
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            let file_size = tokio::fs::metadata(&path).await?.len();
+            let max_file_size = self.node.placement.max_file_size;
+            ensure!(
+                max_file_size == 0 || file_size <= max_file_size,
+                "{:?} has {} bytes, more than the limit of {} bytes",
+                path,
+                file_size,
+                max_file_size
+            );
+            let hash = match &self.node.cipher {
+                // files are sealed one at a time
+                Some(_) => {
+                    let blob = tokio::fs::read(&path).await?;
+                    let blob =
+                        crypto::seal_content(self.node.cipher.as_ref(), self.doc.id(), blob)?;
+                    store.add_bytes(blob).await?.hash
+                }
+                None => store.add_path(&path).await?.hash,
+            };
+            size += file_size;
+            entries.push((name, hash));
+        }
+        let files = entries.len() as u64;
+        let collection: Collection = entries.into_iter().collect();
+        let temp_tag = collection.store(store).await?;
+        // a temporary tag only protects the collection until it is dropped
+        store.tags().create(temp_tag.hash_and_format()).await?;
+        // the hash sequence lists the names blob and every file
+        self.node.quota.added(temp_tag.hash());
+        let seq = HashSeq::try_from(store.blobs().get_bytes(temp_tag.hash()).await?)?;
+        for hash in seq.iter() {
+            self.node.quota.added(hash);
+        }
+
+        let name = dir
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| "collection".to_string());
+        let resource = Resource {
+            id: self.1.key(&temp_tag.hash()),
+            name,
+            blob: vec![],
+            placement: Placement::Collection {
+                hash: temp_tag.hash(),
+                size,
+                files,
+            },
+            content_type: Some(COLLECTION_CONTENT_TYPE.to_string()),
+            size,
+            created_at: now_millis(),
+            folder_id: None,
+            image: None,
+            digest: None,
+        };
+        self.0.insert(resource.id.as_bytes(), &resource).await?;
+        Ok(resource.id)
+    }
+
+    /// Write the files of a resource added with [`Resources::add_collection`] below `dir`
+    ///
+    /// A collection added on another node is downloaded first. Returns the number of files written
+    pub async fn export_collection(&self, id: &str, dir: &Path) -> Result<usize> {
+        self.prefetch(id).await?;
+        let resource = self
+            .get(id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Resource '{}' does not exist", id))?;
+        let Placement::Collection { hash, .. } = resource.placement else {
+            return Err(anyhow::anyhow!(
+                "Resource '{}' is not a collection of files",
+                id
+            ));
+        };
+        let store = &self.node.blobs_store;
+        let collection = Collection::load(hash, store)
+            .await
+            .with_context(|| format!("Collection {} is not available locally", hash.fmt_short()))?;
+        let dir = std::path::absolute(dir)?;
+        let mut count = 0;
+        for (name, hash) in collection.iter() {
+            // only plain path components, a synced name must not point outside `dir`
+            let relative: PathBuf = Path::new(name)
+                .components()
+                .filter(|c| matches!(c, std::path::Component::Normal(_)))
+                .collect();
+            ensure!(
+                relative.file_name().is_some(),
+                "Invalid file name '{}' in collection",
+                name
+            );
+            let target = dir.join(relative);
+            if let Some(parent) = target.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            if self.node.cipher.is_some() {
+                let content = store.blobs().get_bytes(*hash).await?;
+                let content = crypto::open_content(self.node.cipher.as_ref(), content.to_vec())?;
+                tokio::fs::write(&target, content).await?;
+            } else {
+                store
+                    .blobs()
+                    .export(*hash, &target)
+                    .await
+                    .with_context(|| format!("Failed to export {:?}", target))?;
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Add many resources at once and return their ids in order, mime types are guessed
+    pub async fn add_files(&self, files: Vec<(String, Vec<u8>)>) -> Result<Vec<String>> {
+        let files = files
+            .into_iter()
+            .map(|(name, blob)| {
+                let content_type = guess_content_type(&name);
+                (name, content_type, blob)
+            })
+            .collect();
+        self.add_files_with_type(files).await
+    }
+
+    /// Add many resources with known mime types and return their ids in order
+    ///
+    /// Content is placed and entries are written with several operations in flight
+    pub async fn add_files_with_type(
+        &self,
+        files: Vec<(String, Option<String>, Vec<u8>)>,
+    ) -> Result<Vec<String>> {
+        self.add_files_with_progress(files, |_| {}).await
+    }
+
+    /// Add many resources like [`Resources::add_files_with_type`]
+    ///
+    /// `progress` is called with the content size of the whole batch each time a file is stored
+    pub async fn add_files_with_progress(
+        &self,
+        files: Vec<(String, Option<String>, Vec<u8>)>,
+        progress: impl Fn(UploadProgress) + Send + Sync,
+    ) -> Result<Vec<String>> {
+        let created_at = now_millis();
+        let total = files.iter().map(|(_, _, blob)| blob.len() as u64).sum();
+        let written = AtomicU64::new(0);
+        let (written, progress) = (&written, &progress);
+        let resources: Vec<Resource> = stream::iter(files)
+            .map(|(name, content_type, blob)| async move {
+                let id = self.new_key(&blob)?;
+                let size = blob.len() as u64;
+                let resource = self
+                    .build_resource(
+                        NewFile {
+                            created_at,
+                            ..NewFile::new(id, name, content_type, blob)
+                        },
+                        &|_| {},
+                    )
+                    .await?;
+                let written = written.fetch_add(size, Ordering::SeqCst) + size;
+                progress(UploadProgress { written, total });
+                anyhow::Ok(resource)
+            })
+            .buffered(BATCH_CONCURRENCY)
+            .try_collect()
+            .await?;
+        // the whole batch is rejected if one file fails validation
+        let items = resources
+            .iter()
+            .map(|r| {
+                self.validators.check(r)?;
+                Ok((r.id.as_bytes().to_vec(), r.as_bytes()?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.0.insert_batch(items).await?;
+        Ok(resources.into_iter().map(|r| r.id).collect())
+    }
+
+    /// Index the stored resources for [`Resources::add_file_dedup`]
+    pub async fn content_index(&self) -> Result<ContentIndex> {
+        Ok(self
+            .search()
+            .await?
+            .into_iter()
+            .map(|r| ((r.name.clone(), r.content_id()), r.entity.id))
+            .collect())
+    }
+
+    /// Key of a file in a [`ContentIndex`]
+    pub fn content_key(&self, name: &str, blob: &[u8]) -> Result<ContentKey> {
+        let id = match &self.node.cipher {
+            Some(cipher) => cipher.digest(self.doc.id(), blob)?,
+            None => Hash::new(blob),
+        };
+        Ok((name.to_string(), id))
+    }
+
+    /// Key of the file at `path` in a [`ContentIndex`], hashed without reading it into memory
+    pub async fn path_content_key(&self, name: &str, path: &Path) -> Result<ContentKey> {
+        let id = match &self.node.cipher {
+            Some(cipher) => hash_file_with(path, cipher.digester(self.doc.id())?).await?,
+            None => hash_file(path).await?,
+        };
+        Ok((name.to_string(), id))
+    }
+
+    /// Add a resource unless one with the same name and content is already in `index`
+    ///
+    /// Returns the id of the new resource, or `None` for a duplicate
+    pub async fn add_file_dedup(
+        &self,
+        index: &mut ContentIndex,
+        name: String,
+        content_type: Option<String>,
+        blob: Vec<u8>,
+    ) -> Result<Option<String>> {
+        let key = self.content_key(&name, &blob)?;
+        if index.contains_key(&key) {
+            return Ok(None);
+        }
+        let id = self.add_file_with_type(name, content_type, blob).await?;
+        index.insert(key, id.clone());
+        Ok(Some(id))
+    }
+
+    /// Create or overwrite the resource stored under `id`
+    ///
+    /// An overwritten resource keeps its creation time and folder
+    pub async fn put_file(&self, id: &str, name: String, blob: Vec<u8>) -> Result<()> {
+        let (created_at, folder_id) = match self.get(id).await {
+            Ok(Some(existing)) => (existing.created_at, existing.folder_id),
+            _ => (now_millis(), None),
+        };
+        let content_type = guess_content_type(&name);
+        let file = NewFile {
+            created_at,
+            folder_id,
+            ..NewFile::new(id.to_string(), name, content_type, blob)
+        };
+        self.store_file(file, &|_| {}).await
+    }
+
+    /// Resources filed into `folder_id`, or the unfiled ones for `None`
+    pub async fn list_in_folder(&self, folder_id: Option<&str>) -> Result<Vec<Resource>> {
+        Ok(self
+            .search()
+            .await?
+            .into_iter()
+            .map(|r| r.into_inner())
+            .filter(|r| r.folder_id.as_deref() == folder_id)
+            .collect())
+    }
+
+    /// File a resource into `folder_id`, or unfile it for `None`
+    ///
+    /// Only the entry is rewritten, the content stays where it is.
+    /// Use [`StoreState::move_to_folder`] to check that the folder exists
+    ///
+    /// [`StoreState::move_to_folder`]: crate::store::StoreState::move_to_folder
+    pub async fn move_to_folder(&self, id: &str, folder_id: Option<String>) -> Result<()> {
+        let mut resource = self
+            .get(id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Resource '{}' does not exist", id))?;
+        resource.folder_id = folder_id;
+        self.0.insert(resource.id.as_bytes(), &resource).await
+    }
+
+    async fn store_file(
+        &self,
+        file: NewFile,
+        progress: &(dyn Fn(UploadProgress) + Send + Sync),
+    ) -> Result<()> {
+        let resource = self.build_resource(file, progress).await?;
+        self.0.insert(resource.id.as_bytes(), &resource).await
+    }
+
+    // seal and place the content of a new resource
+    async fn build_resource(
+        &self,
+        file: NewFile,
+        progress: &(dyn Fn(UploadProgress) + Send + Sync),
+    ) -> Result<Resource> {
+        let NewFile {
+            id,
+            name,
+            content_type,
             blob,
+            created_at,
+            folder_id,
+        } = file;
+        let size = blob.len() as u64;
+        let image = if self.extracts_image_meta(content_type.as_deref()) {
+            image_meta::extract(&blob)
+        } else {
+            None
+        };
+        let digest = match &self.node.cipher {
+            Some(cipher) => Some(cipher.digest(self.doc.id(), &blob)?),
+            None => None,
+        };
+        // inline content is sealed once, together with the record
+        let blob = if blob.len() <= self.node.placement.inline_max {
+            blob
+        } else {
+            crypto::seal_content(self.node.cipher.as_ref(), self.doc.id(), blob)?
+        };
+        let total = blob.len() as u64;
+        let placement = self
+            .node
+            .placement
+            .place_with_progress(&self.node, &blob, &|written| {
+                progress(UploadProgress { written, total })
+            })
+            .await?;
+        let blob = if placement == Placement::Inline {
+            blob
+        } else {
+            vec![]
         };
+        Ok(Resource {
+            id,
+            name,
+            blob,
+            placement,
+            content_type,
+            size,
+            created_at,
+            folder_id,
+            image,
+            digest,
+        })
+    }
+
+    /// Move the resource stored under `id` to the trash
+    pub async fn remove(&self, id: &str) -> Result<()> {
+        self.delete(id).await
+    }
+
+    /// Find a resource by id
+    pub async fn get(&self, id: &str) -> Result<Option<Resource>> {
+        self.get_exact(id).await
+    }
+
+    /// Stream the content of a resource to a file
+    ///
+    /// With encryption enabled the content is decrypted in memory instead.
+    /// Returns the number of bytes written
+    pub async fn export(&self, id: &str, path: &Path) -> Result<u64> {
+        let resource = self
+            .get(id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Resource '{}' does not exist", id))?;
+        self.write_content(&resource, path).await
+    }
+
+    /// Write the content of a resource to `path`, or to a file named like the resource
+    /// in the current directory
+    ///
+    /// Missing content is downloaded ahead of the background sync, see [`DownloadScheduler::request`]
+    ///
+    /// [`DownloadScheduler::request`]: crate::downloader::DownloadScheduler::request
+    ///
+    /// Returns the resource, the file written and the number of bytes written
+    pub async fn fetch(&self, id: &str, path: Option<PathBuf>) -> Result<(Resource, PathBuf, u64)> {
+        self.prefetch(id).await?;
+        let resource = self
+            .get(id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Resource '{}' does not exist", id))?;
+        let path = match path {
+            Some(path) => path,
+            // only the file name, a synced name must not point outside the current directory
+            None => Path::new(&resource.name)
+                .file_name()
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(&resource.id)),
+        };
+        let size = self.write_content(&resource, &path).await?;
+        Ok((resource, path, size))
+    }
+
+    /// Keep the content of a resource available locally, downloading it first if needed
+    ///
+    /// Pinned blobs are exempt from garbage collection and storage quota eviction
+    pub async fn pin(&self, id: &str) -> Result<()> {
+        self.prefetch(id).await?;
+        let entry = self
+            .doc
+            .get_one(Query::single_latest_per_key().key_exact(self.doc_key(id)))
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Resource '{}' does not exist", id))?;
+        let resource = self
+            .entity(&entry)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Resource '{}' does not exist", id))?;
+        let mut blobs = vec![HashAndFormat::raw(entry.content_hash())];
+        match &resource.placement {
+            Placement::Local { hash, .. } => blobs.push(HashAndFormat::raw(*hash)),
+            Placement::Chunked { chunks, .. } => {
+                blobs.extend(chunks.iter().map(|hash| HashAndFormat::raw(*hash)))
+            }
+            Placement::Collection { hash, .. } => blobs.push(HashAndFormat::hash_seq(*hash)),
+            Placement::Inline | Placement::External { .. } => {}
+        }
+        pin::pin(&self.node, id, blobs).await
+    }
+
+    /// Remove the pin of a resource, returns whether it was pinned
+    pub async fn unpin(&self, id: &str) -> Result<bool> {
+        pin::unpin(&self.node, id).await
+    }
+
+    /// Every blob the collection refers to, its entries as well as the content they place
+    /// in the blob store
+    pub async fn blob_hashes(&self) -> Result<HashSet<Hash>> {
+        let mut hashes = HashSet::new();
+        let query = Query::single_latest_per_key().key_prefix(&self.prefix);
+        for entry in self.entries(query).await? {
+            hashes.insert(entry.content_hash());
+            let Some(resource) = self.entity(&entry).await? else {
+                continue;
+            };
+            match &resource.placement {
+                Placement::Local { hash, .. } => {
+                    hashes.insert(*hash);
+                }
+                Placement::Chunked { chunks, .. } => hashes.extend(chunks.iter().copied()),
+                Placement::Collection { hash, .. } => {
+                    hashes.insert(*hash);
+                    if let Ok(bytes) = self.node.blobs_store.blobs().get_bytes(*hash).await
+                        && let Ok(seq) = HashSeq::try_from(bytes)
+                    {
+                        hashes.extend(seq.iter());
+                    }
+                }
+                Placement::Inline | Placement::External { .. } => {}
+            }
+        }
+        Ok(hashes)
+    }
+
+    /// Blobs placed by every resource, keyed by the content hash of its entry
+    pub async fn placements(&self) -> Result<HashMap<Hash, Vec<HashAndFormat>>> {
+        let mut placements = HashMap::new();
+        let query = Query::single_latest_per_key().key_prefix(&self.prefix);
+        for entry in self.entries(query).await? {
+            let Some(resource) = self.entity(&entry).await? else {
+                continue;
+            };
+            let blobs = resource
+                .placement
+                .blobs()
+                .into_iter()
+                .map(|(content, _)| content);
+            placements.insert(entry.content_hash(), blobs.collect());
+        }
+        Ok(placements)
+    }
+
+    // download the entry of `id` and its content blobs from the sync peers, if they are missing
+    async fn prefetch(&self, id: &str) -> Result<()> {
+        let Some(entry) = self
+            .doc
+            .get_one(Query::single_latest_per_key().key_exact(self.doc_key(id)))
+            .await?
+        else {
+            return Ok(());
+        };
+        let providers = self.providers().await?;
+        let scheduler = &self.node.scheduler;
+        scheduler
+            .request(entry.content_hash(), entry.content_len(), providers.clone())
+            .await?;
+        let Some(resource) = self.entity(&entry).await? else {
+            return Ok(());
+        };
+        // a collection is fetched as its hash sequence, together with every file it holds
+        for (content, size) in self.missing_blobs(&resource).await? {
+            scheduler.request(content, size, providers.clone()).await?;
+        }
+        Ok(())
+    }
+
+    // peers the doc synced with, they are asked for content this node is missing
+    async fn providers(&self) -> Result<Vec<PublicKey>> {
+        let me = self.node.router.endpoint().id();
+        Ok(previous_peers(&self.doc, me)
+            .await?
+            .into_iter()
+            .map(|addr| addr.id)
+            .collect())
+    }
+
+    /// Placement blobs of `resource` that are not complete in the local blob store
+    pub async fn missing_blobs(&self, resource: &Resource) -> Result<Vec<(HashAndFormat, u64)>> {
+        let mut missing = vec![];
+        for (content, size) in resource.placement.blobs() {
+            if !has_content(&self.node.blobs_store, content).await? {
+                missing.push((content, size));
+            }
+        }
+        Ok(missing)
+    }
+
+    // queue the missing placement blobs of `id` in the background, `from` is asked first,
+    // resources outside `filter` are skipped
+    async fn fetch_content(
+        &self,
+        id: &str,
+        from: Option<PublicKey>,
+        filter: Option<&SyncFilter>,
+    ) -> Result<usize> {
+        let Some(resource) = self.get(id).await? else {
+            return Ok(0);
+        };
+        if let Some(filter) = filter
+            && !filter.matches(&resource.name, resource.size)
+        {
+            debug!(id, "outside the sync filter, keeping the record only");
+            return Ok(0);
+        }
+        let missing = self.missing_blobs(&resource).await?;
+        if missing.is_empty() {
+            return Ok(0);
+        }
+        let mut providers = self.providers().await?;
+        if let Some(from) = from
+            && !providers.contains(&from)
+        {
+            providers.insert(0, from);
+        }
+        for (content, size) in &missing {
+            debug!(id, hash = %content.hash.fmt_short(), "queueing placement blob");
+            self.node
+                .scheduler
+                .queue(*content, *size, providers.clone());
+        }
+        Ok(missing.len())
+    }
+
+    /// Queue the missing placement blobs of every resource that matches `filter`, returns the
+    /// number of blobs queued
+    pub async fn fetch_missing_content(&self, filter: Option<&SyncFilter>) -> Result<usize> {
+        let mut queued = 0;
+        for resource in self.search().await? {
+            queued += self.fetch_content(&resource.id, None, filter).await?;
+        }
+        Ok(queued)
+    }
+
+    /// Number of placement blobs of the resources matching `filter` that are not complete
+    /// in the local blob store
+    pub async fn count_missing_content(&self, filter: Option<&SyncFilter>) -> Result<usize> {
+        let mut missing = 0;
+        for resource in self.search().await? {
+            if let Some(filter) = filter
+                && !filter.matches(&resource.name, resource.size)
+            {
+                continue;
+            }
+            missing += self.missing_blobs(&resource).await?.len();
+        }
+        Ok(missing)
+    }
 
-        self.0
-            .insert_bytes(resource.id.as_bytes(), resource.as_bytes()?)
+    async fn write_content(&self, resource: &Resource, path: &Path) -> Result<u64> {
+        if self.node.cipher.is_some() {
+            let content = self.read_content(&resource).await?;
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::write(path, &content).await?;
+            return Ok(content.len() as u64);
+        }
+        self.node
+            .placement
+            .export(&self.node, &resource.placement, &resource.blob, path)
             .await
     }
+
+    /// Read the content of a resource wherever it is placed
+    pub async fn read_content(&self, resource: &Resource) -> Result<Bytes> {
+        let content = self
+            .node
+            .placement
+            .read(&self.node, &resource.placement, &resource.blob)
+            .await?;
+        // inline content was opened together with the record
+        if resource.placement == Placement::Inline {
+            return Ok(content);
+        }
+        Ok(crypto::open_content(self.node.cipher.as_ref(), content.to_vec())?.into())
+    }
+
+    /// Read the whole content of a resource, downloading it first if needed
+    ///
+    /// Readers of many parts of encrypted content load it once with this instead of
+    /// calling [`Resources::read_range`] for every part
+    pub async fn load_content(&self, resource: &Resource) -> Result<Bytes> {
+        self.prefetch(&resource.id).await?;
+        self.read_content(resource).await
+    }
+
+    /// Read `len` bytes of the content of a resource from `offset`, downloading it first if needed
+    ///
+    /// Encrypted content can't be read in parts, it is decrypted as a whole in memory and cut
+    /// on every call
+    pub async fn read_range(&self, resource: &Resource, offset: u64, len: u64) -> Result<Bytes> {
+        self.prefetch(&resource.id).await?;
+        if self.node.cipher.is_some() {
+            let content = self.read_content(resource).await?;
+            let start = (offset as usize).min(content.len());
+            let end = offset.saturating_add(len).min(content.len() as u64) as usize;
+            return Ok(content.slice(start..end));
+        }
+        self.node
+            .placement
+            .read_range(&self.node, &resource.placement, &resource.blob, offset, len)
+            .await
+    }
+}
+
+/// Download the placement blobs of the resources peers write, so the content replicates
+/// together with the entries
+///
+/// The entry of a resource only holds its record, the docs engine never downloads blobs
+/// placed outside of it. Once the record of a remote write is local, its blobs are queued
+/// from the writing peer and the previous sync peers of the doc. Blobs still missing from
+/// an earlier run are queued when the task starts. Resources outside the sync filter of
+/// `table` keep only their record
+pub fn spawn_content_fetcher(
+    resources: Resources,
+    table: String,
+    filters: SyncFilters,
+) -> JoinHandle<()> {
+    let span = info_span!("content", namespace = %resources.doc.id());
+    tokio::spawn(
+        async move {
+            // subscribe before the first pass so no write in between is missed
+            let mut events = match resources.doc.subscribe().await {
+                Ok(events) => events,
+                Err(e) => {
+                    warn!(error = %e, "failed to subscribe");
+                    return;
+                }
+            };
+            let filter = filters.read().await.get(&table).cloned();
+            if let Err(e) = resources.fetch_missing_content(filter.as_ref()).await {
+                warn!(error = %e, "failed to look for missing content");
+            }
+
+            // keys of remote records that have not arrived yet and the peers that wrote them
+            let mut pending: HashMap<Hash, (Vec<u8>, Option<PublicKey>)> = HashMap::new();
+            while let Some(Ok(event)) = events.next().await {
+                let (key, from) = match event {
+                    LiveEvent::InsertRemote {
+                        entry,
+                        content_status,
+                        from,
+                    } => {
+                        // other tables sharing the doc fetch their own content
+                        if entry.content_len() == 0 || !resources.owns(entry.key()) {
+                            continue;
+                        }
+                        let from = PublicKey::from_bytes(&from).ok();
+                        if content_status != ContentStatus::Complete {
+                            pending.insert(entry.content_hash(), (entry.key().to_vec(), from));
+                            continue;
+                        }
+                        (entry.key().to_vec(), from)
+                    }
+                    LiveEvent::ContentReady { hash } => match pending.remove(&hash) {
+                        Some(pending) => pending,
+                        None => continue,
+                    },
+                    _ => continue,
+                };
+                let id = String::from_utf8_lossy(resources.table_key(&key)).to_string();
+                let filter = filters.read().await.get(&table).cloned();
+                if let Err(e) = resources.fetch_content(&id, from, filter.as_ref()).await {
+                    warn!(id, error = %e, "failed to queue placement blobs");
+                }
+            }
+        }
+        .instrument(span),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a resource as the first release encoded it
+    #[derive(Serialize)]
+    struct BaseResource {
+        id: String,
+        name: String,
+        blob: Vec<u8>,
+    }
+
+    #[test]
+    fn decodes_resources_of_the_first_release() {
+        let bytes = bincode::serialize(&BaseResource {
+            id: "1".to_string(),
+            name: "2.png".to_string(),
+            blob: vec![1, 2, 3],
+        })
+        .unwrap();
+        let (resource, version) = Resource::from_bytes_versioned(bytes.into()).unwrap();
+        assert_eq!(version, 0);
+        assert_eq!(resource.id, "1");
+        assert_eq!(resource.name, "2.png");
+        assert_eq!(resource.blob, vec![1, 2, 3]);
+        assert_eq!(resource.placement, Placement::Inline);
+        assert_eq!(resource.size, 3);
+        assert_eq!(resource.content_type.as_deref(), Some("image/png"));
+    }
+
+    // a resource as version 0 encoded it once its content could be placed outside the record
+    #[derive(Serialize)]
+    struct PlacedResource {
+        id: String,
+        name: String,
+        blob: Vec<u8>,
+        placement: Placement,
+    }
+
+    // a resource as version 0 encoded it once the content type, size and creation time
+    // were recorded
+    #[derive(Serialize)]
+    struct TypedResource {
+        id: String,
+        name: String,
+        blob: Vec<u8>,
+        placement: Placement,
+        content_type: Option<String>,
+        size: u64,
+        created_at: u64,
+    }
+
+    // a resource as version 1 encoded it, bincode writes the fields one after the other
+    #[derive(Serialize)]
+    struct FiledResource {
+        typed: TypedResource,
+        folder_id: Option<String>,
+    }
+
+    // a resource as version 2 encoded it
+    #[derive(Serialize)]
+    struct ImageResource {
+        filed: FiledResource,
+        image: Option<ImageMeta>,
+    }
+
+    fn local() -> Placement {
+        Placement::Local {
+            hash: Hash::new(b"content"),
+            size: 7,
+        }
+    }
+
+    fn typed() -> TypedResource {
+        TypedResource {
+            id: "1".to_string(),
+            name: "2.png".to_string(),
+            blob: vec![],
+            placement: local(),
+            content_type: Some("image/png".to_string()),
+            size: 7,
+            created_at: 42,
+        }
+    }
+
+    fn versioned<T: Serialize>(version: u32, old: &T) -> Bytes {
+        crate::schema::wrap(version, crate::codec::Codec::Bincode.encode(old).unwrap()).into()
+    }
+
+    #[test]
+    fn decodes_placed_resources_of_version_0() {
+        let bytes = bincode::serialize(&PlacedResource {
+            id: "1".to_string(),
+            name: "2.txt".to_string(),
+            blob: vec![],
+            placement: local(),
+        })
+        .unwrap();
+        let (resource, version) = Resource::from_bytes_versioned(bytes.into()).unwrap();
+        assert_eq!(version, 0);
+        assert_eq!(resource.placement, local());
+        assert_eq!(resource.size, 7);
+        assert_eq!(resource.created_at, 0);
+        assert_eq!(resource.content_type.as_deref(), Some("text/plain"));
+    }
+
+    #[test]
+    fn decodes_typed_resources_of_version_0() {
+        let bytes = bincode::serialize(&typed()).unwrap();
+        let (resource, version) = Resource::from_bytes_versioned(bytes.into()).unwrap();
+        assert_eq!(version, 0);
+        assert_eq!(resource.placement, local());
+        assert_eq!(resource.created_at, 42);
+        assert_eq!(resource.folder_id, None);
+    }
+
+    #[test]
+    fn decodes_resources_of_version_1() {
+        let old = FiledResource {
+            typed: typed(),
+            folder_id: Some("f".to_string()),
+        };
+        let (resource, version) = Resource::from_bytes_versioned(versioned(1, &old)).unwrap();
+        assert_eq!(version, 1);
+        assert_eq!(resource.created_at, 42);
+        assert_eq!(resource.folder_id.as_deref(), Some("f"));
+        assert_eq!(resource.image, None);
+    }
+
+    #[test]
+    fn decodes_resources_of_version_2() {
+        let image = ImageMeta {
+            width: 3,
+            height: 4,
+            ..Default::default()
+        };
+        let old = ImageResource {
+            filed: FiledResource {
+                typed: typed(),
+                folder_id: Some("f".to_string()),
+            },
+            image: Some(image.clone()),
+        };
+        let (resource, version) = Resource::from_bytes_versioned(versioned(2, &old)).unwrap();
+        assert_eq!(version, 2);
+        assert_eq!(resource.folder_id.as_deref(), Some("f"));
+        assert_eq!(resource.image, Some(image));
+        assert_eq!(resource.digest, None);
+    }
+
+    #[test]
+    fn decodes_resources_of_the_current_version() {
+        let mut resource = Resource::missing_file("1".to_string());
+        resource.digest = Some(Hash::new(b"digest"));
+        let (decoded, version) =
+            Resource::from_bytes_versioned(resource.as_bytes().unwrap()).unwrap();
+        assert_eq!(version, Resource::VERSION);
+        assert_eq!(decoded.id, "1");
+        assert_eq!(decoded.digest, resource.digest);
+    }
 }
@@ -0,0 +1,582 @@
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use fuser::{
+    BackgroundSession, FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyCreate,
+    ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyOpen, ReplyWrite, Request, TimeOrNow,
+};
+use tokio::runtime::Handle;
+use tracing::warn;
+
+use crate::model::folder::Folders;
+use crate::model::resource::{Resource, Resources};
+
+// inode of the mount point, the top level of the folder table
+const ROOT_INO: u64 = 1;
+// how long the kernel may cache attributes and lookups
+const TTL: Duration = Duration::from_secs(1);
+const BLOCK_SIZE: u32 = 4096;
+
+/// What an inode stands for
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Node {
+    // a folder of the folder table, `None` for the top level
+    Folder(Option<String>),
+    // a resource, by id
+    File(String),
+}
+
+// subfolders and resources of a folder with their names
+type Listing = Vec<(Node, String, Option<Resource>)>;
+
+/// A file opened through the mount
+struct OpenFile {
+    id: String,
+    // content being written, loaded on the first write and stored on release
+    content: Option<Vec<u8>>,
+    dirty: bool,
+}
+
+/// Folders and the resources of one table as a filesystem, see [`mount`]
+struct ResourceFs {
+    folders: Folders,
+    resources: Resources,
+    // the callbacks run on the FUSE thread and block on the store
+    runtime: Handle,
+    nodes: HashMap<u64, Node>,
+    inodes: HashMap<Node, u64>,
+    open: HashMap<u64, OpenFile>,
+    next_fh: u64,
+    // listings by folder, reused for `TTL` so a lookup of every entry doesn't list the folder again
+    listings: HashMap<Option<String>, (Instant, Listing)>,
+}
+
+impl ResourceFs {
+    fn new(folders: Folders, resources: Resources, runtime: Handle) -> Self {
+        let root = Node::Folder(None);
+        ResourceFs {
+            folders,
+            resources,
+            runtime,
+            nodes: HashMap::from([(ROOT_INO, root.clone())]),
+            inodes: HashMap::from([(root, ROOT_INO)]),
+            open: HashMap::new(),
+            next_fh: 1,
+            listings: HashMap::new(),
+        }
+    }
+
+    // inodes are handed out on first sight and kept while mounted
+    fn ino(&mut self, node: Node) -> u64 {
+        if let Some(ino) = self.inodes.get(&node) {
+            return *ino;
+        }
+        let ino = self.nodes.len() as u64 + 1;
+        self.nodes.insert(ino, node.clone());
+        self.inodes.insert(node, ino);
+        ino
+    }
+
+    fn folder_of(&self, ino: u64) -> Option<Option<String>> {
+        match self.nodes.get(&ino)? {
+            Node::Folder(folder_id) => Some(folder_id.clone()),
+            Node::File(_) => None,
+        }
+    }
+
+    // subfolders and resources of a folder with their names, listed at most once per `TTL`
+    fn children(&mut self, folder_id: Option<&str>) -> Result<Listing> {
+        let key = folder_id.map(str::to_string);
+        if let Some((listed, children)) = self.listings.get(&key)
+            && listed.elapsed() < TTL
+        {
+            return Ok(children.clone());
+        }
+        let children = self.list(folder_id)?;
+        self.listings
+            .insert(key, (Instant::now(), children.clone()));
+        Ok(children)
+    }
+
+    // listings are dropped on every change made through the mount
+    fn changed(&mut self) {
+        self.listings.clear();
+    }
+
+    fn list(&self, folder_id: Option<&str>) -> Result<Listing> {
+        self.runtime.block_on(async {
+            let mut children = vec![];
+            for folder in self.folders.children(folder_id).await? {
+                children.push((
+                    Node::Folder(Some(folder.folder_id)),
+                    folder.folder_name,
+                    None,
+                ));
+            }
+            for file in self.resources.list_in_folder(folder_id).await? {
+                children.push((Node::File(file.id.clone()), file.name.clone(), Some(file)));
+            }
+            Ok(children)
+        })
+    }
+
+    fn child(&mut self, parent: u64, name: &OsStr) -> Result<Option<(Node, Option<Resource>)>> {
+        let Some(folder_id) = self.folder_of(parent) else {
+            return Ok(None);
+        };
+        let name = name.to_string_lossy();
+        Ok(self
+            .children(folder_id.as_deref())?
+            .into_iter()
+            .find(|(_, child, _)| *child == name)
+            .map(|(node, _, file)| (node, file)))
+    }
+
+    fn attr(&self, req: &Request<'_>, ino: u64, file: Option<&Resource>) -> FileAttr {
+        let (kind, perm, size, time) = match file {
+            Some(file) => {
+                // a file being written reports the size of its buffer
+                let size = self
+                    .open
+                    .values()
+                    .find(|open| open.id == file.id)
+                    .and_then(|open| open.content.as_ref())
+                    .map_or(file.size, |content| content.len() as u64);
+                let time = UNIX_EPOCH + Duration::from_millis(file.created_at);
+                (FileType::RegularFile, 0o644, size, time)
+            }
+            None => (FileType::Directory, 0o755, 0, SystemTime::now()),
+        };
+        FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(BLOCK_SIZE as u64),
+            atime: time,
+            mtime: time,
+            ctime: time,
+            crtime: time,
+            kind,
+            perm,
+            nlink: if kind == FileType::Directory { 2 } else { 1 },
+            uid: req.uid(),
+            gid: req.gid(),
+            rdev: 0,
+            blksize: BLOCK_SIZE,
+            flags: 0,
+        }
+    }
+
+    fn resource(&self, ino: u64) -> Result<Option<Resource>> {
+        match self.nodes.get(&ino) {
+            Some(Node::File(id)) => self.runtime.block_on(self.resources.get(id)),
+            _ => Ok(None),
+        }
+    }
+
+    fn open_file(&mut self, id: String, content: Option<Vec<u8>>) -> u64 {
+        let fh = self.next_fh;
+        self.next_fh += 1;
+        let dirty = content.is_some();
+        self.open.insert(fh, OpenFile { id, content, dirty });
+        fh
+    }
+
+    // load what is stored into an open file, unless it holds its content already
+    fn load(&mut self, fh: u64) -> Result<()> {
+        let open = self
+            .open
+            .get(&fh)
+            .ok_or_else(|| anyhow::anyhow!("File handle {} is not open", fh))?;
+        if open.content.is_none() {
+            let id = open.id.clone();
+            let content = self.runtime.block_on(async {
+                let resource = self
+                    .resources
+                    .get(&id)
+                    .await?
+                    .ok_or_else(|| anyhow::anyhow!("Resource '{}' does not exist", id))?;
+                self.resources.load_content(&resource).await
+            })?;
+            self.open_mut(fh)?.content = Some(content.to_vec());
+        }
+        Ok(())
+    }
+
+    fn open_mut(&mut self, fh: u64) -> Result<&mut OpenFile> {
+        self.open
+            .get_mut(&fh)
+            .ok_or_else(|| anyhow::anyhow!("File handle {} is not open", fh))
+    }
+
+    // the content of an open file, loading what is stored on the first change
+    fn content_mut(&mut self, fh: u64) -> Result<&mut Vec<u8>> {
+        self.load(fh)?;
+        let open = self.open_mut(fh)?;
+        open.dirty = true;
+        Ok(open.content.get_or_insert_default())
+    }
+
+    // files being written are held in memory, up to the largest file size of the placement policy
+    fn fits(&self, size: u64) -> bool {
+        let max = self.resources.node.placement.max_file_size;
+        max == 0 || size <= max
+    }
+
+    // write the content of an open file back as its resource
+    fn store(&self, open: &OpenFile) -> Result<()> {
+        let Some(content) = &open.content else {
+            return Ok(());
+        };
+        self.runtime.block_on(async {
+            let resource = self
+                .resources
+                .get(&open.id)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Resource '{}' does not exist", open.id))?;
+            self.resources
+                .put_file(&open.id, resource.name, content.clone())
+                .await
+        })
+    }
+}
+
+// errno for a failed store operation
+fn io_error(e: anyhow::Error) -> i32 {
+    warn!(error = %e, "mount operation failed");
+    libc::EIO
+}
+
+impl Filesystem for ResourceFs {
+    fn lookup(&mut self, req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        match self.child(parent, name) {
+            Ok(Some((node, file))) => {
+                let ino = self.ino(node);
+                reply.entry(&TTL, &self.attr(req, ino, file.as_ref()), 0);
+            }
+            Ok(None) => reply.error(libc::ENOENT),
+            Err(e) => reply.error(io_error(e)),
+        }
+    }
+
+    fn getattr(&mut self, req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        match self.nodes.get(&ino) {
+            Some(Node::Folder(_)) => reply.attr(&TTL, &self.attr(req, ino, None)),
+            Some(Node::File(_)) => match self.resource(ino) {
+                Ok(Some(file)) => reply.attr(&TTL, &self.attr(req, ino, Some(&file))),
+                Ok(None) => reply.error(libc::ENOENT),
+                Err(e) => reply.error(io_error(e)),
+            },
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn setattr(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        _mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        size: Option<u64>,
+        _atime: Option<TimeOrNow>,
+        _mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        let file = match self.resource(ino) {
+            Ok(Some(file)) => file,
+            Ok(None) if self.folder_of(ino).is_some() => {
+                return reply.attr(&TTL, &self.attr(req, ino, None));
+            }
+            Ok(None) => return reply.error(libc::ENOENT),
+            Err(e) => return reply.error(io_error(e)),
+        };
+        // only truncation is supported, other changes are accepted and ignored
+        if let Some(size) = size {
+            if !self.fits(size) {
+                return reply.error(libc::EFBIG);
+            }
+            let result = match fh.filter(|fh| self.open.contains_key(fh)) {
+                Some(fh) => self
+                    .content_mut(fh)
+                    .map(|content| content.resize(size as usize, 0)),
+                None => {
+                    let fh = self.open_file(file.id.clone(), None);
+                    let result = self
+                        .content_mut(fh)
+                        .map(|content| content.resize(size as usize, 0))
+                        .and_then(|_| self.store(&self.open[&fh]));
+                    self.open.remove(&fh);
+                    result
+                }
+            };
+            self.changed();
+            if let Err(e) = result {
+                return reply.error(io_error(e));
+            }
+        }
+        match self.resource(ino) {
+            Ok(Some(file)) => reply.attr(&TTL, &self.attr(req, ino, Some(&file))),
+            Ok(None) => reply.error(libc::ENOENT),
+            Err(e) => reply.error(io_error(e)),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let Some(folder_id) = self.folder_of(ino) else {
+            return reply.error(libc::ENOTDIR);
+        };
+        let children = match self.children(folder_id.as_deref()) {
+            Ok(children) => children,
+            Err(e) => return reply.error(io_error(e)),
+        };
+        let mut entries = vec![
+            (ino, FileType::Directory, ".".to_string()),
+            (ino, FileType::Directory, "..".to_string()),
+        ];
+        for (node, name, _) in children {
+            let kind = match node {
+                Node::Folder(_) => FileType::Directory,
+                Node::File(_) => FileType::RegularFile,
+            };
+            entries.push((self.ino(node), kind, name));
+        }
+        for (i, (ino, kind, name)) in entries.into_iter().enumerate().skip(offset as usize) {
+            // the buffer is full, the kernel asks again from the next offset
+            if reply.add(ino, i as i64 + 1, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    fn mkdir(
+        &mut self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        reply: ReplyEntry,
+    ) {
+        let Some(parent_id) = self.folder_of(parent) else {
+            return reply.error(libc::ENOTDIR);
+        };
+        let name = name.to_string_lossy().to_string();
+        let created = self
+            .runtime
+            .block_on(self.folders.create_folder(name, parent_id));
+        self.changed();
+        match created {
+            Ok(folder_id) => {
+                let ino = self.ino(Node::Folder(Some(folder_id)));
+                reply.entry(&TTL, &self.attr(req, ino, None), 0);
+            }
+            Err(e) => reply.error(io_error(e)),
+        }
+    }
+
+    fn rmdir(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let folder_id = match self.child(parent, name) {
+            Ok(Some((Node::Folder(Some(folder_id)), _))) => folder_id,
+            Ok(Some(_)) => return reply.error(libc::ENOTDIR),
+            Ok(None) => return reply.error(libc::ENOENT),
+            Err(e) => return reply.error(io_error(e)),
+        };
+        match self.children(Some(&folder_id)) {
+            Ok(children) if !children.is_empty() => return reply.error(libc::ENOTEMPTY),
+            Ok(_) => {}
+            Err(e) => return reply.error(io_error(e)),
+        }
+        let deleted = self.runtime.block_on(self.folders.delete(&folder_id));
+        self.changed();
+        match deleted {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(io_error(e)),
+        }
+    }
+
+    fn unlink(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let id = match self.child(parent, name) {
+            Ok(Some((Node::File(id), _))) => id,
+            Ok(Some(_)) => return reply.error(libc::EISDIR),
+            Ok(None) => return reply.error(libc::ENOENT),
+            Err(e) => return reply.error(io_error(e)),
+        };
+        let removed = self.runtime.block_on(self.resources.remove(&id));
+        self.changed();
+        match removed {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(io_error(e)),
+        }
+    }
+
+    fn create(
+        &mut self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        flags: i32,
+        reply: ReplyCreate,
+    ) {
+        let Some(folder_id) = self.folder_of(parent) else {
+            return reply.error(libc::ENOTDIR);
+        };
+        let name = name.to_string_lossy().to_string();
+        // the resource exists right away, its content is stored on release
+        let created = self.runtime.block_on(async {
+            let id = self.resources.add_file(name, vec![]).await?;
+            if folder_id.is_some() {
+                self.resources.move_to_folder(&id, folder_id).await?;
+            }
+            self.resources
+                .get(&id)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Resource '{}' does not exist", id))
+        });
+        self.changed();
+        match created {
+            Ok(file) => {
+                let ino = self.ino(Node::File(file.id.clone()));
+                let fh = self.open_file(file.id.clone(), Some(vec![]));
+                reply.created(&TTL, &self.attr(req, ino, Some(&file)), 0, fh, flags as u32);
+            }
+            Err(e) => reply.error(io_error(e)),
+        }
+    }
+
+    fn open(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        let Some(Node::File(id)) = self.nodes.get(&ino).cloned() else {
+            return reply.error(libc::EISDIR);
+        };
+        // content opened for truncation is not loaded
+        let content = (flags & libc::O_TRUNC != 0).then(Vec::new);
+        let fh = self.open_file(id, content);
+        reply.opened(fh, flags as u32);
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        // encrypted content can't be read in parts, it is decrypted once per open file
+        if self.resources.node.cipher.is_some()
+            && let Err(e) = self.load(fh)
+        {
+            return reply.error(io_error(e));
+        }
+        if let Some(content) = self.open.get(&fh).and_then(|open| open.content.as_ref()) {
+            let start = (offset as usize).min(content.len());
+            let end = (start + size as usize).min(content.len());
+            return reply.data(&content[start..end]);
+        }
+        let file = match self.resource(ino) {
+            Ok(Some(file)) => file,
+            Ok(None) => return reply.error(libc::ENOENT),
+            Err(e) => return reply.error(io_error(e)),
+        };
+        // the content is downloaded from the peers if it is missing
+        let read =
+            self.runtime
+                .block_on(self.resources.read_range(&file, offset as u64, size as u64));
+        match read {
+            Ok(data) => reply.data(&data),
+            Err(e) => reply.error(io_error(e)),
+        }
+    }
+
+    fn write(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        if !self.fits(offset as u64 + data.len() as u64) {
+            return reply.error(libc::EFBIG);
+        }
+        match self.content_mut(fh) {
+            Ok(content) => {
+                let end = offset as usize + data.len();
+                if content.len() < end {
+                    content.resize(end, 0);
+                }
+                content[offset as usize..end].copy_from_slice(data);
+                reply.written(data.len() as u32);
+            }
+            Err(e) => reply.error(io_error(e)),
+        }
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        let Some(open) = self.open.remove(&fh) else {
+            return reply.ok();
+        };
+        if !open.dirty {
+            return reply.ok();
+        }
+        let stored = self.store(&open);
+        self.changed();
+        match stored {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(io_error(e)),
+        }
+    }
+}
+
+/// Mount the folders and the resources of a table at `path` until the session is dropped
+///
+/// Folders are directories and resources are files. Content is read from the blob store
+/// when a file is read, downloading it from the peers first if needed. Files written
+/// through the mount are kept in memory as a whole and stored as resources when they are
+/// closed, so writes beyond `--max-file-size` fail with `EFBIG` and every open file being
+/// written takes up to that much memory. Folder listings are reused for a second, changes
+/// of the peers show up after that. Renaming is not supported
+pub fn mount(folders: Folders, resources: Resources, path: &Path) -> Result<BackgroundSession> {
+    let fs = ResourceFs::new(folders, resources, Handle::current());
+    let options = [
+        MountOption::FSName("iroh-test".to_string()),
+        MountOption::DefaultPermissions,
+    ];
+    fuser::spawn_mount2(fs, path, &options).with_context(|| format!("Failed to mount {:?}", path))
+}
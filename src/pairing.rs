@@ -0,0 +1,400 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::{Context, Result, ensure};
+use futures::StreamExt;
+use iroh::{
+    Endpoint, EndpointAddr, PublicKey, SecretKey,
+    endpoint::{Connection, RecvStream, SendStream},
+    protocol::{AcceptError, ProtocolHandler, Router},
+};
+use iroh_gossip::{api::Event, net::Gossip, proto::TopicId};
+use rand::Rng;
+use spake2::{Ed25519Group, Identity, Password, Spake2};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::RelayChoice;
+
+pub const PAIRING_ALPN: &[u8] = b"iroh-test/pairing/3";
+
+// digits of a PIN
+const PIN_LEN: usize = 6;
+
+// a pairing stops answering after this many wrong PINs, so the PIN can't be guessed online
+const MAX_FAILED_ATTEMPTS: usize = 5;
+
+// how often the server announces its pairing endpoint for peers that joined the topic in between
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(2);
+
+// announcements start with a counter, gossip drops messages it has seen before
+const COUNTER_LEN: usize = 8;
+
+// upper bound for a handshake message
+const MAX_FRAME_SIZE: usize = 1024;
+
+// upper bound for a ticket bundle
+const MAX_BUNDLE_SIZE: usize = 64 * 1024;
+
+// how long a client looks for a server pairing under its PIN
+pub const DEFAULT_PAIRING_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Generate a random numeric PIN
+pub fn generate_pin() -> String {
+    let mut rng = rand::rng();
+    (0..PIN_LEN)
+        .map(|_| char::from(b'0' + rng.random_range(0..10u8)))
+        .collect()
+}
+
+// the lobby endpoint and the gossip topic are derived from the PIN, so a client finds the
+// server with the PIN alone
+//
+// anyone who knows the PIN can take the lobby key as well, that only hides the server: the
+// tickets are handed out after a SPAKE2 exchange, which no endpoint completes without the PIN
+fn lobby_key(pin: &str) -> SecretKey {
+    SecretKey::from_bytes(&blake3::derive_key(
+        "iroh-test pairing v3 lobby",
+        pin.trim().as_bytes(),
+    ))
+}
+
+fn pin_topic(pin: &str) -> TopicId {
+    TopicId::from_bytes(blake3::derive_key(
+        "iroh-test pairing v3 topic",
+        pin.trim().as_bytes(),
+    ))
+}
+
+// SPAKE2 run of both sides, bound to the endpoint serving the bundle
+fn start_exchange(pin: &str, server: &PublicKey) -> (Spake2<Ed25519Group>, Vec<u8>) {
+    Spake2::<Ed25519Group>::start_symmetric(
+        &Password::new(pin.trim().as_bytes()),
+        &Identity::new(server.as_bytes()),
+    )
+}
+
+fn finish_exchange(state: Spake2<Ed25519Group>, message: &[u8]) -> Result<[u8; 32]> {
+    let key = state
+        .finish(message)
+        .map_err(|e| anyhow::anyhow!("Invalid pairing message: {:?}", e))?;
+    Ok(blake3::derive_key("iroh-test pairing v3 key", &key))
+}
+
+// proof of `side` that it derived `key`, bound to the client of the exchange
+fn confirmation(key: &[u8; 32], side: &str, client: &PublicKey) -> blake3::Hash {
+    blake3::keyed_hash(
+        key,
+        &[side.as_bytes(), client.as_bytes().as_slice()].concat(),
+    )
+}
+
+async fn write_frame(send: &mut SendStream, frame: &[u8]) -> Result<()> {
+    send.write_all(&(frame.len() as u32).to_be_bytes()).await?;
+    send.write_all(frame).await?;
+    Ok(())
+}
+
+async fn read_frame(recv: &mut RecvStream) -> Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    recv.read_exact(&mut len).await?;
+    let len = u32::from_be_bytes(len) as usize;
+    ensure!(len <= MAX_FRAME_SIZE, "Pairing message is too large");
+    let mut frame = vec![0u8; len];
+    recv.read_exact(&mut frame).await?;
+    Ok(frame)
+}
+
+/// Hands out the ticket bundle to clients that complete the exchange with the PIN
+#[derive(Debug, Clone)]
+struct PairingProtocol {
+    pin: String,
+    server: PublicKey,
+    bundle: Arc<String>,
+    failed: Arc<AtomicUsize>,
+}
+
+impl PairingProtocol {
+    async fn handle(&self, connection: Connection) -> Result<()> {
+        let remote = connection.remote_id()?;
+        let (mut send, mut recv) = connection.accept_bi().await?;
+        if self.failed.load(Ordering::SeqCst) >= MAX_FAILED_ATTEMPTS {
+            warn!(from = %remote.fmt_short(), "refused pairing, too many wrong PINs");
+            return Ok(());
+        }
+        let (state, message) = start_exchange(&self.pin, &self.server);
+        let theirs = read_frame(&mut recv).await?;
+        write_frame(&mut send, &message).await?;
+        // the client confirms first, so every run without the PIN counts as a wrong guess
+        let key = finish_exchange(state, &theirs);
+        let proof = read_frame(&mut recv).await?;
+        let valid = key.as_ref().is_ok_and(|key| {
+            // `blake3::Hash` compares in constant time
+            <[u8; 32]>::try_from(proof.as_slice()).is_ok_and(|proof| {
+                blake3::Hash::from_bytes(proof) == confirmation(key, "client", &remote)
+            })
+        });
+        let key = match key {
+            Ok(key) if valid => key,
+            _ => {
+                let failed = self.failed.fetch_add(1, Ordering::SeqCst) + 1;
+                warn!(from = %remote.fmt_short(), failed, "wrong pairing PIN");
+                return Ok(());
+            }
+        };
+        write_frame(&mut send, confirmation(&key, "server", &remote).as_bytes()).await?;
+        send.write_all(self.bundle.as_bytes()).await?;
+        send.finish()?;
+        connection.closed().await;
+        info!(to = %remote.fmt_short(), "paired");
+        Ok(())
+    }
+}
+
+impl ProtocolHandler for PairingProtocol {
+    async fn accept(&self, connection: Connection) -> Result<(), AcceptError> {
+        self.handle(connection)
+            .await
+            .map_err(|e| AcceptError::from_err(std::io::Error::other(e)))
+    }
+}
+
+/// A ticket bundle served under a PIN, until it expires or is dropped
+pub struct Pairing {
+    pub pin: String,
+    router: Router,
+    lobby: Router,
+    announce: JoinHandle<()>,
+}
+
+impl Drop for Pairing {
+    fn drop(&mut self) {
+        self.announce.abort();
+        let routers = [self.router.clone(), self.lobby.clone()];
+        tokio::spawn(async move {
+            for router in routers {
+                let _ = router.shutdown().await;
+            }
+        });
+    }
+}
+
+/// Serve `bundle` under a fresh PIN for `ttl`
+///
+/// The bundle is served by an endpoint with a random key, which is announced on a gossip
+/// topic derived from the PIN, so a client needs nothing but the PIN. Both sides prove they
+/// know the PIN with a SPAKE2 exchange before the bundle is sent, and the pairing closes after
+/// a few wrong ones, so neither a client nor a node posing as the server can guess it. Peers
+/// on the same network find each other through local discovery, others through the relay
+pub async fn publish_pairing(
+    bundle: String,
+    ttl: Duration,
+    relay: &RelayChoice,
+) -> Result<Pairing> {
+    let pin = generate_pin();
+    let endpoint = Endpoint::builder()
+        .secret_key(SecretKey::generate(&mut rand::rng()))
+        .relay_mode(relay.relay_mode())
+        .discovery_n0()
+        .discovery_local_network()
+        .bind()
+        .await?;
+    let node_id = endpoint.id();
+    let router = Router::builder(endpoint)
+        .accept(
+            PAIRING_ALPN,
+            PairingProtocol {
+                pin: pin.clone(),
+                server: node_id,
+                bundle: Arc::new(bundle),
+                failed: Arc::default(),
+            },
+        )
+        .spawn();
+
+    let lobby_endpoint = Endpoint::builder()
+        .secret_key(lobby_key(&pin))
+        .relay_mode(relay.relay_mode())
+        .discovery_n0()
+        .discovery_local_network()
+        .bind()
+        .await?;
+    let gossip = Gossip::builder().spawn(lobby_endpoint.clone());
+    let lobby = Router::builder(lobby_endpoint)
+        .accept(iroh_gossip::ALPN, Arc::new(gossip.clone()))
+        .spawn();
+
+    let (sender, mut receiver) = gossip.subscribe(pin_topic(&pin), vec![]).await?.split();
+    let announce = tokio::spawn(async move {
+        let mut interval = tokio::time::interval(ANNOUNCE_INTERVAL);
+        let mut counter = 0u64;
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                event = receiver.next() => match event {
+                    Some(Ok(Event::NeighborUp(peer))) => {
+                        info!(peer = %peer.fmt_short(), "pairing peer joined");
+                    }
+                    Some(_) => continue,
+                    None => return,
+                },
+            }
+            counter += 1;
+            let message = [counter.to_be_bytes().as_slice(), node_id.as_bytes()].concat();
+            if let Err(e) = sender.broadcast(message.into()).await {
+                debug!(error = %e, "failed to announce the pairing endpoint");
+            }
+        }
+    });
+
+    let expiring = [router.clone(), lobby.clone()];
+    let expired_pin = pin.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(ttl).await;
+        info!(pin = %expired_pin, "pairing PIN expired");
+        for router in expiring {
+            if !router.is_shutdown() {
+                let _ = router.shutdown().await;
+            }
+        }
+    });
+    Ok(Pairing {
+        pin,
+        router,
+        lobby,
+        announce,
+    })
+}
+
+// pairing endpoint named by an announcement on the topic
+fn announced_server(content: &[u8]) -> Option<PublicKey> {
+    let id: &[u8; 32] = content.get(COUNTER_LEN..)?.try_into().ok()?;
+    PublicKey::from_bytes(id).ok()
+}
+
+// run the exchange with `server` and receive its bundle
+async fn exchange(
+    endpoint: &Endpoint,
+    server: PublicKey,
+    pin: &str,
+    relay: &RelayChoice,
+) -> Result<String> {
+    let mut addr = EndpointAddr::new(server);
+    if let Some(url) = relay.relay_url() {
+        addr = addr.with_relay_url(url.clone());
+    }
+    // the connection only succeeds with the endpoint holding the key of `server`
+    let connection = endpoint.connect(addr, PAIRING_ALPN).await?;
+    let (mut send, mut recv) = connection.open_bi().await?;
+    let (state, message) = start_exchange(pin, &server);
+    write_frame(&mut send, &message).await?;
+    let key = finish_exchange(state, &read_frame(&mut recv).await?)?;
+    let me = endpoint.id();
+    write_frame(&mut send, confirmation(&key, "client", &me).as_bytes()).await?;
+    send.finish()?;
+    let proof = read_frame(&mut recv)
+        .await
+        .with_context(|| format!("The server rejected PIN {}", pin))?;
+    let valid = <[u8; 32]>::try_from(proof.as_slice())
+        .is_ok_and(|proof| blake3::Hash::from_bytes(proof) == confirmation(&key, "server", &me));
+    ensure!(valid, "The server does not know PIN {}", pin);
+    let bundle = recv.read_to_end(MAX_BUNDLE_SIZE).await?;
+    connection.close(0u32.into(), b"done");
+    Ok(String::from_utf8(bundle)?)
+}
+
+/// Receive the ticket bundle of the server pairing under `pin`, waiting up to `timeout`
+///
+/// Every endpoint announced on the topic of the PIN is tried once, only the one that knows
+/// the PIN hands out a bundle
+pub async fn pair(pin: &str, relay: &RelayChoice, timeout: Duration) -> Result<String> {
+    let endpoint = Endpoint::builder()
+        .relay_mode(relay.relay_mode())
+        .discovery_n0()
+        .discovery_local_network()
+        .bind()
+        .await?;
+    let gossip = Gossip::builder().spawn(endpoint.clone());
+    let router = Router::builder(endpoint.clone())
+        .accept(iroh_gossip::ALPN, Arc::new(gossip.clone()))
+        .spawn();
+
+    let receive = async {
+        let (_sender, mut receiver) = gossip
+            .subscribe_and_join(pin_topic(pin), vec![lobby_key(pin).public()])
+            .await?
+            .split();
+        let mut tried = HashSet::new();
+        while let Some(event) = receiver.next().await {
+            let Event::Received(message) = event? else {
+                continue;
+            };
+            let Some(server) = announced_server(&message.content) else {
+                continue;
+            };
+            if !tried.insert(server) {
+                continue;
+            }
+            match exchange(&endpoint, server, pin, relay).await {
+                Ok(bundle) => return Ok(bundle),
+                Err(e) => warn!(server = %server.fmt_short(), error = %e, "pairing failed"),
+            }
+        }
+        anyhow::bail!("The pairing topic closed")
+    };
+    let bundle = tokio::time::timeout(timeout, receive)
+        .await
+        .with_context(|| format!("No pairing found for PIN {}", pin))?;
+    router.shutdown().await?;
+    bundle
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exchange_keys(server_pin: &str, client_pin: &str) -> ([u8; 32], [u8; 32]) {
+        let server = SecretKey::from_bytes(&[1; 32]).public();
+        let (server_state, server_message) = start_exchange(server_pin, &server);
+        let (client_state, client_message) = start_exchange(client_pin, &server);
+        (
+            finish_exchange(server_state, &client_message).unwrap(),
+            finish_exchange(client_state, &server_message).unwrap(),
+        )
+    }
+
+    #[test]
+    fn agrees_on_a_key_with_the_same_pin() {
+        let (server, client) = exchange_keys("123456", " 123456\n");
+        assert_eq!(server, client);
+    }
+
+    #[test]
+    fn derives_different_keys_from_different_pins() {
+        let (server, client) = exchange_keys("123456", "123457");
+        assert_ne!(server, client);
+    }
+
+    #[test]
+    fn confirms_each_side_separately() {
+        let (key, _) = exchange_keys("123456", "123456");
+        let client = SecretKey::from_bytes(&[2; 32]).public();
+        assert_ne!(
+            confirmation(&key, "server", &client),
+            confirmation(&key, "client", &client)
+        );
+    }
+
+    #[test]
+    fn derives_the_lobby_from_the_pin() {
+        assert_eq!(lobby_key("123456").public(), lobby_key(" 123456 ").public());
+        assert_ne!(lobby_key("123456").public(), lobby_key("123457").public());
+        assert_ne!(pin_topic("123456"), pin_topic("123457"));
+    }
+}
@@ -0,0 +1,147 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use iroh::{Endpoint, PublicKey, Watcher, endpoint::ConnectionType};
+use tokio::sync::RwLock;
+
+/// Sync state of a single table with a peer
+#[derive(Debug, Clone, Default)]
+pub struct TableSyncStatus {
+    // whether the peer is currently a gossip neighbor for the table
+    pub connected: bool,
+    // end of the last sync run with the peer
+    pub last_sync: Option<SystemTime>,
+    // error of the last sync run, if it failed
+    pub last_error: Option<String>,
+}
+
+/// A peer and the tables it syncs with this node
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    pub peer: PublicKey,
+    // relay, direct or mixed, `None` when no connection is open
+    pub conn_type: Option<ConnectionType>,
+    pub tables: HashMap<String, TableSyncStatus>,
+}
+
+impl PeerInfo {
+    /// Whether the peer is a neighbor for at least one table
+    pub fn is_connected(&self) -> bool {
+        self.tables.values().any(|t| t.connected)
+    }
+}
+
+/// Peers seen by the doc subscriptions of all tables
+#[derive(Debug, Clone, Default)]
+pub struct PeerRegistry {
+    peers: Arc<RwLock<HashMap<PublicKey, HashMap<String, TableSyncStatus>>>>,
+}
+
+impl PeerRegistry {
+    pub async fn neighbor_up(&self, peer: PublicKey, table: &str) {
+        let mut peers = self.peers.write().await;
+        let status = peers
+            .entry(peer)
+            .or_default()
+            .entry(table.to_string())
+            .or_default();
+        status.connected = true;
+    }
+
+    pub async fn neighbor_down(&self, peer: PublicKey, table: &str) {
+        if let Some(status) = self
+            .peers
+            .write()
+            .await
+            .get_mut(&peer)
+            .and_then(|tables| tables.get_mut(table))
+        {
+            status.connected = false;
+        }
+    }
+
+    /// Whether any peer is currently a gossip neighbor for `table`
+    pub async fn has_neighbor(&self, table: &str) -> bool {
+        self.peers
+            .read()
+            .await
+            .values()
+            .any(|tables| tables.get(table).is_some_and(|status| status.connected))
+    }
+
+    /// Record the outcome of a sync run with `peer`
+    pub async fn sync_finished(
+        &self,
+        peer: PublicKey,
+        table: &str,
+        finished: SystemTime,
+        result: Result<(), String>,
+    ) {
+        let mut peers = self.peers.write().await;
+        let status = peers
+            .entry(peer)
+            .or_default()
+            .entry(table.to_string())
+            .or_default();
+        status.last_sync = Some(finished);
+        status.last_error = result.err();
+    }
+
+    /// Snapshot of all known peers with their current connection type
+    pub async fn list(&self, endpoint: &Endpoint) -> Vec<PeerInfo> {
+        let peers = self.peers.read().await;
+        let mut list: Vec<PeerInfo> = peers
+            .iter()
+            .map(|(peer, tables)| PeerInfo {
+                peer: *peer,
+                conn_type: endpoint.conn_type(*peer).map(|mut watcher| watcher.get()),
+                tables: tables.clone(),
+            })
+            .collect();
+        list.sort_by_key(|p| (!p.is_connected(), p.peer));
+        list
+    }
+}
+
+/// Number of remotes the endpoint currently has a direct, relayed or mixed path to
+pub fn open_connections(endpoint: &Endpoint) -> usize {
+    endpoint
+        .remote_info_iter()
+        .filter(|info| !matches!(info.conn_type, ConnectionType::None))
+        .count()
+}
+
+/// Path and latency of the connection to a remote endpoint
+#[derive(Debug, Clone)]
+pub struct ConnInfo {
+    pub peer: PublicKey,
+    pub conn_type: ConnectionType,
+    // round trip time of the path in use
+    pub rtt: Option<Duration>,
+    pub relay_url: Option<String>,
+    // direct addresses with their measured latency
+    pub addrs: Vec<(SocketAddr, Option<Duration>)>,
+    // time since data was last sent or received
+    pub last_used: Option<Duration>,
+}
+
+/// Connection diagnostics for every remote the endpoint has talked to, most recent first
+pub fn connection_info(endpoint: &Endpoint) -> Vec<ConnInfo> {
+    let mut infos: Vec<ConnInfo> = endpoint
+        .remote_info_iter()
+        .map(|info| ConnInfo {
+            peer: info.endpoint_id,
+            conn_type: info.conn_type,
+            rtt: info.latency,
+            relay_url: info.relay_url.map(|r| r.relay_url.to_string()),
+            addrs: info.addrs.iter().map(|a| (a.addr, a.latency)).collect(),
+            last_used: info.last_used,
+        })
+        .collect();
+    infos.sort_by_key(|i| i.last_used.unwrap_or(Duration::MAX));
+    infos
+}
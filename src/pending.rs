@@ -0,0 +1,94 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+/// A local write no peer has received yet
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingWrite {
+    pub table: String,
+    pub key: String,
+    // entry timestamp in microseconds since the unix epoch
+    pub timestamp: u64,
+}
+
+/// Local writes that have not been replicated to any peer, e.g. while offline
+///
+/// Writes always land in the local doc first. A write made while the table has a gossip
+/// neighbor is broadcast to it right away and never tracked, any other counts as replicated
+/// once a sync run with any peer that started after it finished successfully, e.g. the
+/// run with a neighbor that comes up. Shared by all clones, the tracking is not kept
+/// across restarts
+#[derive(Debug, Clone, Default)]
+pub struct PendingWrites {
+    // latest unreplicated write per key, keyed by table
+    writes: Arc<Mutex<HashMap<String, BTreeMap<String, u64>>>>,
+}
+
+impl PendingWrites {
+    /// Remember a local write of `key` in `table`
+    pub fn record(&self, table: &str, key: String, timestamp: u64) {
+        self.writes
+            .lock()
+            .unwrap()
+            .entry(table.to_string())
+            .or_default()
+            .insert(key, timestamp);
+    }
+
+    /// Mark the writes of `table` up to `synced_until` as replicated and return their keys
+    pub fn confirm(&self, table: &str, synced_until: u64) -> Vec<String> {
+        let mut writes = self.writes.lock().unwrap();
+        let Some(table_writes) = writes.get_mut(table) else {
+            return vec![];
+        };
+        let replicated: Vec<String> = table_writes
+            .iter()
+            .filter(|(_, timestamp)| **timestamp <= synced_until)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &replicated {
+            table_writes.remove(key);
+        }
+        if table_writes.is_empty() {
+            writes.remove(table);
+        }
+        replicated
+    }
+
+    /// Whether a local write of `key` in `table` has not reached any peer
+    pub fn contains(&self, table: &str, key: &str) -> bool {
+        self.writes
+            .lock()
+            .unwrap()
+            .get(table)
+            .is_some_and(|writes| writes.contains_key(key))
+    }
+
+    /// All unreplicated writes, oldest first
+    pub fn list(&self) -> Vec<PendingWrite> {
+        let mut pending: Vec<PendingWrite> = self
+            .writes
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(|(table, writes)| {
+                writes.iter().map(|(key, timestamp)| PendingWrite {
+                    table: table.clone(),
+                    key: key.clone(),
+                    timestamp: *timestamp,
+                })
+            })
+            .collect();
+        pending.sort_by_key(|write| write.timestamp);
+        pending
+    }
+
+    pub fn len(&self) -> usize {
+        self.writes.lock().unwrap().values().map(|w| w.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::Result;
+use futures::TryStreamExt;
+use iroh_blobs::{BlobFormat, Hash, HashAndFormat, hashseq::HashSeq};
+
+use crate::server::IrohNet;
+
+// prefix of the blob store tags that pin the blobs of a resource
+pub const PIN_TAG_PREFIX: &str = "pin/";
+
+/// Pin `blobs` for the resource `id`
+///
+/// Pins are tags of the blob store, so they survive restarts and keep the blobs from
+/// garbage collection, and [`StorageQuota`] never evicts them
+///
+/// [`StorageQuota`]: crate::quota::StorageQuota
+pub async fn pin(node: &IrohNet, id: &str, blobs: Vec<HashAndFormat>) -> Result<()> {
+    let tags = node.blobs_store.tags();
+    for (i, blob) in blobs.into_iter().enumerate() {
+        tags.set(format!("{}{}/{}", PIN_TAG_PREFIX, id, i), blob)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Remove the pins of the resource `id`, returns whether it was pinned
+pub async fn unpin(node: &IrohNet, id: &str) -> Result<bool> {
+    let pinned = pins(node).await?.contains_key(id);
+    node.blobs_store
+        .tags()
+        .delete_prefix(format!("{}{}/", PIN_TAG_PREFIX, id))
+        .await?;
+    Ok(pinned)
+}
+
+/// Pinned resource ids and their pinned blobs, sorted by id
+pub async fn pins(node: &IrohNet) -> Result<BTreeMap<String, Vec<HashAndFormat>>> {
+    let tags: Vec<_> = node
+        .blobs_store
+        .tags()
+        .list_prefix(PIN_TAG_PREFIX)
+        .await?
+        .try_collect()
+        .await?;
+    let mut pins: BTreeMap<String, Vec<HashAndFormat>> = BTreeMap::new();
+    for tag in tags {
+        let name = String::from_utf8_lossy(tag.name.as_ref()).to_string();
+        let Some((id, _)) = name
+            .strip_prefix(PIN_TAG_PREFIX)
+            .and_then(|rest| rest.rsplit_once('/'))
+        else {
+            continue;
+        };
+        pins.entry(id.to_string())
+            .or_default()
+            .push(tag.hash_and_format());
+    }
+    Ok(pins)
+}
+
+/// Every blob kept by a pin, including the children of pinned hash sequences
+pub async fn pinned_hashes(node: &IrohNet) -> Result<HashSet<Hash>> {
+    let mut hashes = HashSet::new();
+    for blob in pins(node).await?.into_values().flatten() {
+        hashes.insert(blob.hash);
+        if blob.format == BlobFormat::HashSeq
+            && let Ok(bytes) = node.blobs_store.blobs().get_bytes(blob.hash).await
+            && let Ok(seq) = HashSeq::try_from(bytes)
+        {
+            hashes.extend(seq.iter());
+        }
+    }
+    Ok(hashes)
+}
@@ -0,0 +1,582 @@
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, ensure};
+use bytes::Bytes;
+use iroh_blobs::{Hash, HashAndFormat};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use crate::server::IrohNet;
+
+// payloads up to this size stay inline in the doc entry
+pub const DEFAULT_INLINE_MAX: usize = 64 * 1024;
+// payloads from this size go to the external root, if one is configured
+pub const DEFAULT_EXTERNAL_MIN: usize = 32 * 1024 * 1024;
+// largest file accepted, 0 for no limit
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+// local payloads larger than this are split into blobs of this size
+pub const DEFAULT_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+// read size when hashing or copying files
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Decides where the content of a resource is stored
+#[derive(Clone, Debug)]
+pub struct PlacementPolicy {
+    // maximum size kept inline in the doc entry
+    pub inline_max: usize,
+    // minimum size sent to `external_root`
+    pub external_min: usize,
+    // secondary storage root, e.g. an external drive
+    pub external_root: Option<PathBuf>,
+    // largest accepted content, 0 for no limit
+    pub max_file_size: u64,
+    // size of the blobs larger local content is split into
+    pub chunk_size: usize,
+}
+
+impl Default for PlacementPolicy {
+    fn default() -> Self {
+        PlacementPolicy {
+            inline_max: DEFAULT_INLINE_MAX,
+            external_min: DEFAULT_EXTERNAL_MIN,
+            external_root: None,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+}
+
+/// Where the content of a resource lives
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum Placement {
+    // content is stored in the resource record itself
+    Inline,
+    // content is a separate blob in the local blob store
+    Local {
+        hash: Hash,
+        size: u64,
+    },
+    // content is a file named after its hash under the external root
+    External {
+        hash: Hash,
+        size: u64,
+    },
+    // content is split into blobs of `chunk_size` bytes in the local blob store,
+    // `hash` covers the whole content
+    Chunked {
+        hash: Hash,
+        size: u64,
+        chunk_size: u64,
+        chunks: Vec<Hash>,
+    },
+    // several files stored as one iroh blobs collection (hash sequence) in the local blob store,
+    // `hash` is the root of the collection and `size` the total size of its files
+    Collection {
+        hash: Hash,
+        size: u64,
+        files: u64,
+    },
+}
+
+impl Placement {
+    /// Blobs holding the content in the blob store and their sizes, the doc entry of the
+    /// resource does not refer to them, so peers fetch them on their own
+    ///
+    /// A collection is listed as its hash sequence, which covers every file. Inline content
+    /// travels with the entry, external content stays on the node that placed it
+    pub fn blobs(&self) -> Vec<(HashAndFormat, u64)> {
+        match self {
+            Placement::Inline | Placement::External { .. } => vec![],
+            Placement::Local { hash, size } => vec![(HashAndFormat::raw(*hash), *size)],
+            Placement::Chunked {
+                size,
+                chunk_size,
+                chunks,
+                ..
+            } => chunks
+                .iter()
+                .enumerate()
+                .map(|(index, hash)| {
+                    (
+                        HashAndFormat::raw(*hash),
+                        chunk_len(*size, *chunk_size, index),
+                    )
+                })
+                .collect(),
+            Placement::Collection { hash, size, .. } => {
+                vec![(HashAndFormat::hash_seq(*hash), *size)]
+            }
+        }
+    }
+}
+
+impl PlacementPolicy {
+    /// Store `content` according to the policy and return its placement
+    ///
+    /// Inline content is not written anywhere, the caller keeps it in the record
+    pub async fn place(&self, node: &IrohNet, content: &[u8]) -> Result<Placement> {
+        self.place_with_progress(node, content, &|_| {}).await
+    }
+
+    /// Like [`PlacementPolicy::place`], calling `progress` with the number of bytes stored so far
+    pub async fn place_with_progress(
+        &self,
+        node: &IrohNet,
+        content: &[u8],
+        progress: &(dyn Fn(u64) + Send + Sync),
+    ) -> Result<Placement> {
+        let size = content.len() as u64;
+        ensure!(
+            self.max_file_size == 0 || size <= self.max_file_size,
+            "File of {} bytes exceeds the limit of {} bytes",
+            size,
+            self.max_file_size
+        );
+        if content.len() <= self.inline_max {
+            progress(size);
+            return Ok(Placement::Inline);
+        }
+
+        if let Some(root) = &self.external_root
+            && content.len() >= self.external_min
+        {
+            let hash = Hash::new(content);
+            tokio::fs::create_dir_all(root)
+                .await
+                .with_context(|| format!("Failed to create external storage: {:?}", root))?;
+            let path = root.join(hash.to_hex());
+            tokio::fs::write(&path, content)
+                .await
+                .with_context(|| format!("Failed to write external blob: {:?}", path))?;
+            progress(size);
+            return Ok(Placement::External { hash, size });
+        }
+
+        if content.len() > self.chunk_size {
+            return self.place_chunks(node, content, progress).await;
+        }
+
+        let tag = node
+            .blobs_store
+            .add_bytes(Bytes::copy_from_slice(content))
+            .await?;
+        node.quota.added(tag.hash);
+        progress(size);
+        Ok(Placement::Local {
+            hash: tag.hash,
+            size,
+        })
+    }
+
+    /// Store the file at `path` like [`PlacementPolicy::place`] without reading it into memory
+    ///
+    /// Files up to `inline_max` bytes belong in the record, the caller has to read those and
+    /// use [`PlacementPolicy::place`] instead. Chunked files are read one chunk at a time
+    pub async fn place_file(
+        &self,
+        node: &IrohNet,
+        path: &Path,
+        progress: &(dyn Fn(u64) + Send + Sync),
+    ) -> Result<Placement> {
+        let size = tokio::fs::metadata(path)
+            .await
+            .with_context(|| format!("Failed to read file: {:?}", path))?
+            .len();
+        ensure!(
+            self.max_file_size == 0 || size <= self.max_file_size,
+            "File of {} bytes exceeds the limit of {} bytes",
+            size,
+            self.max_file_size
+        );
+        ensure!(
+            size > self.inline_max as u64,
+            "File of {} bytes is stored inline and has to be read",
+            size
+        );
+
+        if let Some(root) = &self.external_root
+            && size >= self.external_min as u64
+        {
+            let hash = hash_file(path).await?;
+            tokio::fs::create_dir_all(root)
+                .await
+                .with_context(|| format!("Failed to create external storage: {:?}", root))?;
+            let target = root.join(hash.to_hex());
+            tokio::fs::copy(path, &target)
+                .await
+                .with_context(|| format!("Failed to write external blob: {:?}", target))?;
+            progress(size);
+            return Ok(Placement::External { hash, size });
+        }
+
+        if size > self.chunk_size as u64 {
+            let file = tokio::fs::File::open(path)
+                .await
+                .with_context(|| format!("Failed to read file: {:?}", path))?;
+            return self
+                .place_chunks(node, file, progress)
+                .await
+                .with_context(|| format!("Failed to place file: {:?}", path));
+        }
+
+        // the blob store only imports absolute paths
+        let path = std::path::absolute(path)?;
+        let tag = node
+            .blobs_store
+            .add_path(&path)
+            .await
+            .with_context(|| format!("Failed to add {:?} to the blob store", path))?;
+        node.quota.added(tag.hash);
+        progress(size);
+        Ok(Placement::Local {
+            hash: tag.hash,
+            size,
+        })
+    }
+
+    // split `reader` into chunk blobs as it is read, only one chunk is held in memory
+    async fn place_chunks(
+        &self,
+        node: &IrohNet,
+        mut reader: impl AsyncRead + Unpin,
+        progress: &(dyn Fn(u64) + Send + Sync),
+    ) -> Result<Placement> {
+        let mut hasher = blake3::Hasher::new();
+        let mut chunks = Vec::new();
+        let mut written = 0;
+        loop {
+            let mut chunk = Vec::with_capacity(self.chunk_size);
+            (&mut reader)
+                .take(self.chunk_size as u64)
+                .read_to_end(&mut chunk)
+                .await?;
+            if chunk.is_empty() {
+                break;
+            }
+            hasher.update(&chunk);
+            written += chunk.len() as u64;
+            let tag = node.blobs_store.add_bytes(Bytes::from(chunk)).await?;
+            node.quota.added(tag.hash);
+            chunks.push(tag.hash);
+            progress(written);
+        }
+        Ok(Placement::Chunked {
+            hash: hasher.finalize().into(),
+            size: written,
+            chunk_size: self.chunk_size as u64,
+            chunks,
+        })
+    }
+
+    /// Read content stored with [`PlacementPolicy::place`]
+    pub async fn read(
+        &self,
+        node: &IrohNet,
+        placement: &Placement,
+        inline: &[u8],
+    ) -> Result<Bytes> {
+        match placement {
+            Placement::Inline => Ok(Bytes::copy_from_slice(inline)),
+            Placement::Local { hash, .. } => {
+                node.quota.touch(*hash);
+                node.blobs_store
+                    .blobs()
+                    .get_bytes(*hash)
+                    .await
+                    .with_context(|| format!("Blob {} is not available locally", hash.fmt_short()))
+            }
+            Placement::External { hash, .. } => {
+                let root = self
+                    .external_root
+                    .as_ref()
+                    .ok_or_else(|| anyhow::anyhow!("No external storage root configured"))?;
+                let path = root.join(hash.to_hex());
+                let content = tokio::fs::read(&path)
+                    .await
+                    .with_context(|| format!("Failed to read external blob: {:?}", path))?;
+                ensure!(
+                    Hash::new(&content) == *hash,
+                    "External blob {:?} is corrupted",
+                    path
+                );
+                Ok(content.into())
+            }
+            Placement::Collection { hash, .. } => Err(anyhow::anyhow!(
+                "Blob {} is a collection of files, export it to a directory",
+                hash.fmt_short()
+            )),
+            Placement::Chunked {
+                hash,
+                size,
+                chunk_size,
+                chunks,
+            } => {
+                let mut content = Vec::with_capacity(*size as usize);
+                for (index, chunk) in chunks.iter().enumerate() {
+                    let len = chunk_len(*size, *chunk_size, index);
+                    content.extend_from_slice(&read_chunk(node, chunk, len).await?);
+                }
+                ensure!(
+                    Hash::new(&content) == *hash,
+                    "Chunks of {} do not add up to its content",
+                    hash.fmt_short()
+                );
+                Ok(content.into())
+            }
+        }
+    }
+
+    /// Read `len` bytes from `offset` of content stored with [`PlacementPolicy::place`]
+    ///
+    /// Only the requested part is loaded, the range is cut at the end of the content
+    pub async fn read_range(
+        &self,
+        node: &IrohNet,
+        placement: &Placement,
+        inline: &[u8],
+        offset: u64,
+        len: u64,
+    ) -> Result<Bytes> {
+        match placement {
+            Placement::Inline => {
+                let start = (offset as usize).min(inline.len());
+                let end = offset.saturating_add(len).min(inline.len() as u64) as usize;
+                Ok(Bytes::copy_from_slice(&inline[start..end]))
+            }
+            Placement::Local { hash, .. } => {
+                Ok(read_blob_range(node, hash, offset, len).await?.into())
+            }
+            Placement::External { hash, .. } => {
+                let root = self
+                    .external_root
+                    .as_ref()
+                    .ok_or_else(|| anyhow::anyhow!("No external storage root configured"))?;
+                let path = root.join(hash.to_hex());
+                let mut file = tokio::fs::File::open(&path)
+                    .await
+                    .with_context(|| format!("Failed to read external blob: {:?}", path))?;
+                file.seek(SeekFrom::Start(offset)).await?;
+                let mut content = Vec::new();
+                file.take(len).read_to_end(&mut content).await?;
+                Ok(content.into())
+            }
+            Placement::Collection { hash, .. } => Err(anyhow::anyhow!(
+                "Blob {} is a collection of files, export it to a directory",
+                hash.fmt_short()
+            )),
+            Placement::Chunked {
+                size,
+                chunk_size,
+                chunks,
+                ..
+            } => {
+                let end = offset.saturating_add(len).min(*size);
+                let mut content = Vec::new();
+                let mut position = offset;
+                // only the chunks overlapping the range are read
+                while position < end {
+                    let index = (position / chunk_size) as usize;
+                    let chunk = chunks
+                        .get(index)
+                        .ok_or_else(|| anyhow::anyhow!("Chunk {} is missing", index))?;
+                    let chunk_start = index as u64 * chunk_size;
+                    let from = position - chunk_start;
+                    let to = (end - chunk_start).min(chunk_len(*size, *chunk_size, index));
+                    ensure!(from < to, "Chunk {} is shorter than expected", index);
+                    // only the part in the range, not the whole chunk
+                    let part = read_blob_range(node, chunk, from, to - from).await?;
+                    ensure!(
+                        part.len() as u64 == to - from,
+                        "Chunk {} is shorter than expected",
+                        index
+                    );
+                    content.extend_from_slice(&part);
+                    position = chunk_start + to;
+                }
+                Ok(content.into())
+            }
+        }
+    }
+
+    /// Write content stored with [`PlacementPolicy::place`] to `target` without loading it into memory
+    ///
+    /// Returns the number of bytes written
+    pub async fn export(
+        &self,
+        node: &IrohNet,
+        placement: &Placement,
+        inline: &[u8],
+        target: &Path,
+    ) -> Result<u64> {
+        // the blob store only exports to absolute paths
+        let target = std::path::absolute(target)?;
+        if let Some(parent) = target.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        match placement {
+            Placement::Inline => {
+                tokio::fs::write(&target, inline)
+                    .await
+                    .with_context(|| format!("Failed to write {:?}", target))?;
+                Ok(inline.len() as u64)
+            }
+            Placement::Local { hash, .. } => {
+                node.quota.touch(*hash);
+                let size = node
+                    .blobs_store
+                    .blobs()
+                    .export(*hash, &target)
+                    .await
+                    .with_context(|| format!("Failed to export blob {}", hash.fmt_short()))?;
+                Ok(size)
+            }
+            Placement::External { hash, .. } => {
+                let root = self
+                    .external_root
+                    .as_ref()
+                    .ok_or_else(|| anyhow::anyhow!("No external storage root configured"))?;
+                let source = root.join(hash.to_hex());
+                let size = tokio::fs::copy(&source, &target)
+                    .await
+                    .with_context(|| format!("Failed to copy {:?} to {:?}", source, target))?;
+                Ok(size)
+            }
+            Placement::Collection { hash, .. } => Err(anyhow::anyhow!(
+                "Blob {} is a collection of files, export it to a directory",
+                hash.fmt_short()
+            )),
+            Placement::Chunked {
+                hash,
+                size,
+                chunk_size,
+                chunks,
+            } => {
+                // only one chunk is held in memory at a time
+                let mut file = tokio::fs::File::create(&target)
+                    .await
+                    .with_context(|| format!("Failed to create {:?}", target))?;
+                let mut hasher = blake3::Hasher::new();
+                for (index, chunk) in chunks.iter().enumerate() {
+                    let content =
+                        read_chunk(node, chunk, chunk_len(*size, *chunk_size, index)).await?;
+                    hasher.update(&content);
+                    file.write_all(&content).await?;
+                }
+                file.flush().await?;
+                if Hash::from(hasher.finalize()) != *hash {
+                    drop(file);
+                    let _ = tokio::fs::remove_file(&target).await;
+                    anyhow::bail!(
+                        "Chunks of {} do not add up to its content",
+                        hash.fmt_short()
+                    );
+                }
+                Ok(*size)
+            }
+        }
+    }
+}
+
+// length of chunk `index` of content of `size` bytes, only the last one is shorter
+fn chunk_len(size: u64, chunk_size: u64, index: usize) -> u64 {
+    size.saturating_sub(index as u64 * chunk_size)
+        .min(chunk_size)
+}
+
+// `len` bytes from `offset` of a blob, the blob store verifies what it reads against the hash
+async fn read_blob_range(node: &IrohNet, hash: &Hash, offset: u64, len: u64) -> Result<Vec<u8>> {
+    node.quota.touch(*hash);
+    let mut reader = node.blobs_store.blobs().reader(*hash);
+    reader.seek(SeekFrom::Start(offset)).await?;
+    let mut content = Vec::new();
+    reader
+        .take(len)
+        .read_to_end(&mut content)
+        .await
+        .with_context(|| format!("Blob {} is not available locally", hash.fmt_short()))?;
+    Ok(content)
+}
+
+// load a chunk and check it is the one the placement lists, with the expected length
+async fn read_chunk(node: &IrohNet, hash: &Hash, len: u64) -> Result<Bytes> {
+    node.quota.touch(*hash);
+    let content = node
+        .blobs_store
+        .blobs()
+        .get_bytes(*hash)
+        .await
+        .with_context(|| format!("Chunk {} is not available locally", hash.fmt_short()))?;
+    ensure!(
+        content.len() as u64 == len && Hash::new(&content) == *hash,
+        "Chunk {} is corrupted",
+        hash.fmt_short()
+    );
+    Ok(content)
+}
+
+/// Hash of the file at `path`, as [`Hash::new`] of its content, read piece by piece
+pub async fn hash_file(path: &Path) -> Result<Hash> {
+    hash_file_with(path, blake3::Hasher::new()).await
+}
+
+/// Hash of the file at `path` with `hasher`, e.g. a keyed one, read piece by piece
+pub async fn hash_file_with(path: &Path, mut hasher: blake3::Hasher) -> Result<Hash> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("Failed to read file: {:?}", path))?;
+    let mut buffer = vec![0; READ_BUFFER_SIZE];
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .await
+            .with_context(|| format!("Failed to read file: {:?}", path))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.finalize().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_the_blobs_of_each_placement() {
+        let hash = Hash::new(b"content");
+        assert!(Placement::Inline.blobs().is_empty());
+        assert!(Placement::External { hash, size: 7 }.blobs().is_empty());
+        assert_eq!(
+            Placement::Local { hash, size: 7 }.blobs(),
+            vec![(HashAndFormat::raw(hash), 7)]
+        );
+        assert_eq!(
+            Placement::Collection {
+                hash,
+                size: 7,
+                files: 2
+            }
+            .blobs(),
+            vec![(HashAndFormat::hash_seq(hash), 7)]
+        );
+    }
+
+    #[test]
+    fn sizes_the_last_chunk_by_the_remainder() {
+        let chunks = vec![Hash::new(b"1"), Hash::new(b"2"), Hash::new(b"3")];
+        let placement = Placement::Chunked {
+            hash: Hash::new(b"content"),
+            size: 10,
+            chunk_size: 4,
+            chunks,
+        };
+        let sizes: Vec<u64> = placement
+            .blobs()
+            .into_iter()
+            .map(|(_, size)| size)
+            .collect();
+        assert_eq!(sizes, vec![4, 4, 2]);
+    }
+}
@@ -0,0 +1,263 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use futures::TryStreamExt;
+use iroh_blobs::{Hash, HashAndFormat, api::blobs::BlobStatus};
+use iroh_docs::store::Query;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::{now_millis, pin::pinned_hashes, replication::held_by_peers, server::IrohNet};
+
+// how often the blob store size is checked against the quota
+pub const QUOTA_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+// file of the access times inside the storage path
+pub const QUOTA_FILE: &str = "quota.json";
+
+/// Result of one [`StorageQuota::enforce`] run
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Eviction {
+    // size of the complete blobs before the run
+    pub usage: u64,
+    pub evicted: usize,
+    pub freed: u64,
+    // blobs placed by this node that were kept because no peer holds a copy yet
+    pub unconfirmed: usize,
+}
+
+// access times and unconfirmed blobs
+#[derive(Debug, Default)]
+struct QuotaState {
+    // milliseconds since the unix epoch when a blob was added or last read
+    accessed: HashMap<Hash, u64>,
+    // blobs placed by this node that no peer has confirmed to hold yet
+    unconfirmed: HashSet<Hash>,
+}
+
+// layout of the quota file
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct QuotaFile {
+    accessed: Vec<(Hash, u64)>,
+    unconfirmed: Vec<Hash>,
+}
+
+/// Largest size of the blob store and when its blobs were last read
+///
+/// Shared by all clones. Once the complete blobs exceed the quota, the least recently
+/// read ones are deleted. Blobs holding doc entries are never evicted, so the metadata
+/// stays and evicted content can be downloaded again when it is read. Pinned blobs,
+/// see [`crate::pin`], are never evicted either, and neither are blobs this node placed
+/// until a peer holds a copy, they may be the only one.
+/// Access times are kept under the storage path, so they survive restarts
+#[derive(Debug, Clone, Default)]
+pub struct StorageQuota {
+    // 0 for no limit
+    max_bytes: Arc<AtomicU64>,
+    // `None` for access times that are only kept in memory
+    path: Option<PathBuf>,
+    state: Arc<std::sync::Mutex<QuotaState>>,
+    // the state changed since it was saved
+    dirty: Arc<AtomicBool>,
+}
+
+impl StorageQuota {
+    /// Load the access times stored at `path`, or start without any
+    pub async fn open(path: PathBuf) -> Result<Self> {
+        let mut state = QuotaState::default();
+        if path.exists() {
+            let bytes = tokio::fs::read(&path)
+                .await
+                .with_context(|| format!("Failed to read quota file {:?}", path))?;
+            match serde_json::from_slice::<QuotaFile>(&bytes) {
+                Ok(file) => {
+                    state.accessed = file.accessed.into_iter().collect();
+                    state.unconfirmed = file.unconfirmed.into_iter().collect();
+                }
+                // blobs without an access time count as new
+                Err(e) => warn!(?path, error = %e, "ignoring unreadable quota file"),
+            }
+        }
+        Ok(StorageQuota {
+            path: Some(path),
+            state: Arc::new(std::sync::Mutex::new(state)),
+            ..Default::default()
+        })
+    }
+
+    /// Change the quota, 0 removes it
+    pub fn set_max_bytes(&self, max_bytes: u64) {
+        self.max_bytes.store(max_bytes, Ordering::SeqCst);
+    }
+
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes.load(Ordering::SeqCst)
+    }
+
+    /// Record that a blob was read
+    pub fn touch(&self, hash: Hash) {
+        self.state
+            .lock()
+            .unwrap()
+            .accessed
+            .insert(hash, now_millis());
+        self.dirty.store(true, Ordering::SeqCst);
+    }
+
+    /// Record a blob this node placed, it is not evicted before a peer holds a copy
+    pub fn added(&self, hash: Hash) {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.accessed.insert(hash, now_millis());
+            state.unconfirmed.insert(hash);
+        }
+        self.dirty.store(true, Ordering::SeqCst);
+    }
+
+    /// Write the access times to the storage path if they changed
+    pub async fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if !self.dirty.swap(false, Ordering::SeqCst) {
+            return Ok(());
+        }
+        let json = {
+            let state = self.state.lock().unwrap();
+            serde_json::to_vec(&QuotaFile {
+                accessed: state.accessed.iter().map(|(h, t)| (*h, *t)).collect(),
+                unconfirmed: state.unconfirmed.iter().copied().collect(),
+            })?
+        };
+        // write next to the file and rename, so a crash never leaves a partial file
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, json).await?;
+        tokio::fs::rename(&tmp, path).await?;
+        Ok(())
+    }
+
+    /// Delete the least recently read blobs until the store fits the quota
+    pub async fn enforce(&self, node: &IrohNet) -> Result<Eviction> {
+        let blobs = node.blobs_store.blobs();
+        let mut sizes = vec![];
+        for hash in blobs.list().hashes().await? {
+            if let BlobStatus::Complete { size } = blobs.status(hash).await? {
+                sizes.push((hash, size));
+            }
+        }
+        let usage = sizes.iter().map(|(_, size)| size).sum();
+        let mut eviction = Eviction {
+            usage,
+            ..Default::default()
+        };
+        let max_bytes = self.max_bytes();
+        if max_bytes == 0 || usage <= max_bytes {
+            return Ok(eviction);
+        }
+
+        let mut protected = entry_hashes(node).await?;
+        protected.extend(pinned_hashes(node).await?);
+        let now = now_millis();
+        let mut candidates: Vec<(Hash, u64, u64)> = {
+            let mut state = self.state.lock().unwrap();
+            sizes
+                .into_iter()
+                .filter(|(hash, _)| !protected.contains(hash))
+                // a blob seen for the first time, e.g. a download, counts as new
+                .map(|(hash, size)| (hash, size, *state.accessed.entry(hash).or_insert(now)))
+                .collect()
+        };
+        self.dirty.store(true, Ordering::SeqCst);
+        candidates.sort_by_key(|(_, _, accessed)| *accessed);
+
+        // ask the peers about the placed blobs first, only what they hold may go
+        let unconfirmed: Vec<HashAndFormat> = {
+            let state = self.state.lock().unwrap();
+            candidates
+                .iter()
+                .filter(|(hash, _, _)| state.unconfirmed.contains(hash))
+                .map(|(hash, _, _)| HashAndFormat::raw(*hash))
+                .collect()
+        };
+        if !unconfirmed.is_empty() {
+            let held = held_by_peers(node, unconfirmed).await?;
+            let mut state = self.state.lock().unwrap();
+            for content in held {
+                state.unconfirmed.remove(&content.hash);
+            }
+            candidates.retain(|(hash, _, _)| !state.unconfirmed.contains(hash));
+            eviction.unconfirmed = state.unconfirmed.len();
+        }
+
+        let tags: Vec<_> = node.blobs_store.tags().list().await?.try_collect().await?;
+        let mut remaining = usage;
+        for (hash, size, _) in candidates {
+            if remaining <= max_bytes {
+                break;
+            }
+            for tag in tags.iter().filter(|tag| tag.hash == hash) {
+                node.blobs_store.tags().delete(tag.name.clone()).await?;
+            }
+            if let Err(e) = blobs.delete([hash]).await {
+                warn!(hash = %hash.fmt_short(), error = %e, "failed to evict blob");
+                continue;
+            }
+            debug!(hash = %hash.fmt_short(), size, "evicted blob");
+            self.state.lock().unwrap().accessed.remove(&hash);
+            remaining = remaining.saturating_sub(size);
+            eviction.evicted += 1;
+            eviction.freed += size;
+        }
+        info!(
+            usage,
+            max_bytes,
+            evicted = eviction.evicted,
+            freed = eviction.freed,
+            unconfirmed = eviction.unconfirmed,
+            "storage quota enforced"
+        );
+        Ok(eviction)
+    }
+}
+
+/// Content hashes of the entries of every doc on the node
+pub async fn entry_hashes(node: &IrohNet) -> Result<HashSet<Hash>> {
+    let mut hashes = HashSet::new();
+    let namespaces: Vec<_> = node.docs.list().await?.try_collect().await?;
+    for (namespace, _) in namespaces {
+        let Some(doc) = node.docs.open(namespace).await? else {
+            continue;
+        };
+        let entries: Vec<_> = doc.get_many(Query::all()).await?.try_collect().await?;
+        hashes.extend(entries.iter().map(|entry| entry.content_hash()));
+    }
+    Ok(hashes)
+}
+
+/// Check the blob store against [`IrohNet::quota`] every [`QUOTA_CHECK_INTERVAL`] and save
+/// the access times
+pub fn spawn_quota_enforcer(node: IrohNet) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(QUOTA_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            if node.quota.max_bytes() != 0
+                && let Err(e) = node.quota.enforce(&node).await
+            {
+                warn!(error = %e, "failed to enforce the storage quota");
+            }
+            if let Err(e) = node.quota.save().await {
+                warn!(error = %e, "failed to save the access times");
+            }
+        }
+    })
+}
@@ -0,0 +1,148 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use iroh_docs::{
+    DocTicket, NamespaceId,
+    api::protocol::{AddrInfoOptions, ShareMode},
+};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::keys::KeyScheme;
+use crate::server::IrohNet;
+use crate::sync_filter::SyncFilter;
+
+// file of the saved tickets inside the storage path
+pub const TICKETS_FILE: &str = "tickets.json";
+
+/// A table this node has open, saved so it is reopened after a restart
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedTable {
+    pub table: String,
+    pub namespace: NamespaceId,
+    // ticket the table was created or joined with
+    pub ticket: String,
+    // entries whose content is downloaded, all when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sync_filter: Option<SyncFilter>,
+    // how new resources are keyed, random when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_scheme: Option<KeyScheme>,
+}
+
+/// Tables of a node as saved by [`save_tables`]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SavedTables {
+    pub tables: Vec<SavedTable>,
+    // collections this node left, announcements of them in the manifest are not joined
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub left: Vec<NamespaceId>,
+}
+
+impl SavedTables {
+    /// Sync filters of the tables that have one
+    pub fn sync_filters(&self) -> HashMap<String, SyncFilter> {
+        self.tables
+            .iter()
+            .filter_map(|saved| Some((saved.table.clone(), saved.sync_filter.clone()?)))
+            .collect()
+    }
+
+    /// Key scheme saved with a table, if it has one
+    pub fn key_scheme(&self, table: &str) -> Option<KeyScheme> {
+        self.tables
+            .iter()
+            .find(|saved| saved.table == table)
+            .and_then(|saved| saved.key_scheme)
+    }
+
+    /// Whether the tables whose name passes `filter` were saved in single-doc mode
+    pub fn share_one_doc(&self, filter: impl Fn(&str) -> bool) -> bool {
+        let namespaces: Vec<NamespaceId> = self
+            .tables
+            .iter()
+            .filter(|saved| filter(&saved.table))
+            .map(|saved| saved.namespace)
+            .collect();
+        namespaces.len() > 1 && namespaces.iter().all(|n| *n == namespaces[0])
+    }
+
+    /// Whether the tables whose name passes `filter` were saved in more than one doc
+    pub fn separate_docs(&self, filter: impl Fn(&str) -> bool) -> bool {
+        let namespaces: HashSet<NamespaceId> = self
+            .tables
+            .iter()
+            .filter(|saved| filter(&saved.table))
+            .map(|saved| saved.namespace)
+            .collect();
+        namespaces.len() > 1
+    }
+
+    /// Tickets of the tables whose name passes `filter`
+    pub fn tickets(&self, filter: impl Fn(&str) -> bool) -> HashMap<String, DocTicket> {
+        self.tables
+            .iter()
+            .filter(|saved| filter(&saved.table))
+            .filter_map(|saved| match saved.ticket.parse() {
+                Ok(ticket) => Some((saved.table.clone(), ticket)),
+                Err(e) => {
+                    warn!(table = %saved.table, error = %e, "ignoring unreadable saved ticket");
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+/// Write the tables of a node below its storage path, replacing earlier ones
+pub async fn save_tables(root: &Path, tables: &SavedTables) -> Result<()> {
+    let path = root.join(TICKETS_FILE);
+    let json = serde_json::to_vec_pretty(tables)?;
+    // write next to the file and rename, so a crash never leaves a partial file
+    let tmp = path.with_extension("tmp");
+    tokio::fs::write(&tmp, json)
+        .await
+        .with_context(|| format!("Failed to write {:?}", tmp))?;
+    tokio::fs::rename(&tmp, &path).await?;
+    debug!(?path, tables = tables.tables.len(), "saved tickets");
+    Ok(())
+}
+
+/// Tables saved below the storage path, `None` on the first start
+pub async fn load_tables(root: &Path) -> Result<Option<SavedTables>> {
+    let path = root.join(TICKETS_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let json = tokio::fs::read(&path)
+        .await
+        .with_context(|| format!("Failed to read {:?}", path))?;
+    let tables = serde_json::from_slice(&json)
+        .with_context(|| format!("Failed to parse saved tickets {:?}", path))?;
+    Ok(Some(tables))
+}
+
+/// Fresh tickets for the saved tables, for a node that is not running its tables
+///
+/// The docs are opened from the storage of `node` without joining their tables
+pub async fn share_saved_tables(
+    node: &IrohNet,
+    saved: &SavedTables,
+    mode: ShareMode,
+    addr: AddrInfoOptions,
+) -> Result<Vec<(String, String)>> {
+    let mut tickets = vec![];
+    for table in &saved.tables {
+        let doc = node
+            .docs
+            .open(table.namespace)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Doc of table '{}' is not stored", table.table))?;
+        let ticket = doc.share(mode, addr).await?;
+        tickets.push((table.table.clone(), ticket.to_string()));
+    }
+    Ok(tickets)
+}
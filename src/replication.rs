@@ -0,0 +1,284 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use futures::{StreamExt, TryStreamExt};
+use iroh::{
+    Endpoint, EndpointAddr, PublicKey,
+    endpoint::Connection,
+    protocol::{AcceptError, ProtocolHandler},
+};
+use iroh_blobs::{Hash, HashAndFormat, api::Store};
+use iroh_docs::{Entry, NamespaceId, api::Doc, protocol::Docs, store::Query};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::{acl::Acl, admin::AdminProtocol, downloader::has_content, server::IrohNet};
+
+pub const REPLICATION_ALPN: &[u8] = b"iroh-test/replication/0";
+
+// upper bound for a single request or manifest
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Serialize, Deserialize)]
+enum ReplicationRequest {
+    // ask which of the given content of a doc the peer holds locally
+    Manifest {
+        namespace: NamespaceId,
+        content: Vec<HashAndFormat>,
+    },
+    // ask the peer to sync the doc with us so it downloads what it lacks
+    Resync {
+        namespace: NamespaceId,
+    },
+}
+
+/// Content of a doc a peer holds locally
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub hashes: Vec<HashAndFormat>,
+}
+
+/// Replication state of a single entry
+#[derive(Debug, Clone)]
+pub struct ReplicaStatus {
+    pub key: String,
+    pub hash: Hash,
+    // number of nodes, including this one, holding the entry and all the blobs it places
+    pub replicas: usize,
+}
+
+/// Answers manifest and resync requests from other peers
+///
+/// Only peers the doc synced with, members of the access list and admins are answered
+#[derive(Debug, Clone)]
+pub struct ReplicationProtocol {
+    docs: Docs,
+    blobs_store: Store,
+    acl: Acl,
+    admin: AdminProtocol,
+}
+
+impl ReplicationProtocol {
+    pub fn new(docs: Docs, blobs_store: Store, acl: Acl, admin: AdminProtocol) -> Self {
+        ReplicationProtocol {
+            docs,
+            blobs_store,
+            acl,
+            admin,
+        }
+    }
+
+    // whether `remote` may ask about `doc`
+    async fn is_allowed(&self, doc: &Doc, remote: &PublicKey) -> Result<bool> {
+        if self.acl.role(remote.as_bytes()).is_some() || self.admin.is_allowed(remote).await {
+            return Ok(true);
+        }
+        Ok(sync_peers(doc).await?.contains(remote))
+    }
+
+    async fn handle(&self, connection: Connection) -> Result<()> {
+        let remote = connection.remote_id()?;
+        let (mut send, mut recv) = connection.accept_bi().await?;
+        let request: ReplicationRequest =
+            bincode::deserialize(&recv.read_to_end(MAX_MESSAGE_SIZE).await?)?;
+        let namespace = match &request {
+            ReplicationRequest::Manifest { namespace, .. } => *namespace,
+            ReplicationRequest::Resync { namespace } => *namespace,
+        };
+        let doc = match self.docs.open(namespace).await? {
+            Some(doc) if self.is_allowed(&doc, &remote).await? => Some(doc),
+            Some(_) => {
+                warn!(from = %remote.fmt_short(), %namespace, "rejected replication request");
+                None
+            }
+            None => None,
+        };
+        let response = match (request, doc) {
+            (ReplicationRequest::Manifest { content, .. }, Some(_)) => {
+                let hashes = held_content(&self.blobs_store, content).await?;
+                bincode::serialize(&Manifest { hashes })?
+            }
+            (ReplicationRequest::Manifest { .. }, None) => {
+                bincode::serialize(&Manifest::default())?
+            }
+            (ReplicationRequest::Resync { .. }, Some(doc)) => {
+                info!(from = %remote.fmt_short(), %namespace, "resync requested");
+                doc.start_sync(vec![EndpointAddr::new(remote)]).await?;
+                Vec::new()
+            }
+            (ReplicationRequest::Resync { .. }, None) => Vec::new(),
+        };
+        send.write_all(&response).await?;
+        send.finish()?;
+        connection.closed().await;
+        Ok(())
+    }
+}
+
+impl ProtocolHandler for ReplicationProtocol {
+    async fn accept(&self, connection: Connection) -> Result<(), AcceptError> {
+        self.handle(connection)
+            .await
+            .map_err(|e| AcceptError::from_err(std::io::Error::other(e)))
+    }
+}
+
+// the part of `content` that is complete in the local blob store
+async fn held_content(
+    blobs_store: &Store,
+    content: impl IntoIterator<Item = HashAndFormat>,
+) -> Result<Vec<HashAndFormat>> {
+    let mut held = Vec::new();
+    for content in content {
+        if has_content(blobs_store, content).await? {
+            held.push(content);
+        }
+    }
+    Ok(held)
+}
+
+async fn request(
+    endpoint: &Endpoint,
+    peer: PublicKey,
+    request: &ReplicationRequest,
+) -> Result<Vec<u8>> {
+    let connection = endpoint.connect(peer, REPLICATION_ALPN).await?;
+    let (mut send, mut recv) = connection.open_bi().await?;
+    send.write_all(&bincode::serialize(request)?).await?;
+    send.finish()?;
+    let response = recv.read_to_end(MAX_MESSAGE_SIZE).await?;
+    connection.close(0u32.into(), b"done");
+    Ok(response)
+}
+
+// peers this doc has synced with
+async fn sync_peers(doc: &Doc) -> Result<Vec<PublicKey>> {
+    let peers = doc.get_sync_peers().await?.unwrap_or_default();
+    Ok(peers
+        .iter()
+        .filter_map(|p| PublicKey::from_bytes(p).ok())
+        .collect())
+}
+
+/// Count how many known peers hold each entry of a table and return the entries below `factor`
+///
+/// Only the entries under `prefix` belong to the table, see [`Table::prefix`](crate::tables::Table::prefix), their keys are
+/// reported without it. `placements` maps the content hash of an entry to the blobs it places, e.g. the chunks of a
+/// resource, a node only counts as a replica when it holds the entry and all of them
+pub async fn under_replicated(
+    iroh: &IrohNet,
+    doc: &Doc,
+    prefix: &[u8],
+    placements: &HashMap<Hash, Vec<HashAndFormat>>,
+    factor: usize,
+) -> Result<Vec<ReplicaStatus>> {
+    let entries = doc
+        .get_many(Query::single_latest_per_key().key_prefix(prefix))
+        .await?;
+    let entries = entries.collect::<Vec<Result<Entry>>>().await;
+    let entries: Vec<(Entry, Vec<HashAndFormat>)> = entries
+        .into_iter()
+        .map_while(Result::ok)
+        .filter(|entry| entry.content_len() > 0)
+        .map(|entry| {
+            let mut content = vec![HashAndFormat::raw(entry.content_hash())];
+            if let Some(blobs) = placements.get(&entry.content_hash()) {
+                content.extend(blobs.iter().copied());
+            }
+            (entry, content)
+        })
+        .collect();
+    let wanted: HashSet<HashAndFormat> = entries
+        .iter()
+        .flat_map(|(_, content)| content.iter().copied())
+        .collect();
+
+    // content held by this node and by every peer that answered
+    let mut holders: Vec<HashSet<HashAndFormat>> = vec![
+        held_content(&iroh.blobs_store, wanted.iter().copied())
+            .await?
+            .into_iter()
+            .collect(),
+    ];
+    let endpoint = iroh.router.endpoint();
+    let namespace = doc.id();
+    for peer in sync_peers(doc).await? {
+        let manifest = ReplicationRequest::Manifest {
+            namespace,
+            content: wanted.iter().copied().collect(),
+        };
+        let response = request(endpoint, peer, &manifest).await;
+        match response.and_then(|r| Ok(bincode::deserialize::<Manifest>(&r)?)) {
+            Ok(manifest) => holders.push(manifest.hashes.into_iter().collect()),
+            Err(e) => warn!(peer = %peer.fmt_short(), error = %e, "no manifest"),
+        }
+    }
+
+    let mut statuses = Vec::new();
+    for (entry, content) in entries {
+        let replicas = holders
+            .iter()
+            .filter(|held| content.iter().all(|c| held.contains(c)))
+            .count();
+        if replicas < factor {
+            statuses.push(ReplicaStatus {
+                key: String::from_utf8_lossy(entry.key().strip_prefix(prefix).unwrap_or_default())
+                    .to_string(),
+                hash: entry.content_hash(),
+                replicas,
+            });
+        }
+    }
+    Ok(statuses)
+}
+
+/// The part of `content` that at least one peer of a doc on this node holds
+///
+/// The sync peers of every doc are asked in turn until all of `content` is accounted for
+pub async fn held_by_peers(
+    iroh: &IrohNet,
+    content: Vec<HashAndFormat>,
+) -> Result<HashSet<HashAndFormat>> {
+    let endpoint = iroh.router.endpoint();
+    let mut held = HashSet::new();
+    let namespaces: Vec<_> = iroh.docs.list().await?.try_collect().await?;
+    for (namespace, _) in namespaces {
+        let Some(doc) = iroh.docs.open(namespace).await? else {
+            continue;
+        };
+        for peer in sync_peers(&doc).await? {
+            let missing: Vec<_> = content
+                .iter()
+                .filter(|c| !held.contains(c))
+                .copied()
+                .collect();
+            if missing.is_empty() {
+                return Ok(held);
+            }
+            let manifest = ReplicationRequest::Manifest {
+                namespace,
+                content: missing,
+            };
+            let response = request(endpoint, peer, &manifest).await;
+            match response.and_then(|r| Ok(bincode::deserialize::<Manifest>(&r)?)) {
+                Ok(manifest) => held.extend(manifest.hashes),
+                Err(e) => debug!(peer = %peer.fmt_short(), error = %e, "no manifest"),
+            }
+        }
+    }
+    Ok(held)
+}
+
+/// Ask every known peer of `doc` to sync with this node so they fetch missing content
+pub async fn push_replicas(iroh: &IrohNet, doc: &Doc) -> Result<usize> {
+    let endpoint = iroh.router.endpoint();
+    let namespace = doc.id();
+    let mut pushed = 0;
+    for peer in sync_peers(doc).await? {
+        match request(endpoint, peer, &ReplicationRequest::Resync { namespace }).await {
+            Ok(_) => pushed += 1,
+            Err(e) => warn!(peer = %peer.fmt_short(), error = %e, "resync request failed"),
+        }
+    }
+    Ok(pushed)
+}
@@ -0,0 +1,202 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use iroh::PublicKey;
+use iroh_blobs::Hash;
+use tokio::{sync::Mutex, task::JoinHandle};
+use tracing::{info, warn};
+
+use crate::{doc_subcribe::AppEvent, event_channel::EventSender, server::IrohNet};
+
+// how often pending downloads are checked
+const RETRY_TICK: Duration = Duration::from_secs(1);
+
+/// Backoff settings for re-requesting missing content
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    // time the docs engine gets before the first retry
+    pub initial_delay: Duration,
+    // upper bound of the delay between two retries
+    pub max_delay: Duration,
+    // retries after which a hash is reported as failed
+    pub max_attempts: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            initial_delay: Duration::from_secs(30),
+            max_delay: Duration::from_secs(600),
+            max_attempts: 8,
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn delay(&self, attempts: u32) -> Duration {
+        self.initial_delay
+            .saturating_mul(2u32.saturating_pow(attempts))
+            .min(self.max_delay)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PendingBlob {
+    key: String,
+    size: u64,
+    // peer that announced the entry
+    from: PublicKey,
+    attempts: u32,
+    next_attempt: Instant,
+}
+
+/// Content that has not arrived after the retries ran out
+#[derive(Debug, Clone)]
+pub struct FailedBlob {
+    pub hash: Hash,
+    pub key: String,
+    pub attempts: u32,
+}
+
+/// Re-requests content whose download never completed
+#[derive(Debug, Clone)]
+pub struct RetryScheduler {
+    policy: RetryPolicy,
+    pending: Arc<Mutex<HashMap<Hash, PendingBlob>>>,
+    failed: Arc<Mutex<Vec<FailedBlob>>>,
+}
+
+impl RetryScheduler {
+    pub fn new(policy: RetryPolicy) -> Self {
+        RetryScheduler {
+            policy,
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            failed: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Watch `hash` until its content is ready
+    pub async fn track(&self, hash: Hash, key: String, size: u64, from: PublicKey) {
+        self.pending
+            .lock()
+            .await
+            .entry(hash)
+            .or_insert(PendingBlob {
+                key,
+                size,
+                from,
+                attempts: 0,
+                next_attempt: Instant::now() + self.policy.initial_delay,
+            });
+    }
+
+    /// Stop watching `hash` because its content arrived
+    pub async fn resolve(&self, hash: &Hash) {
+        self.pending.lock().await.remove(hash);
+    }
+
+    /// Hashes whose retries ran out
+    pub async fn failed(&self) -> Vec<FailedBlob> {
+        self.failed.lock().await.clone()
+    }
+
+    /// Number of hashes still waiting for content
+    pub async fn pending_len(&self) -> usize {
+        self.pending.lock().await.len()
+    }
+
+    /// Start the background task retrying due downloads
+    pub fn spawn(&self, iroh: IrohNet, table_name: String, events: EventSender) -> JoinHandle<()> {
+        let scheduler = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(RETRY_TICK);
+            loop {
+                interval.tick().await;
+                let now = Instant::now();
+                let due: Vec<(Hash, PendingBlob)> = scheduler
+                    .pending
+                    .lock()
+                    .await
+                    .iter()
+                    .filter(|(_, p)| p.next_attempt <= now)
+                    .map(|(h, p)| (*h, p.clone()))
+                    .collect();
+
+                for (hash, pending) in due {
+                    let present = iroh.blobs_store.blobs().has(hash).await.unwrap_or(false);
+                    let result = if present {
+                        Ok(())
+                    } else {
+                        info!(
+                            table = %table_name,
+                            hash = %hash.fmt_short(),
+                            from = %pending.from.fmt_short(),
+                            attempt = pending.attempts + 1,
+                            "re-requesting blob"
+                        );
+                        iroh.downloader
+                            .download(hash, pending.size, vec![pending.from])
+                            .await
+                    };
+
+                    match result {
+                        Ok(()) => {
+                            scheduler.resolve(&hash).await;
+                            events
+                                .send(AppEvent::BlobReady {
+                                    table: table_name.clone(),
+                                    key: pending.key,
+                                })
+                                .await;
+                        }
+                        Err(e) => scheduler.reschedule(hash, &table_name, &events, e).await,
+                    }
+                }
+            }
+        })
+    }
+
+    async fn reschedule(
+        &self,
+        hash: Hash,
+        table_name: &str,
+        events: &EventSender,
+        error: impl std::fmt::Display,
+    ) {
+        let mut pending = self.pending.lock().await;
+        let Some(blob) = pending.get_mut(&hash) else {
+            return;
+        };
+        blob.attempts += 1;
+        if blob.attempts < self.policy.max_attempts {
+            blob.next_attempt = Instant::now() + self.policy.delay(blob.attempts);
+            return;
+        }
+
+        let blob = pending.remove(&hash).unwrap();
+        warn!(
+            table = %table_name,
+            hash = %hash.fmt_short(),
+            attempts = blob.attempts,
+            %error,
+            "giving up on blob"
+        );
+        // not sent while holding the lock, a blocking channel would hold up `track`
+        drop(pending);
+        events
+            .send(AppEvent::BlobFailed {
+                table: table_name.to_string(),
+                key: blob.key.clone(),
+                hash,
+            })
+            .await;
+        self.failed.lock().await.push(FailedBlob {
+            hash,
+            key: blob.key,
+            attempts: blob.attempts,
+        });
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+
+use anyhow::{Result, ensure};
+use serde::Deserialize;
+
+use crate::codec;
+
+// marks a payload wrapped in a version envelope, followed by the version as u32 LE
+//
+// payloads written before versioning have no envelope and count as version 0
+const VERSION_MAGIC: [u8; 2] = [0xfc, b'v'];
+
+// upgrades a payload of one old version to the current entity
+type Upgrade<T> = Box<dyn Fn(&[u8]) -> Result<T> + Send + Sync>;
+
+/// Wrap an encoded entity in a version envelope
+pub fn wrap(version: u32, payload: Vec<u8>) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(VERSION_MAGIC.len() + 4 + payload.len());
+    buf.extend_from_slice(&VERSION_MAGIC);
+    buf.extend_from_slice(&version.to_le_bytes());
+    buf.extend_from_slice(&payload);
+    buf
+}
+
+/// Split a payload into its version and the encoded entity
+pub fn unwrap(bytes: &[u8]) -> Result<(u32, &[u8])> {
+    let Some(rest) = bytes.strip_prefix(&VERSION_MAGIC) else {
+        return Ok((0, bytes));
+    };
+    ensure!(rest.len() >= 4, "Version envelope is truncated");
+    let (version, payload) = rest.split_at(4);
+    Ok((u32::from_le_bytes(version.try_into()?), payload))
+}
+
+/// Upgrades entries written with older schema versions of an entity
+///
+/// Each migration reads the payload as the entity looked in that version and
+/// converts it to the current one. A version registered more than once had several
+/// layouts, they are tried in the order they were registered
+pub struct Migrations<T> {
+    upgrades: HashMap<u32, Vec<Upgrade<T>>>,
+}
+
+impl<T> Default for Migrations<T> {
+    fn default() -> Self {
+        Migrations {
+            upgrades: HashMap::new(),
+        }
+    }
+}
+
+impl<T> Migrations<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the upgrade of `version` entries, decoded as `Old`
+    pub fn register<Old>(
+        mut self,
+        version: u32,
+        upgrade: impl Fn(Old) -> T + Send + Sync + 'static,
+    ) -> Self
+    where
+        Old: for<'a> Deserialize<'a>,
+    {
+        self.upgrades
+            .entry(version)
+            .or_default()
+            .push(Box::new(move |payload| {
+                Ok(upgrade(codec::decode::<Old>(payload)?))
+            }));
+        self
+    }
+
+    /// Upgrade a payload written with `version`
+    pub fn upgrade(&self, version: u32, payload: &[u8]) -> Result<T> {
+        let upgrades = self
+            .upgrades
+            .get(&version)
+            .ok_or_else(|| anyhow::anyhow!("No migration from schema version {}", version))?;
+        let mut error = None;
+        for upgrade in upgrades {
+            match upgrade(payload) {
+                Ok(entity) => return Ok(entity),
+                Err(e) => error = Some(e),
+            }
+        }
+        Err(error
+            .unwrap_or_else(|| anyhow::anyhow!("No migration from schema version {}", version))
+            .context(format!("Entry does not match schema version {}", version)))
+    }
+}
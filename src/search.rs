@@ -0,0 +1,243 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, RwLock};
+use tracing::warn;
+
+use crate::indexer::{EntityIndex, EntityMeta};
+
+// file of the index inside the storage path
+pub const SEARCH_INDEX_FILE: &str = "search_index.json";
+
+// changes are collected this long before the index file is rewritten
+const SAVE_DELAY: Duration = Duration::from_secs(2);
+
+/// A match of [`SearchIndex::search`]
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub table: String,
+    pub key: String,
+    // indexed text of the entity
+    pub text: String,
+}
+
+#[derive(Debug, Default)]
+struct IndexData {
+    // indexed text keyed by table and entry key, this is what gets persisted
+    texts: HashMap<String, HashMap<String, String>>,
+    // word to (table, key) postings, rebuilt from `texts` on load
+    words: BTreeMap<String, HashSet<(String, String)>>,
+}
+
+impl IndexData {
+    fn insert(&mut self, table: &str, key: &str, text: String) {
+        self.remove(table, key);
+        for word in tokenize(&text) {
+            self.words
+                .entry(word)
+                .or_default()
+                .insert((table.to_string(), key.to_string()));
+        }
+        self.texts
+            .entry(table.to_string())
+            .or_default()
+            .insert(key.to_string(), text);
+    }
+
+    fn remove(&mut self, table: &str, key: &str) {
+        let Some(text) = self.texts.get_mut(table).and_then(|t| t.remove(key)) else {
+            return;
+        };
+        let posting = (table.to_string(), key.to_string());
+        for word in tokenize(&text) {
+            if let Some(postings) = self.words.get_mut(&word) {
+                postings.remove(&posting);
+                if postings.is_empty() {
+                    self.words.remove(&word);
+                }
+            }
+        }
+    }
+
+    // postings of every word starting with `prefix`
+    fn prefixed(&self, prefix: &str) -> HashSet<(String, String)> {
+        self.words
+            .range(prefix.to_string()..)
+            .take_while(|(word, _)| word.starts_with(prefix))
+            .flat_map(|(_, postings)| postings.iter().cloned())
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct IndexFile {
+    texts: HashMap<String, HashMap<String, String>>,
+}
+
+/// Inverted index over the names and metadata of all tables, persisted under the storage path
+///
+/// Kept up to date by [`spawn_indexer`] from the doc events of every table
+///
+/// [`spawn_indexer`]: crate::indexer::spawn_indexer
+#[derive(Debug, Clone)]
+pub struct SearchIndex {
+    // `None` for an index that is only kept in memory
+    path: Option<PathBuf>,
+    data: Arc<RwLock<IndexData>>,
+    // serializes writes of the index file
+    save_lock: Arc<Mutex<()>>,
+    // a save is scheduled and will pick up further changes
+    save_pending: Arc<AtomicBool>,
+}
+
+impl SearchIndex {
+    /// Load the index stored at `path`, or start an empty one
+    pub async fn open(path: PathBuf) -> Result<Self> {
+        let mut data = IndexData::default();
+        if path.exists() {
+            let bytes = tokio::fs::read(&path)
+                .await
+                .with_context(|| format!("Failed to read search index {:?}", path))?;
+            match serde_json::from_slice::<IndexFile>(&bytes) {
+                Ok(file) => {
+                    for (table, texts) in file.texts {
+                        for (key, text) in texts {
+                            data.insert(&table, &key, text);
+                        }
+                    }
+                }
+                // the index is rebuilt from the docs anyway
+                Err(e) => warn!(?path, error = %e, "ignoring unreadable search index"),
+            }
+        }
+        Ok(SearchIndex {
+            path: Some(path),
+            data: Arc::new(RwLock::new(data)),
+            save_lock: Arc::new(Mutex::new(())),
+            save_pending: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// An empty index that is never written to disk
+    pub fn memory() -> Self {
+        SearchIndex {
+            path: None,
+            data: Arc::new(RwLock::new(IndexData::default())),
+            save_lock: Arc::new(Mutex::new(())),
+            save_pending: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Entities whose text contains every word of `query`, words match by prefix
+    pub async fn search(&self, query: &str) -> Vec<SearchHit> {
+        let words = tokenize(query);
+        if words.is_empty() {
+            return vec![];
+        }
+        let data = self.data.read().await;
+        let mut matches: Option<HashSet<(String, String)>> = None;
+        for word in words {
+            let postings = data.prefixed(&word);
+            matches = Some(match matches {
+                Some(matches) => matches.intersection(&postings).cloned().collect(),
+                None => postings,
+            });
+        }
+        let mut hits: Vec<SearchHit> = matches
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(table, key)| SearchHit {
+                text: data.texts[&table][&key].clone(),
+                table,
+                key,
+            })
+            .collect();
+        hits.sort_by(|a, b| (&a.table, &a.text).cmp(&(&b.table, &b.text)));
+        hits
+    }
+
+    // write the index after `SAVE_DELAY`, changes until then are saved with it
+    //
+    // a crash loses at most the last changes, the indexers rebuild them from the docs
+    fn schedule_save(&self) {
+        if self.path.is_none() || self.save_pending.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let index = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(SAVE_DELAY).await;
+            index.save_pending.store(false, Ordering::SeqCst);
+            if let Err(e) = index.save().await {
+                warn!(error = %e, "failed to save search index");
+            }
+        });
+    }
+
+    async fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let _guard = self.save_lock.lock().await;
+        let json = {
+            let data = self.data.read().await;
+            serde_json::to_vec(&IndexFile {
+                texts: data.texts.clone(),
+            })?
+        };
+        // write next to the index and rename, so a crash never leaves a partial file
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, json).await?;
+        tokio::fs::rename(&tmp, path).await?;
+        Ok(())
+    }
+}
+
+impl EntityIndex for SearchIndex {
+    async fn reindex(&self, table: &str, entities: Vec<EntityMeta>) -> Result<()> {
+        {
+            let mut data = self.data.write().await;
+            let keys: Vec<String> = data
+                .texts
+                .get(table)
+                .map(|t| t.keys().cloned().collect())
+                .unwrap_or_default();
+            for key in keys {
+                data.remove(table, &key);
+            }
+            for entity in entities {
+                data.insert(table, &entity.key, entity.text);
+            }
+        }
+        self.schedule_save();
+        Ok(())
+    }
+
+    async fn update(&self, table: &str, key: &str, entity: Option<EntityMeta>) -> Result<()> {
+        {
+            let mut data = self.data.write().await;
+            match entity {
+                Some(entity) => data.insert(table, key, entity.text),
+                None => data.remove(table, key),
+            }
+        }
+        self.schedule_save();
+        Ok(())
+    }
+}
+
+// lowercase words of letters and digits
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect()
+}
@@ -1,60 +1,484 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    net::{SocketAddr, SocketAddrV4, SocketAddrV6},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
-use iroh::{RelayMode, Watcher, protocol::Router};
-use iroh_blobs::store::fs::FsStore;
+use iroh::{
+    PublicKey, RelayUrl, Watcher,
+    protocol::{DynProtocolHandler, Router},
+};
+use iroh_blobs::api::{Store, blobs::BlobStatus};
+use iroh_blobs::store::{fs::FsStore, mem::MemStore};
+use iroh_docs::NamespaceId;
+use iroh_docs::api::{Doc, protocol::AddrInfoOptions};
+use tokio::sync::OnceCell;
 
-use crate::default_relay_map;
+use crate::acl::Acl;
+use crate::admin::{ADMIN_ALPN, AdminProtocol};
+use crate::anti_entropy::DEFAULT_ANTI_ENTROPY_INTERVAL;
+use crate::control::{CONTROL_ALPN, ControlProtocol};
+use crate::crypto::DocCipher;
+use crate::downloader::{
+    BlobDownloader, DEFAULT_DOWNLOAD_CONCURRENCY, DownloadScheduler, RateLimiter, upload_events,
+};
+use crate::event_channel::EventChannelConfig;
+use crate::peers::{PeerRegistry, open_connections};
+use crate::placement::PlacementPolicy;
+use crate::quota::{QUOTA_FILE, StorageQuota};
+use crate::replication::{REPLICATION_ALPN, ReplicationProtocol};
+use crate::search::{SEARCH_INDEX_FILE, SearchIndex};
+use crate::sql_index::{SQL_INDEX_FILE, SqlIndex};
+use crate::view::FolderView;
+use crate::{RelayChoice, get_images_directory};
 
 #[derive(Clone, Debug)]
 pub struct IrohNet {
     pub router: Router,
     pub gossip: iroh_gossip::net::Gossip,
-    pub blobs_store: FsStore,
+    pub blobs_store: Store,
     pub docs: iroh_docs::protocol::Docs,
+    // where resource content is stored
+    pub placement: PlacementPolicy,
+    // bounded parallel blob fetching
+    pub downloader: BlobDownloader,
+    // upload bandwidth limit, charged for every chunk served to other peers
+    pub upload_limiter: Arc<RateLimiter>,
+    // orders announced and requested downloads, shared by the table subscriptions
+    pub scheduler: DownloadScheduler,
+    // size limit of the blob store and blob access times for eviction
+    pub quota: StorageQuota,
+    // encrypts doc values end to end when set
+    pub cipher: Option<DocCipher>,
+    // peers seen by the table subscriptions
+    pub peers: PeerRegistry,
+    // relays are disabled, peers are found on the local network
+    pub lan_only: bool,
+    // answers control commands from other peers
+    pub control: ControlProtocol,
+    // queues admin requests of allowed operators
+    pub admin: AdminProtocol,
+    // full-text index over all tables
+    pub search: SearchIndex,
+    // metadata of all tables for listings without decoding entries
+    pub sql_index: SqlIndex,
+    // folders joined with the resources filed into them
+    pub view: FolderView,
+    // test data loaded into new resource tables, found next to the executable when unset
+    pub images_dir: Option<PathBuf>,
+    // where blobs, docs and indexes are kept
+    pub storage: StorageMode,
+    // roles granted by the access list doc, unrestricted while it is empty
+    pub acl: Acl,
+    // name of this peer in the node table, the short node id when unset
+    pub display_name: Option<String>,
+    // store dimensions, capture time and camera of images added to resource tables
+    pub extract_image_meta: bool,
+    // the one doc holding every table in single-doc mode, opened by the first table
+    pub shared_doc: Option<Arc<OnceCell<Doc>>>,
+    // size and overflow policy of the application event channel of every table
+    pub events: EventChannelConfig,
+    // how often every doc is resynced with its previous peers, zero to never resync
+    pub anti_entropy: Duration,
 }
 
+/// Health of a node, see [`IrohNet::status`]
+#[derive(Debug, Clone)]
+pub struct NetStatus {
+    pub node_id: PublicKey,
+    // `None` while no relay is connected or relays are disabled
+    pub home_relay: Option<RelayUrl>,
+    // local sockets the endpoint listens on
+    pub bound_addrs: Vec<SocketAddr>,
+    // remotes with an open direct, relayed or mixed path
+    pub connections: usize,
+    // size of the complete blobs in the local store
+    pub blob_store_size: u64,
+    // largest size before blobs are evicted, 0 for no limit
+    pub blob_store_quota: u64,
+    pub docs: Vec<DocStatus>,
+}
+
+/// Sync state of one table in [`NetStatus`]
+#[derive(Debug, Clone)]
+pub struct DocStatus {
+    pub table: String,
+    pub namespace: NamespaceId,
+    // live sync with peers is running
+    pub syncing: bool,
+    // peers the doc remembers to sync with
+    pub sync_peers: usize,
+    // peers that are currently neighbors for the table
+    pub neighbors: usize,
+    // end of the most recent sync run with any peer
+    pub last_sync: Option<SystemTime>,
+    // error of the most recent sync run, if it failed
+    pub last_error: Option<String>,
+}
+
+impl IrohNet {
+    /// Collect the health of the endpoint, the blob store and every registered table
+    pub async fn status(&self) -> anyhow::Result<NetStatus> {
+        let endpoint = self.router.endpoint();
+        let peers = self.peers.list(endpoint).await;
+
+        let mut blob_store_size = 0;
+        for hash in self.blobs_store.blobs().list().hashes().await? {
+            if let BlobStatus::Complete { size } = self.blobs_store.blobs().status(hash).await? {
+                blob_store_size += size;
+            }
+        }
+
+        let mut docs = Vec::new();
+        for (table, doc) in self.control.tables().await {
+            let state = doc.status().await?;
+            let sync_peers = doc.get_sync_peers().await?.map_or(0, |p| p.len());
+            let runs = peers.iter().filter_map(|p| p.tables.get(&table));
+            let neighbors = runs.clone().filter(|s| s.connected).count();
+            let latest = runs
+                .filter(|s| s.last_sync.is_some())
+                .max_by_key(|s| s.last_sync);
+            docs.push(DocStatus {
+                namespace: doc.id(),
+                syncing: state.sync,
+                sync_peers,
+                neighbors,
+                last_sync: latest.and_then(|s| s.last_sync),
+                last_error: latest.and_then(|s| s.last_error.clone()),
+                table,
+            });
+        }
+
+        Ok(NetStatus {
+            node_id: endpoint.id(),
+            home_relay: endpoint.addr().relay_urls().next().cloned(),
+            bound_addrs: endpoint.bound_sockets(),
+            connections: open_connections(endpoint),
+            blob_store_size,
+            blob_store_quota: self.quota.max_bytes(),
+            docs,
+        })
+    }
+
+    /// Replace the downloader with one fetching at most `concurrency` blobs at once
+    ///
+    /// Starts a new [`IrohNet::scheduler`], call it before any table is subscribed
+    pub fn set_download_concurrency(&mut self, concurrency: usize) {
+        let limiter = self.downloader.limiter.clone();
+        self.downloader =
+            BlobDownloader::new(&self.blobs_store, self.router.endpoint(), concurrency);
+        self.downloader.limiter = limiter;
+        self.scheduler = DownloadScheduler::new(self.downloader.clone());
+    }
+
+    /// Directory of the persistent storage, `None` for an in-memory node
+    pub fn storage_path(&self) -> Option<&Path> {
+        match &self.storage {
+            StorageMode::Persistent(path) => Some(path),
+            StorageMode::Memory => None,
+        }
+    }
+
+    /// Address information put into tickets, direct addresses only without relays
+    pub fn addr_info(&self) -> AddrInfoOptions {
+        addr_info(self.lan_only)
+    }
+
+    /// Directory of the test data loaded into new resource tables
+    ///
+    /// The configured [`IrohNet::images_dir`] if set, otherwise [`get_images_directory`] guesses
+    pub fn images_directory(&self) -> anyhow::Result<PathBuf> {
+        match &self.images_dir {
+            Some(dir) => {
+                anyhow::ensure!(dir.is_dir(), "Images directory {:?} does not exist", dir);
+                Ok(dir.clone())
+            }
+            None => get_images_directory(),
+        }
+    }
+
+    /// Keep all tables in one doc under key prefixes, so a single ticket joins them all
+    ///
+    /// Call it before any table is opened, see [`crate::iroh_table_doc`]
+    pub fn enable_single_doc(&mut self) {
+        self.shared_doc = Some(Arc::new(OnceCell::new()));
+    }
+
+    /// Whether the tables share one doc, see [`IrohNet::enable_single_doc`]
+    pub fn single_doc(&self) -> bool {
+        self.shared_doc.is_some()
+    }
+
+    /// Limit blob downloads to `bytes_per_sec`, 0 removes the limit
+    ///
+    /// Only transfers of the [`BlobDownloader`] are paced, i.e. the placement blobs of resources
+    /// and content queued by the retry and anti-entropy tasks. The docs engine downloads the
+    /// content of doc entries on its own, those are small records and not limited
+    pub fn set_download_limit(&self, bytes_per_sec: u64) {
+        self.downloader.limiter.set_limit(bytes_per_sec);
+    }
+
+    /// Limit blobs served to other peers to `bytes_per_sec`, 0 removes the limit
+    ///
+    /// Every chunk the blobs protocol sends is charged, the content of doc entries as well,
+    /// only the set reconciliation of the docs engine is not limited
+    pub fn set_upload_limit(&self, bytes_per_sec: u64) {
+        self.upload_limiter.set_limit(bytes_per_sec);
+    }
+}
+
+/// Address information put into tickets, see [`IrohNet::addr_info`]
+pub fn addr_info(lan_only: bool) -> AddrInfoOptions {
+    if lan_only {
+        AddrInfoOptions::Addresses
+    } else {
+        AddrInfoOptions::RelayAndAddresses
+    }
+}
+
+/// Parse the addressing mode of a ticket: `id`, `relay`, `direct` or `both`
+pub fn parse_addr_info(s: &str) -> anyhow::Result<AddrInfoOptions> {
+    match s {
+        "id" => Ok(AddrInfoOptions::Id),
+        "relay" => Ok(AddrInfoOptions::Relay),
+        "direct" => Ok(AddrInfoOptions::Addresses),
+        "both" => Ok(AddrInfoOptions::RelayAndAddresses),
+        _ => Err(anyhow::anyhow!(
+            "Unknown addressing mode '{}', expected id, relay, direct or both",
+            s
+        )),
+    }
+}
+
+/// Where an [`IrohNet`] keeps blobs, docs and its indexes
+#[derive(Debug, Clone, PartialEq)]
+pub enum StorageMode {
+    // everything below this directory, kept across restarts
+    Persistent(PathBuf),
+    // nothing touches the filesystem, all data is lost on shutdown
+    Memory,
+}
+
+/// Configures and starts an [`IrohNet`]
+///
+/// ```ignore
+/// let iroh = IrohNetBuilder::new(secret_key)
+///     .storage(StorageMode::Memory)
+///     .relay(RelayChoice::Disabled)
+///     .spawn()
+///     .await?;
+/// ```
+pub struct IrohNetBuilder {
+    secret_key: iroh::SecretKey,
+    storage: StorageMode,
+    relay: RelayChoice,
+    // look up peers by node id through number0's dns servers
+    discovery_n0: bool,
+    // find peers on the local network, always on when relays are disabled
+    discovery_local_network: bool,
+    bind_addr_v4: Option<SocketAddrV4>,
+    bind_addr_v6: Option<SocketAddrV6>,
+    // accept incoming gossip connections
+    gossip: bool,
+    // extra protocols served next to the built-in ones
+    protocols: Vec<(Vec<u8>, Box<dyn DynProtocolHandler>)>,
+}
+
+impl IrohNetBuilder {
+    /// Persistent storage in the working directory, default relays, gossip on
+    pub fn new(secret_key: iroh::SecretKey) -> Self {
+        IrohNetBuilder {
+            secret_key,
+            storage: StorageMode::Persistent(PathBuf::from(".")),
+            relay: RelayChoice::Default,
+            discovery_n0: false,
+            discovery_local_network: false,
+            bind_addr_v4: None,
+            bind_addr_v6: None,
+            gossip: true,
+            protocols: Vec::new(),
+        }
+    }
+
+    pub fn storage(mut self, storage: StorageMode) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    /// Keep all data below `path`
+    pub fn persistent(self, path: impl Into<PathBuf>) -> Self {
+        self.storage(StorageMode::Persistent(path.into()))
+    }
+
+    pub fn relay(mut self, relay: RelayChoice) -> Self {
+        self.relay = relay;
+        self
+    }
+
+    pub fn discovery_n0(mut self, enabled: bool) -> Self {
+        self.discovery_n0 = enabled;
+        self
+    }
+
+    pub fn discovery_local_network(mut self, enabled: bool) -> Self {
+        self.discovery_local_network = enabled;
+        self
+    }
+
+    /// Listen on `addr` instead of a random port on all interfaces
+    pub fn bind_addr(mut self, addr: SocketAddr) -> Self {
+        match addr {
+            SocketAddr::V4(addr) => self.bind_addr_v4 = Some(addr),
+            SocketAddr::V6(addr) => self.bind_addr_v6 = Some(addr),
+        }
+        self
+    }
+
+    /// Whether incoming gossip connections are accepted
+    ///
+    /// The docs engine still gossips over connections it opens itself, a node without gossip
+    /// only learns about remote changes when it syncs with a peer
+    pub fn gossip(mut self, enabled: bool) -> Self {
+        self.gossip = enabled;
+        self
+    }
+
+    /// Serve `handler` for connections with `alpn`, next to the built-in protocols
+    pub fn accept(
+        mut self,
+        alpn: impl AsRef<[u8]>,
+        handler: impl Into<Box<dyn DynProtocolHandler>>,
+    ) -> Self {
+        self.protocols
+            .push((alpn.as_ref().to_vec(), handler.into()));
+        self
+    }
+
+    /// Bind the endpoint, open the stores and start serving all protocols
+    pub async fn spawn(self) -> anyhow::Result<IrohNet> {
+        let lan_only = self.relay == RelayChoice::Disabled;
+        // create endpoint
+        let mut builder = iroh::Endpoint::builder()
+            .secret_key(self.secret_key)
+            .relay_mode(self.relay.relay_mode());
+        if self.discovery_n0 {
+            builder = builder.discovery_n0();
+        }
+        if self.discovery_local_network || lan_only {
+            // without relays peers have to find each other on the local network
+            builder = builder.discovery_local_network();
+        }
+        if let Some(addr) = self.bind_addr_v4 {
+            builder = builder.bind_addr_v4(addr);
+        }
+        if let Some(addr) = self.bind_addr_v6 {
+            builder = builder.bind_addr_v6(addr);
+        }
+        let endpoint = builder.bind().await?;
+
+        // // ensure relay is initialized
+        // endpoint.home_relay().initialized().await;
+
+        // add iroh gossip
+        let gossip = iroh_gossip::net::Gossip::builder().spawn(endpoint.clone());
+
+        // add iroh blobs and docs, with the indexes next to them
+        let (store, docs, search, sql_index, quota) = match &self.storage {
+            StorageMode::Persistent(root) => {
+                let store = Store::from(FsStore::load(root).await?);
+                let docs = iroh_docs::protocol::Docs::persistent(root.to_owned())
+                    .spawn(endpoint.clone(), store.clone(), gossip.clone())
+                    .await?;
+                let search = SearchIndex::open(root.join(SEARCH_INDEX_FILE)).await?;
+                let sql_index = SqlIndex::open(&root.join(SQL_INDEX_FILE))?;
+                let quota = StorageQuota::open(root.join(QUOTA_FILE)).await?;
+                (store, docs, search, sql_index, quota)
+            }
+            StorageMode::Memory => {
+                let store = Store::from(MemStore::new());
+                let docs = iroh_docs::protocol::Docs::memory()
+                    .spawn(endpoint.clone(), store.clone(), gossip.clone())
+                    .await?;
+                (
+                    store,
+                    docs,
+                    SearchIndex::memory(),
+                    SqlIndex::memory()?,
+                    StorageQuota::default(),
+                )
+            }
+        };
+        let upload_limiter = Arc::new(RateLimiter::default());
+        let blobs =
+            iroh_blobs::BlobsProtocol::new(&store, Some(upload_events(upload_limiter.clone())));
+
+        let acl = Acl::default();
+        let admin = AdminProtocol::new(acl.clone());
+        let control = ControlProtocol::new(lan_only, acl.clone(), admin.clone());
+
+        // build the protocol router
+        let mut builder = iroh::protocol::Router::builder(endpoint.clone());
+        if self.gossip {
+            builder = builder.accept(iroh_gossip::ALPN, Arc::new(gossip.clone()));
+        }
+        builder = builder
+            .accept(iroh_blobs::ALPN, blobs)
+            .accept(iroh_docs::ALPN, docs.clone())
+            .accept(
+                REPLICATION_ALPN,
+                ReplicationProtocol::new(docs.clone(), store.clone(), acl.clone(), admin.clone()),
+            )
+            .accept(CONTROL_ALPN, control.clone())
+            .accept(ADMIN_ALPN, admin.clone());
+        for (alpn, handler) in self.protocols {
+            builder = builder.accept(alpn, handler);
+        }
+
+        let router = builder.spawn();
+        let downloader = BlobDownloader::new(&store, &endpoint, DEFAULT_DOWNLOAD_CONCURRENCY);
+
+        let iroh_net = IrohNet {
+            router,
+            gossip,
+            blobs_store: store,
+            docs,
+            placement: PlacementPolicy::default(),
+            scheduler: DownloadScheduler::new(downloader.clone()),
+            downloader,
+            upload_limiter,
+            quota,
+            cipher: None,
+            peers: PeerRegistry::default(),
+            lan_only,
+            control,
+            admin,
+            search,
+            sql_index,
+            view: FolderView::default(),
+            images_dir: None,
+            storage: self.storage,
+            acl,
+            display_name: None,
+            extract_image_meta: false,
+            shared_doc: None,
+            events: EventChannelConfig::default(),
+            anti_entropy: DEFAULT_ANTI_ENTROPY_INTERVAL,
+        };
+
+        Ok(iroh_net)
+    }
+}
+
+/// Start a node storing its data below `iroh_db_path`, see [`IrohNetBuilder`] for more options
 pub async fn start_server(
     secret_key: iroh::SecretKey,
     iroh_db_path: String,
+    relay: &RelayChoice,
 ) -> anyhow::Result<IrohNet> {
-    let root = PathBuf::from(iroh_db_path);
-    // create endpoint
-    let endpoint = iroh::Endpoint::builder()
-        .secret_key(secret_key)
-        .relay_mode(RelayMode::Custom(default_relay_map()))
-        .bind()
-        .await?;
-
-    // // ensure relay is initialized
-    // endpoint.home_relay().initialized().await;
-
-    // add iroh gossip
-    let gossip = iroh_gossip::net::Gossip::builder().spawn(endpoint.clone());
-
-    // add iroh blobs
-    let store = FsStore::load(&root).await?;
-
-    let blobs = iroh_blobs::BlobsProtocol::new(&store, None);
-
-    // add iroh docs
-    let docs = iroh_docs::protocol::Docs::persistent(root.to_owned())
-        .spawn(endpoint.clone(), (*blobs).clone(), gossip.clone())
-        .await?;
-
-    // build the protocol router
-    let builder = iroh::protocol::Router::builder(endpoint.clone())
-        .accept(iroh_gossip::ALPN, Arc::new(gossip.clone()))
-        .accept(iroh_blobs::ALPN, blobs)
-        .accept(iroh_docs::ALPN, docs.clone());
-
-    let router = builder.spawn();
-
-    let iroh_net = IrohNet {
-        router,
-        gossip,
-        blobs_store: store,
-        docs,
-    };
-
-    Ok(iroh_net)
+    IrohNetBuilder::new(secret_key)
+        .persistent(iroh_db_path)
+        .relay(relay.clone())
+        .spawn()
+        .await
 }
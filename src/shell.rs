@@ -0,0 +1,250 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::Result;
+use rustyline::{
+    Context, Editor, Helper, Highlighter, Hinter, Validator, completion::Completer,
+    error::ReadlineError, history::DefaultHistory,
+};
+use tokio::sync::mpsc;
+
+// file of the command history inside the storage path
+pub const HISTORY_FILE: &str = "history.txt";
+
+// command names offered by tab completion
+const COMMANDS: &[&str] = &[
+    "help",
+    "quit",
+    "exit",
+    "status",
+    "wait-ready",
+    "du",
+    "pending",
+    "add",
+    "add_folder",
+    "mkdir",
+    "mvdir",
+    "tree",
+    "mv",
+    "ls-folder",
+    "ls",
+    "get",
+    "get_folder",
+    "get_page",
+    "sorted",
+    "meta",
+    "search",
+    "history",
+    "conflicts",
+    "undo",
+    "rm",
+    "trash",
+    "restore",
+    "migrate",
+    "replication",
+    "share-folder",
+    "pin",
+    "unpin",
+    "pins",
+    "bundle",
+    "unbundle",
+    "watch",
+    "unwatch",
+    "sync",
+    "serve-files",
+    "serve-webdav",
+    "unserve",
+    "mount",
+    "unmount",
+    "import",
+    "export",
+    "create-collection",
+    "join-collection",
+    "join-announced",
+    "collections",
+    "share-collection",
+    "leave",
+    "copy-doc",
+    "share",
+    "ticket",
+    "peers",
+    "conn-info",
+    "filter",
+    "filters",
+    "keys",
+    "limit",
+    "pause",
+    "resume",
+    "paused",
+    "subscriptions",
+    "unsubscribe",
+    "resubscribe",
+    "control",
+    "author",
+    "acl",
+    "log",
+    "nodes",
+];
+
+/// A line read by the [`Shell`]
+#[derive(Debug)]
+pub enum ShellInput {
+    Line(String),
+    // Ctrl+C while editing
+    Interrupted,
+    // input closed, e.g. Ctrl+D
+    Eof,
+    Error(String),
+}
+
+#[derive(Helper, Hinter, Highlighter, Validator)]
+struct ShellHelper {
+    // table names and entity ids completed after the command name
+    candidates: Arc<RwLock<Vec<String>>>,
+}
+
+impl Completer for ShellHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos].rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let word = &line[start..pos];
+        let matches = if start == 0 {
+            COMMANDS
+                .iter()
+                .filter(|c| c.starts_with(word))
+                .map(|c| c.to_string())
+                .collect()
+        } else {
+            let candidates = self.candidates.read().unwrap_or_else(|e| e.into_inner());
+            candidates
+                .iter()
+                .filter(|c| c.starts_with(word))
+                .cloned()
+                .collect()
+        };
+        Ok((start, matches))
+    }
+}
+
+/// Line editor with history and tab completion for the interactive loop
+///
+/// The editor blocks, so it runs on its own thread and only reads a line when asked to,
+/// which keeps the prompt behind the output of the previous command
+pub struct Shell {
+    prompts: std::sync::mpsc::Sender<String>,
+    lines: mpsc::Receiver<ShellInput>,
+    candidates: Arc<RwLock<Vec<String>>>,
+    // a prompt was sent and its line not received yet
+    pending: bool,
+}
+
+impl Shell {
+    /// Start the editor, loading and appending to the history stored at `history`
+    ///
+    /// Without a path the history only lasts as long as the shell
+    pub fn spawn(history: Option<PathBuf>) -> Result<Self> {
+        let candidates = Arc::new(RwLock::new(Vec::new()));
+        let mut editor: Editor<ShellHelper, DefaultHistory> = Editor::new()?;
+        editor.set_helper(Some(ShellHelper {
+            candidates: candidates.clone(),
+        }));
+        if let Some(history) = &history {
+            // there is no history on the first start
+            let _ = editor.load_history(history);
+        }
+
+        let (prompts, prompt_rx) = std::sync::mpsc::channel::<String>();
+        let (line_tx, lines) = mpsc::channel(1);
+        std::thread::spawn(move || {
+            while let Ok(prompt) = prompt_rx.recv() {
+                let input = match editor.readline(&prompt) {
+                    Ok(line) => {
+                        if !line.trim().is_empty() {
+                            let _ = editor.add_history_entry(line.as_str());
+                            if let Some(history) = &history {
+                                let _ = editor.save_history(history);
+                            }
+                        }
+                        ShellInput::Line(line)
+                    }
+                    Err(ReadlineError::Interrupted) => ShellInput::Interrupted,
+                    Err(ReadlineError::Eof) => ShellInput::Eof,
+                    Err(e) => ShellInput::Error(e.to_string()),
+                };
+                if line_tx.blocking_send(input).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Shell {
+            prompts,
+            lines,
+            candidates,
+            pending: false,
+        })
+    }
+
+    /// Read the next line, showing `prompt`
+    ///
+    /// Cancel safe, a line requested by a dropped call is returned by the next one
+    pub async fn read_line(&mut self, prompt: &str) -> ShellInput {
+        if !self.pending {
+            if self.prompts.send(prompt.to_string()).is_err() {
+                return ShellInput::Eof;
+            }
+            self.pending = true;
+        }
+        let input = self.lines.recv().await.unwrap_or(ShellInput::Eof);
+        self.pending = false;
+        input
+    }
+
+    /// Replace the words completed after the command name
+    pub fn set_candidates(&self, candidates: Vec<String>) {
+        *self.candidates.write().unwrap_or_else(|e| e.into_inner()) = candidates;
+    }
+}
+
+/// Split a command line into words, text in single or double quotes stays one word
+pub fn split_args(line: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    // a word was started, possibly by an empty pair of quotes
+    let mut in_word = false;
+    let mut quote = None;
+    for c in line.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if let Some(q) = quote {
+        return Err(anyhow::anyhow!("Missing closing {}", q));
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
@@ -0,0 +1,59 @@
+use anyhow::Result;
+
+/// Signals that stop the interactive loop
+///
+/// SIGINT and SIGTERM on Unix, Ctrl+C, Ctrl+Break and closing the console window on Windows
+pub struct ShutdownSignal {
+    #[cfg(unix)]
+    interrupt: tokio::signal::unix::Signal,
+    #[cfg(unix)]
+    terminate: tokio::signal::unix::Signal,
+    #[cfg(windows)]
+    ctrl_c: tokio::signal::windows::CtrlC,
+    #[cfg(windows)]
+    ctrl_break: tokio::signal::windows::CtrlBreak,
+    #[cfg(windows)]
+    ctrl_close: tokio::signal::windows::CtrlClose,
+}
+
+impl ShutdownSignal {
+    /// Install the handlers, signals arriving before the first [`ShutdownSignal::recv`] are kept
+    #[cfg(unix)]
+    pub fn install() -> Result<Self> {
+        use tokio::signal::unix::{SignalKind, signal};
+        Ok(ShutdownSignal {
+            interrupt: signal(SignalKind::interrupt())?,
+            terminate: signal(SignalKind::terminate())?,
+        })
+    }
+
+    /// Install the handlers, signals arriving before the first [`ShutdownSignal::recv`] are kept
+    #[cfg(windows)]
+    pub fn install() -> Result<Self> {
+        use tokio::signal::windows::{ctrl_break, ctrl_c, ctrl_close};
+        Ok(ShutdownSignal {
+            ctrl_c: ctrl_c()?,
+            ctrl_break: ctrl_break()?,
+            ctrl_close: ctrl_close()?,
+        })
+    }
+
+    /// Wait for the next signal and return its name
+    #[cfg(unix)]
+    pub async fn recv(&mut self) -> &'static str {
+        tokio::select! {
+            _ = self.interrupt.recv() => "SIGINT (Ctrl+C)",
+            _ = self.terminate.recv() => "SIGTERM",
+        }
+    }
+
+    /// Wait for the next signal and return its name
+    #[cfg(windows)]
+    pub async fn recv(&mut self) -> &'static str {
+        tokio::select! {
+            _ = self.ctrl_c.recv() => "Ctrl+C",
+            _ = self.ctrl_break.recv() => "Ctrl+Break",
+            _ = self.ctrl_close.recv() => "console close",
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, ensure};
+use iroh_blobs::api::blobs::BlobStatus;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    crypto,
+    rejoin::{SavedTables, save_tables},
+    server::IrohNet,
+    usage::DOCS_STORE_FILE,
+};
+
+// layout of the archive, bump when it changes
+//
+// version 1 held the latest entries as JSON, restoring them rewrote every entry with a fresh
+// timestamp so the snapshot overwrote newer writes of the peers
+const SNAPSHOT_VERSION: u32 = 2;
+// files inside the archive
+const INDEX_FILE: &str = "snapshot.json";
+// the docs store of the node, encrypted with the backup passphrase
+const SEALED_DOCS_FILE: &str = "docs.sealed";
+const BLOBS_DIR: &str = "blobs";
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// Index of a snapshot archive
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotIndex {
+    pub version: u32,
+    // tables of the node, with their sync filters and key schemes
+    pub saved: SavedTables,
+    pub blobs: usize,
+}
+
+/// Write the docs store and every complete blob of the store to a zstd compressed tar
+/// archive at `out`, shutting `node` down on the way
+///
+/// The docs store holds the signed entries of every doc with their authors and timestamps,
+/// as well as the namespace and author secrets, so it is encrypted with `passphrase`. The
+/// docs engine writes to the store while it runs, so the blobs are exported first and the
+/// store is only copied once the node is shut down. Content placed under an external
+/// storage root is not part of the blob store and not included
+pub async fn backup(
+    node: IrohNet,
+    saved: &SavedTables,
+    out: &Path,
+    passphrase: &str,
+) -> Result<SnapshotIndex> {
+    let staging = staging_dir(out);
+    let result = async {
+        tokio::fs::create_dir_all(staging.join(BLOBS_DIR)).await?;
+        let root = node
+            .storage_path()
+            .ok_or_else(|| anyhow::anyhow!("An in-memory node has no docs store to back up"))?
+            .to_path_buf();
+        let blobs = stage_blobs(&node, &staging).await;
+        node.router.shutdown().await?;
+        let index = SnapshotIndex {
+            version: SNAPSHOT_VERSION,
+            saved: saved.clone(),
+            blobs: blobs?,
+        };
+        stage_docs(&root, &staging, passphrase, &index).await?;
+
+        let tar = out.with_extension("tar.tmp");
+        let file = tokio::fs::File::create(&tar)
+            .await
+            .with_context(|| format!("Failed to create {:?}", tar))?;
+        let mut builder = tokio_tar::Builder::new(file);
+        builder.append_dir_all(".", &staging).await?;
+        builder.into_inner().await?;
+        let target = out.to_path_buf();
+        tokio::task::spawn_blocking(move || compress_file(&tar, &target)).await??;
+        anyhow::Ok(index)
+    }
+    .await;
+    let _ = tokio::fs::remove_dir_all(&staging).await;
+    result
+}
+
+// export every complete blob into `staging`, returns how many were exported
+async fn stage_blobs(node: &IrohNet, staging: &Path) -> Result<usize> {
+    let blobs = node.blobs_store.blobs();
+    let mut count = 0;
+    for hash in blobs.list().hashes().await? {
+        if !matches!(blobs.status(hash).await?, BlobStatus::Complete { .. }) {
+            continue;
+        }
+        let target = staging.join(BLOBS_DIR).join(hash.to_hex());
+        blobs
+            .export(hash, &target)
+            .await
+            .with_context(|| format!("Failed to export blob {}", hash.fmt_short()))?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+// seal the docs store under the storage directory `root` and write it with `index` into
+// `staging`, the node must be shut down
+async fn stage_docs(
+    root: &Path,
+    staging: &Path,
+    passphrase: &str,
+    index: &SnapshotIndex,
+) -> Result<()> {
+    let docs = tokio::fs::read(root.join(DOCS_STORE_FILE))
+        .await
+        .context("Failed to read the docs store")?;
+    let sealed = crypto::encrypt_with_passphrase(passphrase, &docs)?;
+    tokio::fs::write(staging.join(SEALED_DOCS_FILE), sealed).await?;
+    tokio::fs::write(staging.join(INDEX_FILE), serde_json::to_vec_pretty(index)?).await?;
+    info!(
+        tables = index.saved.tables.len(),
+        blobs = index.blobs,
+        "staged snapshot"
+    );
+    Ok(())
+}
+
+/// A snapshot archive unpacked next to it, removed again when dropped
+///
+/// Restoring takes two steps around the start of the node: [`Snapshot::restore_docs`] puts
+/// the docs store in place before the node opens it, [`Snapshot::restore_blobs`] adds the
+/// blobs to the store of the running node
+pub struct Snapshot {
+    pub index: SnapshotIndex,
+    staging: PathBuf,
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.staging);
+    }
+}
+
+/// Unpack a snapshot written by [`backup`]
+pub async fn unpack(input: &Path) -> Result<Snapshot> {
+    let staging = staging_dir(input);
+    tokio::fs::create_dir_all(&staging).await?;
+    match read_archive(input, &staging).await {
+        Ok(index) => Ok(Snapshot { index, staging }),
+        Err(e) => {
+            let _ = tokio::fs::remove_dir_all(&staging).await;
+            Err(e)
+        }
+    }
+}
+
+// extract the archive at `input` into `staging` and read its index
+async fn read_archive(input: &Path, staging: &Path) -> Result<SnapshotIndex> {
+    let tar = staging.with_extension("tar.tmp");
+    let result = async {
+        let (source, target) = (input.to_path_buf(), tar.clone());
+        tokio::task::spawn_blocking(move || decompress_file(&source, &target)).await??;
+        let file = tokio::fs::File::open(&tar).await?;
+        tokio_tar::Archive::new(file).unpack(staging).await?;
+        anyhow::Ok(())
+    }
+    .await;
+    let _ = tokio::fs::remove_file(&tar).await;
+    result?;
+
+    let bytes = tokio::fs::read(staging.join(INDEX_FILE))
+        .await
+        .context("Not a snapshot archive")?;
+    let index: SnapshotIndex = serde_json::from_slice(&bytes)
+        .context("Snapshot written by an older build, restore it with that build")?;
+    ensure!(
+        index.version == SNAPSHOT_VERSION,
+        "Snapshot version {} is not supported by this build",
+        index.version
+    );
+    Ok(index)
+}
+
+impl Snapshot {
+    /// Put the docs store and the saved tables of the snapshot into the storage directory
+    /// `root` of a node that is not running
+    ///
+    /// The entries keep their authors and timestamps, so writes the peers made after the
+    /// backup still win when the restored node syncs with them
+    pub async fn restore_docs(&self, root: &Path, passphrase: &str) -> Result<()> {
+        let target = root.join(DOCS_STORE_FILE);
+        ensure!(
+            !tokio::fs::try_exists(&target).await?,
+            "{:?} already holds docs, restore into a fresh storage directory",
+            root
+        );
+        let sealed = tokio::fs::read(self.staging.join(SEALED_DOCS_FILE)).await?;
+        let docs = crypto::decrypt_with_passphrase(passphrase, &sealed)
+            .context("Wrong passphrase for the snapshot")?;
+        tokio::fs::create_dir_all(root).await?;
+        tokio::fs::write(&target, docs)
+            .await
+            .with_context(|| format!("Failed to write {:?}", target))?;
+        save_tables(root, &self.index.saved).await?;
+        info!(tables = self.index.saved.tables.len(), "restored docs");
+        Ok(())
+    }
+
+    /// Add the blobs of the snapshot to the store of `node`, returns how many were added
+    pub async fn restore_blobs(&self, node: &IrohNet) -> Result<usize> {
+        let store = &node.blobs_store;
+        let mut blobs = tokio::fs::read_dir(self.staging.join(BLOBS_DIR)).await?;
+        let mut count = 0;
+        while let Some(blob) = blobs.next_entry().await? {
+            store.add_path(blob.path()).await?;
+            count += 1;
+        }
+        info!(blobs = count, "restored blobs");
+        Ok(count)
+    }
+}
+
+// next to `path`, so the staged blobs stay on the same disk as the archive
+fn staging_dir(path: &Path) -> PathBuf {
+    path.with_extension("staging")
+}
+
+fn compress_file(source: &Path, target: &Path) -> Result<()> {
+    let input = std::fs::File::open(source)?;
+    let output =
+        std::fs::File::create(target).with_context(|| format!("Failed to create {:?}", target))?;
+    zstd::stream::copy_encode(input, output, COMPRESSION_LEVEL)?;
+    std::fs::remove_file(source)?;
+    Ok(())
+}
+
+fn decompress_file(source: &Path, target: &Path) -> Result<()> {
+    let input =
+        std::fs::File::open(source).with_context(|| format!("Failed to open {:?}", source))?;
+    let output = std::fs::File::create(target)?;
+    zstd::stream::copy_decode(input, output).context("Not a zstd compressed snapshot")?;
+    Ok(())
+}
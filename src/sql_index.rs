@@ -0,0 +1,267 @@
+use std::{
+    fmt::Display,
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use iroh_docs::store::SortDirection;
+use rusqlite::{
+    Connection, Row, params, params_from_iter,
+    types::{Type, Value},
+};
+
+use crate::indexer::{EntityIndex, EntityMeta};
+use crate::store::SortField;
+
+// file of the index inside the storage path
+pub const SQL_INDEX_FILE: &str = "metadata.sqlite";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS entities (
+    tbl TEXT NOT NULL,
+    key TEXT NOT NULL,
+    name TEXT,
+    text TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    folder_id TEXT,
+    author TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    created_at INTEGER,
+    hash TEXT NOT NULL,
+    captured_at INTEGER,
+    PRIMARY KEY (tbl, key)
+);
+CREATE INDEX IF NOT EXISTS entities_name ON entities (tbl, name);
+CREATE INDEX IF NOT EXISTS entities_size ON entities (tbl, size);
+CREATE INDEX IF NOT EXISTS entities_time ON entities (tbl, timestamp);
+CREATE INDEX IF NOT EXISTS entities_folder ON entities (tbl, folder_id);
+";
+
+const COLUMNS: &str =
+    "key, name, text, size, folder_id, author, timestamp, created_at, hash, captured_at";
+
+/// Conditions of a [`SqlIndex::query`], `None` means no restriction
+#[derive(Debug, Clone)]
+pub struct MetaQuery {
+    pub table: String,
+    // only entities filed into this folder
+    pub folder_id: Option<String>,
+    // only entities whose name contains this text, case insensitive
+    pub name_contains: Option<String>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub sort: SortField,
+    pub direction: SortDirection,
+    pub offset: u64,
+    pub limit: Option<u64>,
+}
+
+impl MetaQuery {
+    /// All entities of `table` in key order
+    pub fn table(table: &str) -> Self {
+        MetaQuery {
+            table: table.to_string(),
+            folder_id: None,
+            name_contains: None,
+            min_size: None,
+            max_size: None,
+            sort: SortField::Key,
+            direction: SortDirection::Asc,
+            offset: 0,
+            limit: None,
+        }
+    }
+}
+
+/// Local SQLite mirror of entity metadata, so listings don't decode every entry
+///
+/// Kept up to date by [`spawn_indexer`] from the doc events of every table
+///
+/// [`spawn_indexer`]: crate::indexer::spawn_indexer
+#[derive(Debug, Clone)]
+pub struct SqlIndex {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqlIndex {
+    /// Open or create the index database at `path`
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed to open metadata index {:?}", path))?;
+        conn.execute_batch(SCHEMA)?;
+        migrate(&conn)?;
+        Ok(SqlIndex {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// An empty index that is never written to disk
+    pub fn memory() -> Result<Self> {
+        let conn = Connection::open_in_memory()?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(SqlIndex {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Metadata of the entities matching `query`
+    pub async fn query(&self, query: MetaQuery) -> Result<Vec<EntityMeta>> {
+        let mut sql = format!("SELECT {} FROM entities WHERE tbl = ?", COLUMNS);
+        let mut values = vec![Value::Text(query.table)];
+        if let Some(folder_id) = query.folder_id {
+            sql.push_str(" AND folder_id = ?");
+            values.push(Value::Text(folder_id));
+        }
+        if let Some(name) = query.name_contains {
+            sql.push_str(" AND name LIKE ?");
+            values.push(Value::Text(format!("%{}%", name)));
+        }
+        if let Some(min) = query.min_size {
+            sql.push_str(" AND size >= ?");
+            values.push(Value::Integer(min as i64));
+        }
+        if let Some(max) = query.max_size {
+            sql.push_str(" AND size <= ?");
+            values.push(Value::Integer(max as i64));
+        }
+        let column = match query.sort {
+            SortField::Key => "key",
+            SortField::Name => "name",
+            SortField::Size => "size",
+            SortField::Time => "timestamp",
+            // same fallbacks as the in-memory sort of `IrohCls::search_sorted`
+            SortField::Captured => "COALESCE(captured_at, created_at, timestamp / 1000)",
+        };
+        let direction = match query.direction {
+            SortDirection::Desc => "DESC",
+            _ => "ASC",
+        };
+        sql.push_str(&format!(" ORDER BY {} {}, key", column, direction));
+        // sqlite only accepts an offset after a limit, -1 means no limit
+        sql.push_str(" LIMIT ? OFFSET ?");
+        values.push(Value::Integer(query.limit.map_or(-1, |l| l as i64)));
+        values.push(Value::Integer(query.offset as i64));
+
+        self.run(move |conn| {
+            let mut stmt = conn.prepare(&sql)?;
+            let rows = stmt.query_map(params_from_iter(values), from_row)?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()
+        })
+        .await
+    }
+
+    // run `f` on the connection without blocking the runtime
+    async fn run<R, F>(&self, f: F) -> Result<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<R> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().unwrap_or_else(|e| e.into_inner());
+            f(&mut conn)
+        })
+        .await?;
+        Ok(result?)
+    }
+}
+
+impl EntityIndex for SqlIndex {
+    async fn reindex(&self, table: &str, entities: Vec<EntityMeta>) -> Result<()> {
+        let table = table.to_string();
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute("DELETE FROM entities WHERE tbl = ?", params![table])?;
+            for entity in &entities {
+                upsert(&tx, &table, entity)?;
+            }
+            tx.commit()
+        })
+        .await
+    }
+
+    async fn update(&self, table: &str, key: &str, entity: Option<EntityMeta>) -> Result<()> {
+        let table = table.to_string();
+        let key = key.to_string();
+        self.run(move |conn| {
+            match entity {
+                Some(entity) => upsert(conn, &table, &entity)?,
+                None => {
+                    conn.execute(
+                        "DELETE FROM entities WHERE tbl = ? AND key = ?",
+                        params![table, key],
+                    )?;
+                }
+            }
+            Ok(())
+        })
+        .await
+    }
+}
+
+fn upsert(conn: &Connection, table: &str, entity: &EntityMeta) -> rusqlite::Result<()> {
+    conn.execute(
+        &format!(
+            "INSERT OR REPLACE INTO entities (tbl, {}) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            COLUMNS
+        ),
+        params![
+            table,
+            entity.key,
+            entity.name,
+            entity.text,
+            entity.size as i64,
+            entity.folder_id,
+            entity.author.to_string(),
+            entity.timestamp as i64,
+            entity.created_at.map(|t| t as i64),
+            entity.hash.to_string(),
+            entity.captured_at.map(|t| t as i64),
+        ],
+    )?;
+    Ok(())
+}
+
+fn from_row(row: &Row) -> rusqlite::Result<EntityMeta> {
+    Ok(EntityMeta {
+        key: row.get(0)?,
+        name: row.get(1)?,
+        text: row.get(2)?,
+        size: row.get::<_, i64>(3)? as u64,
+        folder_id: row.get(4)?,
+        author: parse_column(row, 5)?,
+        timestamp: row.get::<_, i64>(6)? as u64,
+        created_at: row.get::<_, Option<i64>>(7)?.map(|t| t as u64),
+        hash: parse_column(row, 8)?,
+        captured_at: row.get::<_, Option<i64>>(9)?.map(|t| t as u64),
+    })
+}
+
+// add the columns introduced after the first schema, the indexer refills them on its first pass
+fn migrate(conn: &Connection) -> rusqlite::Result<()> {
+    let has_captured = conn
+        .prepare("SELECT 1 FROM pragma_table_info('entities') WHERE name = 'captured_at'")?
+        .exists([])?;
+    if !has_captured {
+        conn.execute("ALTER TABLE entities ADD COLUMN captured_at INTEGER", [])?;
+    }
+    Ok(())
+}
+
+// read a column stored as the string form of `T`
+fn parse_column<T>(row: &Row, index: usize) -> rusqlite::Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let value: String = row.get(index)?;
+    value.parse().map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(
+            index,
+            Type::Text,
+            anyhow::anyhow!("{}", e).into(),
+        )
+    })
+}
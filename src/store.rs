@@ -1,30 +1,99 @@
 use anyhow::{Context, Result, ensure};
 use bytes::Bytes;
-use futures::StreamExt;
+use futures::{Stream, StreamExt, TryStreamExt, stream::BoxStream};
+use iroh_blobs::Hash;
 use iroh_docs::Entry;
+use iroh_docs::store::{DownloadPolicy, Query, SortDirection};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 use strum::IntoEnumIterator;
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, RwLock, broadcast, mpsc};
+use tokio_util::task::AbortOnDropHandle;
+use tracing::{Instrument, debug, info, info_span, trace, warn};
 
-use iroh_docs::{AuthorId, DocTicket, api::Doc};
+use iroh_docs::{
+    AuthorId, Capability, ContentStatus, DocTicket, NamespaceId,
+    api::{
+        Doc,
+        protocol::{AddrInfoOptions, ShareMode},
+    },
+    engine::LiveEvent,
+};
 
-use crate::doc_subcribe::EventRemoteSync;
-use crate::get_images_directory;
+use crate::acl::{ACL_TABLE, AclTable, Role, parse_key, spawn_acl_follower};
+use crate::anti_entropy::{SystemDocs, spawn_anti_entropy};
+use crate::audit::{AUDIT_TABLE, AuditLog, existing_keys};
+use crate::codec::{self, Codec};
+use crate::compression;
+use crate::control::{ControlRequest, ControlResponse, send_control};
+use crate::coordinator::{SyncCoordinator, TableReadiness};
+use crate::crypto;
+use crate::doc_subcribe::{AppEvent, EventRemoteSync, SyncProgress};
+use crate::event_channel::{DroppedEvents, EventReceiver, EventSender, event_channel};
+use crate::history::{self, CONFLICT_WINDOW, HistoryEntry};
+use crate::import::{DirImporter, import_into};
+use crate::indexer::{EntityIndex, spawn_indexer};
+use crate::keys::KeyScheme;
+use crate::manifest::{MANIFEST_TABLE, Manifest, ManifestEntry, spawn_manifest_follower};
+use crate::pending::PendingWrites;
+use crate::pin::pinned_hashes;
+use crate::quota::{entry_hashes, spawn_quota_enforcer};
+use crate::rejoin::{SavedTable, SavedTables, load_tables, save_tables};
+use crate::schema::{self, Migrations};
+use crate::subscription::SubscriptionManager;
+use crate::subshare::{
+    CopyReport, FOLDER_PREFIX, Mirror, RESOURCE_PREFIX, SubShare, copy_entries, create_sub_share,
+};
+use crate::supervisor::{EventSenders, PausedTables, previous_peers, spawn_supervisor};
+use crate::sync_filter::{SyncFilter, SyncFilters};
+use crate::tables::Tables;
+use crate::trash::{self, Tombstone};
+use crate::validate::{Validator, Validators};
 use crate::{
-    TableType,
-    model::{folder::Folders, node::Nodes, resource::Resources},
+    TableType, iroh_create_author, iroh_create_doc,
+    model::{
+        folder::{Folder, Folders},
+        node::{Node, Nodes, spawn_heartbeat},
+        resource::{Resource, Resources, spawn_content_fetcher},
+    },
     server::IrohNet,
 };
 
-const MAX_FILE_SIZE: usize = 150 * 1024 * 1024;
+// largest encoded entity, file content above the inline limit is stored outside the entry
+const MAX_ENTRY_SIZE: usize = 150 * 1024 * 1024;
+
+// entry writes of a batch in flight at the same time
+const INSERT_CONCURRENCY: usize = 16;
+
+// progress updates buffered per subscriber before the oldest are dropped
+const PROGRESS_CHANNEL_CAPACITY: usize = 256;
+
+// how long a joining client waits for a peer to hand out its access list
+const ACL_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// how often `wait_ready` checks the placement blobs that are still downloading
+const PLACEMENT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// order of the tables in the ticket string printed by the server
+pub const TICKET_ORDER: [&str; 6] = [
+    "resource",
+    "folder",
+    "node",
+    "resource1",
+    "resource2",
+    "resource3",
+];
 
 pub trait GetProperties {
     // Get document
     fn get_doc(&self) -> &Doc;
+    // prefix of the table's keys inside the doc, empty when the table has the doc to itself
+    fn key_prefix(&self) -> &[u8] {
+        &[]
+    }
 }
 pub trait ToBytes<T>
 where
@@ -32,47 +101,308 @@ where
     Self: Serialize,
 {
     fn from_bytes(bytes: Bytes) -> anyhow::Result<T> {
-        let record = bincode::deserialize(&bytes).context("Invalid json data")?;
-        Ok(record)
+        Ok(Self::from_bytes_versioned(bytes)?.0)
+    }
+    // decode a record together with the schema version it was stored with,
+    // older versions are upgraded through `migrations`
+    fn from_bytes_versioned(bytes: Bytes) -> anyhow::Result<(T, u32)> {
+        let bytes = compression::decompress(bytes)?;
+        let (version, payload) = schema::unwrap(&bytes)?;
+        ensure!(
+            version <= Self::VERSION,
+            "Entry has schema version {} but only versions up to {} are known",
+            version,
+            Self::VERSION
+        );
+        let record = if version == Self::VERSION {
+            codec::decode(payload).with_context(|| {
+                let codec = codec::codec_of(payload)
+                    .map_or("unknown".to_string(), |c| format!("{:?}", c).to_lowercase());
+                format!("Invalid {} payload of schema version {}", codec, version)
+            })?
+        } else {
+            Self::migrations().upgrade(version, payload)?
+        };
+        Ok((record, version))
     }
     fn from_string(str: String) -> anyhow::Result<T> {
         let record = serde_json::from_str(&str).context("Invalid string data")?;
         Ok(record)
     }
     fn as_bytes(&self) -> anyhow::Result<Bytes> {
-        let buf = bincode::serialize(self)?;
-        println!("{}", buf.len());
-        ensure!(buf.len() < MAX_FILE_SIZE, "File size exceeds limit");
-        Ok(buf.into())
+        let buf = schema::wrap(Self::VERSION, Self::codec().encode(self)?);
+        trace!(size = buf.len(), "encoded entity");
+        ensure!(buf.len() < MAX_ENTRY_SIZE, "Entry size exceeds limit");
+        Ok(compression::compress(buf)?.into())
     }
     fn missing_file(id: String) -> T;
+    // schema version new records are written with, bump it when the layout changes
+    // and register a migration for the previous version
+    const VERSION: u32 = 0;
+    fn migrations() -> Migrations<T> {
+        Migrations::new()
+    }
+    // format new records of the table are written in, any format can be read
+    fn codec() -> Codec {
+        Codec::Bincode
+    }
+    // name used by sorted listings
+    fn sort_name(&self) -> Option<&str> {
+        None
+    }
+    // folder holding the entity, used by the metadata index
+    fn folder_id(&self) -> Option<&str> {
+        None
+    }
+    // creation time in milliseconds since the unix epoch, if recorded
+    fn created_at(&self) -> Option<u64> {
+        None
+    }
+    // time the content was captured, e.g. when a photo was taken, if known
+    fn captured_at(&self) -> Option<u64> {
+        None
+    }
+    // text matched by the full-text search, defaults to the sort name
+    fn search_text(&self) -> String {
+        self.sort_name().unwrap_or_default().to_string()
+    }
+    // content size used by sorted listings, defaults to the entry size
+    fn content_size(&self) -> Option<u64> {
+        None
+    }
+}
+
+// ordering of sorted listings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortField {
+    // entry key, sorted by the doc store
+    Key,
+    // entity name
+    Name,
+    // content size
+    Size,
+    // insertion time
+    Time,
+    // capture time of images, the creation time when unknown
+    Captured,
+}
+
+impl std::str::FromStr for SortField {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "key" => Ok(SortField::Key),
+            "name" => Ok(SortField::Name),
+            "size" => Ok(SortField::Size),
+            "time" => Ok(SortField::Time),
+            "captured" => Ok(SortField::Captured),
+            _ => Err(anyhow::anyhow!("Unknown sort field '{}'", s)),
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct IrohCls<Entity> {
     pub node: IrohNet,
     pub doc: Doc,
     pub ticket: Option<DocTicket>,
     pub author: AuthorId,
     pub entity: Option<Entity>,
+    // checks run by `insert` before an entity is written
+    pub validators: Validators<Entity>,
+    // put in front of every key, lets several tables share one doc, see [`table_prefix`]
+    pub prefix: Vec<u8>,
 }
 
 pub struct Pair<T>(IrohCls<T>);
 
+// search conditions, `None` means no restriction
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    // only keys starting with this prefix
+    pub key_prefix: Option<Vec<u8>>,
+    // only entries written by this author
+    pub author: Option<AuthorId>,
+    // minimum content size in bytes
+    pub min_size: Option<u64>,
+    // maximum content size in bytes
+    pub max_size: Option<u64>,
+}
+
+impl Filter {
+    // query inside the keys starting with `table_prefix`
+    fn query(&self, table_prefix: &[u8]) -> Query {
+        let mut prefix = table_prefix.to_vec();
+        prefix.extend(self.key_prefix.iter().flatten());
+        let mut query = Query::single_latest_per_key().key_prefix(prefix);
+        if let Some(author) = self.author {
+            query = query.author(author);
+        }
+        query.build()
+    }
+
+    // the doc store can't filter by content length, so sizes are checked on the entry
+    // metadata while the query streams, before any content is fetched
+    fn matches_size(&self, entry: &Entry) -> bool {
+        let len = entry.content_len();
+        self.min_size.is_none_or(|min| len >= min) && self.max_size.is_none_or(|max| len <= max)
+    }
+}
+
+/// A change of a single key, see [`IrohCls::watch`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyChange {
+    // the key got an entity, written locally or by a remote
+    Inserted { author: AuthorId, timestamp: u64 },
+    // the entity of the key was replaced
+    Updated { author: AuthorId, timestamp: u64 },
+    // the content of the latest remote write has been downloaded
+    ContentReady,
+    // the entity was deleted or moved to the trash
+    Deleted { author: AuthorId, timestamp: u64 },
+}
+
+/// An entity together with the metadata of the entry it was read from
+#[derive(Debug, Clone)]
+pub struct VersionedEntity<T> {
+    pub entity: T,
+    // author that wrote the entry
+    pub author: AuthorId,
+    // time of the write in microseconds since the unix epoch
+    pub timestamp: u64,
+}
+
+impl<T> VersionedEntity<T> {
+    pub fn into_inner(self) -> T {
+        self.entity
+    }
+}
+
+impl<T> std::ops::Deref for VersionedEntity<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.entity
+    }
+}
+
+/// Metadata of a doc entry, read without fetching its content
+#[derive(Debug, Clone)]
+pub struct EntryInfo {
+    pub key: String,
+    // size of the stored entry content
+    pub size: u64,
+    pub hash: Hash,
+    pub author: AuthorId,
+    // time of the write in microseconds since the unix epoch
+    pub timestamp: u64,
+}
+
+impl From<&Entry> for EntryInfo {
+    fn from(entry: &Entry) -> Self {
+        EntryInfo {
+            key: String::from_utf8_lossy(entry.key()).to_string(),
+            size: entry.content_len(),
+            hash: entry.content_hash(),
+            author: entry.author(),
+            timestamp: entry.timestamp(),
+        }
+    }
+}
+
+/// What [`IrohCls::migrate`] did
+#[derive(Debug, Clone, Default)]
+pub struct MigrateReport {
+    pub migrated: usize,
+    // keys of the entries that could not be upgraded or rewritten, and why, they are left as is
+    pub failed: Vec<(String, String)>,
+}
+
 pub trait IrohProperties<Entity>
 where
     Entity: ToBytes<Entity> + Serialize + Clone + for<'a> Deserialize<'a> + Send,
 {
     fn ticket(&self) -> String;
 
-    fn search(&self) -> impl std::future::Future<Output = Result<Vec<Entity>>>;
+    /// Fetch the entity stored under exactly `key`, `None` if there is none or it was deleted
+    fn get_exact(
+        &self,
+        key: impl AsRef<[u8]>,
+    ) -> impl std::future::Future<Output = Result<Option<Entity>>>;
 
+    /// Whether an entity is stored under exactly `key`, see [`trash::is_deleted`]
+    fn exists(&self, key: impl AsRef<[u8]>) -> impl std::future::Future<Output = Result<bool>>;
+
+    /// Number of keys holding an entity, entries moved to the trash are not counted
+    ///
+    /// Like [`IrohProperties::exists`] no entity is decoded
+    fn count(&self) -> impl std::future::Future<Output = Result<usize>>;
+
+    /// Fetch all entities with the author and time of their latest write
+    fn search(&self) -> impl std::future::Future<Output = Result<Vec<VersionedEntity<Entity>>>>;
+
+    /// Fetch all entities together with the author that wrote them
+    #[deprecated(note = "use `search`, its results carry the author and time of the write")]
+    fn search_authored(&self)
+    -> impl std::future::Future<Output = Result<Vec<(AuthorId, Entity)>>>;
+
+    /// All entities from the oldest to the newest
+    ///
+    /// Entries are read in key order, which is creation order for tables keyed with
+    /// [`KeyScheme::Time`], so no entity has to be decoded to order them
+    fn search_ordered_by_creation(
+        &self,
+    ) -> impl std::future::Future<Output = Result<Vec<VersionedEntity<Entity>>>>;
+
+    /// Stream all entities as they are read
+    ///
+    /// An entry that fails to load yields an error without ending the stream
+    fn search_stream(&self) -> impl Stream<Item = Result<VersionedEntity<Entity>>> + '_;
+
+    /// Keys of all entries, read from the doc without fetching any content
+    ///
+    /// Deleted entries are skipped, entries moved to the trash are still listed
+    fn list_keys(&self) -> impl std::future::Future<Output = Result<Vec<String>>>;
+
+    /// Metadata of all entries, read from the doc without fetching any content
+    ///
+    /// Deleted entries are skipped, entries moved to the trash are still listed
+    fn list_entries(&self) -> impl std::future::Future<Output = Result<Vec<EntryInfo>>>;
+
+    /// Fetch at most `limit` entities, skipping the first `offset` keys
+    fn search_page(
+        &self,
+        offset: u64,
+        limit: u64,
+    ) -> impl std::future::Future<Output = Result<Vec<VersionedEntity<Entity>>>>;
+
+    /// Fetch the entities matching `filter`
+    fn search_filtered(
+        &self,
+        filter: Filter,
+    ) -> impl std::future::Future<Output = Result<Vec<VersionedEntity<Entity>>>>;
+
+    /// Fetch all entities ordered by `field`
+    fn search_sorted(
+        &self,
+        field: SortField,
+        direction: SortDirection,
+    ) -> impl std::future::Future<Output = Result<Vec<VersionedEntity<Entity>>>>;
+
+    /// Write an encoded entity as is, without running the validators of [`IrohCls::insert`]
     fn insert_bytes(
         &self,
         key: impl AsRef<[u8]>,
         content: Bytes,
     ) -> impl std::future::Future<Output = Result<()>>;
 
+    /// Write many entries, keeping several writes in flight instead of one round-trip each
+    fn insert_batch(
+        &self,
+        items: Vec<(Vec<u8>, Bytes)>,
+    ) -> impl std::future::Future<Output = Result<()>>;
+
     fn bytes_from_entry(
         &self,
         entry: &Entry,
@@ -91,32 +421,234 @@ where
         }
     }
 
-    async fn insert_bytes(&self, key: impl AsRef<[u8]>, content: Bytes) -> anyhow::Result<()> {
-        self.doc
-            .set_bytes(self.author, key.as_ref().to_vec(), content)
+    async fn get_exact(&self, key: impl AsRef<[u8]>) -> Result<Option<Entity>> {
+        let entry = self
+            .doc
+            .get_one(Query::single_latest_per_key().key_exact(self.doc_key(key)))
             .await?;
-        Ok(())
+        match entry {
+            Some(entry) => self.entity(&entry).await,
+            None => Ok(None),
+        }
     }
 
-    async fn search(&self) -> Result<Vec<Entity>> {
-        let entries = self
+    async fn exists(&self, key: impl AsRef<[u8]>) -> Result<bool> {
+        let entry = self
             .doc
-            .get_many(iroh_docs::store::Query::single_latest_per_key())
+            .get_one(Query::single_latest_per_key().key_exact(self.doc_key(key)))
             .await?;
-        let mut entries = entries.collect::<Vec<Result<Entry>>>().await;
-        let mut entries = entries.iter_mut();
-        let mut entities = Vec::new();
-        while let Some(Ok(entry)) = entries.next() {
-            let entity = self.bytes_from_entry(&entry).await?;
-            entities.push(entity);
+        match entry {
+            Some(entry) => match self.allowed_entry(&entry).await? {
+                Some(entry) => Ok(!trash::is_deleted(&self.node, &entry).await?),
+                None => Ok(false),
+            },
+            None => Ok(false),
         }
-        Ok(entities)
+    }
+
+    async fn count(&self) -> Result<usize> {
+        let mut count = 0;
+        let query = Query::single_latest_per_key().key_prefix(&self.prefix);
+        for entry in self.entries(query).await? {
+            if let Some(entry) = self.allowed_entry(&entry).await?
+                && !trash::is_deleted(&self.node, &entry).await?
+            {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    async fn insert_bytes(&self, key: impl AsRef<[u8]>, content: Bytes) -> anyhow::Result<()> {
+        let key = self.doc_key(key);
+        let content = crypto::seal(
+            self.node.cipher.as_ref(),
+            self.doc.id(),
+            &key,
+            content.to_vec(),
+        )?;
+        self.doc.set_bytes(self.author, key, content).await?;
+        Ok(())
+    }
+
+    async fn insert_batch(&self, items: Vec<(Vec<u8>, Bytes)>) -> Result<()> {
+        let cipher = self.node.cipher.as_ref();
+        futures::stream::iter(items)
+            .map(|(key, content)| async move {
+                let key = self.doc_key(key);
+                let content = crypto::seal(cipher, self.doc.id(), &key, content.to_vec())?;
+                self.doc.set_bytes(self.author, key, content).await?;
+                anyhow::Ok(())
+            })
+            .buffer_unordered(INSERT_CONCURRENCY)
+            .try_collect::<()>()
+            .await
+    }
+
+    async fn search(&self) -> Result<Vec<VersionedEntity<Entity>>> {
+        let query = Query::single_latest_per_key().key_prefix(&self.prefix);
+        self.search_query(query, |_| true).await
+    }
+
+    async fn search_authored(&self) -> Result<Vec<(AuthorId, Entity)>> {
+        Ok(self
+            .search()
+            .await?
+            .into_iter()
+            .map(|versioned| (versioned.author, versioned.entity))
+            .collect())
+    }
+
+    async fn search_ordered_by_creation(&self) -> Result<Vec<VersionedEntity<Entity>>> {
+        let query = Query::single_latest_per_key()
+            .key_prefix(&self.prefix)
+            .sort_direction(SortDirection::Asc);
+        self.search_query(query, |_| true).await
+    }
+
+    fn search_stream(&self) -> impl Stream<Item = Result<VersionedEntity<Entity>>> + '_ {
+        futures::stream::once(async move {
+            let query = Query::single_latest_per_key().key_prefix(&self.prefix);
+            let entries = self.doc.get_many(query).await?;
+            anyhow::Ok(entries.map_err(anyhow::Error::from))
+        })
+        .try_flatten()
+        .and_then(move |entry| async move { self.versioned(&entry).await })
+        // deleted entries are skipped
+        .filter_map(|result| async move { result.transpose() })
+    }
+
+    async fn list_keys(&self) -> Result<Vec<String>> {
+        Ok(self
+            .list_entries()
+            .await?
+            .into_iter()
+            .map(|info| info.key)
+            .collect())
+    }
+
+    async fn list_entries(&self) -> Result<Vec<EntryInfo>> {
+        let mut infos = Vec::new();
+        let query = Query::single_latest_per_key().key_prefix(&self.prefix);
+        for entry in self.entries(query).await? {
+            // writes of authors the access list does not allow are not listed
+            let Some(entry) = self.allowed_entry(&entry).await? else {
+                continue;
+            };
+            if entry.content_len() == 0 {
+                continue;
+            }
+            infos.push(EntryInfo {
+                key: String::from_utf8_lossy(self.table_key(entry.key())).to_string(),
+                ..EntryInfo::from(&entry)
+            });
+        }
+        Ok(infos)
+    }
+
+    async fn search_page(&self, offset: u64, limit: u64) -> Result<Vec<VersionedEntity<Entity>>> {
+        let query = Query::single_latest_per_key()
+            .key_prefix(&self.prefix)
+            .offset(offset)
+            .limit(limit);
+        self.search_query(query, |_| true).await
+    }
+
+    async fn search_filtered(&self, filter: Filter) -> Result<Vec<VersionedEntity<Entity>>> {
+        self.search_query(filter.query(&self.prefix), |entry| {
+            filter.matches_size(entry)
+        })
+        .await
+    }
+
+    async fn search_sorted(
+        &self,
+        field: SortField,
+        direction: SortDirection,
+    ) -> Result<Vec<VersionedEntity<Entity>>> {
+        let query = Query::single_latest_per_key()
+            .key_prefix(&self.prefix)
+            .sort_direction(direction);
+        let mut pairs = Vec::new();
+        for entry in self.entries(query).await? {
+            if let Some(entity) = self.versioned(&entry).await? {
+                pairs.push((entry, entity));
+            }
+        }
+
+        // key order already comes from the doc store
+        if field != SortField::Key {
+            match field {
+                SortField::Name => {
+                    pairs.sort_by(|(_, a), (_, b)| a.sort_name().cmp(&b.sort_name()))
+                }
+                SortField::Size => pairs.sort_by_key(|(entry, entity)| {
+                    entity.content_size().unwrap_or(entry.content_len())
+                }),
+                SortField::Time => pairs.sort_by_key(|(_, entity)| entity.timestamp),
+                SortField::Captured => pairs.sort_by_key(|(_, entity)| {
+                    entity
+                        .captured_at()
+                        .or(entity.created_at())
+                        .unwrap_or(entity.timestamp / 1000)
+                }),
+                SortField::Key => {}
+            }
+            if direction == SortDirection::Desc {
+                pairs.reverse();
+            }
+        }
+        Ok(pairs.into_iter().map(|(_, entity)| entity).collect())
     }
 
     async fn bytes_from_entry(&self, entry: &Entry) -> anyhow::Result<Entity> {
+        self.entity(entry).await?.ok_or_else(|| {
+            anyhow::anyhow!(
+                "'{}' has been deleted",
+                String::from_utf8_lossy(entry.key())
+            )
+        })
+    }
+}
+
+impl<Entity> IrohCls<Entity>
+where
+    Entity: ToBytes<Entity> + Serialize + Clone + for<'a> Deserialize<'a> + Send,
+{
+    // entity of an entry, `None` once it has been deleted or moved to the trash
+    //
+    // entries of authors the access list does not allow to write are ignored,
+    // the latest entry of an allowed author counts instead
+    pub(crate) async fn entity(&self, entry: &Entry) -> Result<Option<Entity>> {
+        match self.allowed_entry(entry).await? {
+            Some(entry) => self.decode(&entry).await,
+            None => Ok(None),
+        }
+    }
+
+    // the entry that counts for the key of `entry`: `entry` itself when the access list
+    // allows its author to write, otherwise the latest entry of an allowed author
+    pub(crate) async fn allowed_entry(&self, entry: &Entry) -> Result<Option<Entry>> {
+        if self.node.acl.may_write(&entry.author()) {
+            return Ok(Some(entry.clone()));
+        }
+        Ok(self
+            .entries(Query::key_exact(entry.key()))
+            .await?
+            .into_iter()
+            .filter(|entry| self.node.acl.may_write(&entry.author()))
+            .max_by_key(|entry| entry.timestamp()))
+    }
+
+    // entity stored in `entry` itself, whoever wrote it
+    pub(crate) async fn decode(&self, entry: &Entry) -> Result<Option<Entity>> {
+        if entry.content_len() == 0 {
+            return Ok(None);
+        }
         // In UTF-8, a character is three bytes. If the bytes are not aligned to multiples of 3,
         // an error will occur here, indicating that the key-value pair has a problem
-        let id = String::from_utf8(entry.key().to_owned()).context("invalid key")?;
+        let id =
+            String::from_utf8(self.table_key(entry.key()).to_owned()).context("invalid key")?;
         match self
             .node
             .blobs_store
@@ -124,73 +656,1205 @@ where
             .get_bytes(entry.content_hash())
             .await
         {
-            Ok(b) => Entity::from_bytes(b),
-            Err(_) => Ok(Entity::missing_file(id)),
+            Ok(b) => {
+                let b = crypto::open(
+                    self.node.cipher.as_ref(),
+                    self.doc.id(),
+                    entry.key(),
+                    b.to_vec(),
+                )?;
+                if trash::is_tombstone(&b) {
+                    return Ok(None);
+                }
+                Entity::from_bytes(b.into()).map(Some)
+            }
+            Err(_) => Ok(Some(Entity::missing_file(id))),
+        }
+    }
+
+    /// Check `entity` with the validators of the table and write it under `key`
+    pub async fn insert(&self, key: impl AsRef<[u8]>, entity: &Entity) -> Result<()> {
+        self.validators.check(entity)?;
+        self.insert_bytes(key, entity.as_bytes()?).await
+    }
+
+    /// Run `validator` on every entity written with [`IrohCls::insert`] from now on
+    ///
+    /// Validators are shared by all handles of the table
+    pub fn add_validator(&self, validator: impl Validator<Entity> + 'static) {
+        self.validators.add(validator);
+    }
+
+    /// Move the entity stored under `key` to the trash, see [`trash::delete`]
+    pub async fn delete(&self, key: impl AsRef<[u8]>) -> Result<()> {
+        trash::delete(&self.node, &self.doc, self.author, &self.doc_key(key)).await
+    }
+
+    /// Restore an entity from the trash
+    pub async fn restore(&self, key: impl AsRef<[u8]>) -> Result<()> {
+        trash::restore(&self.node, &self.doc, self.author, &self.doc_key(key)).await
+    }
+
+    /// Entities of this table in the trash, keyed by their key
+    pub async fn trash(&self) -> Result<Vec<(String, Tombstone)>> {
+        let prefix = String::from_utf8_lossy(&self.prefix).to_string();
+        Ok(trash::list(&self.node, &self.doc)
+            .await?
+            .into_iter()
+            .filter_map(|(key, tombstone)| {
+                Some((key.strip_prefix(prefix.as_str())?.to_string(), tombstone))
+            })
+            .collect())
+    }
+
+    /// `key` as stored in the doc, behind the prefix of the table
+    pub fn doc_key(&self, key: impl AsRef<[u8]>) -> Vec<u8> {
+        [self.prefix.as_slice(), key.as_ref()].concat()
+    }
+
+    /// Key of a doc entry within the table, the prefix of the table removed
+    pub fn table_key<'a>(&self, doc_key: &'a [u8]) -> &'a [u8] {
+        doc_key
+            .strip_prefix(self.prefix.as_slice())
+            .unwrap_or(doc_key)
+    }
+
+    /// Whether a doc entry belongs to this table, always true unless the doc is shared
+    pub fn owns(&self, doc_key: &[u8]) -> bool {
+        doc_key.starts_with(&self.prefix)
+    }
+
+    // entries up to the first one that fails to load
+    pub(crate) async fn entries(&self, query: impl Into<Query>) -> Result<Vec<Entry>> {
+        let entries = self.doc.get_many(query).await?;
+        let entries = entries.collect::<Vec<Result<Entry>>>().await;
+        Ok(entries.into_iter().map_while(Result::ok).collect())
+    }
+
+    /// All versions written for `key`, newest first, see [`history::history`]
+    pub async fn history(&self, key: impl AsRef<[u8]>) -> Result<Vec<HistoryEntry>> {
+        history::history(&self.doc, self.doc_key(key)).await
+    }
+
+    /// Versions of `key` that lost against the latest one and their entities, newest first
+    ///
+    /// The entity is `None` for deleted versions, and [`ToBytes::missing_file`] while the
+    /// content of a version has not been downloaded
+    pub async fn losing_versions(
+        &self,
+        key: impl AsRef<[u8]>,
+    ) -> Result<Vec<(HistoryEntry, Option<Entity>)>> {
+        let key = self.doc_key(key);
+        let mut versions = vec![];
+        for version in history::losing_versions(&self.doc, &key).await? {
+            let entry = self
+                .doc
+                .get_exact(version.author, key.clone(), false)
+                .await?;
+            let entity = match entry {
+                Some(entry) => self.decode(&entry).await.ok().flatten(),
+                None => None,
+            };
+            versions.push((version, entity));
+        }
+        Ok(versions)
+    }
+
+    /// Notify about the changes of `key` until the returned stream is dropped
+    ///
+    /// Built on the doc subscription, events of other keys and writes of authors the
+    /// access list does not allow are skipped. A remote write whose content is not local
+    /// yet is followed by [`KeyChange::ContentReady`] once it has been downloaded. Moves to
+    /// the trash are reported as [`KeyChange::Deleted`], see [`trash::is_deleted`]
+    pub async fn watch(
+        &self,
+        key: impl AsRef<[u8]>,
+    ) -> Result<BoxStream<'static, Result<KeyChange>>> {
+        let key = self.doc_key(key);
+        // subscribe before reading the current entry so no change in between is lost
+        let events = self.doc.subscribe().await?;
+        let existed = match self
+            .doc
+            .get_one(Query::single_latest_per_key().key_exact(&key))
+            .await?
+        {
+            Some(entry) => !trash::is_deleted(&self.node, &entry).await?,
+            None => false,
+        };
+        let node = self.node.clone();
+        let acl = self.node.acl.clone();
+        // the hash of the latest write while its content is downloading
+        let downloading: Option<Hash> = None;
+        let stream = futures::stream::unfold(
+            (events, existed, downloading),
+            move |(mut events, mut existed, mut downloading)| {
+                let key = key.clone();
+                let node = node.clone();
+                let acl = acl.clone();
+                async move {
+                    loop {
+                        let (entry, complete) = match events.next().await? {
+                            Ok(LiveEvent::InsertLocal { entry }) => (entry, true),
+                            Ok(LiveEvent::InsertRemote {
+                                entry,
+                                content_status,
+                                ..
+                            }) => (entry, content_status == ContentStatus::Complete),
+                            Ok(LiveEvent::ContentReady { hash }) if downloading == Some(hash) => {
+                                let state = (events, existed, None);
+                                return Some((Ok(KeyChange::ContentReady), state));
+                            }
+                            Ok(_) => continue,
+                            Err(e) => {
+                                let state = (events, existed, downloading);
+                                return Some((Err(anyhow::Error::from(e)), state));
+                            }
+                        };
+                        if entry.key() != key.as_slice() || !acl.may_write(&entry.author()) {
+                            continue;
+                        }
+                        let (author, timestamp) = (entry.author(), entry.timestamp());
+                        // a tombstone is only recognized once its content is local
+                        let deleted = match trash::is_deleted(&node, &entry).await {
+                            Ok(deleted) => deleted,
+                            Err(e) => {
+                                let state = (events, existed, downloading);
+                                return Some((Err(e), state));
+                            }
+                        };
+                        let change = if deleted {
+                            if !existed {
+                                continue;
+                            }
+                            downloading = None;
+                            KeyChange::Deleted { author, timestamp }
+                        } else {
+                            downloading = (!complete).then_some(entry.content_hash());
+                            if existed {
+                                KeyChange::Updated { author, timestamp }
+                            } else {
+                                KeyChange::Inserted { author, timestamp }
+                            }
+                        };
+                        existed = !deleted;
+                        return Some((Ok(change), (events, existed, downloading)));
+                    }
+                }
+            },
+        );
+        Ok(stream.boxed())
+    }
+
+    /// Go back to the latest version of `key` by another author, see [`history::undo`]
+    pub async fn undo(&self, key: impl AsRef<[u8]>) -> Result<Option<HistoryEntry>> {
+        history::undo(&self.doc, self.author, self.doc_key(key)).await
+    }
+
+    /// Rewrite the entries stored with an older schema version in the current one
+    ///
+    /// Entries that fail are skipped and reported, so one broken entry does not stop the rest
+    pub async fn migrate(&self) -> Result<MigrateReport> {
+        let mut report = MigrateReport::default();
+        let query = Query::single_latest_per_key().key_prefix(&self.prefix);
+        for entry in self.entries(query).await? {
+            if entry.content_len() == 0 {
+                continue;
+            }
+            let Ok(bytes) = self
+                .node
+                .blobs_store
+                .blobs()
+                .get_bytes(entry.content_hash())
+                .await
+            else {
+                continue;
+            };
+            let key = String::from_utf8_lossy(entry.key()).to_string();
+            match self.migrate_entry(&entry, bytes.to_vec()).await {
+                Ok(true) => report.migrated += 1,
+                Ok(false) => {}
+                Err(e) => {
+                    warn!(%key, error = %e, "failed to migrate entry");
+                    report.failed.push((key, e.to_string()));
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    // rewrite one entry when it is outdated, returns whether it was
+    //
+    // with a cipher, entries sealed before values were bound to their entry are rewritten too
+    async fn migrate_entry(&self, entry: &Entry, bytes: Vec<u8>) -> Result<bool> {
+        let cipher = self.node.cipher.as_ref();
+        let unbound = cipher.is_some() && !crypto::is_bound(&bytes);
+        let bytes = crypto::open(cipher, self.doc.id(), entry.key(), bytes)?;
+        if trash::is_tombstone(&bytes) {
+            if unbound {
+                self.insert_bytes(self.table_key(entry.key()), bytes.into())
+                    .await?;
+            }
+            return Ok(unbound);
+        }
+        let (entity, version) = Entity::from_bytes_versioned(bytes.into())?;
+        if version >= Entity::VERSION && !unbound {
+            return Ok(false);
+        }
+        self.insert_bytes(self.table_key(entry.key()), entity.as_bytes()?)
+            .await?;
+        Ok(true)
+    }
+
+    // entries are checked with `predicate` as they are read, the ones it rejects are
+    // neither kept nor decoded
+    async fn search_query(
+        &self,
+        query: impl Into<Query>,
+        predicate: impl Fn(&Entry) -> bool,
+    ) -> Result<Vec<VersionedEntity<Entity>>> {
+        let entries = self.doc.get_many(query).await?;
+        let mut entries = std::pin::pin!(entries);
+        let mut entities = Vec::new();
+        // up to the first entry that fails to load, like `entries`
+        while let Some(Ok(entry)) = entries.next().await {
+            if !predicate(&entry) {
+                continue;
+            }
+            if let Some(entity) = self.versioned(&entry).await? {
+                entities.push(entity);
+            }
         }
+        Ok(entities)
+    }
+
+    async fn versioned(&self, entry: &Entry) -> Result<Option<VersionedEntity<Entity>>> {
+        Ok(self.entity(entry).await?.map(|entity| VersionedEntity {
+            entity,
+            author: entry.author(),
+            timestamp: entry.timestamp(),
+        }))
     }
 }
 
-type ResourceHandle = Arc<RwLock<Option<Resources>>>;
-type FolderHandle = Arc<RwLock<Option<Folders>>>;
-type NodeHandle = Arc<RwLock<Option<Nodes>>>;
 pub struct StoreState {
-    pub resource: ResourceHandle,
-    pub resource1: ResourceHandle,
-    pub resource2: ResourceHandle,
-    pub resource3: ResourceHandle,
-    pub folder: FolderHandle,
-    pub node: NodeHandle,
+    pub iroh: IrohNet,
+    // built-in tables and resource collections created at runtime, keyed by name
+    pub tables: Tables,
     pub ticket_string: String,
+    // folders shared through their own derived doc, keyed by folder id
+    pub sub_shares: Arc<RwLock<HashMap<String, SubShare>>>,
+    // sync progress of every subscribed table
+    pub progress: broadcast::Sender<SyncProgress>,
+    // application events of every subscribed table, until taken by a consumer
+    pub events: Arc<Mutex<HashMap<String, EventReceiver>>>,
+    // initial sync progress over all subscribed tables
+    pub sync: SyncCoordinator,
+    // senders of the application events, for events not caused by a doc
+    pub event_senders: EventSenders,
+    // events dropped by full event channels, see `EventChannelConfig`
+    pub dropped_events: DroppedEvents,
+    // tasks processing the doc events of every subscribed table
+    pub subscriptions: SubscriptionManager,
+    // tables whose sync was paused with `pause_sync`
+    paused: PausedTables,
+    // tables that only download the content of some entries, see `set_sync_filter`
+    sync_filters: SyncFilters,
+    // local writes that have not reached any peer yet
+    pub pending: PendingWrites,
+    // doc of the access list, once created or joined
+    acl: Arc<RwLock<Option<AclTable>>>,
+    // the access list, manifest and audit docs, resynced with the tables
+    system_docs: SystemDocs,
+    // local record of every write to the tables, `None` if it could not be opened
+    pub audit: Option<AuditLog>,
+    // doc announcing the tickets of all tables, `None` for clients joined without one
+    pub manifest: Option<Manifest>,
+    // tables announced in the manifest, until taken by a consumer
+    manifest_updates: Arc<Mutex<Option<mpsc::Receiver<ManifestEntry>>>>,
+    // tables written to the tickets file under the storage path
+    saved_tables: Arc<Mutex<SavedTables>>,
+}
+
+impl StoreState {
+    /// Take the [`AppEvent`] receiver of a table or collection
+    ///
+    /// Each receiver can only be taken once
+    pub async fn take_events(&self, table: &str) -> Option<EventReceiver> {
+        self.events.lock().await.remove(table)
+    }
+
+    /// Take the receiver of the tables announced in the manifest, see [`spawn_manifest_follower`]
+    ///
+    /// Every table is sent once, joining the ones that are not open yet is up to the consumer
+    pub async fn take_manifest_updates(&self) -> Option<mpsc::Receiver<ManifestEntry>> {
+        self.manifest_updates.lock().await.take()
+    }
+
+    /// Ticket of the manifest, joining it joins all tables of this node
+    pub fn manifest_ticket(&self) -> Option<String> {
+        self.manifest.as_ref().map(|manifest| manifest.ticket())
+    }
+
+    /// Wait until every joined table has finished a sync run and downloaded the content it
+    /// announced, including the blobs placing its resources, so reads see the complete data
+    ///
+    /// Fails right away without joined tables, and after `timeout` naming the state of every
+    /// table that is not ready yet. Tables nobody synced with, e.g. the ones a server created,
+    /// never become ready
+    pub async fn wait_ready(&self, timeout: Duration) -> Result<Vec<TableReadiness>> {
+        ensure!(
+            !self.sync.readiness().is_empty(),
+            "No joined tables to wait for"
+        );
+        match tokio::time::timeout(timeout, self.wait_placements()).await {
+            Ok(tables) => tables,
+            Err(_) => {
+                let pending: Vec<String> = self
+                    .sync
+                    .readiness()
+                    .iter()
+                    .filter(|table| !table.is_ready())
+                    .map(ToString::to_string)
+                    .collect();
+                anyhow::bail!(
+                    "Not ready after {} seconds: {}",
+                    timeout.as_secs(),
+                    pending.join(", ")
+                )
+            }
+        }
+    }
+
+    // wait for the synced entries and their content, then for the placement blobs the
+    // content fetcher downloads after them
+    async fn wait_placements(&self) -> Result<Vec<TableReadiness>> {
+        loop {
+            self.sync.wait_synced().await;
+            let mut ready = true;
+            for table in self.sync.readiness() {
+                let missing = self.missing_placement_blobs(&table.table).await?;
+                self.sync.set_missing_blobs(&table.table, missing);
+                ready &= missing == 0;
+            }
+            if ready {
+                return Ok(self.sync.readiness());
+            }
+            tokio::time::sleep(PLACEMENT_POLL_INTERVAL).await;
+        }
+    }
+
+    // placement blobs of `table` that are not here yet, resources outside its sync filter
+    // are not fetched and don't count
+    async fn missing_placement_blobs(&self, table: &str) -> Result<usize> {
+        let Some(resources) = self.tables.get::<Resources>(table).await else {
+            return Ok(0);
+        };
+        let filter = self.sync_filters.read().await.get(table).cloned();
+        resources.count_missing_content(filter.as_ref()).await
+    }
+
+    /// Receive [`SyncProgress`] updates of all tables
+    pub fn subscribe_progress(&self) -> broadcast::Receiver<SyncProgress> {
+        self.progress.subscribe()
+    }
+
+    /// Doc backing a table or runtime-defined collection
+    pub async fn doc(&self, table: &str) -> Option<Doc> {
+        self.tables.doc(table).await
+    }
+
+    /// Create a new named resource collection backed by its own doc
+    ///
+    /// Returns the share ticket of the new collection
+    pub async fn create_collection(&self, name: &str) -> Result<String> {
+        self.open_collection(name, None).await
+    }
+
+    /// Join an existing collection shared by another peer
+    pub async fn join_collection(&self, name: &str, ticket: DocTicket) -> Result<String> {
+        self.open_collection(name, Some(ticket)).await
+    }
+
+    async fn open_collection(&self, name: &str, ticket: Option<DocTicket>) -> Result<String> {
+        ensure!(!name.is_empty(), "Collection name must not be empty");
+        ensure!(!is_reserved(name), "Collection name '{}' is reserved", name);
+        ensure!(
+            !(self.iroh.single_doc() && name.contains('/')),
+            "Collection names must not contain '/' when the tables share one doc"
+        );
+        ensure!(
+            !self.tables.contains(name).await,
+            "Collection '{}' already exists",
+            name
+        );
+
+        let mut resources = Resources::new(&ticket, self.iroh.clone()).await?;
+        resources.prefix = table_prefix(&self.iroh, name);
+        if let Some(scheme) = self.saved_tables.lock().await.key_scheme(name) {
+            resources.set_key_scheme(scheme);
+        }
+        info!(collection = %name, namespace = %resources.doc.id(), "opened collection");
+        let app_events = subscribe_doc(&resources, name.to_string(), self).await?;
+        self.events
+            .lock()
+            .await
+            .insert(name.to_string(), app_events);
+        let ticket_share_str = collection_ticket(&resources).await?;
+        index_table(&self.iroh, name, &resources);
+        spawn_content_fetcher(
+            resources.clone(),
+            name.to_string(),
+            self.sync_filters.clone(),
+        );
+        self.iroh
+            .control
+            .register_table(name, resources.doc.clone(), resources.prefix.clone())
+            .await;
+        let saved_ticket = match &ticket {
+            Some(ticket) => ticket.to_string(),
+            None => ticket_share_str.clone(),
+        };
+        self.remember_table(name, resources.doc.id(), saved_ticket)
+            .await?;
+        // joined collections were announced by the node that created them
+        if ticket.is_none()
+            && let Some(manifest) = &self.manifest
+        {
+            manifest
+                .publish(name, resources.doc.id(), ticket_share_str.clone())
+                .await?;
+        }
+        self.tables.insert(name, resources).await?;
+        Ok(ticket_share_str)
+    }
+
+    // save a table to reopen it on the next start, in-memory nodes don't keep anything
+    async fn remember_table(
+        &self,
+        table: &str,
+        namespace: NamespaceId,
+        ticket: String,
+    ) -> Result<()> {
+        let mut saved = self.saved_tables.lock().await;
+        // a collection joined again is followed in the manifest again
+        saved.left.retain(|left| *left != namespace);
+        let Some(root) = self.iroh.storage_path() else {
+            return Ok(());
+        };
+        let previous = saved
+            .tables
+            .iter()
+            .find(|t| t.table == table && t.namespace == namespace);
+        let sync_filter = previous.and_then(|t| t.sync_filter.clone());
+        let key_scheme = previous.and_then(|t| t.key_scheme);
+        saved.tables.retain(|t| t.table != table);
+        saved.tables.push(SavedTable {
+            table: table.to_string(),
+            namespace,
+            ticket,
+            sync_filter,
+            key_scheme,
+        });
+        save_tables(root, &saved).await
+    }
+
+    /// Whether this node left the collection of `namespace`, see [`StoreState::leave_collection`]
+    pub async fn has_left(&self, namespace: &NamespaceId) -> bool {
+        self.saved_tables.lock().await.left.contains(namespace)
+    }
+
+    // keep a left collection from being joined again through the manifest
+    async fn remember_left(&self, namespace: NamespaceId) -> Result<()> {
+        let mut saved = self.saved_tables.lock().await;
+        if !saved.left.contains(&namespace) {
+            saved.left.push(namespace);
+        }
+        match self.iroh.storage_path() {
+            Some(root) => save_tables(root, &saved).await,
+            None => Ok(()),
+        }
+    }
+
+    // stop reopening a table on the next start
+    async fn forget_table(&self, table: &str) -> Result<()> {
+        let Some(root) = self.iroh.storage_path() else {
+            return Ok(());
+        };
+        let mut saved = self.saved_tables.lock().await;
+        saved.tables.retain(|t| t.table != table);
+        save_tables(root, &saved).await
+    }
+
+    // open the access list doc and keep the roles of the node in line with it
+    async fn open_acl(&self, ticket: Option<DocTicket>) -> Result<()> {
+        let table = AclTable::new(&ticket, self.iroh.clone()).await?;
+        info!(namespace = %table.doc.id(), "opened access list");
+        let saved_ticket = match &ticket {
+            Some(ticket) => ticket.to_string(),
+            None => table.ticket(),
+        };
+        self.iroh
+            .control
+            .register_table(ACL_TABLE, table.doc.clone(), Vec::new())
+            .await;
+        self.remember_table(ACL_TABLE, table.doc.id(), saved_ticket)
+            .await?;
+        spawn_acl_follower(table.clone());
+        {
+            let mut system_docs = self.system_docs.write().await;
+            system_docs.retain(|(name, _)| *name != ACL_TABLE);
+            system_docs.push((ACL_TABLE, table.doc.clone()));
+        }
+        *self.acl.write().await = Some(table);
+        Ok(())
+    }
+
+    async fn acl_table(&self) -> Result<AclTable> {
+        self.acl.read().await.clone().ok_or_else(|| {
+            anyhow::anyhow!("This node has no access list, join one with its ticket")
+        })
+    }
+
+    /// Give an author or node id a role in the access list, `None` revokes its access
+    pub async fn acl_grant(&self, key: &str, role: Option<Role>) -> Result<()> {
+        self.acl_table().await?.grant(parse_key(key)?, role).await
+    }
+
+    /// Ticket of the access list doc, a write ticket lets another admin change it
+    pub async fn share_acl(&self, mode: ShareMode) -> Result<String> {
+        let table = self.acl_table().await?;
+        let ticket = table.doc.share(mode, self.iroh.addr_info()).await?;
+        Ok(ticket.to_string())
+    }
+
+    /// Join the access list of another node, or gain write access to the joined one
+    pub async fn join_acl(&self, ticket: DocTicket) -> Result<()> {
+        let current = self.acl.read().await.clone();
+        match current {
+            Some(table) => {
+                ensure!(
+                    table.doc.id() == ticket.capability.id(),
+                    "This node already follows another access list"
+                );
+                // importing again merges the capability of the ticket into the stored one
+                self.iroh.docs.import(ticket.clone()).await?;
+                self.remember_table(ACL_TABLE, table.doc.id(), ticket.to_string())
+                    .await
+            }
+            None => self.open_acl(Some(ticket)).await,
+        }
+    }
+
+    /// Share a single folder and the resources filed into it through a derived doc and return
+    /// its read ticket
+    ///
+    /// The derived doc follows the folder and resource tables
+    pub async fn share_folder(&self, folder_id: &str) -> Result<String> {
+        if let Some(sub_share) = self.sub_shares.read().await.get(folder_id) {
+            return Ok(sub_share.ticket.clone());
+        }
+        let folders = self.tables.require::<Folders>("folder").await?;
+        ensure!(
+            folders.get(folder_id).await?.is_some(),
+            "Folder '{}' does not exist",
+            folder_id
+        );
+
+        let resources = self.tables.require::<Resources>("resource").await?;
+        let id = folders.doc_key(folder_id);
+        let folder = folder_id.to_string();
+        let mirrors = vec![
+            Mirror::with_prefix(folders.doc.clone(), FOLDER_PREFIX, move |key| {
+                key == id.as_slice()
+            })
+            .of_table(folders.prefix.clone()),
+            Mirror::with_filter(resources.doc.clone(), RESOURCE_PREFIX, {
+                let resources = resources.clone();
+                move |entry| {
+                    let resources = resources.clone();
+                    let folder = folder.clone();
+                    Box::pin(async move {
+                        matches!(
+                            resources.entity(&entry).await,
+                            Ok(Some(resource)) if resource.folder_id.as_deref() == Some(folder.as_str())
+                        )
+                    })
+                }
+            })
+            .of_table(resources.prefix.clone()),
+        ];
+        let sub_share = create_sub_share(&self.iroh, mirrors).await?;
+        let ticket = sub_share.ticket.clone();
+        self.sub_shares
+            .write()
+            .await
+            .insert(folder_id.to_string(), sub_share);
+        Ok(ticket)
+    }
+
+    /// File a resource of a resource table or collection into a folder of the folder table
+    ///
+    /// `None` takes the resource out of its folder
+    pub async fn move_to_folder(
+        &self,
+        table: &str,
+        resource_id: &str,
+        folder_id: Option<String>,
+    ) -> Result<()> {
+        if let Some(folder_id) = &folder_id {
+            let folders = self.tables.require::<Folders>("folder").await?;
+            ensure!(
+                folders.get(folder_id).await?.is_some(),
+                "Folder '{}' does not exist",
+                folder_id
+            );
+        }
+        self.resource_table(table)
+            .await?
+            .move_to_folder(resource_id, folder_id)
+            .await
+    }
+
+    /// Resources of a resource table or collection filed into `folder_id`
+    pub async fn list_in_folder(
+        &self,
+        table: &str,
+        folder_id: Option<&str>,
+    ) -> Result<Vec<Resource>> {
+        self.resource_table(table)
+            .await?
+            .list_in_folder(folder_id)
+            .await
+    }
+
+    /// Dump all entities of a table or collection to a JSON file
+    ///
+    /// Returns the number of exported entities
+    pub async fn export_table(&self, table: &str, path: &Path) -> Result<usize> {
+        self.tables.table(table).await?.export(table, path).await
+    }
+
+    /// Restore entities dumped with [`StoreState::export_table`] into a table or collection
+    ///
+    /// Returns the number of imported entities
+    pub async fn import_table(&self, table: &str, path: &Path) -> Result<usize> {
+        self.tables.table(table).await?.import(path).await
+    }
+
+    /// Rewrite the outdated entries of a table or collection, see [`IrohCls::migrate`]
+    pub async fn migrate_table(&self, table: &str) -> Result<MigrateReport> {
+        self.tables.table(table).await?.migrate().await
+    }
+
+    /// Resource table or runtime-defined collection called `table`
+    pub async fn resource_table(&self, table: &str) -> Result<Resources> {
+        self.tables.require::<Resources>(table).await
+    }
+
+    /// Entry metadata of a table or collection, see [`IrohProperties::list_entries`]
+    pub async fn list_entries(&self, table: &str) -> Result<Vec<EntryInfo>> {
+        self.tables.table(table).await?.list_entries().await
+    }
+
+    /// Names of all runtime-defined collections
+    pub async fn list_collections(&self) -> Vec<String> {
+        let mut names = self.tables.names().await;
+        names.retain(|name| TableType::iter().all(|t| t.as_ref() != name));
+        names
+    }
+
+    /// Copy the latest entries of a table or collection into the doc of a write ticket
+    ///
+    /// The copies are written by this node's author and reference the same content, e.g.
+    /// to move a table to a new namespace after its ticket leaked, or to split a resource
+    /// table into a collection that is then joined with the ticket
+    pub async fn copy_doc(&self, table: &str, ticket: DocTicket) -> Result<CopyReport> {
+        ensure!(
+            matches!(ticket.capability, Capability::Write(_)),
+            "Copying needs a write ticket of the target doc"
+        );
+        let source = self
+            .doc(table)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Table '{}' does not exist", table))?;
+        ensure!(
+            source.id() != ticket.capability.id(),
+            "The target is the doc of '{}' itself",
+            table
+        );
+        let target = iroh_create_doc(&self.iroh, &Some(ticket)).await?;
+        let author = iroh_create_author(&self.iroh).await?;
+        // in single-doc mode only the entries of the table are copied, without their prefix
+        let prefix = table_prefix(&self.iroh, table);
+        copy_entries(&self.iroh, &source, &target, author, |key| {
+            key.strip_prefix(prefix.as_slice()).map(<[u8]>::to_vec)
+        })
+        .await
+    }
+
+    /// Share ticket of a runtime-defined collection
+    pub async fn share_collection(&self, name: &str) -> Result<String> {
+        ensure!(
+            TableType::iter().all(|t| t.as_ref() != name),
+            "'{}' is a built-in table, not a collection",
+            name
+        );
+        let resources = self
+            .tables
+            .get::<Resources>(name)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Collection '{}' does not exist", name))?;
+        collection_ticket(&resources).await
+    }
+
+    /// Stop syncing a collection and remove it with its entries from this node
+    ///
+    /// The doc is dropped locally only, peers keep their copy. With `gc` the blobs the
+    /// collection referred to are deleted as well, unless another doc refers to them or
+    /// they are pinned. Returns the number of deleted blobs
+    pub async fn leave_collection(&self, name: &str, gc: bool) -> Result<usize> {
+        ensure!(
+            !is_reserved(name),
+            "'{}' is a built-in table, only collections can be left",
+            name
+        );
+        ensure!(
+            !self.iroh.single_doc(),
+            "Collections can not be left when the tables share one doc"
+        );
+        let resources = self
+            .tables
+            .get::<Resources>(name)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Collection '{}' does not exist", name))?;
+        let candidates = if gc {
+            resources.blob_hashes().await?
+        } else {
+            HashSet::new()
+        };
+
+        self.tables.remove(name).await;
+        self.iroh.control.unregister_table(name).await;
+        self.subscriptions.remove(name).await;
+        self.events.lock().await.remove(name);
+        self.event_senders.lock().await.remove(name);
+        self.paused.write().await.remove(name);
+        self.sync_filters.write().await.remove(name);
+        self.sync.forget(name);
+        self.forget_table(name).await?;
+        let namespace = resources.doc.id();
+        // the node that announced the collection withdraws it, others only skip it from now on
+        if let Some(manifest) = &self.manifest {
+            manifest.unpublish(name, namespace).await?;
+        }
+        self.remember_left(namespace).await?;
+
+        // dropping the doc ends its event stream, which stops the indexers
+        resources.doc.leave().await?;
+        self.iroh.docs.drop_doc(namespace).await?;
+        self.iroh.search.reindex(name, vec![]).await?;
+        self.iroh.sql_index.reindex(name, vec![]).await?;
+        info!(collection = %name, %namespace, "left collection");
+
+        if candidates.is_empty() {
+            return Ok(0);
+        }
+        let mut protected = entry_hashes(&self.iroh).await?;
+        protected.extend(pinned_hashes(&self.iroh).await?);
+        for other in self.tables.names().await {
+            if let Some(other) = self.tables.get::<Resources>(&other).await {
+                protected.extend(other.blob_hashes().await?);
+            }
+        }
+        let tags: Vec<_> = self
+            .iroh
+            .blobs_store
+            .tags()
+            .list()
+            .await?
+            .try_collect()
+            .await?;
+        let blobs = self.iroh.blobs_store.blobs();
+        let mut deleted = 0;
+        for hash in candidates.difference(&protected) {
+            for tag in tags.iter().filter(|tag| tag.hash == *hash) {
+                self.iroh
+                    .blobs_store
+                    .tags()
+                    .delete(tag.name.clone())
+                    .await?;
+            }
+            match blobs.delete([*hash]).await {
+                Ok(()) => deleted += 1,
+                Err(e) => warn!(hash = %hash.fmt_short(), error = %e, "failed to delete blob"),
+            }
+        }
+        info!(collection = %name, deleted, "deleted blobs of the left collection");
+        Ok(deleted)
+    }
+
+    /// Stop processing the doc events of a subscription, see [`SubscriptionManager`]
+    ///
+    /// `name` is the name its events are published under. Returns whether it was running
+    pub async fn stop_subscription(&self, name: &str) -> Result<bool> {
+        self.subscriptions
+            .stop(name)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("No subscription named '{}'", name))
+    }
+
+    /// Subscribe to the doc of a subscription again, e.g. after [`StoreState::stop_subscription`]
+    ///
+    /// Receivers taken with [`StoreState::take_events`] keep receiving the events
+    pub async fn restart_subscription(&self, name: &str) -> Result<()> {
+        let source = self
+            .subscriptions
+            .source(name)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("No subscription named '{}'", name))?;
+        subscribe(source.doc, source.prefix, name.to_string(), source.tx, self).await?;
+        info!(table = name, "restarted subscription");
+        Ok(())
+    }
+
+    /// Stop syncing a table with its peers, e.g. on a metered connection
+    ///
+    /// Local reads and writes keep working, they reach the peers after [`StoreState::resume_sync`].
+    /// Pausing is not kept across restarts
+    pub async fn pause_sync(&self, table: &str) -> Result<()> {
+        let doc = self.tables.table(table).await?.doc().clone();
+        doc.leave().await?;
+        self.paused.write().await.insert(table.to_string());
+        info!(table, "paused sync");
+        Ok(())
+    }
+
+    /// Restart syncing a table paused with [`StoreState::pause_sync`]
+    ///
+    /// Returns the number of peers the table synced with before, which it reconnects to
+    pub async fn resume_sync(&self, table: &str) -> Result<usize> {
+        let doc = self.tables.table(table).await?.doc().clone();
+        let peers = previous_peers(&doc, self.iroh.router.endpoint().id()).await?;
+        let count = peers.len();
+        doc.start_sync(peers).await?;
+        self.paused.write().await.remove(table);
+        info!(table, peers = count, "resumed sync");
+        Ok(count)
+    }
+
+    /// Names of the tables whose sync is paused, sorted
+    pub async fn paused_tables(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.paused.read().await.iter().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Only download the content of the resources of `table` that match `filter`
+    ///
+    /// The records of all resources are still synced, the placement blobs of the others are
+    /// not downloaded. An empty filter downloads everything again. The filter is saved with
+    /// the table and kept across restarts.
+    /// Returns the number of blobs of already synced resources that are requested now
+    pub async fn set_sync_filter(&self, table: &str, filter: SyncFilter) -> Result<usize> {
+        let resources = self.resource_table(table).await?;
+        // filters used to hold back the records as well, those are always downloaded now
+        resources
+            .doc
+            .set_download_policy(DownloadPolicy::default())
+            .await?;
+        {
+            let mut filters = self.sync_filters.write().await;
+            if filter.is_empty() {
+                filters.remove(table);
+            } else {
+                filters.insert(table.to_string(), filter.clone());
+            }
+        }
+        if let Some(root) = self.iroh.storage_path() {
+            let mut saved = self.saved_tables.lock().await;
+            if let Some(saved_table) = saved.tables.iter_mut().find(|t| t.table == table) {
+                saved_table.sync_filter = Some(filter.clone()).filter(|f| !f.is_empty());
+                save_tables(root, &saved).await?;
+            }
+        }
+        info!(table, %filter, "sync filter set");
+        // content skipped by the previous filter is requested now
+        resources.fetch_missing_content(Some(&filter)).await
+    }
+
+    /// Choose how new entries of the folder table, a resource table or a collection are keyed
+    ///
+    /// The scheme is saved with the table and kept across restarts. Peers key their own
+    /// writes, so a collection only dedups across peers that all use content keys
+    pub async fn set_key_scheme(&self, table: &str, scheme: KeyScheme) -> Result<()> {
+        if table == "folder" {
+            self.tables
+                .require::<Folders>(table)
+                .await?
+                .set_key_scheme(scheme);
+        } else {
+            self.resource_table(table).await?.set_key_scheme(scheme);
+        }
+        if let Some(root) = self.iroh.storage_path() {
+            let mut saved = self.saved_tables.lock().await;
+            if let Some(saved_table) = saved.tables.iter_mut().find(|t| t.table == table) {
+                saved_table.key_scheme = Some(scheme).filter(|s| *s != KeyScheme::Random);
+                save_tables(root, &saved).await?;
+            }
+        }
+        info!(table, %scheme, "key scheme set");
+        Ok(())
+    }
+
+    /// Tables with a sync filter and their filters, sorted by table
+    pub async fn sync_filters(&self) -> Vec<(String, SyncFilter)> {
+        let mut filters: Vec<(String, SyncFilter)> = self
+            .sync_filters
+            .read()
+            .await
+            .iter()
+            .map(|(table, filter)| (table.clone(), filter.clone()))
+            .collect();
+        filters.sort_by(|a, b| a.0.cmp(&b.0));
+        filters
+    }
+
+    /// Fresh ticket of one open table or collection
+    ///
+    /// A read-only ticket can be shared for any doc this node writes to, so viewers can be
+    /// added later without re-creating the doc. See [`read_only_ticket`] to downgrade a
+    /// ticket that was handed out before
+    ///
+    /// [`read_only_ticket`]: crate::read_only_ticket
+    pub async fn share_table(
+        &self,
+        table: &str,
+        mode: ShareMode,
+        addr: AddrInfoOptions,
+    ) -> Result<String> {
+        let doc = self
+            .doc(table)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Table '{}' does not exist", table))?;
+        let ticket = doc.share(mode, addr).await?;
+        Ok(ticket.to_string())
+    }
+
+    /// Fresh tickets for every open table, the built-in ones in [`TICKET_ORDER`] first
+    ///
+    /// Unlike [`StoreState::ticket_string`] they carry the current addresses of this node
+    pub async fn share_tickets(
+        &self,
+        mode: ShareMode,
+        addr: AddrInfoOptions,
+    ) -> Result<Vec<(String, String)>> {
+        let mut names: Vec<String> = TICKET_ORDER.iter().map(|t| t.to_string()).collect();
+        names.extend(self.list_collections().await);
+        let mut tickets = vec![];
+        for name in names {
+            let Some(doc) = self.doc(&name).await else {
+                continue;
+            };
+            let ticket = doc.share(mode, addr).await?;
+            tickets.push((name, ticket.to_string()));
+        }
+        Ok(tickets)
+    }
+}
+
+/// Key prefix of a table, empty unless the tables share one doc
+///
+/// In single-doc mode the entries of table `folder` are stored under `folder/…`,
+/// see [`IrohNet::enable_single_doc`]
+pub fn table_prefix(node: &IrohNet, table: &str) -> Vec<u8> {
+    if node.single_doc() {
+        format!("{}/", table).into_bytes()
+    } else {
+        Vec::new()
+    }
+}
+
+// open a resource table keyed with the scheme it was saved with
+async fn open_resources(
+    iroh: &IrohNet,
+    ticket: &Option<DocTicket>,
+    saved: Option<&SavedTables>,
+    table: &str,
+) -> Result<Resources> {
+    let mut resources = Resources::new(ticket, iroh.clone()).await?;
+    resources.prefix = table_prefix(iroh, table);
+    if let Some(scheme) = saved.and_then(|s| s.key_scheme(table)) {
+        resources.set_key_scheme(scheme);
+    }
+    Ok(resources)
+}
+
+async fn collection_ticket(resources: &Resources) -> Result<String> {
+    let ticket = resources
+        .doc
+        .share(ShareMode::Write, resources.node.addr_info())
+        .await?;
+    Ok(ticket.to_string())
 }
 
 pub async fn create_files(
     iroh: &IrohNet,
     tickets: Option<HashMap<String, DocTicket>>,
 ) -> Result<StoreState> {
-    let tickets = if let Some(ticket) = tickets {
-        ticket
-    } else {
-        HashMap::new()
+    // without tickets the tables of the previous start are reopened, if there are any
+    let saved = match (&tickets, iroh.storage_path()) {
+        (None, Some(root)) => load_tables(root).await?,
+        _ => None,
+    };
+    let is_builtin = |name: &str| TableType::iter().any(|t| t.as_ref() == name);
+    let tickets = match (tickets, &saved) {
+        (Some(tickets), _) => tickets,
+        (None, Some(saved)) => {
+            info!(tables = saved.tables.len(), "reopening saved tables");
+            saved.tickets(is_builtin)
+        }
+        (None, None) => HashMap::new(),
+    };
+
+    if let Some(saved) = &saved {
+        ensure!(
+            iroh.single_doc() || !saved.share_one_doc(is_builtin),
+            "The saved tables share one doc, enable single-doc mode to reopen them"
+        );
+        ensure!(
+            !iroh.single_doc() || !saved.separate_docs(is_builtin),
+            "The saved tables have a doc each, disable single-doc mode to reopen them"
+        );
+    }
+
+    // the audit log is local to this node, it is only reopened, never joined
+    let audit_ticket = saved.as_ref().and_then(|saved| {
+        saved
+            .tickets(|name| name == AUDIT_TABLE)
+            .remove(AUDIT_TABLE)
+    });
+    let audit = match AuditLog::new(&audit_ticket, iroh.clone()).await {
+        Ok(audit) => Some(audit),
+        Err(e) => {
+            warn!(error = %e, "failed to open the audit log, writes are not recorded");
+            None
+        }
     };
 
+    let system_docs: SystemDocs = Arc::new(RwLock::new(
+        audit
+            .iter()
+            .map(|audit| (AUDIT_TABLE, audit.doc.clone()))
+            .collect(),
+    ));
+    let progress = broadcast::channel(PROGRESS_CHANNEL_CAPACITY).0;
     let mut store_state = StoreState {
-        resource: Arc::new(RwLock::new(None)),
-        resource1: Arc::new(RwLock::new(None)),
-        resource2: Arc::new(RwLock::new(None)),
-        resource3: Arc::new(RwLock::new(None)),
-        folder: Arc::new(RwLock::new(None)),
-        node: Arc::new(RwLock::new(None)),
+        iroh: iroh.clone(),
+        tables: Tables::default(),
         ticket_string: String::new(),
+        sub_shares: Arc::new(RwLock::new(HashMap::new())),
+        sync: SyncCoordinator::new(&progress),
+        event_senders: EventSenders::default(),
+        dropped_events: DroppedEvents::default(),
+        subscriptions: SubscriptionManager::default(),
+        paused: PausedTables::default(),
+        sync_filters: Arc::new(RwLock::new(
+            saved.as_ref().map(|s| s.sync_filters()).unwrap_or_default(),
+        )),
+        pending: PendingWrites::default(),
+        acl: Arc::new(RwLock::new(None)),
+        system_docs,
+        audit,
+        manifest: None,
+        manifest_updates: Arc::new(Mutex::new(None)),
+        progress,
+        events: Arc::new(Mutex::new(HashMap::new())),
+        saved_tables: Arc::new(Mutex::new(saved.clone().unwrap_or_default())),
     };
 
+    // the manifest of the previous start, the one a client joins, or a new one on a new server
+    let manifest_ticket = saved
+        .as_ref()
+        .and_then(|saved| {
+            saved
+                .tickets(|name| name == MANIFEST_TABLE)
+                .remove(MANIFEST_TABLE)
+        })
+        .or_else(|| tickets.get(MANIFEST_TABLE).cloned());
+    let manifest_ticket = match manifest_ticket {
+        Some(ticket) => Some(Some(ticket)),
+        None if tickets.is_empty() => Some(None),
+        None => None,
+    };
+    if let Some(ticket) = manifest_ticket {
+        match Manifest::new(&ticket, iroh.clone()).await {
+            Ok(manifest) => {
+                info!(namespace = %manifest.doc.id(), "opened manifest");
+                let saved_ticket = ticket.map_or_else(|| manifest.ticket(), |t| t.to_string());
+                store_state
+                    .remember_table(MANIFEST_TABLE, manifest.doc.id(), saved_ticket)
+                    .await?;
+                store_state.manifest_updates =
+                    Arc::new(Mutex::new(Some(spawn_manifest_follower(manifest.clone()))));
+                store_state
+                    .system_docs
+                    .write()
+                    .await
+                    .push((MANIFEST_TABLE, manifest.doc.clone()));
+                store_state.manifest = Some(manifest);
+            }
+            Err(e) => warn!(error = %e, "failed to open the manifest, tables are not announced"),
+        }
+    }
+
     // Store a ticket array for client use
     let mut ticket_array = vec![String::new(); 6];
 
     for table_type in TableType::iter() {
         let doc_ticket = tickets.get(table_type.as_ref()).map(|f| f.clone());
         if table_type.as_ref() == "resource" {
-            let resources = Resources::new(&doc_ticket, iroh.clone()).await?;
+            let resources =
+                open_resources(iroh, &doc_ticket, saved.as_ref(), table_type.as_ref()).await?;
             let namespace_id = &resources.doc.id();
 
-            println!("Resource namespace ID: {}", namespace_id);
+            info!(table = "resource", namespace = %namespace_id, "opened table");
 
             let ticket_share_str = &resources.ticket();
-            subscribe_doc(&resources, String::from("resources")).await?;
+            let app_events =
+                subscribe_doc(&resources, table_type.as_ref().to_string(), &store_state).await?;
+            store_state
+                .events
+                .lock()
+                .await
+                .insert(table_type.as_ref().to_string(), app_events);
             ticket_array[0] = ticket_share_str.clone();
 
             if doc_ticket.is_none() {
-                let images_dir = get_images_directory()?;
-                println!("Loading images from directory: {:?}", images_dir);
+                let images_dir = iroh.images_directory()?;
+                info!(?images_dir, "loading images");
                 load_images_to_resources(&resources, &images_dir).await?;
             }
-            store_state.resource = Arc::new(RwLock::new(Some(resources)));
+            store_state
+                .tables
+                .insert(table_type.as_ref(), resources)
+                .await?;
         } else if table_type.as_ref() == "folder" {
-            let folders = Folders::new(&doc_ticket, iroh.clone()).await?;
+            let mut folders = Folders::new(&doc_ticket, iroh.clone()).await?;
+            folders.prefix = table_prefix(iroh, table_type.as_ref());
+            if let Some(scheme) = saved.as_ref().and_then(|s| s.key_scheme("folder")) {
+                folders.set_key_scheme(scheme);
+            }
             let namespace_id = &folders.doc.id();
-            println!("Folder namespace ID: {}", namespace_id);
+            info!(table = "folder", namespace = %namespace_id, "opened table");
 
             let ticket_share_str = &folders.ticket();
-            subscribe_doc(&folders, String::from("folders")).await?;
+            let app_events =
+                subscribe_doc(&folders, table_type.as_ref().to_string(), &store_state).await?;
+            store_state
+                .events
+                .lock()
+                .await
+                .insert(table_type.as_ref().to_string(), app_events);
             ticket_array[1] = ticket_share_str.clone();
 
             if doc_ticket.is_none() {
@@ -198,112 +1862,533 @@ pub async fn create_files(
                     folders.insert_folder(format!("New Folder{}", i)).await?;
                 }
             }
-            store_state.folder = Arc::new(RwLock::new(Some(folders)));
+            store_state
+                .tables
+                .insert(table_type.as_ref(), folders)
+                .await?;
         } else if table_type.as_ref() == "node" {
-            let nodes = Nodes::new(&doc_ticket, iroh.clone()).await?;
+            let mut nodes = Nodes::new(&doc_ticket, iroh.clone()).await?;
+            nodes.prefix = table_prefix(iroh, table_type.as_ref());
             let namespace_id = &nodes.doc.id();
-            println!("Node namespace ID: {}", namespace_id);
+            info!(table = "node", namespace = %namespace_id, "opened table");
 
             let ticket_share_str = &nodes.ticket();
-            subscribe_doc(&nodes, String::from("nodes")).await?;
+            let app_events =
+                subscribe_doc(&nodes, table_type.as_ref().to_string(), &store_state).await?;
+            store_state
+                .events
+                .lock()
+                .await
+                .insert(table_type.as_ref().to_string(), app_events);
             ticket_array[2] = ticket_share_str.clone();
-            store_state.node = Arc::new(RwLock::new(Some(nodes)));
+            store_state
+                .tables
+                .insert(table_type.as_ref(), nodes)
+                .await?;
         } else if table_type.as_ref() == "resource1" {
-            let resources = Resources::new(&doc_ticket, iroh.clone()).await?;
+            let resources =
+                open_resources(iroh, &doc_ticket, saved.as_ref(), table_type.as_ref()).await?;
             let namespace_id = &resources.doc.id();
 
-            println!("Resource1 namespace ID: {}", namespace_id);
+            info!(table = "resource1", namespace = %namespace_id, "opened table");
 
             let ticket_share_str = &resources.ticket();
-            subscribe_doc(&resources, String::from("resources1")).await?;
+            let app_events =
+                subscribe_doc(&resources, table_type.as_ref().to_string(), &store_state).await?;
+            store_state
+                .events
+                .lock()
+                .await
+                .insert(table_type.as_ref().to_string(), app_events);
             ticket_array[3] = ticket_share_str.clone();
 
             if doc_ticket.is_none() {
-                let images_dir = get_images_directory()?;
-                println!("Loading images from directory: {:?}", images_dir);
+                let images_dir = iroh.images_directory()?;
+                info!(?images_dir, "loading images");
                 load_images_to_resources(&resources, &images_dir).await?;
             }
-            store_state.resource1 = Arc::new(RwLock::new(Some(resources)));
+            store_state
+                .tables
+                .insert(table_type.as_ref(), resources)
+                .await?;
         } else if table_type.as_ref() == "resource2" {
-            let resources = Resources::new(&doc_ticket, iroh.clone()).await?;
+            let resources =
+                open_resources(iroh, &doc_ticket, saved.as_ref(), table_type.as_ref()).await?;
             let namespace_id = &resources.doc.id();
 
-            println!("Resource2 namespace ID: {}", namespace_id);
+            info!(table = "resource2", namespace = %namespace_id, "opened table");
 
             let ticket_share_str = &resources.ticket();
-            subscribe_doc(&resources, String::from("resources2")).await?;
+            let app_events =
+                subscribe_doc(&resources, table_type.as_ref().to_string(), &store_state).await?;
+            store_state
+                .events
+                .lock()
+                .await
+                .insert(table_type.as_ref().to_string(), app_events);
             ticket_array[4] = ticket_share_str.clone();
-            store_state.resource2 = Arc::new(RwLock::new(Some(resources)));
+            store_state
+                .tables
+                .insert(table_type.as_ref(), resources)
+                .await?;
         } else if table_type.as_ref() == "resource3" {
-            let resources = Resources::new(&doc_ticket, iroh.clone()).await?;
+            let resources =
+                open_resources(iroh, &doc_ticket, saved.as_ref(), table_type.as_ref()).await?;
             let namespace_id = &resources.doc.id();
 
-            println!("Resource3 namespace ID: {}", namespace_id);
+            info!(table = "resource3", namespace = %namespace_id, "opened table");
 
             let ticket_share_str = &resources.ticket();
-            subscribe_doc(&resources, String::from("resources3")).await?;
+            let app_events =
+                subscribe_doc(&resources, table_type.as_ref().to_string(), &store_state).await?;
+            store_state
+                .events
+                .lock()
+                .await
+                .insert(table_type.as_ref().to_string(), app_events);
             ticket_array[5] = ticket_share_str.clone();
-            store_state.resource3 = Arc::new(RwLock::new(Some(resources)));
+            store_state
+                .tables
+                .insert(table_type.as_ref(), resources)
+                .await?;
+        }
+    }
+    for (table_name, ticket) in TICKET_ORDER.iter().zip(ticket_array.iter_mut()) {
+        let Some(doc) = store_state.doc(table_name).await else {
+            continue;
+        };
+        match tickets.get(*table_name) {
+            Some(joined) => {
+                // reopened tables of the server hand out fresh tickets with its current addresses
+                if saved.is_some()
+                    && let Ok(shared) = doc.share(ShareMode::Write, iroh.addr_info()).await
+                {
+                    *ticket = shared.to_string();
+                }
+                store_state
+                    .remember_table(table_name, doc.id(), joined.to_string())
+                    .await?;
+            }
+            None => {
+                store_state
+                    .remember_table(table_name, doc.id(), ticket.clone())
+                    .await?;
+            }
+        }
+        // clients that joined a table have no ticket of their own to announce
+        if let Some(manifest) = &store_state.manifest
+            && !ticket.is_empty()
+        {
+            manifest
+                .publish(table_name, doc.id(), ticket.clone())
+                .await?;
         }
     }
+    if iroh.single_doc() {
+        // every table is in the same doc, one ticket joins them all
+        ticket_array.truncate(1);
+    }
     store_state.ticket_string = ticket_array.join(" ");
+    if let Some(audit) = &store_state.audit {
+        let ticket = audit_ticket.map_or_else(|| audit.ticket(), |t| t.to_string());
+        store_state
+            .remember_table(AUDIT_TABLE, audit.doc.id(), ticket)
+            .await?;
+    }
+    spawn_indexers(&store_state).await;
+    for table in ["resource", "resource1", "resource2", "resource3"] {
+        if let Some(resources) = store_state.tables.get::<Resources>(table).await {
+            spawn_content_fetcher(
+                resources,
+                table.to_string(),
+                store_state.sync_filters.clone(),
+            );
+        }
+    }
+    if let Some(saved) = &saved {
+        for (name, ticket) in saved.tickets(|name| !is_reserved(name)) {
+            if let Err(e) = store_state.join_collection(&name, ticket).await {
+                warn!(collection = %name, error = %e, "failed to reopen collection");
+            }
+        }
+    }
+    for table_name in TICKET_ORDER {
+        if let Some(doc) = store_state.doc(table_name).await {
+            iroh.control
+                .register_table(table_name, doc, table_prefix(iroh, table_name))
+                .await;
+        }
+    }
+    // the access list of the previous start, a new one on a new server,
+    // or the one of the server a client joins
+    let saved_acl = saved
+        .as_ref()
+        .and_then(|saved| saved.tickets(|name| name == ACL_TABLE).remove(ACL_TABLE));
+    let acl_ticket = match saved_acl {
+        Some(ticket) => Some(Some(ticket)),
+        None if tickets.is_empty() => Some(None),
+        None => request_acl_ticket(iroh, &tickets).await.map(Some),
+    };
+    match acl_ticket {
+        Some(ticket) => {
+            if let Err(e) = store_state.open_acl(ticket).await {
+                warn!(error = %e, "failed to open the access list");
+            }
+        }
+        None => info!("no access list found, access is not restricted"),
+    }
+    spawn_quota_enforcer(iroh.clone());
+    if !iroh.anti_entropy.is_zero() {
+        spawn_anti_entropy(
+            iroh.clone(),
+            store_state.tables.clone(),
+            store_state.paused.clone(),
+            store_state.sync_filters.clone(),
+            store_state.system_docs.clone(),
+            iroh.anti_entropy,
+        );
+    }
+    // every peer keeps its own entry in the node table up to date
+    if let Some(nodes) = store_state.tables.get::<Nodes>("node").await {
+        spawn_heartbeat(nodes);
+    }
+    // without a relay there is no relay connection to watch
+    if !iroh.lan_only {
+        spawn_supervisor(
+            iroh.router.endpoint().clone(),
+            store_state.tables.clone(),
+            store_state.event_senders.clone(),
+            store_state.paused.clone(),
+        );
+    }
     Ok(store_state)
 }
 
-/// Traverse and read files in the images directory, and add them to Resources storage
-pub async fn load_images_to_resources(resources: &Resources, images_path: &PathBuf) -> Result<()> {
-    if !images_path.exists() {
-        return Err(anyhow::anyhow!(
-            "Images directory does not exist: {:?}",
-            images_path
-        ));
+// names of tables and docs that cannot be used for collections
+fn is_reserved(name: &str) -> bool {
+    TableType::iter().any(|t| t.as_ref() == name)
+        || name == ACL_TABLE
+        || name == AUDIT_TABLE
+        || name == MANIFEST_TABLE
+}
+
+// ask the peers of the node ticket for a read-only ticket of their access list
+async fn request_acl_ticket(
+    iroh: &IrohNet,
+    tickets: &HashMap<String, DocTicket>,
+) -> Option<DocTicket> {
+    let endpoint = iroh.router.endpoint();
+    let peers = tickets.get("node")?.nodes.iter().map(|addr| addr.id);
+    for peer in peers.filter(|peer| *peer != endpoint.id()) {
+        let request = ControlRequest::RequestTicket {
+            table: ACL_TABLE.to_string(),
+        };
+        let response =
+            tokio::time::timeout(ACL_REQUEST_TIMEOUT, send_control(endpoint, peer, &request)).await;
+        match response {
+            Ok(Ok(ControlResponse::Ticket(ticket))) => match ticket.parse() {
+                Ok(ticket) => return Some(ticket),
+                Err(e) => warn!(peer = %peer.fmt_short(), error = %e, "invalid access list ticket"),
+            },
+            Ok(Ok(response)) => {
+                debug!(peer = %peer.fmt_short(), ?response, "peer has no access list")
+            }
+            Ok(Err(e)) => {
+                warn!(peer = %peer.fmt_short(), error = %e, "failed to ask for the access list")
+            }
+            Err(_) => warn!(peer = %peer.fmt_short(), "timed out asking for the access list"),
+        }
+    }
+    None
+}
+
+// keep the local indexes of every built-in table up to date
+async fn spawn_indexers(state: &StoreState) {
+    if let Some(folders) = state.tables.get::<Folders>("folder").await {
+        index_table(&state.iroh, "folder", &folders);
+        view_table(&state.iroh, "folder", &folders);
+    }
+    if let Some(nodes) = state.tables.get::<Nodes>("node").await {
+        index_table(&state.iroh, "node", &nodes);
+    }
+    for table in ["resource", "resource1", "resource2", "resource3"] {
+        if let Some(resources) = state.tables.get::<Resources>(table).await {
+            index_table(&state.iroh, table, &resources);
+            view_table(&state.iroh, table, &resources);
+        }
     }
+}
+
+// follow a table with the full-text and the metadata index
+fn index_table<Entity>(iroh: &IrohNet, table: &str, cls: &IrohCls<Entity>)
+where
+    Entity: ToBytes<Entity> + Serialize + Clone + for<'a> Deserialize<'a> + Send + Sync + 'static,
+{
+    spawn_indexer(iroh.search.clone(), table.to_string(), cls.clone());
+    spawn_indexer(iroh.sql_index.clone(), table.to_string(), cls.clone());
+}
+
+// join the folder table or a resource table into the folder view
+fn view_table<Entity>(iroh: &IrohNet, table: &str, cls: &IrohCls<Entity>)
+where
+    Entity: ToBytes<Entity> + Serialize + Clone + for<'a> Deserialize<'a> + Send + Sync + 'static,
+{
+    spawn_indexer(iroh.view.clone(), table.to_string(), cls.clone());
+}
+
+/// Traverse files in the images directory and add them to Resources storage, large files are streamed
+pub async fn load_images_to_resources(resources: &Resources, images_path: &PathBuf) -> Result<()> {
+    let importer = DirImporter {
+        path: images_path.clone(),
+    };
+    import_into(resources, importer).await?;
+    Ok(())
+}
 
-    let entries = fs::read_dir(images_path)
-        .with_context(|| format!("Failed to read directory: {:?}", images_path))?;
+/// Entities of one table read by [`read_tables`]
+#[derive(Debug, Clone)]
+pub enum TableListing {
+    Folders(Vec<VersionedEntity<Folder>>),
+    Nodes(Vec<VersionedEntity<Node>>),
+    Resources(Vec<VersionedEntity<Resource>>),
+}
 
-    for entry in entries {
-        let entry = entry?;
-        let path = entry.path();
+/// Join the docs of a ticket bundle read-only, wait for their initial sync and read their
+/// entities, in the order of [`TICKET_ORDER`]
+pub async fn read_tables(
+    iroh: &IrohNet,
+    tickets: HashMap<String, DocTicket>,
+    timeout: Duration,
+) -> Result<Vec<(String, TableListing)>> {
+    let mut listings = Vec::new();
+    for table_name in TICKET_ORDER {
+        let Some(ticket) = tickets.get(table_name) else {
+            continue;
+        };
+        let ticket = Some(read_only_ticket(ticket));
+        import_and_wait(iroh, ticket.clone().unwrap(), timeout).await?;
 
-        // Skip directories and hidden files (like .DS_Store)
-        if path.is_file() && !path.file_name().unwrap().to_string_lossy().starts_with('.') {
-            let file_name = path
-                .file_name()
-                .ok_or_else(|| anyhow::anyhow!("Invalid file name"))?
-                .to_string_lossy()
-                .to_string();
+        let listing = if table_name == "folder" {
+            TableListing::Folders(Folders::new(&ticket, iroh.clone()).await?.search().await?)
+        } else if table_name == "node" {
+            TableListing::Nodes(Nodes::new(&ticket, iroh.clone()).await?.search().await?)
+        } else {
+            TableListing::Resources(
+                Resources::new(&ticket, iroh.clone())
+                    .await?
+                    .search()
+                    .await?,
+            )
+        };
+        info!(table = table_name, "read table");
+        listings.push((table_name.to_string(), listing));
+    }
+    Ok(listings)
+}
 
-            // Read file content
-            let file_content =
-                fs::read(&path).with_context(|| format!("Failed to read file: {:?}", path))?;
+/// Join a resource table read-only, wait for its initial sync and write one resource to disk
+///
+/// See [`Resources::fetch`] for where the content is written
+pub async fn fetch_resource(
+    iroh: &IrohNet,
+    ticket: DocTicket,
+    id: &str,
+    path: Option<PathBuf>,
+    timeout: Duration,
+) -> Result<(Resource, PathBuf, u64)> {
+    let ticket = read_only_ticket(&ticket);
+    import_and_wait(iroh, ticket.clone(), timeout).await?;
+    let resources = Resources::new(&Some(ticket), iroh.clone()).await?;
+    resources.fetch(id, path).await
+}
 
-            println!("Adding file: {} ({} bytes)", file_name, file_content.len());
+// Downgrade a ticket so the doc is joined without write capability
+fn read_only_ticket(ticket: &DocTicket) -> DocTicket {
+    DocTicket::new(
+        Capability::Read(ticket.capability.id()),
+        ticket.nodes.clone(),
+    )
+}
 
-            // Call add_file to add to storage
-            resources
-                .add_file(file_name, file_content)
-                .await
-                .with_context(|| format!("Failed to add file to resources: {:?}", path))?;
+// Import a doc and wait until its entries and content have been downloaded
+async fn import_and_wait(iroh: &IrohNet, ticket: DocTicket, timeout: Duration) -> Result<()> {
+    let (doc, mut events) = iroh.docs.import_and_subscribe(ticket).await?;
+    let wait = async {
+        let mut synced = false;
+        let mut content_ready = false;
+        while let Some(event) = events.next().await {
+            match event? {
+                LiveEvent::SyncFinished(_) => synced = true,
+                LiveEvent::PendingContentReady => content_ready = true,
+                _ => {}
+            }
+            if synced && content_ready {
+                break;
+            }
+        }
+        anyhow::Ok(())
+    };
+    match tokio::time::timeout(timeout, wait).await {
+        Ok(result) => result,
+        Err(_) => {
+            warn!(namespace = %doc.id(), "timed out waiting for sync, listing partial data");
+            Ok(())
         }
     }
-
-    Ok(())
 }
 
-async fn subscribe_doc<'a, T>(table: &T, table_name: String) -> Result<()>
+/// Listen for changes of a table
+///
+/// Returns the receiver of the table's [`AppEvent`]s
+pub async fn subscribe_doc<'a, T>(
+    table: &T,
+    table_name: String,
+    state: &StoreState,
+) -> Result<EventReceiver>
 where
     T: GetProperties,
 {
-    let namespace_id = table.get_doc().id();
+    let (tx, app_events) = event_channel(state.iroh.events, state.dropped_events.clone());
+    subscribe(
+        table.get_doc().clone(),
+        table.key_prefix().to_vec(),
+        table_name,
+        tx,
+        state,
+    )
+    .await?;
+    Ok(app_events)
+}
+
+// start the event loop of a doc, its tasks are handed to the `SubscriptionManager`
+async fn subscribe(
+    doc: Doc,
+    prefix: Vec<u8>,
+    table_name: String,
+    tx: EventSender,
+    state: &StoreState,
+) -> Result<()> {
+    let namespace_id = doc.id();
+    state.sync.expect(&table_name);
     // Listen for document modifications
-    let mut events = table.get_doc().subscribe().await?;
+    let mut events = doc.subscribe().await?;
 
-    let mut event_remote_sync = EventRemoteSync::new(namespace_id, table_name);
-    let events_handle = tokio::spawn(async move {
-        while let Some(Ok(event)) = events.next().await {
-            event_remote_sync.emit_doc_edit(event).await;
+    // events go to the sender of the subscription, so a restart keeps the receiver working
+    let mut event_remote_sync = EventRemoteSync::new(
+        namespace_id,
+        table_name.clone(),
+        state.progress.clone(),
+        state.iroh.scheduler.clone(),
+        state.iroh.peers.clone(),
+        state.pending.clone(),
+        tx.clone(),
+    );
+    event_remote_sync.prefix = prefix.clone();
+    state
+        .event_senders
+        .lock()
+        .await
+        .insert(table_name.clone(), event_remote_sync.tx.clone());
+    let retry_handle = event_remote_sync.retry.spawn(
+        state.iroh.clone(),
+        table_name.clone(),
+        event_remote_sync.tx.clone(),
+    );
+    let acl = state.iroh.acl.clone();
+    let audit = state.audit.clone();
+    let pending = state.pending.clone();
+    let me = state.iroh.docs.author_default().await?;
+    // keys holding an entry, to tell inserts from updates in the audit log
+    let mut known = match &audit {
+        Some(_) => existing_keys(&doc).await?,
+        None => HashSet::new(),
+    };
+    let span =
+        info_span!("table", table = %event_remote_sync.table_name, namespace = %namespace_id);
+    let (source, source_prefix) = (doc.clone(), prefix.clone());
+    let events_handle = tokio::spawn(
+        async move {
+            // the retry scheduler ends with the subscription, also when it is stopped
+            let _retry = AbortOnDropHandle::new(retry_handle);
+            while let Some(Ok(event)) = events.next().await {
+                // writes of other tables sharing the doc are handled by their own subscriptions
+                if let LiveEvent::InsertLocal { entry } | LiveEvent::InsertRemote { entry, .. } =
+                    &event
+                    && !entry.key().starts_with(&prefix)
+                {
+                    continue;
+                }
+                // writes of authors the access list does not allow are ignored and flagged
+                if let LiveEvent::InsertRemote { entry, .. } = &event
+                    && !acl.may_write(&entry.author())
+                {
+                    let key = String::from_utf8_lossy(entry.key()).to_string();
+                    warn!(key, author = %entry.author().fmt_short(), "ignoring unauthorized write");
+                    event_remote_sync
+                        .tx
+                        .send(AppEvent::Unauthorized {
+                            table: event_remote_sync.table_name.clone(),
+                            key,
+                            author: entry.author(),
+                        })
+                        .await;
+                    continue;
+                }
+                // the newest write of a key wins, concurrent ones of other authors are flagged
+                if let LiveEvent::InsertRemote { entry, .. } = &event
+                    && entry.content_len() > 0
+                {
+                    let table = &event_remote_sync.table_name;
+                    let key = String::from_utf8_lossy(entry.key()).to_string();
+                    let unsynced = pending.contains(table, &key).then_some(me);
+                    match history::concurrent_writes(&doc, entry, CONFLICT_WINDOW, unsynced).await {
+                        Ok(others) if !others.is_empty() => {
+                            let mut versions = others;
+                            versions.push(HistoryEntry::from(entry));
+                            versions.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+                            let authors = versions.iter().map(|v| v.author).collect();
+                            info!(key, writes = versions.len(), "concurrent writes");
+                            event_remote_sync
+                                .tx
+                                .send(AppEvent::Conflict {
+                                    table: table.clone(),
+                                    key,
+                                    authors,
+                                })
+                                .await;
+                        }
+                        Ok(_) => {}
+                        Err(e) => warn!(key, error = %e, "failed to check for conflicts"),
+                    }
+                }
+                if let Some(audit) = &audit {
+                    let written = match &event {
+                        LiveEvent::InsertLocal { entry } => Some((entry, false)),
+                        LiveEvent::InsertRemote { entry, .. } => Some((entry, true)),
+                        _ => None,
+                    };
+                    if let Some((entry, remote)) = written {
+                        let table = &event_remote_sync.table_name;
+                        let existed = if entry.content_len() == 0 {
+                            known.remove(entry.key())
+                        } else {
+                            !known.insert(entry.key().to_vec())
+                        };
+                        // a node refreshing its own entry is a heartbeat, only joins are kept
+                        let heartbeat = table == "node" && existed && entry.content_len() > 0;
+                        if !heartbeat {
+                            audit.record(table, entry, existed, remote);
+                        }
+                    }
+                }
+                event_remote_sync.emit_doc_edit(event).await;
+            }
         }
-    });
+        .instrument(span),
+    );
+    state
+        .subscriptions
+        .insert(&table_name, source, source_prefix, tx, events_handle)
+        .await;
     Ok(())
 }
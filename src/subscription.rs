@@ -0,0 +1,139 @@
+use std::{collections::HashMap, sync::Arc};
+
+use iroh_docs::api::Doc;
+use tokio::{sync::Mutex, task::JoinHandle};
+use tracing::{debug, info};
+
+use crate::doc_subcribe::AppEvent;
+use crate::event_channel::EventSender;
+
+/// A doc subscription of a table, see [`crate::store::subscribe_doc`]
+struct Subscription {
+    doc: Doc,
+    // prefix of the table's keys inside the doc
+    prefix: Vec<u8>,
+    // kept across restarts, so the receiver taken by a consumer keeps getting events
+    tx: EventSender,
+    // the event loop, `None` while stopped
+    task: Option<JoinHandle<()>>,
+}
+
+impl Subscription {
+    fn abort(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        self.task.as_ref().is_some_and(|task| !task.is_finished())
+    }
+}
+
+/// What a stopped subscription is restarted from
+pub struct SubscriptionSource {
+    pub doc: Doc,
+    pub prefix: Vec<u8>,
+    pub tx: EventSender,
+}
+
+/// Owns the tasks processing the doc events of the subscribed tables, keyed by the name
+/// their events and progress are published under
+///
+/// Stopping a subscription leaves the doc syncing, its changes are no longer processed:
+/// no [`AppEvent`] is sent and announced content is not downloaded until it is restarted
+#[derive(Clone, Default)]
+pub struct SubscriptionManager {
+    subscriptions: Arc<Mutex<HashMap<String, Subscription>>>,
+}
+
+impl SubscriptionManager {
+    /// Take over the task of a subscription, a previous one under `name` is aborted
+    pub async fn insert(
+        &self,
+        name: &str,
+        doc: Doc,
+        prefix: Vec<u8>,
+        tx: EventSender,
+        task: JoinHandle<()>,
+    ) {
+        let subscription = Subscription {
+            doc,
+            prefix,
+            tx,
+            task: Some(task),
+        };
+        if let Some(mut previous) = self
+            .subscriptions
+            .lock()
+            .await
+            .insert(name.to_string(), subscription)
+        {
+            previous.abort();
+        }
+    }
+
+    /// Abort the task of a subscription, returns whether it was running or `None` if there
+    /// is no subscription under `name`
+    pub async fn stop(&self, name: &str) -> Option<bool> {
+        let mut subscriptions = self.subscriptions.lock().await;
+        let subscription = subscriptions.get_mut(name)?;
+        let running = subscription.is_running();
+        subscription.abort();
+        info!(table = name, "stopped subscription");
+        Some(running)
+    }
+
+    /// Abort a subscription and forget it, it can not be restarted afterwards
+    pub async fn remove(&self, name: &str) -> bool {
+        match self.subscriptions.lock().await.remove(name) {
+            Some(mut subscription) => {
+                subscription.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The doc, key prefix and event sender to restart a subscription with, after stopping it
+    pub async fn source(&self, name: &str) -> Option<SubscriptionSource> {
+        let mut subscriptions = self.subscriptions.lock().await;
+        let subscription = subscriptions.get_mut(name)?;
+        subscription.abort();
+        Some(SubscriptionSource {
+            doc: subscription.doc.clone(),
+            prefix: subscription.prefix.clone(),
+            tx: subscription.tx.clone(),
+        })
+    }
+
+    /// Subscribed names and whether their tasks are running, sorted by name
+    pub async fn list(&self) -> Vec<(String, bool)> {
+        let mut names: Vec<(String, bool)> = self
+            .subscriptions
+            .lock()
+            .await
+            .iter()
+            .map(|(name, subscription)| (name.clone(), subscription.is_running()))
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Abort every subscription and wait for their tasks to end, before the node shuts down
+    pub async fn shutdown(&self) {
+        let tasks: Vec<JoinHandle<()>> = self
+            .subscriptions
+            .lock()
+            .await
+            .drain()
+            .filter_map(|(_, subscription)| subscription.task)
+            .collect();
+        for task in &tasks {
+            task.abort();
+        }
+        let count = tasks.len();
+        futures::future::join_all(tasks).await;
+        debug!(tasks = count, "subscriptions shut down");
+    }
+}
@@ -0,0 +1,251 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use futures::{StreamExt, future::BoxFuture};
+use iroh_docs::{
+    AuthorId, Entry, NamespaceId,
+    api::{Doc, protocol::ShareMode},
+    engine::LiveEvent,
+    store::Query,
+};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::{iroh_create_author, server::IrohNet};
+
+// key prefixes used inside a derived doc
+pub const FOLDER_PREFIX: &str = "folder/";
+pub const RESOURCE_PREFIX: &str = "resource/";
+
+type EntryFilter = Arc<dyn Fn(Entry) -> BoxFuture<'static, bool> + Send + Sync>;
+
+/// A table mirrored into a derived doc
+#[derive(Clone)]
+pub struct Mirror {
+    pub source: Doc,
+    // prefix of the mirrored keys in the derived doc
+    pub prefix: &'static str,
+    // prefix of the table in a shared source doc, only its entries are mirrored, without it
+    pub table_prefix: Vec<u8>,
+    // whether an entry of the source is mirrored, entries that stop matching are removed
+    pub include: EntryFilter,
+}
+
+impl Mirror {
+    /// Mirror the entries of `source` whose key satisfies `include` under `prefix`
+    pub fn with_prefix(
+        source: Doc,
+        prefix: &'static str,
+        include: impl Fn(&[u8]) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        Self::with_filter(source, prefix, move |entry| {
+            let included = include(entry.key());
+            Box::pin(async move { included })
+        })
+    }
+
+    /// Like [`Mirror::with_prefix`], deciding on the whole entry, e.g. its decoded entity
+    pub fn with_filter(
+        source: Doc,
+        prefix: &'static str,
+        include: impl Fn(Entry) -> BoxFuture<'static, bool> + Send + Sync + 'static,
+    ) -> Self {
+        Mirror {
+            source,
+            prefix,
+            table_prefix: Vec::new(),
+            include: Arc::new(include),
+        }
+    }
+
+    /// Mirror only the entries of the table with `table_prefix`, see [`table_prefix`]
+    ///
+    /// [`table_prefix`]: crate::store::table_prefix
+    pub fn of_table(mut self, table_prefix: Vec<u8>) -> Self {
+        self.table_prefix = table_prefix;
+        self
+    }
+
+    // write `entry` into `to` if it is included, remove it from `to` otherwise
+    async fn apply(&self, iroh: &IrohNet, to: &Doc, author: AuthorId, entry: Entry) -> Result<()> {
+        let Some(table_key) = entry.key().strip_prefix(self.table_prefix.as_slice()) else {
+            return Ok(());
+        };
+        let key = [self.prefix.as_bytes(), table_key].concat();
+        if entry.content_len() > 0 && (self.include)(entry.clone()).await {
+            copy_entry(iroh, self.source.id(), to, author, key, &entry).await?;
+            return Ok(());
+        }
+        let current = to
+            .get_one(Query::single_latest_per_key().key_exact(&key))
+            .await?;
+        if current.is_some_and(|c| c.content_len() > 0) {
+            to.del(author, key).await?;
+        }
+        Ok(())
+    }
+}
+
+/// A read-only doc holding a subset of the main tables, kept up to date by bridge tasks
+pub struct SubShare {
+    pub doc: Doc,
+    pub ticket: String,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl Drop for SubShare {
+    fn drop(&mut self) {
+        for handle in &self.handles {
+            handle.abort();
+        }
+    }
+}
+
+/// Create a derived doc from `mirrors` and forward every change of the sources into it
+///
+/// The ticket only grants read access, changes of the peers never reach the sources
+pub async fn create_sub_share(iroh: &IrohNet, mirrors: Vec<Mirror>) -> Result<SubShare> {
+    let author = iroh_create_author(iroh).await?;
+    let doc = iroh.docs.create().await?;
+    info!(namespace = %doc.id(), "created sub-share doc");
+
+    let mut handles = Vec::new();
+    for mirror in mirrors {
+        // subscribe before copying so no change in between is lost
+        handles.push(bridge(iroh.clone(), mirror.clone(), doc.clone(), author).await?);
+
+        let entries = mirror
+            .source
+            .get_many(Query::single_latest_per_key())
+            .await?;
+        let entries = entries.collect::<Vec<Result<Entry>>>().await;
+        for entry in entries.into_iter().map_while(Result::ok) {
+            mirror.apply(iroh, &doc, author, entry).await?;
+        }
+    }
+
+    let ticket = doc
+        .share(ShareMode::Read, iroh.addr_info())
+        .await?
+        .to_string();
+    Ok(SubShare {
+        doc,
+        ticket,
+        handles,
+    })
+}
+
+// forward every change of the mirror's source into `to`
+async fn bridge(
+    iroh: IrohNet,
+    mirror: Mirror,
+    to: Doc,
+    author: AuthorId,
+) -> Result<JoinHandle<()>> {
+    let mut events = mirror.source.subscribe().await?;
+    Ok(tokio::spawn(async move {
+        while let Some(Ok(event)) = events.next().await {
+            let entry = match event {
+                LiveEvent::InsertLocal { entry } | LiveEvent::InsertRemote { entry, .. } => entry,
+                _ => continue,
+            };
+            if let Err(e) = mirror.apply(&iroh, &to, author, entry).await {
+                warn!(to = %to.id(), error = %e, "failed to forward entry");
+            }
+        }
+    }))
+}
+
+/// What [`copy_entries`] did
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CopyReport {
+    pub copied: usize,
+    // already held with the same content by the target
+    pub unchanged: usize,
+    // copied, but the content is not stored locally, so the peers of the target can only
+    // get it from other nodes that have it
+    pub missing_content: usize,
+}
+
+/// Write the latest entries of `from` accepted by `map` into `to` under the mapped key
+///
+/// The entries are written by `author`, content is shared by hash and not copied. With a
+/// cipher values are bound to their entry, so they are sealed again for `to` and entries
+/// whose content is not stored locally are skipped
+pub async fn copy_entries(
+    iroh: &IrohNet,
+    from: &Doc,
+    to: &Doc,
+    author: AuthorId,
+    map: impl Fn(&[u8]) -> Option<Vec<u8>>,
+) -> Result<CopyReport> {
+    let entries = from.get_many(Query::single_latest_per_key()).await?;
+    let entries = entries.collect::<Vec<Result<Entry>>>().await;
+    let blobs = iroh.blobs_store.blobs();
+    let mut report = CopyReport::default();
+    for entry in entries.into_iter().map_while(Result::ok) {
+        // deleted entries are not carried over
+        if entry.content_len() == 0 {
+            continue;
+        }
+        let Some(key) = map(entry.key()) else {
+            continue;
+        };
+        let local = blobs.has(entry.content_hash()).await?;
+        if !local && iroh.cipher.is_some() {
+            report.missing_content += 1;
+            continue;
+        }
+        if !copy_entry(iroh, from.id(), to, author, key, &entry).await? {
+            report.unchanged += 1;
+            continue;
+        }
+        report.copied += 1;
+        if !local {
+            report.missing_content += 1;
+        }
+    }
+    info!(from = %from.id(), to = %to.id(), copied = report.copied, "copied entries");
+    Ok(report)
+}
+
+// write `entry` of the doc `from` under `key` unless `doc` already holds the same content,
+// which stops echo loops
+//
+// returns whether the entry was written
+async fn copy_entry(
+    iroh: &IrohNet,
+    from: NamespaceId,
+    doc: &Doc,
+    author: AuthorId,
+    key: Vec<u8>,
+    entry: &Entry,
+) -> Result<bool> {
+    let current = doc
+        .get_one(Query::single_latest_per_key().key_exact(&key))
+        .await?;
+    let Some(cipher) = iroh.cipher.as_ref() else {
+        if current.is_some_and(|c| c.content_hash() == entry.content_hash()) {
+            return Ok(false);
+        }
+        doc.set_hash(author, key, entry.content_hash(), entry.content_len())
+            .await?;
+        return Ok(true);
+    };
+
+    // sealed values only open in their own entry, so the plain values are compared
+    let blobs = iroh.blobs_store.blobs();
+    let content = blobs.get_bytes(entry.content_hash()).await?;
+    let content = cipher.decrypt(from, entry.key(), &content)?;
+    if let Some(current) = current.filter(|c| c.content_len() > 0)
+        && let Ok(bytes) = blobs.get_bytes(current.content_hash()).await
+        && cipher
+            .decrypt(doc.id(), &key, &bytes)
+            .is_ok_and(|plain| plain == content)
+    {
+        return Ok(false);
+    }
+    let content = cipher.encrypt(doc.id(), &key, &content)?;
+    doc.set_bytes(author, key, content).await?;
+    Ok(true)
+}
@@ -0,0 +1,114 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use iroh::{Endpoint, EndpointAddr, PublicKey, Watcher};
+use iroh_docs::api::Doc;
+use tokio::{
+    sync::{Mutex, RwLock},
+    task::JoinHandle,
+};
+use tracing::{debug, info, warn};
+
+use crate::doc_subcribe::AppEvent;
+use crate::event_channel::EventSender;
+use crate::tables::Tables;
+
+// application event senders of the subscribed tables, keyed by table name
+pub type EventSenders = Arc<Mutex<HashMap<String, EventSender>>>;
+
+// tables whose sync was paused on purpose and is not restarted on reconnect
+pub type PausedTables = Arc<RwLock<HashSet<String>>>;
+
+/// Watch the home relay of `endpoint` and resync all tables once it is reachable again
+///
+/// Sync stalls while the relay is gone, because the gossip neighbors of the docs drop.
+/// When a home relay is back, every open doc restarts syncing with the peers it synced
+/// with before, unless it is in `paused`. The subscribed tables are told through [`AppEvent::Disconnected`] and
+/// [`AppEvent::Reconnected`]
+pub fn spawn_supervisor(
+    endpoint: Endpoint,
+    tables: Tables,
+    events: EventSenders,
+    paused: PausedTables,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut watcher = endpoint.watch_addr();
+        let mut connected = watcher.get().relay_urls().next().is_some();
+        while let Ok(addr) = watcher.updated().await {
+            let now = addr.relay_urls().next().is_some();
+            if now == connected {
+                continue;
+            }
+            connected = now;
+            if connected {
+                info!("home relay reachable again, resyncing tables");
+                resync(&endpoint, &tables, &events, &paused).await;
+            } else {
+                warn!("home relay lost, sync stalls until it is back");
+                notify(&events, |table| AppEvent::Disconnected { table }).await;
+            }
+        }
+        debug!("endpoint closed, connection supervisor stopped");
+    })
+}
+
+// restart sync of every open doc that is not paused with its previous sync peers
+async fn resync(
+    endpoint: &Endpoint,
+    tables: &Tables,
+    events: &EventSenders,
+    paused: &PausedTables,
+) {
+    let me = endpoint.id();
+    for name in tables.names().await {
+        if paused.read().await.contains(&name) {
+            continue;
+        }
+        let Some(doc) = tables.doc(&name).await else {
+            continue;
+        };
+        let peers = match previous_peers(&doc, me).await {
+            Ok(peers) => peers,
+            Err(e) => {
+                warn!(table = %name, error = %e, "failed to read sync peers");
+                vec![]
+            }
+        };
+        debug!(table = %name, peers = peers.len(), "restarting sync");
+        if let Err(e) = doc.start_sync(peers).await {
+            warn!(table = %name, error = %e, "failed to restart sync");
+        }
+    }
+    notify(events, |table| AppEvent::Reconnected { table }).await;
+}
+
+/// Peers `doc` synced with before, other than `me`, to restart its sync with
+pub async fn previous_peers(doc: &Doc, me: PublicKey) -> Result<Vec<EndpointAddr>> {
+    Ok(doc
+        .get_sync_peers()
+        .await?
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|bytes| PublicKey::from_bytes(bytes).ok())
+        .filter(|id| *id != me)
+        .map(EndpointAddr::new)
+        .collect())
+}
+
+// send an event to every subscribed table
+//
+// never waits for a full channel, a table nobody reads must not hold up the others
+async fn notify(events: &EventSenders, event: impl Fn(String) -> AppEvent) {
+    let senders: Vec<(String, EventSender)> = events
+        .lock()
+        .await
+        .iter()
+        .map(|(table, tx)| (table.clone(), tx.clone()))
+        .collect();
+    for (table, tx) in senders {
+        tx.try_send(event(table));
+    }
+}
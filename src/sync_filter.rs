@@ -0,0 +1,60 @@
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+// sync filters of the tables that have one, keyed by table name
+pub type SyncFilters = Arc<RwLock<HashMap<String, SyncFilter>>>;
+
+/// Which resources of a table get their content downloaded
+///
+/// The records of all resources are synced, so every file is listed with its name and size.
+/// The placement blobs of the ones that do not match are not downloaded. All set conditions
+/// have to match
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SyncFilter {
+    // names starting with one of these, any name when empty
+    pub prefixes: Vec<String>,
+    // largest content downloaded in bytes
+    pub max_size: Option<u64>,
+    // file extensions of the name, compared without case, any when empty
+    pub extensions: Vec<String>,
+}
+
+impl SyncFilter {
+    /// Whether the filter lets every entry through
+    pub fn is_empty(&self) -> bool {
+        self.prefixes.is_empty() && self.max_size.is_none() && self.extensions.is_empty()
+    }
+
+    /// Whether the content of a resource named `name` with `size` bytes is downloaded
+    pub fn matches(&self, name: &str, size: u64) -> bool {
+        let prefix = self.prefixes.is_empty() || self.prefixes.iter().any(|p| name.starts_with(p));
+        let size = self.max_size.is_none_or(|max| size <= max);
+        let extension = self.extensions.is_empty()
+            || Path::new(name)
+                .extension()
+                .map(|e| e.to_string_lossy().to_lowercase())
+                .is_some_and(|e| self.extensions.contains(&e));
+        prefix && size && extension
+    }
+}
+
+impl std::fmt::Display for SyncFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = vec![];
+        if !self.prefixes.is_empty() {
+            parts.push(format!("prefix {}", self.prefixes.join(", ")));
+        }
+        if let Some(max) = self.max_size {
+            parts.push(format!("at most {} bytes", max));
+        }
+        if !self.extensions.is_empty() {
+            parts.push(format!("extension {}", self.extensions.join(", ")));
+        }
+        if parts.is_empty() {
+            return f.write_str("everything");
+        }
+        f.write_str(&parts.join(", "))
+    }
+}
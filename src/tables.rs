@@ -0,0 +1,156 @@
+use std::{any::Any, collections::HashMap, ops::Deref, path::Path, sync::Arc};
+
+use anyhow::{Result, ensure};
+use futures::future::BoxFuture;
+use iroh_docs::api::Doc;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::export::{export_table, import_table};
+use crate::store::{EntryInfo, IrohCls, IrohProperties, MigrateReport, ToBytes};
+
+/// Operations every table supports, whatever its entity type
+pub trait Table: Send + Sync {
+    fn doc(&self) -> &Doc;
+
+    /// Prefix of the table's keys in its doc, empty unless the doc is shared
+    fn prefix(&self) -> &[u8];
+
+    // the concrete table, for typed access through `Tables::get`
+    fn as_any(&self) -> &dyn Any;
+
+    fn list_entries(&self) -> BoxFuture<'_, Result<Vec<EntryInfo>>>;
+
+    /// Dump all entities to a JSON file, see [`export_table`]
+    fn export<'a>(&'a self, name: &'a str, path: &'a Path) -> BoxFuture<'a, Result<usize>>;
+
+    /// Restore entities dumped with [`Table::export`], see [`import_table`]
+    fn import<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<usize>>;
+
+    /// Rewrite outdated entries, see [`IrohCls::migrate`]
+    fn migrate(&self) -> BoxFuture<'_, Result<MigrateReport>>;
+}
+
+// every table type wraps an `IrohCls`, e.g. `Resources`
+impl<T, Entity> Table for T
+where
+    T: Deref<Target = IrohCls<Entity>> + Send + Sync + 'static,
+    Entity: ToBytes<Entity> + Serialize + Clone + for<'a> Deserialize<'a> + Send + Sync + 'static,
+{
+    fn doc(&self) -> &Doc {
+        &self.doc
+    }
+
+    fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn list_entries(&self) -> BoxFuture<'_, Result<Vec<EntryInfo>>> {
+        Box::pin(IrohProperties::list_entries(&**self))
+    }
+
+    fn export<'a>(&'a self, name: &'a str, path: &'a Path) -> BoxFuture<'a, Result<usize>> {
+        Box::pin(export_table(self, name, path))
+    }
+
+    fn import<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<usize>> {
+        Box::pin(import_table(self, path))
+    }
+
+    fn migrate(&self) -> BoxFuture<'_, Result<MigrateReport>> {
+        Box::pin(IrohCls::migrate(self))
+    }
+}
+
+/// Open tables keyed by name, the built-in ones as well as collections created at runtime
+///
+/// ```ignore
+/// let resources = state.tables.get::<Resources>("resource").await;
+/// ```
+#[derive(Clone, Default)]
+pub struct Tables {
+    tables: Arc<RwLock<HashMap<String, Arc<dyn Table>>>>,
+}
+
+impl Tables {
+    /// Add a table under `name`, which must not be taken yet
+    pub async fn insert(&self, name: &str, table: impl Table + 'static) -> Result<()> {
+        let mut tables = self.tables.write().await;
+        ensure!(
+            !tables.contains_key(name),
+            "Table '{}' already exists",
+            name
+        );
+        tables.insert(name.to_string(), Arc::new(table));
+        Ok(())
+    }
+
+    /// The table called `name` if it is open and of type `T`
+    pub async fn get<T: Clone + 'static>(&self, name: &str) -> Option<T> {
+        self.tables
+            .read()
+            .await
+            .get(name)?
+            .as_any()
+            .downcast_ref::<T>()
+            .cloned()
+    }
+
+    /// Like [`Tables::get`], with an error naming what is missing
+    pub async fn require<T: Clone + 'static>(&self, name: &str) -> Result<T> {
+        let table = self.table(name).await?;
+        table
+            .as_any()
+            .downcast_ref::<T>()
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Table '{}' holds another kind of entity", name))
+    }
+
+    /// The table called `name`, without knowing its type
+    pub async fn table(&self, name: &str) -> Result<Arc<dyn Table>> {
+        self.tables
+            .read()
+            .await
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Table '{}' is not open", name))
+    }
+
+    /// Remove the table called `name`, returns it if it was open
+    pub async fn remove(&self, name: &str) -> Option<Arc<dyn Table>> {
+        self.tables.write().await.remove(name)
+    }
+
+    pub async fn contains(&self, name: &str) -> bool {
+        self.tables.read().await.contains_key(name)
+    }
+
+    /// Doc backing the table called `name`
+    pub async fn doc(&self, name: &str) -> Option<Doc> {
+        self.tables
+            .read()
+            .await
+            .get(name)
+            .map(|table| table.doc().clone())
+    }
+
+    /// Prefix of the keys of the table called `name` in its doc, see [`Table::prefix`]
+    pub async fn prefix(&self, name: &str) -> Option<Vec<u8>> {
+        self.tables
+            .read()
+            .await
+            .get(name)
+            .map(|table| table.prefix().to_vec())
+    }
+
+    /// Names of all open tables, sorted
+    pub async fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.tables.read().await.keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
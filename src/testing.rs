@@ -0,0 +1,173 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::{Result, ensure};
+use iroh_blobs::Hash;
+use tracing::info;
+
+use crate::{
+    RelayChoice, generate_private_key,
+    model::resource::Resources,
+    server::{IrohNet, IrohNetBuilder, StorageMode},
+    store::{StoreState, TICKET_ORDER, create_files},
+};
+
+// how often convergence is checked while waiting
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Nodes running inside one process, for integration tests
+///
+/// Every node keeps its data in memory and listens on localhost without a relay.
+/// The first node creates and seeds the tables like `server`, the others join it like `client`
+pub struct TestNetwork {
+    pub nodes: Vec<StoreState>,
+}
+
+impl TestNetwork {
+    /// Start `count` nodes joined to the tables of the first one
+    pub async fn spawn(count: usize) -> Result<Self> {
+        ensure!(count > 0, "A test network needs at least one node");
+        let first = create_files(&spawn_node().await?, None).await?;
+        let tickets: Vec<&str> = first.ticket_string.split_whitespace().collect();
+        let mut nodes = vec![];
+        for _ in 1..count {
+            let mut map = HashMap::new();
+            for (table, ticket) in TICKET_ORDER.iter().zip(&tickets) {
+                map.insert(table.to_string(), ticket.parse()?);
+            }
+            nodes.push(create_files(&spawn_node().await?, Some(map)).await?);
+        }
+        nodes.insert(0, first);
+        info!(nodes = count, "test network started");
+        Ok(TestNetwork { nodes })
+    }
+
+    pub fn node(&self, index: usize) -> &StoreState {
+        &self.nodes[index]
+    }
+
+    /// Wait until every joined node finished its initial sync
+    pub async fn wait_initial_sync(&self, timeout: Duration) -> Result<()> {
+        let waits = self.nodes[1..]
+            .iter()
+            .map(|node| node.sync.wait_for_initial_sync());
+        tokio::time::timeout(timeout, futures::future::join_all(waits))
+            .await
+            .map_err(|_| anyhow::anyhow!("Initial sync did not finish within {:?}", timeout))?;
+        Ok(())
+    }
+
+    /// Wait until all nodes hold the same entries of `table` and the content of all of them,
+    /// including the placement blobs of resources
+    pub async fn wait_converged(&self, table: &str, timeout: Duration) -> Result<()> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let differences = self.differences(table).await?;
+            if differences.is_empty() {
+                return Ok(());
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Err(anyhow::anyhow!(
+                    "Table '{}' did not converge within {:?}: {}",
+                    table,
+                    timeout,
+                    differences.join(", ")
+                ));
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Wait until every built-in table converged, see [`TestNetwork::wait_converged`]
+    pub async fn wait_all_converged(&self, timeout: Duration) -> Result<()> {
+        for table in TICKET_ORDER {
+            self.wait_converged(table, timeout).await?;
+        }
+        Ok(())
+    }
+
+    /// Fail unless all nodes hold the same entries of `table` right now
+    pub async fn assert_converged(&self, table: &str) -> Result<()> {
+        let differences = self.differences(table).await?;
+        ensure!(
+            differences.is_empty(),
+            "Table '{}' differs between nodes: {}",
+            table,
+            differences.join(", ")
+        );
+        Ok(())
+    }
+
+    /// Shut down the routers of all nodes
+    pub async fn shutdown(self) -> Result<()> {
+        for node in self.nodes {
+            node.iroh.router.shutdown().await?;
+        }
+        Ok(())
+    }
+
+    // how every node differs from the first one, empty once they converged
+    async fn differences(&self, table: &str) -> Result<Vec<String>> {
+        let expected = entries(&self.nodes[0], table).await?;
+        let mut differences = vec![];
+        for (index, node) in self.nodes.iter().enumerate().skip(1) {
+            let actual = entries(node, table).await?;
+            let missing = expected.difference(&actual).count();
+            let extra = actual.difference(&expected).count();
+            if missing > 0 || extra > 0 {
+                differences.push(format!(
+                    "node {} misses {} and has {} other entries",
+                    index, missing, extra
+                ));
+                continue;
+            }
+            let blobs = node.iroh.blobs_store.blobs();
+            let mut absent = 0;
+            for (_, hash) in &actual {
+                if !blobs.has(*hash).await? {
+                    absent += 1;
+                }
+            }
+            if absent > 0 {
+                differences.push(format!(
+                    "node {} misses the content of {} entries",
+                    index, absent
+                ));
+                continue;
+            }
+            // resources hold their files in placement blobs next to the entry content
+            if let Some(resources) = node.tables.get::<Resources>(table).await {
+                let missing = resources.count_missing_content(None).await?;
+                if missing > 0 {
+                    differences.push(format!("node {} misses {} placement blobs", index, missing));
+                }
+            }
+        }
+        Ok(differences)
+    }
+}
+
+// key and content hash of every entry of `table`
+async fn entries(node: &StoreState, table: &str) -> Result<BTreeSet<(String, Hash)>> {
+    Ok(node
+        .list_entries(table)
+        .await?
+        .into_iter()
+        .map(|entry| (entry.key, entry.hash))
+        .collect())
+}
+
+// a node on localhost that keeps everything in memory, seeding from the bundled images
+async fn spawn_node() -> Result<IrohNet> {
+    let mut node = IrohNetBuilder::new(generate_private_key())
+        .storage(StorageMode::Memory)
+        .relay(RelayChoice::Disabled)
+        .bind_addr("127.0.0.1:0".parse()?)
+        .spawn()
+        .await?;
+    node.images_dir = Some(Path::new(env!("CARGO_MANIFEST_DIR")).join("images"));
+    Ok(node)
+}
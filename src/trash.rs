@@ -0,0 +1,152 @@
+use anyhow::{Result, ensure};
+use futures::StreamExt;
+use iroh_docs::{AuthorId, Entry, api::Doc, store::Query};
+use serde::{Deserialize, Serialize};
+
+use crate::{crypto, now_millis, server::IrohNet};
+
+// marks an entry whose entity has been moved to the trash
+const TOMBSTONE_MAGIC: [u8; 4] = [0xfb, b't', b'm', b'b'];
+
+/// Replaces a deleted entity so it can be listed and restored later
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tombstone {
+    pub deleted_by: AuthorId,
+    // deletion time in milliseconds since the unix epoch
+    pub deleted_at: u64,
+    // entry content before the deletion, exactly as it was stored
+    #[serde(with = "crate::export::base64_bytes")]
+    pub content: Vec<u8>,
+}
+
+impl Tombstone {
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut buf = TOMBSTONE_MAGIC.to_vec();
+        bincode::serialize_into(&mut buf, self)?;
+        Ok(buf)
+    }
+
+    /// Parse a tombstone, `None` if `bytes` hold a regular entity
+    pub fn from_bytes(bytes: &[u8]) -> Result<Option<Self>> {
+        match bytes.strip_prefix(&TOMBSTONE_MAGIC) {
+            Some(rest) => Ok(Some(bincode::deserialize(rest)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Whether decrypted entry content is a [`Tombstone`]
+pub fn is_tombstone(bytes: &[u8]) -> bool {
+    bytes.starts_with(&TOMBSTONE_MAGIC)
+}
+
+/// Whether `entry` leaves its key without an entity, because it is empty or a [`Tombstone`]
+///
+/// Only the content is checked, no entity is decoded. Content that has not been downloaded
+/// yet counts as an entity
+pub async fn is_deleted(node: &IrohNet, entry: &Entry) -> Result<bool> {
+    if entry.content_len() == 0 {
+        return Ok(true);
+    }
+    let Ok(bytes) = node
+        .blobs_store
+        .blobs()
+        .get_bytes(entry.content_hash())
+        .await
+    else {
+        return Ok(false);
+    };
+    Ok(is_tombstone(&crypto::open(
+        node.cipher.as_ref(),
+        entry.id().namespace(),
+        entry.key(),
+        bytes.to_vec(),
+    )?))
+}
+
+/// Move the entity stored under `key` to the trash
+pub async fn delete(node: &IrohNet, doc: &Doc, author: AuthorId, key: &[u8]) -> Result<()> {
+    let entry = latest(doc, key)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("'{}' does not exist", String::from_utf8_lossy(key)))?;
+    let content = node
+        .blobs_store
+        .blobs()
+        .get_bytes(entry.content_hash())
+        .await?
+        .to_vec();
+    ensure!(
+        !is_tombstone(&crypto::open(
+            node.cipher.as_ref(),
+            doc.id(),
+            key,
+            content.clone()
+        )?),
+        "'{}' is already in the trash",
+        String::from_utf8_lossy(key)
+    );
+    let tombstone = Tombstone {
+        deleted_by: author,
+        deleted_at: now_millis(),
+        content,
+    };
+    let bytes = crypto::seal(node.cipher.as_ref(), doc.id(), key, tombstone.to_bytes()?)?;
+    doc.set_bytes(author, key.to_vec(), bytes).await?;
+    Ok(())
+}
+
+/// Bring back an entity moved to the trash with [`delete`]
+pub async fn restore(node: &IrohNet, doc: &Doc, author: AuthorId, key: &[u8]) -> Result<()> {
+    let tombstone = match latest(doc, key).await? {
+        Some(entry) => read_tombstone(node, &entry).await?,
+        None => None,
+    };
+    let tombstone = tombstone
+        .ok_or_else(|| anyhow::anyhow!("'{}' is not in the trash", String::from_utf8_lossy(key)))?;
+    doc.set_bytes(author, key.to_vec(), tombstone.content)
+        .await?;
+    Ok(())
+}
+
+/// Keys and tombstones of all entities in the trash of a doc
+pub async fn list(node: &IrohNet, doc: &Doc) -> Result<Vec<(String, Tombstone)>> {
+    let entries = doc.get_many(Query::single_latest_per_key()).await?;
+    let entries = entries.collect::<Vec<Result<Entry>>>().await;
+    let mut trash = Vec::new();
+    for entry in entries.into_iter().map_while(Result::ok) {
+        if let Some(tombstone) = read_tombstone(node, &entry).await? {
+            trash.push((String::from_utf8_lossy(entry.key()).to_string(), tombstone));
+        }
+    }
+    Ok(trash)
+}
+
+// latest non-empty entry of `key`
+async fn latest(doc: &Doc, key: &[u8]) -> Result<Option<Entry>> {
+    let entry = doc
+        .get_one(Query::single_latest_per_key().key_exact(key))
+        .await?;
+    Ok(entry.filter(|e| e.content_len() > 0))
+}
+
+async fn read_tombstone(node: &IrohNet, entry: &Entry) -> Result<Option<Tombstone>> {
+    if entry.content_len() == 0 {
+        return Ok(None);
+    }
+    // content that has not been downloaded yet can't be inspected
+    let Ok(bytes) = node
+        .blobs_store
+        .blobs()
+        .get_bytes(entry.content_hash())
+        .await
+    else {
+        return Ok(None);
+    };
+    let bytes = crypto::open(
+        node.cipher.as_ref(),
+        entry.id().namespace(),
+        entry.key(),
+        bytes.to_vec(),
+    )?;
+    Tombstone::from_bytes(&bytes)
+}
@@ -0,0 +1,130 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use futures::TryStreamExt;
+use iroh_blobs::{BlobFormat, Hash, api::blobs::BlobStatus, hashseq::HashSeq};
+use iroh_docs::store::Query;
+
+use crate::{quota::entry_hashes, server::IrohNet};
+
+// file of the docs store inside the storage path
+pub const DOCS_STORE_FILE: &str = "docs.redb";
+
+/// Entries and content of one table in [`DiskUsage`]
+#[derive(Debug, Clone)]
+pub struct TableUsage {
+    pub table: String,
+    // keys holding a non-empty entry
+    pub entries: usize,
+    // content size of those entries, as announced in the doc
+    pub content_bytes: u64,
+}
+
+/// Storage used by a node, see [`disk_usage`]
+#[derive(Debug, Clone)]
+pub struct DiskUsage {
+    // complete blobs in the blob store
+    pub blobs: usize,
+    pub blob_bytes: u64,
+    // size of the docs store file, `None` for an in-memory node
+    pub docs_store_bytes: Option<u64>,
+    // everything below the storage path, `None` for an in-memory node
+    pub storage_path_bytes: Option<u64>,
+    pub tables: Vec<TableUsage>,
+    // complete blobs neither held by a doc entry nor by a tag, removed by the next GC
+    pub reclaimable_bytes: u64,
+}
+
+/// Measure the blob store, the docs store and the registered tables of a node
+pub async fn disk_usage(node: &IrohNet) -> Result<DiskUsage> {
+    let blobs = node.blobs_store.blobs();
+    let mut sizes = vec![];
+    for hash in blobs.list().hashes().await? {
+        if let BlobStatus::Complete { size } = blobs.status(hash).await? {
+            sizes.push((hash, size));
+        }
+    }
+
+    let mut kept = entry_hashes(node).await?;
+    kept.extend(tagged_hashes(node).await?);
+    let reclaimable_bytes = sizes
+        .iter()
+        .filter(|(hash, _)| !kept.contains(hash))
+        .map(|(_, size)| size)
+        .sum();
+
+    let mut tables = vec![];
+    for (table, doc, prefix) in node.control.prefixed_tables().await {
+        let entries: Vec<_> = doc
+            .get_many(Query::single_latest_per_key().key_prefix(prefix))
+            .await?
+            .try_collect()
+            .await?;
+        let entries: Vec<_> = entries.iter().filter(|e| e.content_len() > 0).collect();
+        tables.push(TableUsage {
+            table,
+            entries: entries.len(),
+            content_bytes: entries.iter().map(|e| e.content_len()).sum(),
+        });
+    }
+    tables.sort_by(|a, b| a.table.cmp(&b.table));
+
+    let root = node.storage_path().map(PathBuf::from);
+    let (docs_store_bytes, storage_path_bytes) = match root {
+        Some(root) => {
+            let docs = tokio::fs::metadata(root.join(DOCS_STORE_FILE))
+                .await
+                .map(|meta| meta.len())
+                .unwrap_or(0);
+            let total = tokio::task::spawn_blocking(move || dir_size(&root)).await?;
+            (Some(docs), Some(total))
+        }
+        None => (None, None),
+    };
+
+    Ok(DiskUsage {
+        blobs: sizes.len(),
+        blob_bytes: sizes.iter().map(|(_, size)| size).sum(),
+        docs_store_bytes,
+        storage_path_bytes,
+        tables,
+        reclaimable_bytes,
+    })
+}
+
+// blobs protected by a tag, including the children of tagged hash sequences
+async fn tagged_hashes(node: &IrohNet) -> Result<HashSet<Hash>> {
+    let tags: Vec<_> = node.blobs_store.tags().list().await?.try_collect().await?;
+    let mut hashes = HashSet::new();
+    for tag in tags {
+        hashes.insert(tag.hash);
+        if tag.format == BlobFormat::HashSeq
+            && let Ok(bytes) = node.blobs_store.blobs().get_bytes(tag.hash).await
+            && let Ok(seq) = HashSeq::try_from(bytes)
+        {
+            hashes.extend(seq.iter());
+        }
+    }
+    Ok(hashes)
+}
+
+// size of the files below `path`, unreadable entries are skipped
+fn dir_size(path: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return 0;
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let meta = entry.metadata().ok()?;
+            Some(if meta.is_dir() {
+                dir_size(&entry.path())
+            } else {
+                meta.len()
+            })
+        })
+        .sum()
+}
@@ -0,0 +1,126 @@
+use std::sync::{Arc, RwLock};
+
+use anyhow::{Result, ensure};
+use serde::{Deserialize, Serialize};
+
+use crate::{model::resource::Resource, store::ToBytes};
+
+/// Check an entity has to pass before it is written, see [`IrohCls::add_validator`]
+///
+/// Any `Fn(&Entity) -> Result<()>` is a validator
+///
+/// [`IrohCls::add_validator`]: crate::store::IrohCls::add_validator
+pub trait Validator<Entity>: Send + Sync {
+    fn validate(&self, entity: &Entity) -> Result<()>;
+}
+
+impl<Entity, F> Validator<Entity> for F
+where
+    F: Fn(&Entity) -> Result<()> + Send + Sync,
+{
+    fn validate(&self, entity: &Entity) -> Result<()> {
+        self(entity)
+    }
+}
+
+/// Validators of a table, shared by all clones of its handle
+pub struct Validators<Entity> {
+    validators: Arc<RwLock<Vec<Arc<dyn Validator<Entity>>>>>,
+}
+
+impl<Entity> Default for Validators<Entity> {
+    fn default() -> Self {
+        Validators {
+            validators: Arc::new(RwLock::new(Vec::new())),
+        }
+    }
+}
+
+impl<Entity> Clone for Validators<Entity> {
+    fn clone(&self) -> Self {
+        Validators {
+            validators: self.validators.clone(),
+        }
+    }
+}
+
+impl<Entity> std::fmt::Debug for Validators<Entity> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Validators")
+            .field("count", &self.validators.read().unwrap().len())
+            .finish()
+    }
+}
+
+impl<Entity> Validators<Entity> {
+    pub fn add(&self, validator: impl Validator<Entity> + 'static) {
+        self.validators.write().unwrap().push(Arc::new(validator));
+    }
+
+    /// Run all validators in the order they were added, the first failure is returned
+    pub fn check(&self, entity: &Entity) -> Result<()> {
+        let validators = self.validators.read().unwrap().clone();
+        for validator in validators {
+            validator.validate(entity)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reject entities whose name is empty or only whitespace, e.g. folders
+pub fn non_empty_name<Entity>() -> impl Fn(&Entity) -> Result<()>
+where
+    Entity: ToBytes<Entity> + Serialize + Clone + for<'a> Deserialize<'a>,
+{
+    |entity: &Entity| {
+        ensure!(
+            entity
+                .sort_name()
+                .is_some_and(|name| !name.trim().is_empty()),
+            "Name must not be empty"
+        );
+        Ok(())
+    }
+}
+
+/// Reject resources whose name does not end in one of `extensions`, compared without case
+pub fn allowed_extensions(extensions: &[&str]) -> impl Fn(&Resource) -> Result<()> + use<> {
+    let extensions: Vec<String> = extensions.iter().map(|e| e.to_lowercase()).collect();
+    move |resource: &Resource| {
+        let extension = std::path::Path::new(&resource.name)
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase());
+        ensure!(
+            extension.is_some_and(|e| extensions.contains(&e)),
+            "File type of '{}' is not allowed, expected one of {}",
+            resource.name,
+            extensions.join(", ")
+        );
+        Ok(())
+    }
+}
+
+/// Reject resources of a content type starting with `content_type` larger than `max_size` bytes
+///
+/// `"image/"` limits all images, `""` all resources
+pub fn max_size_for_type(
+    content_type: &str,
+    max_size: u64,
+) -> impl Fn(&Resource) -> Result<()> + use<> {
+    let prefix = content_type.to_string();
+    move |resource: &Resource| {
+        let matches = resource
+            .content_type
+            .as_deref()
+            .unwrap_or_default()
+            .starts_with(&prefix);
+        ensure!(
+            !matches || resource.size <= max_size,
+            "'{}' has {} bytes, at most {} are allowed",
+            resource.name,
+            resource.size,
+            max_size
+        );
+        Ok(())
+    }
+}
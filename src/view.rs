@@ -0,0 +1,170 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use tokio::sync::RwLock;
+
+use crate::indexer::{EntityIndex, EntityMeta};
+
+// table holding the folders, every other table followed by the view holds resources
+const FOLDER_TABLE: &str = "folder";
+
+/// A resource in [`FolderNode::resources`]
+#[derive(Debug, Clone)]
+pub struct FiledResource {
+    pub table: String,
+    pub meta: EntityMeta,
+}
+
+/// A folder of [`FolderView::folder_tree`] with the resources filed into it
+#[derive(Debug, Clone)]
+pub struct FolderNode {
+    pub folder_id: String,
+    pub name: String,
+    // sorted by name
+    pub resources: Vec<FiledResource>,
+    // sorted by name
+    pub children: Vec<FolderNode>,
+}
+
+#[derive(Debug, Default)]
+struct ViewData {
+    // folders keyed by folder id
+    folders: HashMap<String, EntityMeta>,
+    // resources keyed by table and id
+    resources: HashMap<(String, String), EntityMeta>,
+}
+
+impl ViewData {
+    fn node(
+        &self,
+        folder: &EntityMeta,
+        children: &HashMap<Option<&str>, Vec<&EntityMeta>>,
+        filed: &HashMap<&str, Vec<FiledResource>>,
+        seen: &mut HashSet<String>,
+    ) -> FolderNode {
+        seen.insert(folder.key.clone());
+        let mut nodes = vec![];
+        for child in children
+            .get(&Some(folder.key.as_str()))
+            .into_iter()
+            .flatten()
+        {
+            // folders whose parents form a cycle are left out
+            if !seen.contains(&child.key) {
+                nodes.push(self.node(child, children, filed, seen));
+            }
+        }
+        FolderNode {
+            folder_id: folder.key.clone(),
+            name: folder.name.clone().unwrap_or_default(),
+            resources: filed.get(folder.key.as_str()).cloned().unwrap_or_default(),
+            children: nodes,
+        }
+    }
+}
+
+/// Folders joined with the resources filed into them, kept in memory
+///
+/// Kept up to date by [`spawn_indexer`] from the doc events of the folder table and the
+/// resource tables, so the whole hierarchy is read in one call without decoding entries
+///
+/// [`spawn_indexer`]: crate::indexer::spawn_indexer
+#[derive(Debug, Clone, Default)]
+pub struct FolderView {
+    data: Arc<RwLock<ViewData>>,
+}
+
+impl FolderView {
+    /// The top level folders with their resources and subfolders, sorted by name
+    pub async fn folder_tree(&self) -> Vec<FolderNode> {
+        let data = self.data.read().await;
+        let mut children: HashMap<Option<&str>, Vec<&EntityMeta>> = HashMap::new();
+        for folder in data.folders.values() {
+            // folders in a folder that is not known yet are shown at the top level
+            let parent = folder
+                .folder_id
+                .as_deref()
+                .filter(|parent| data.folders.contains_key(*parent));
+            children.entry(parent).or_default().push(folder);
+        }
+        for folders in children.values_mut() {
+            folders.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+        let mut filed: HashMap<&str, Vec<FiledResource>> = HashMap::new();
+        for ((table, _), meta) in &data.resources {
+            if let Some(folder_id) = meta.folder_id.as_deref() {
+                filed.entry(folder_id).or_default().push(FiledResource {
+                    table: table.clone(),
+                    meta: meta.clone(),
+                });
+            }
+        }
+        for resources in filed.values_mut() {
+            resources.sort_by(|a, b| a.meta.name.cmp(&b.meta.name));
+        }
+
+        let mut seen = HashSet::new();
+        children
+            .get(&None)
+            .into_iter()
+            .flatten()
+            .map(|folder| data.node(folder, &children, &filed, &mut seen))
+            .collect()
+    }
+
+    /// Resources not filed into any known folder, sorted by name
+    pub async fn unfiled(&self) -> Vec<FiledResource> {
+        let data = self.data.read().await;
+        let mut unfiled: Vec<FiledResource> = data
+            .resources
+            .iter()
+            .filter(|(_, meta)| {
+                meta.folder_id
+                    .as_deref()
+                    .is_none_or(|folder_id| !data.folders.contains_key(folder_id))
+            })
+            .map(|((table, _), meta)| FiledResource {
+                table: table.clone(),
+                meta: meta.clone(),
+            })
+            .collect();
+        unfiled.sort_by(|a, b| a.meta.name.cmp(&b.meta.name));
+        unfiled
+    }
+}
+
+impl EntityIndex for FolderView {
+    async fn reindex(&self, table: &str, entities: Vec<EntityMeta>) -> Result<()> {
+        let mut data = self.data.write().await;
+        if table == FOLDER_TABLE {
+            data.folders = entities.into_iter().map(|e| (e.key.clone(), e)).collect();
+        } else {
+            data.resources.retain(|(t, _), _| t != table);
+            for entity in entities {
+                data.resources
+                    .insert((table.to_string(), entity.key.clone()), entity);
+            }
+        }
+        Ok(())
+    }
+
+    async fn update(&self, table: &str, key: &str, entity: Option<EntityMeta>) -> Result<()> {
+        let mut data = self.data.write().await;
+        if table == FOLDER_TABLE {
+            match entity {
+                Some(entity) => data.folders.insert(key.to_string(), entity),
+                None => data.folders.remove(key),
+            };
+        } else {
+            let id = (table.to_string(), key.to_string());
+            match entity {
+                Some(entity) => data.resources.insert(id, entity),
+                None => data.resources.remove(&id),
+            };
+        }
+        Ok(())
+    }
+}
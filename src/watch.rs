@@ -0,0 +1,171 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use notify::{Event, RecursiveMode, Watcher};
+use tokio::{sync::mpsc, task::JoinHandle};
+use tracing::{Instrument, info, info_span, warn};
+
+use crate::model::resource::Resources;
+
+// changes arriving within this window are applied together
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+// links the files of a watched dir to their resources, hidden so it is not synced itself
+const STATE_FILE: &str = ".iroh-watch.json";
+
+// resource id of each synced file, keyed by its path relative to the watched dir
+type Known = HashMap<PathBuf, String>;
+
+/// Mirror the files of `dir` into the resource table until the returned task is aborted
+///
+/// New files are added, changed files overwrite their resource and deleted files remove it.
+/// Only resources added by a watcher of `dir` are touched, they are linked to their files by
+/// relative path in a hidden state file inside `dir`.
+pub fn spawn_watcher(resources: Resources, dir: PathBuf) -> Result<JoinHandle<()>> {
+    let (tx, mut rx) = mpsc::channel::<notify::Result<Event>>(256);
+    let mut watcher = notify::recommended_watcher(move |res| {
+        let _ = tx.blocking_send(res);
+    })?;
+    watcher
+        .watch(&dir, RecursiveMode::NonRecursive)
+        .with_context(|| format!("Failed to watch {:?}", dir))?;
+
+    let span = info_span!("watch", ?dir);
+    Ok(tokio::spawn(
+        async move {
+            // the watcher stops when dropped, so it lives as long as the task
+            let _watcher = watcher;
+            let mut known = match initial_sync(&resources, &dir).await {
+                Ok(known) => known,
+                Err(e) => {
+                    warn!(error = %e, "initial sync failed");
+                    return;
+                }
+            };
+            info!(files = known.len(), "watching");
+
+            while let Some(first) = rx.recv().await {
+                let mut changed = HashSet::new();
+                collect_paths(first, &mut changed);
+                while let Ok(Some(res)) = tokio::time::timeout(DEBOUNCE, rx.recv()).await {
+                    collect_paths(res, &mut changed);
+                }
+                for path in changed {
+                    if let Err(e) = apply_change(&resources, &dir, &mut known, &path).await {
+                        warn!(?path, error = %e, "failed to sync");
+                    }
+                }
+                if let Err(e) = save_known(&dir, &known).await {
+                    warn!(error = %e, "failed to save watch state");
+                }
+            }
+        }
+        .instrument(span),
+    ))
+}
+
+fn collect_paths(res: notify::Result<Event>, changed: &mut HashSet<PathBuf>) {
+    match res {
+        Ok(event) => changed.extend(event.paths),
+        Err(e) => warn!(error = %e, "watcher error"),
+    }
+}
+
+// file name of a path that should be synced, hidden files are skipped
+fn synced_name(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_string_lossy().to_string();
+    (!name.starts_with('.')).then_some(name)
+}
+
+// links saved by an earlier watcher of `dir`
+async fn load_known(resources: &Resources, dir: &Path) -> Result<Known> {
+    let known: Known = match tokio::fs::read(dir.join(STATE_FILE)).await {
+        Ok(bytes) => serde_json::from_slice(&bytes).context("Invalid watch state")?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Known::new(),
+        Err(e) => return Err(e.into()),
+    };
+    // resources removed elsewhere are forgotten, their files are added again
+    let mut live = Known::new();
+    for (path, id) in known {
+        if resources.get(&id).await?.is_some() {
+            live.insert(path, id);
+        }
+    }
+    Ok(live)
+}
+
+async fn save_known(dir: &Path, known: &Known) -> Result<()> {
+    tokio::fs::write(dir.join(STATE_FILE), serde_json::to_vec_pretty(known)?).await?;
+    Ok(())
+}
+
+// add the files that are not stored yet and remove the resources of files deleted meanwhile
+async fn initial_sync(resources: &Resources, dir: &Path) -> Result<Known> {
+    let mut known = load_known(resources, dir).await?;
+
+    let gone: Vec<PathBuf> = known
+        .keys()
+        .filter(|path| !dir.join(path).is_file())
+        .cloned()
+        .collect();
+    for path in gone {
+        if let Some(id) = known.remove(&path) {
+            resources.remove(&id).await?;
+            info!(?path, "removed");
+        }
+    }
+
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let Some(name) = synced_name(&path) else {
+            continue;
+        };
+        let relative = PathBuf::from(&name);
+        if path.is_file() && !known.contains_key(&relative) {
+            let content = tokio::fs::read(&path).await?;
+            let id = resources.add_file(name.clone(), content).await?;
+            info!(%name, "added");
+            known.insert(relative, id);
+        }
+    }
+    save_known(dir, &known).await?;
+    Ok(known)
+}
+
+async fn apply_change(
+    resources: &Resources,
+    dir: &Path,
+    known: &mut Known,
+    path: &Path,
+) -> Result<()> {
+    let Some(name) = synced_name(path) else {
+        return Ok(());
+    };
+    let relative = path.strip_prefix(dir).unwrap_or(path).to_path_buf();
+
+    if path.is_file() {
+        let content = tokio::fs::read(path).await?;
+        match known.get(&relative) {
+            Some(id) => {
+                resources.put_file(id, name.clone(), content).await?;
+                info!(%name, "updated");
+            }
+            None => {
+                let id = resources.add_file(name.clone(), content).await?;
+                info!(%name, "added");
+                known.insert(relative, id);
+            }
+        }
+    } else if !path.exists()
+        && let Some(id) = known.remove(&relative)
+    {
+        resources.remove(&id).await?;
+        info!(%name, "removed");
+    }
+    Ok(())
+}
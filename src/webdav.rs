@@ -0,0 +1,330 @@
+use std::{net::SocketAddr, path::Path};
+
+use anyhow::Result;
+use tokio::{io::BufReader, net::TcpStream, task::JoinHandle};
+use tracing::debug;
+
+use crate::gateway::{
+    Request, copy_body, escape_html, http_date, listen, percent_decode, percent_encode, read_body,
+    read_request, send_content, write_response,
+};
+use crate::import::guess_content_type;
+use crate::model::folder::{Folder, Folders};
+use crate::model::resource::{Resource, Resources, TempFile};
+
+// address `serve-webdav` listens on when none is given
+pub const DEFAULT_WEBDAV_ADDR: &str = "127.0.0.1:8081";
+// methods answered by the share
+const ALLOW: &str = "OPTIONS, GET, HEAD, PUT, DELETE, MKCOL, PROPFIND";
+// bodies of requests other than PUT are read and dropped up to this size
+const MAX_IGNORED_BODY: u64 = 1024 * 1024;
+
+#[derive(Clone)]
+struct Share {
+    folders: Folders,
+    resources: Resources,
+}
+
+/// What a path of the share points to
+enum Target {
+    Root,
+    Folder(Folder),
+    File(Resource),
+}
+
+impl Target {
+    fn folder_id(&self) -> Option<Option<String>> {
+        match self {
+            Target::Root => Some(None),
+            Target::Folder(folder) => Some(Some(folder.folder_id.clone())),
+            Target::File(_) => None,
+        }
+    }
+}
+
+/// Share the folder table and a resource table over WebDAV on `addr` until the returned
+/// task is aborted
+///
+/// Folders are collections and resources are files, named as in the tables. Files can be
+/// downloaded with range requests, uploaded and deleted, and folders created and deleted
+/// while empty. Locking, moving and copying are not supported, so some file managers only
+/// mount the share read-only. Returns the address actually bound
+pub async fn serve_webdav(
+    folders: Folders,
+    resources: Resources,
+    addr: SocketAddr,
+) -> Result<(SocketAddr, JoinHandle<()>)> {
+    let share = Share { folders, resources };
+    listen(addr, "webdav", share, handle_connection).await
+}
+
+// answer a single request, the connection is closed afterwards
+async fn handle_connection(share: Share, stream: TcpStream) -> Result<()> {
+    let mut stream = BufReader::new(stream);
+    let request = read_request(&mut stream).await?;
+    debug!(method = %request.method, path = %request.path, "webdav request");
+    let segments: Vec<String> = request
+        .path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(percent_decode)
+        .collect();
+
+    if request.method == "PUT" {
+        let max = match share.resources.node.placement.max_file_size {
+            0 => u64::MAX,
+            max => max,
+        };
+        // the upload goes to a file first, so its size is only bounded by the limit
+        let mut upload = TempFile::create(&share.resources.node).await?;
+        let body = copy_body(&mut stream, &request, max, &mut upload.file).await;
+        let stream = stream.get_mut();
+        return match body {
+            Ok(_) => put(&share, stream, &segments, &upload.path).await,
+            Err(e) => {
+                debug!(error = %e, "failed to read upload");
+                write_response(stream, "413 Content Too Large", &[], b"").await
+            }
+        };
+    }
+    read_body(&mut stream, &request, MAX_IGNORED_BODY).await?;
+    let stream = stream.get_mut();
+    match request.method.as_str() {
+        "OPTIONS" => {
+            let headers = [("DAV", "1"), ("Allow", ALLOW), ("MS-Author-Via", "DAV")];
+            write_response(stream, "200 OK", &headers, b"").await
+        }
+        "PROPFIND" => propfind(&share, stream, &segments, &request).await,
+        "GET" | "HEAD" => get(&share, stream, &segments, &request).await,
+        "MKCOL" => mkcol(&share, stream, &segments).await,
+        "DELETE" => delete(&share, stream, &segments).await,
+        _ => write_response(stream, "501 Not Implemented", &[("Allow", ALLOW)], b"").await,
+    }
+}
+
+// follow the folder names of `segments`, the last one may also name a file
+async fn resolve(share: &Share, segments: &[String]) -> Result<Option<Target>> {
+    let mut target = Target::Root;
+    for (i, segment) in segments.iter().enumerate() {
+        let Some(parent) = target.folder_id() else {
+            return Ok(None);
+        };
+        let folder = share
+            .folders
+            .children(parent.as_deref())
+            .await?
+            .into_iter()
+            .find(|f| &f.folder_name == segment);
+        target = match folder {
+            Some(folder) => Target::Folder(folder),
+            None if i == segments.len() - 1 => {
+                let file = share
+                    .resources
+                    .list_in_folder(parent.as_deref())
+                    .await?
+                    .into_iter()
+                    .find(|r| &r.name == segment);
+                match file {
+                    Some(file) => Target::File(file),
+                    None => return Ok(None),
+                }
+            }
+            None => return Ok(None),
+        };
+    }
+    Ok(Some(target))
+}
+
+// the folder holding the last segment and the name it gives, `None` if there is no such folder
+async fn resolve_parent(
+    share: &Share,
+    segments: &[String],
+) -> Result<Option<(Option<String>, String)>> {
+    let Some((name, parent)) = segments.split_last() else {
+        return Ok(None);
+    };
+    let parent = resolve(share, parent).await?;
+    Ok(parent
+        .and_then(|p| p.folder_id())
+        .map(|folder_id| (folder_id, name.clone())))
+}
+
+async fn propfind(
+    share: &Share,
+    stream: &mut TcpStream,
+    segments: &[String],
+    request: &Request,
+) -> Result<()> {
+    let Some(target) = resolve(share, segments).await? else {
+        return write_response(stream, "404 Not Found", &[], b"").await;
+    };
+    let href: String = segments
+        .iter()
+        .map(|s| format!("/{}", percent_encode(s)))
+        .collect();
+    let mut body = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n",
+    );
+    match &target {
+        Target::File(file) => body.push_str(&file_response(&href, file)),
+        Target::Root | Target::Folder(_) => {
+            let name = segments.last().map(String::as_str).unwrap_or_default();
+            body.push_str(&collection_response(&format!("{}/", href), name));
+            // infinite depth is answered like depth 1
+            if request.header("depth") != Some("0") {
+                let folder_id = target.folder_id().flatten();
+                for folder in share.folders.children(folder_id.as_deref()).await? {
+                    let child = format!("{}/{}/", href, percent_encode(&folder.folder_name));
+                    body.push_str(&collection_response(&child, &folder.folder_name));
+                }
+                for file in share.resources.list_in_folder(folder_id.as_deref()).await? {
+                    let child = format!("{}/{}", href, percent_encode(&file.name));
+                    body.push_str(&file_response(&child, &file));
+                }
+            }
+        }
+    }
+    body.push_str("</D:multistatus>\n");
+    let headers = [("Content-Type", "application/xml; charset=utf-8")];
+    write_response(stream, "207 Multi-Status", &headers, body.as_bytes()).await
+}
+
+fn collection_response(href: &str, name: &str) -> String {
+    format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>\
+         <D:displayname>{}</D:displayname><D:resourcetype><D:collection/></D:resourcetype>\
+         </D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+        escape_html(href),
+        escape_html(name)
+    )
+}
+
+fn file_response(href: &str, file: &Resource) -> String {
+    let content_type = file
+        .content_type
+        .as_deref()
+        .unwrap_or("application/octet-stream");
+    format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>\
+         <D:displayname>{}</D:displayname><D:resourcetype/>\
+         <D:getcontentlength>{}</D:getcontentlength>\
+         <D:getcontenttype>{}</D:getcontenttype>\
+         <D:getetag>\"{}\"</D:getetag>\
+         <D:getlastmodified>{}</D:getlastmodified>\
+         </D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+        escape_html(href),
+        escape_html(&file.name),
+        file.size,
+        escape_html(content_type),
+        file.content_hash(),
+        http_date(file.created_at)
+    )
+}
+
+async fn get(
+    share: &Share,
+    stream: &mut TcpStream,
+    segments: &[String],
+    request: &Request,
+) -> Result<()> {
+    let head = request.method == "HEAD";
+    match resolve(share, segments).await? {
+        Some(Target::File(file)) => {
+            send_content(
+                &share.resources,
+                stream,
+                &file,
+                request.header("range"),
+                head,
+            )
+            .await
+        }
+        Some(target) => {
+            // a plain page so the share can be browsed without a WebDAV client
+            let folder_id = target.folder_id().flatten();
+            let mut page = String::from("<!DOCTYPE html>\n<html><body><ul>\n");
+            for folder in share.folders.children(folder_id.as_deref()).await? {
+                let name = escape_html(&folder.folder_name);
+                let link = percent_encode(&folder.folder_name);
+                page.push_str(&format!("<li><a href=\"{}/\">{}/</a></li>\n", link, name));
+            }
+            for file in share.resources.list_in_folder(folder_id.as_deref()).await? {
+                let name = escape_html(&file.name);
+                let link = percent_encode(&file.name);
+                page.push_str(&format!("<li><a href=\"{}\">{}</a></li>\n", link, name));
+            }
+            page.push_str("</ul></body></html>\n");
+            let length = page.len().to_string();
+            let headers = [
+                ("Content-Type", "text/html; charset=utf-8"),
+                ("Content-Length", length.as_str()),
+            ];
+            let body: &[u8] = if head { b"" } else { page.as_bytes() };
+            write_response(stream, "200 OK", &headers, body).await
+        }
+        None => write_response(stream, "404 Not Found", &[], b"").await,
+    }
+}
+
+// add the upload spooled to `body`, overwriting the file of the same name in the folder
+async fn put(
+    share: &Share,
+    stream: &mut TcpStream,
+    segments: &[String],
+    body: &Path,
+) -> Result<()> {
+    let Some((folder_id, name)) = resolve_parent(share, segments).await? else {
+        return write_response(stream, "409 Conflict", &[], b"").await;
+    };
+    match resolve(share, segments).await? {
+        Some(Target::File(file)) => {
+            share.resources.put_path(&file.id, name, body).await?;
+            write_response(stream, "204 No Content", &[], b"").await
+        }
+        Some(_) => write_response(stream, "405 Method Not Allowed", &[("Allow", ALLOW)], b"").await,
+        None => {
+            let content_type = guess_content_type(&name);
+            let id = share
+                .resources
+                .add_path_with_progress(body, name, content_type, |_| {})
+                .await?;
+            if folder_id.is_some() {
+                share.resources.move_to_folder(&id, folder_id).await?;
+            }
+            write_response(stream, "201 Created", &[], b"").await
+        }
+    }
+}
+
+async fn mkcol(share: &Share, stream: &mut TcpStream, segments: &[String]) -> Result<()> {
+    let Some((parent_id, name)) = resolve_parent(share, segments).await? else {
+        return write_response(stream, "409 Conflict", &[], b"").await;
+    };
+    if resolve(share, segments).await?.is_some() {
+        return write_response(stream, "405 Method Not Allowed", &[("Allow", ALLOW)], b"").await;
+    }
+    share.folders.create_folder(name, parent_id).await?;
+    write_response(stream, "201 Created", &[], b"").await
+}
+
+// delete a file, or a folder once it is empty
+async fn delete(share: &Share, stream: &mut TcpStream, segments: &[String]) -> Result<()> {
+    match resolve(share, segments).await? {
+        Some(Target::File(file)) => {
+            share.resources.remove(&file.id).await?;
+            write_response(stream, "204 No Content", &[], b"").await
+        }
+        Some(Target::Folder(folder)) => {
+            let id = Some(folder.folder_id.as_str());
+            if !share.folders.children(id).await?.is_empty()
+                || !share.resources.list_in_folder(id).await?.is_empty()
+            {
+                return write_response(stream, "409 Conflict", &[], b"").await;
+            }
+            share.folders.delete(&folder.folder_id).await?;
+            write_response(stream, "204 No Content", &[], b"").await
+        }
+        Some(Target::Root) => write_response(stream, "403 Forbidden", &[], b"").await,
+        None => write_response(stream, "404 Not Found", &[], b"").await,
+    }
+}
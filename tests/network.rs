@@ -0,0 +1,38 @@
+use std::time::Duration;
+
+use iroh_test::testing::TestNetwork;
+
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+#[tokio::test]
+async fn two_nodes_converge_on_a_resource() -> anyhow::Result<()> {
+    let net = TestNetwork::spawn(2).await?;
+    net.wait_initial_sync(TIMEOUT).await?;
+
+    let id = net
+        .node(0)
+        .resource_table("resource")
+        .await?
+        .add_file("hello.txt".into(), b"hello from node 0".to_vec())
+        .await?;
+    net.wait_converged("resource", TIMEOUT).await?;
+    net.assert_converged("resource").await?;
+
+    let copy = net
+        .node(1)
+        .resource_table("resource")
+        .await?
+        .get(&id)
+        .await?
+        .expect("resource synced to node 1");
+    assert_eq!(copy.name, "hello.txt");
+
+    net.shutdown().await
+}
+
+#[tokio::test]
+async fn joined_nodes_converge_on_the_seeded_tables() -> anyhow::Result<()> {
+    let net = TestNetwork::spawn(3).await?;
+    net.wait_all_converged(TIMEOUT).await?;
+    net.shutdown().await
+}